@@ -110,7 +110,7 @@ fn benchmark_real_throughput(c: &mut Criterion) {
 
                     // Cleanup
                     shutdown.store(true, Ordering::Relaxed);
-                    conn.close();
+                    conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
                     client.shutdown().await;
                     server.shutdown().await;
 
@@ -205,7 +205,7 @@ fn benchmark_real_latency(c: &mut Criterion) {
 
             // Cleanup
             shutdown.store(true, Ordering::Relaxed);
-            conn.close();
+            conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
             client.shutdown().await;
             server.shutdown().await;
 
@@ -296,7 +296,7 @@ fn benchmark_concurrent_connections(c: &mut Criterion) {
                             let mut stream = conn.open_stream().await.unwrap();
                             stream.send(&[0x42; 1024]).await.unwrap();
 
-                            conn.close();
+                            conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
                             connect_time
                         });
                         handles.push(handle);
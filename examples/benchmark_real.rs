@@ -91,7 +91,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let conn = client.connect(&endpoint).await?;
     let mut stream = conn.open_stream().await?;
     stream.send(&vec![0; 1024]).await?;
-    conn.close();
+    conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
 
     println!("\n┌─────────────────────────────────────────────────────────┐");
     println!("│              THROUGHPUT BENCHMARKS                         │");
@@ -144,7 +144,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             gbps
         );
 
-        conn.close();
+        conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
     }
 
     println!("└─────────────────────────────────────────────────────────┘\n");
@@ -173,7 +173,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let warm_time = start.elapsed();
         warm_times.push(warm_time.as_micros());
 
-        conn2.close();
+        conn2.close(stoq::protocol::error_codes::NO_ERROR, b"done");
     }
 
     let avg_cold = cold_times.iter().sum::<u128>() / cold_times.len() as u128;
@@ -28,7 +28,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     config.max_concurrent_streams = 100;
     config.send_buffer_size = 2 * 1024 * 1024; // Start with 2MB
 
-    let mut transport = StoqTransport::new(config.clone()).await?;
+    let transport = StoqTransport::new(config.clone()).await?;
 
     // Start the adaptive optimization system
     transport.start_adaptation().await;
@@ -146,7 +146,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     gigabit_config.receive_buffer_size = 8 * 1024 * 1024;
     gigabit_config.max_concurrent_streams = 500;
 
-    transport.update_live_config(gigabit_config).await;
+    transport.update_live_config(gigabit_config)?;
     info!("Configuration updated - connections adapting...");
     sleep(Duration::from_secs(5)).await;
 
@@ -158,7 +158,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     datacenter_config.max_concurrent_streams = 1000;
     datacenter_config.max_datagram_size = 9000; // Jumbo frames
 
-    transport.update_live_config(datacenter_config).await;
+    transport.update_live_config(datacenter_config)?;
     info!("Configuration updated to maximum performance");
     sleep(Duration::from_secs(5)).await;
 
@@ -174,13 +174,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     degraded_config.receive_buffer_size = 512 * 1024;
     degraded_config.max_concurrent_streams = 50;
 
-    transport.update_live_config(degraded_config).await;
+    transport.update_live_config(degraded_config)?;
     info!("Adapted to degraded network conditions");
     sleep(Duration::from_secs(5)).await;
 
     // Scenario 6: Recovery to optimal
     info!("\nPhase 6: Network recovery - returning to optimal");
-    transport.update_live_config(config.clone()).await;
+    transport.update_live_config(config.clone())?;
     transport.auto_detect_tiers().await;
     sleep(Duration::from_secs(5)).await;
 
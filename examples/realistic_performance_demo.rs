@@ -97,7 +97,7 @@ async fn measure_loopback_throughput() -> anyhow::Result<f64> {
     let mbps = (test_data.len() as f64 * 8.0) / (duration.as_secs_f64() * 1_000_000.0);
 
     // Cleanup
-    conn.close();
+    conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
     client.shutdown().await;
     server.shutdown().await;
 
@@ -157,7 +157,7 @@ async fn measure_round_trip_latency() -> anyhow::Result<f64> {
     let avg_latency = latencies.iter().sum::<f64>() / latencies.len() as f64;
 
     // Cleanup
-    conn.close();
+    conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
     client.shutdown().await;
     server.shutdown().await;
 
@@ -209,7 +209,7 @@ async fn measure_connection_rate() -> anyhow::Result<f64> {
             let conn = client_clone.connect(&endpoint_clone).await.unwrap();
             let mut stream = conn.open_stream().await.unwrap();
             stream.send(&[0x42; 10]).await.unwrap();
-            conn.close();
+            conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
         });
         handles.push(handle);
     }
@@ -0,0 +1,27 @@
+//! Regenerate the checked-in XDP program
+//!
+//! Writes `ProgramGenerator::generate_xdp_source` to
+//! `src/transport/ebpf/programs/xdp.c` and compiles it to `stoq_xdp.o` next
+//! to it. Needs clang, the kernel headers and the libbpf headers.
+//!
+//! Run with: cargo run --example regenerate_xdp --features ebpf
+
+use anyhow::Result;
+
+#[cfg(feature = "ebpf")]
+fn main() -> Result<()> {
+    use std::path::Path;
+    use stoq::transport::ebpf::loader::{EbpfLoader, EbpfSources, ProgramGenerator};
+
+    let programs = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/transport/ebpf/programs");
+    let xdp_source = programs.join("xdp.c");
+    ProgramGenerator::save_source(&ProgramGenerator::generate_xdp_source(), &xdp_source)?;
+    EbpfLoader::with_sources(EbpfSources { xdp_source, tc_source: None, output_dir: programs.clone() }).compile()?;
+    println!("Wrote {}", programs.join("stoq_xdp.o").display());
+    Ok(())
+}
+
+#[cfg(not(feature = "ebpf"))]
+fn main() -> Result<()> {
+    anyhow::bail!("build with --features ebpf")
+}
@@ -32,7 +32,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
           config.send_buffer_size / (1024*1024),
           config.max_concurrent_streams);
 
-    let mut transport = StoqTransport::new(config.clone()).await?;
+    let transport = StoqTransport::new(config.clone()).await?;
 
     // Start adaptive optimization
     transport.start_adaptation().await;
@@ -51,7 +51,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
           new_config.send_buffer_size / (1024*1024),
           new_config.max_concurrent_streams);
 
-    transport.update_live_config(new_config).await;
+    transport.update_live_config(new_config)?;
     info!("✓ Configuration updated for live connections");
 
     // Wait for adaptation
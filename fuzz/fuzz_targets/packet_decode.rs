@@ -0,0 +1,19 @@
+//! Decode arbitrary bytes as a StoqPacket
+//!
+//! Run with `cargo +nightly fuzz run packet_decode`. Decoding must never
+//! panic, and any packet that decodes must re-encode canonically.
+
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use stoq::extensions::StoqPacket;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(packet) = StoqPacket::deserialize(Bytes::copy_from_slice(data)) else {
+        return;
+    };
+    let encoded = packet.serialize().expect("decoded packet re-encodes");
+    let again = StoqPacket::deserialize(encoded.clone()).expect("canonical encoding decodes");
+    assert_eq!(again.serialize().unwrap(), encoded);
+});
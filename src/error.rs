@@ -0,0 +1,390 @@
+//! STOQ error types
+//!
+//! Typed errors for the public transport API. Quinn's connection, stream and
+//! datagram errors are preserved as-is so callers can inspect transport error
+//! codes and application close codes, and every error can be classified as
+//! retryable or fatal without string matching.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use quinn::{ConnectionError, ReadError, WriteError, VarInt};
+use quinn::TransportErrorCode;
+use thiserror::Error;
+
+/// Result type used by the public STOQ API
+pub type Result<T, E = StoqError> = std::result::Result<T, E>;
+
+/// Errors returned by the STOQ transport
+#[derive(Debug, Error)]
+pub enum StoqError {
+    /// Failed to initiate an outgoing connection
+    #[error("connect failed: {0}")]
+    Connect(#[from] quinn::ConnectError),
+
+    /// Connection failed during the handshake or was lost afterwards
+    #[error("connection lost: {0}")]
+    Connection(#[from] ConnectionError),
+
+    /// Writing to a stream failed
+    #[error("stream write failed: {0}")]
+    Write(#[from] WriteError),
+
+    /// Reading from a stream failed
+    #[error("stream read failed: {0}")]
+    Read(#[from] ReadError),
+
+    /// Stream data exceeded the read limit
+    #[error("stream exceeded maximum length of {limit} bytes")]
+    StreamTooLong {
+        /// Maximum number of bytes accepted
+        limit: usize,
+    },
+
+    /// Operation on a stream that was already finished or reset
+    #[error("stream already closed")]
+    ClosedStream(#[from] quinn::ClosedStream),
+
+    /// Sending a datagram failed
+    #[error("datagram send failed: {0}")]
+    Datagram(#[from] quinn::SendDatagramError),
+
+    /// Operation did not complete within the configured timeout
+    #[error("operation timed out after {0:?}")]
+    Timeout(Duration),
+
+    /// TLS configuration or handshake failure
+    #[error("TLS error: {0}")]
+    Tls(#[from] rustls::Error),
+
+    /// Peer connected over IPv4, which STOQ rejects
+    #[error("IPv4 peer {0} rejected: STOQ is IPv6-only")]
+    Ipv4Rejected(SocketAddr),
+
+    /// The local endpoint has been closed and accepts no more connections
+    #[error("endpoint closed")]
+    EndpointClosed,
+
+    /// No connection is registered under the given ID
+    #[error("connection not found: {0}")]
+    ConnectionNotFound(String),
+
+    /// Invalid transport configuration
+    #[error("invalid configuration: {0}")]
+    Config(String),
+
+    /// Certificate issuance, rotation or validation failure
+    #[error("certificate error: {0}")]
+    Certificate(String),
+
+    /// FALCON signing or verification failure
+    #[error("FALCON error: {0}")]
+    Falcon(String),
+
+    /// Malformed or unexpected STOQ protocol data
+    #[error("protocol error: {0}")]
+    Protocol(String),
+
+    /// Too few nodes acknowledged a seeded payload
+    #[error("seeding reached {acked} of {required} required acknowledgements")]
+    Quorum {
+        /// Nodes that acknowledged
+        acked: usize,
+        /// Acknowledgements needed
+        required: usize,
+    },
+
+    /// An optional subsystem is disabled or not compiled in
+    #[error("{0} not available")]
+    Unavailable(&'static str),
+
+    /// Socket-level I/O failure
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// Any other failure
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Coarse classification of a [`StoqError`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Idle timeout or operation timeout
+    Timeout,
+    /// TLS failure, including handshake failures reported by the peer
+    Tls,
+    /// Peer reset the connection (stateless reset)
+    PeerReset,
+    /// Peer closed the connection with an application close code
+    ApplicationClosed,
+    /// Peer aborted the connection with a transport error code
+    TransportClosed,
+    /// Connection was closed locally
+    LocallyClosed,
+    /// Peer reset a stream we were reading
+    StreamReset,
+    /// Peer stopped a stream we were writing
+    StreamStopped,
+    /// Flow-control or stream-limit violation
+    FlowControl,
+    /// IPv4 peer rejected
+    Ipv4Rejected,
+    /// Datagram could not be sent
+    Datagram,
+    /// Invalid configuration or arguments
+    Config,
+    /// Certificate management failure
+    Certificate,
+    /// FALCON cryptography failure
+    Falcon,
+    /// Protocol violation or malformed data
+    Protocol,
+    /// Too few replicas acknowledged
+    Quorum,
+    /// Feature not available
+    Unavailable,
+    /// Socket I/O failure
+    Io,
+    /// Unclassified failure
+    Other,
+}
+
+impl ErrorKind {
+    /// Stable lowercase name, suitable for logs and metric labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Timeout => "timeout",
+            Self::Tls => "tls",
+            Self::PeerReset => "peer_reset",
+            Self::ApplicationClosed => "application_closed",
+            Self::TransportClosed => "transport_closed",
+            Self::LocallyClosed => "locally_closed",
+            Self::StreamReset => "stream_reset",
+            Self::StreamStopped => "stream_stopped",
+            Self::FlowControl => "flow_control",
+            Self::Ipv4Rejected => "ipv4_rejected",
+            Self::Datagram => "datagram",
+            Self::Config => "config",
+            Self::Certificate => "certificate",
+            Self::Falcon => "falcon",
+            Self::Protocol => "protocol",
+            Self::Quorum => "quorum",
+            Self::Unavailable => "unavailable",
+            Self::Io => "io",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl StoqError {
+    /// Classify this error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Connect(e) => match e {
+                quinn::ConnectError::CidsExhausted => ErrorKind::Other,
+                quinn::ConnectError::EndpointStopping => ErrorKind::LocallyClosed,
+                quinn::ConnectError::InvalidRemoteAddress(addr) if addr.is_ipv4() => ErrorKind::Ipv4Rejected,
+                _ => ErrorKind::Config,
+            },
+            Self::Connection(e) => connection_error_kind(e),
+            Self::Write(e) => match e {
+                WriteError::Stopped(_) => ErrorKind::StreamStopped,
+                WriteError::ConnectionLost(e) => connection_error_kind(e),
+                WriteError::ClosedStream => ErrorKind::Protocol,
+                WriteError::ZeroRttRejected => ErrorKind::Tls,
+            },
+            Self::Read(e) => match e {
+                ReadError::Reset(_) => ErrorKind::StreamReset,
+                ReadError::ConnectionLost(e) => connection_error_kind(e),
+                ReadError::ClosedStream | ReadError::IllegalOrderedRead => ErrorKind::Protocol,
+                ReadError::ZeroRttRejected => ErrorKind::Tls,
+            },
+            Self::StreamTooLong { .. } => ErrorKind::Protocol,
+            Self::ClosedStream(_) => ErrorKind::Protocol,
+            Self::Datagram(e) => match e {
+                quinn::SendDatagramError::ConnectionLost(e) => connection_error_kind(e),
+                _ => ErrorKind::Datagram,
+            },
+            Self::Timeout(_) => ErrorKind::Timeout,
+            Self::Tls(_) => ErrorKind::Tls,
+            Self::Ipv4Rejected(_) => ErrorKind::Ipv4Rejected,
+            Self::EndpointClosed => ErrorKind::LocallyClosed,
+            Self::ConnectionNotFound(_) | Self::Config(_) => ErrorKind::Config,
+            Self::Certificate(_) => ErrorKind::Certificate,
+            Self::Falcon(_) => ErrorKind::Falcon,
+            Self::Protocol(_) => ErrorKind::Protocol,
+            Self::Quorum { .. } => ErrorKind::Quorum,
+            Self::Unavailable(_) => ErrorKind::Unavailable,
+            Self::Io(_) => ErrorKind::Io,
+            Self::Other(_) => ErrorKind::Other,
+        }
+    }
+
+    /// Whether a fresh attempt (new stream or new connection) may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Connect(quinn::ConnectError::CidsExhausted) => true,
+            Self::Connection(ConnectionError::CidsExhausted) => true,
+            Self::Write(WriteError::ZeroRttRejected) | Self::Read(ReadError::ZeroRttRejected) => true,
+            Self::Io(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::WouldBlock
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::ConnectionReset
+            ),
+            _ => matches!(
+                self.kind(),
+                ErrorKind::Timeout | ErrorKind::PeerReset | ErrorKind::FlowControl
+            ),
+        }
+    }
+
+    /// Whether retrying the operation is pointless
+    pub fn is_fatal(&self) -> bool {
+        !self.is_retryable()
+    }
+
+    /// Underlying quinn connection error, if the failure was caused by the connection
+    pub fn connection_error(&self) -> Option<&ConnectionError> {
+        match self {
+            Self::Connection(e)
+            | Self::Write(WriteError::ConnectionLost(e))
+            | Self::Read(ReadError::ConnectionLost(e))
+            | Self::Datagram(quinn::SendDatagramError::ConnectionLost(e)) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Application close frame sent by the peer, if it closed the connection
+    pub fn application_close(&self) -> Option<&quinn::ApplicationClose> {
+        match self.connection_error()? {
+            ConnectionError::ApplicationClosed(close) => Some(close),
+            _ => None,
+        }
+    }
+
+    /// Application error code supplied by the peer, whether it closed the
+    /// connection, reset the stream we were reading or stopped the stream we
+    /// were writing
+    ///
+    /// Registered codes are listed in [`crate::protocol::error_codes`].
+    pub fn peer_error_code(&self) -> Option<VarInt> {
+        self.application_close()
+            .map(|close| close.error_code)
+            .or_else(|| self.stream_error_code())
+    }
+
+    /// Error code carried by a stream reset or stop from the peer
+    pub fn stream_error_code(&self) -> Option<VarInt> {
+        match self {
+            Self::Write(WriteError::Stopped(code)) | Self::Read(ReadError::Reset(code)) => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl StoqError {
+    /// Convert a failed `read_to_end(limit)`, reporting the limit that was exceeded
+    pub fn from_read_to_end(e: quinn::ReadToEndError, limit: usize) -> Self {
+        match e {
+            quinn::ReadToEndError::Read(e) => Self::Read(e),
+            quinn::ReadToEndError::TooLong => Self::StreamTooLong { limit },
+        }
+    }
+}
+
+impl From<quinn::ReadExactError> for StoqError {
+    fn from(e: quinn::ReadExactError) -> Self {
+        match e {
+            quinn::ReadExactError::ReadError(e) => Self::Read(e),
+            quinn::ReadExactError::FinishedEarly(read) => {
+                Self::Protocol(format!("stream finished early after {} bytes", read))
+            }
+        }
+    }
+}
+
+impl From<quinn::crypto::rustls::NoInitialCipherSuite> for StoqError {
+    fn from(e: quinn::crypto::rustls::NoInitialCipherSuite) -> Self {
+        Self::Tls(rustls::Error::General(e.to_string()))
+    }
+}
+
+/// Classify a quinn connection error
+fn connection_error_kind(e: &ConnectionError) -> ErrorKind {
+    match e {
+        ConnectionError::TimedOut => ErrorKind::Timeout,
+        ConnectionError::Reset => ErrorKind::PeerReset,
+        ConnectionError::ApplicationClosed(_) => ErrorKind::ApplicationClosed,
+        ConnectionError::LocallyClosed => ErrorKind::LocallyClosed,
+        ConnectionError::VersionMismatch => ErrorKind::Protocol,
+        ConnectionError::CidsExhausted => ErrorKind::Other,
+        ConnectionError::ConnectionClosed(close) => transport_code_kind(close.error_code),
+        ConnectionError::TransportError(e) => transport_code_kind(e.code),
+    }
+}
+
+/// Classify a QUIC transport error code
+fn transport_code_kind(code: TransportErrorCode) -> ErrorKind {
+    let raw = u64::from(code);
+    if (0x100..0x200).contains(&raw) {
+        ErrorKind::Tls
+    } else if code == TransportErrorCode::FLOW_CONTROL_ERROR || code == TransportErrorCode::STREAM_LIMIT_ERROR {
+        ErrorKind::FlowControl
+    } else if code == TransportErrorCode::PROTOCOL_VIOLATION || code == TransportErrorCode::FRAME_ENCODING_ERROR {
+        ErrorKind::Protocol
+    } else {
+        ErrorKind::TransportClosed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_timeout_is_retryable() {
+        let err = StoqError::Connection(ConnectionError::TimedOut);
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn test_application_close_preserved() {
+        let err = StoqError::Read(ReadError::ConnectionLost(ConnectionError::ApplicationClosed(
+            quinn::ApplicationClose {
+                error_code: VarInt::from_u32(0x42),
+                reason: Bytes::from_static(b"bye"),
+            },
+        )));
+        assert_eq!(err.kind(), ErrorKind::ApplicationClosed);
+        assert!(err.is_fatal());
+        let close = err.application_close().unwrap();
+        assert_eq!(close.error_code, VarInt::from_u32(0x42));
+        assert_eq!(&close.reason[..], b"bye");
+        assert_eq!(err.peer_error_code(), Some(VarInt::from_u32(0x42)));
+    }
+
+    #[test]
+    fn test_stream_reset_code() {
+        let err = StoqError::Read(ReadError::Reset(VarInt::from_u32(7)));
+        assert_eq!(err.kind(), ErrorKind::StreamReset);
+        assert_eq!(err.stream_error_code(), Some(VarInt::from_u32(7)));
+        assert_eq!(err.peer_error_code(), Some(VarInt::from_u32(7)));
+    }
+
+    #[test]
+    fn test_ipv4_rejection_is_fatal() {
+        let err = StoqError::Ipv4Rejected("127.0.0.1:9292".parse().unwrap());
+        assert_eq!(err.kind(), ErrorKind::Ipv4Rejected);
+        assert!(err.is_fatal());
+    }
+}
@@ -10,6 +10,7 @@
 //! - Seeding and mirroring protocol
 
 use bytes::{Bytes, BytesMut, BufMut};
+pub use crate::protocol::packet::PacketLimits;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 use sha2::{Sha256, Digest};
@@ -164,53 +165,22 @@ impl StoqPacket {
     }
 
     /// Serialize packet for transmission
+    ///
+    /// Uses the versioned, canonical encoding described in
+    /// [`crate::protocol::packet`], so equal packets always serialize to the
+    /// same bytes.
     pub fn serialize(&self) -> Result<Bytes> {
-        let mut buffer = BytesMut::new();
-
-        // Write packet header
-        buffer.put_u32(self.data.len() as u32);
-
-        // Write token if present
-        if let Some(token) = &self.token {
-            buffer.put_u8(1); // Token present flag
-            let token_bytes = bincode::serialize(token)?;
-            buffer.put_u32(token_bytes.len() as u32);
-            buffer.put_slice(&token_bytes);
-        } else {
-            buffer.put_u8(0); // No token flag
-        }
-
-        // Write hop count and hops
-        buffer.put_u32(self.hops.len() as u32);
-        for hop in &self.hops {
-            let hop_bytes = bincode::serialize(hop)?;
-            buffer.put_u32(hop_bytes.len() as u32);
-            buffer.put_slice(&hop_bytes);
-        }
-
-        // Write seed info if present
-        if let Some(seed_info) = &self.seed_info {
-            buffer.put_u8(1); // Seed info present flag
-            let seed_bytes = bincode::serialize(seed_info)?;
-            buffer.put_u32(seed_bytes.len() as u32);
-            buffer.put_slice(&seed_bytes);
-        } else {
-            buffer.put_u8(0); // No seed info flag
-        }
-
-        // Write metadata count and metadata
-        buffer.put_u32(self.metadata.len() as u32);
-        for (key, value) in &self.metadata {
-            buffer.put_u32(key.len() as u32);
-            buffer.put_slice(key.as_bytes());
-            buffer.put_u32(value.len() as u32);
-            buffer.put_slice(value.as_bytes());
-        }
+        Ok(crate::protocol::packet::encode(self, &PacketLimits::default())?)
+    }
 
-        // Write actual packet data
-        buffer.put_slice(&self.data);
+    /// Deserialize a packet produced by [`serialize`](Self::serialize)
+    pub fn deserialize(data: Bytes) -> Result<Self> {
+        Self::deserialize_with_limits(data, &PacketLimits::default())
+    }
 
-        Ok(buffer.freeze())
+    /// Deserialize a packet, enforcing custom size limits
+    pub fn deserialize_with_limits(data: Bytes, limits: &PacketLimits) -> Result<Self> {
+        Ok(crate::protocol::packet::decode(data, limits)?)
     }
 }
 
@@ -454,5 +424,10 @@ mod tests {
 
         let serialized = packet.serialize().unwrap();
         assert!(!serialized.is_empty());
+
+        let deserialized = StoqPacket::deserialize(serialized).unwrap();
+        assert_eq!(deserialized.data, packet.data);
+        assert_eq!(deserialized.token, packet.token);
+        assert_eq!(deserialized.metadata, packet.metadata);
     }
 }
\ No newline at end of file
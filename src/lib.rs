@@ -8,6 +8,7 @@
 
 #![warn(missing_docs)]
 
+pub mod error;
 pub mod transport;
 pub mod config;
 pub mod extensions;
@@ -19,11 +20,14 @@ pub mod protocol;
 use async_trait::async_trait;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use anyhow::Result;
+use error::Result;
 use serde::{Serialize, Deserialize};
 
 // Re-export pure transport types and protocol extensions
+pub use error::{StoqError, ErrorKind};
 pub use transport::{StoqTransport, TransportConfig, Connection, Endpoint, Stream, NetworkTier};
+#[cfg(feature = "trace-context")]
+pub use transport::{TraceContext, TraceParent};
 pub use transport::falcon::{
     FalconEngine, FalconTransport, FalconVariant, FalconPublicKey,
     FalconPrivateKey, FalconSignature
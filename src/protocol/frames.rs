@@ -10,6 +10,7 @@ use anyhow::{Result, anyhow};
 use tracing::trace;
 
 use crate::extensions::{PacketToken, PacketShard, HopInfo, SeedInfo, SeedNode, SeedPriority};
+use super::trace_context::TraceContext;
 
 /// STOQ frame type enum
 #[derive(Debug, Clone)]
@@ -32,6 +33,9 @@ pub enum StoqFrame {
     /// FALCON public key frame
     FalconKey(FalconKeyFrame),
 
+    /// W3C trace context of the sender's span
+    TraceContext(TraceContext),
+
     /// Unknown frame type (for forward compatibility)
     Unknown { frame_type: VarInt, data: Bytes },
 }
@@ -91,6 +95,7 @@ impl StoqFrame {
             Self::Seed(_) => super::frame_types::STOQ_SEED,
             Self::FalconSignature(_) => super::frame_types::FALCON_SIG,
             Self::FalconKey(_) => super::frame_types::FALCON_KEY,
+            Self::TraceContext(_) => super::frame_types::TRACE_CONTEXT,
             Self::Unknown { frame_type, .. } => *frame_type,
         }
     }
@@ -110,6 +115,7 @@ impl StoqFrame {
             Self::Seed(frame) => encode_seed_frame(&mut buf, frame)?,
             Self::FalconSignature(frame) => encode_falcon_sig_frame(&mut buf, frame)?,
             Self::FalconKey(frame) => encode_falcon_key_frame(&mut buf, frame)?,
+            Self::TraceContext(context) => encode_trace_context(&mut buf, context),
             Self::Unknown { data, .. } => buf.put_slice(data),
         }
 
@@ -145,6 +151,9 @@ impl StoqFrame {
             super::frame_types::FALCON_KEY => {
                 Ok(Self::FalconKey(decode_falcon_key_frame(&mut data)?))
             }
+            super::frame_types::TRACE_CONTEXT => {
+                Ok(Self::TraceContext(decode_trace_context(&mut data)?))
+            }
             _ => {
                 trace!("Unknown frame type: {:?}", frame_type);
                 Ok(Self::Unknown { frame_type, data })
@@ -194,7 +203,18 @@ fn encode_shard_frame(buf: &mut BytesMut, frame: &ShardFrame) -> Result<()> {
     Ok(())
 }
 
-fn encode_hop_frame(buf: &mut BytesMut, frame: &HopFrame) -> Result<()> {
+pub(crate) fn encode_trace_context(buf: &mut BytesMut, context: &TraceContext) {
+    buf.put_u8(0); // traceparent version
+    buf.put_slice(&context.trace_id);
+    buf.put_slice(&context.parent_id);
+    buf.put_u8(context.flags);
+
+    let tracestate = context.tracestate.as_deref().unwrap_or("");
+    encode_varint(buf, VarInt::from_u32(tracestate.len() as u32));
+    buf.put_slice(tracestate.as_bytes());
+}
+
+pub(crate) fn encode_hop_frame(buf: &mut BytesMut, frame: &HopFrame) -> Result<()> {
     // Encode hop info
     buf.put_slice(&frame.hop.address.octets());
     buf.put_u16(frame.hop.port);
@@ -216,7 +236,7 @@ fn encode_hop_frame(buf: &mut BytesMut, frame: &HopFrame) -> Result<()> {
     Ok(())
 }
 
-fn encode_seed_frame(buf: &mut BytesMut, frame: &SeedFrame) -> Result<()> {
+pub(crate) fn encode_seed_frame(buf: &mut BytesMut, frame: &SeedFrame) -> Result<()> {
     // Encode packet ID
     buf.put_slice(&frame.packet_id);
 
@@ -340,7 +360,40 @@ fn decode_shard_frame(data: &mut Bytes) -> Result<ShardFrame> {
     })
 }
 
-fn decode_hop_frame(data: &mut Bytes) -> Result<HopFrame> {
+pub(crate) fn decode_trace_context(data: &mut Bytes) -> Result<TraceContext> {
+    if data.len() < 1 + 16 + 8 + 1 {
+        return Err(anyhow!("Trace context frame too short"));
+    }
+
+    let version = data.get_u8();
+    if version != 0 {
+        return Err(anyhow!("Unsupported traceparent version: {}", version));
+    }
+
+    let mut trace_id = [0u8; 16];
+    data.copy_to_slice(&mut trace_id);
+    let mut parent_id = [0u8; 8];
+    data.copy_to_slice(&mut parent_id);
+    let flags = data.get_u8();
+
+    let state_len = decode_varint(data)
+        .ok_or_else(|| anyhow!("Failed to decode tracestate length"))?
+        .into_inner() as usize;
+    if data.len() < state_len {
+        return Err(anyhow!("Tracestate truncated"));
+    }
+    let tracestate = String::from_utf8(data.split_to(state_len).to_vec())
+        .map_err(|_| anyhow!("Tracestate is not valid UTF-8"))?;
+
+    Ok(TraceContext {
+        trace_id,
+        parent_id,
+        flags,
+        tracestate: (!tracestate.is_empty()).then_some(tracestate),
+    })
+}
+
+pub(crate) fn decode_hop_frame(data: &mut Bytes) -> Result<HopFrame> {
     if data.len() < 16 + 2 + 8 + 4 + 2 { // Address + port + timestamp + metadata len + hop counts
         return Err(anyhow!("Hop frame too short"));
     }
@@ -393,7 +446,7 @@ fn decode_hop_frame(data: &mut Bytes) -> Result<HopFrame> {
     })
 }
 
-fn decode_seed_frame(data: &mut Bytes) -> Result<SeedFrame> {
+pub(crate) fn decode_seed_frame(data: &mut Bytes) -> Result<SeedFrame> {
     if data.len() < 32 + 4 { // Packet ID + nodes length
         return Err(anyhow!("Seed frame too short"));
     }
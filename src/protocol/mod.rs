@@ -6,15 +6,20 @@
 use bytes::{Bytes, Buf};
 use quinn::{VarInt, TransportConfig};
 use std::sync::Arc;
-use anyhow::{Result, anyhow};
+use crate::error::{Result, StoqError};
 use tracing::{debug, trace};
 
 pub mod frames;
 pub mod parameters;
 pub mod handshake;
+pub mod packet;
+pub mod relay;
+pub mod signing;
+pub mod trace_context;
 
-use crate::extensions::{PacketToken, PacketShard, StoqProtocolExtension};
+use crate::extensions::{PacketToken, PacketShard, SeedInfo, StoqPacket, StoqProtocolExtension};
 use crate::transport::falcon::FalconSignature;
+use signing::FrameSigningPolicy;
 
 /// STOQ protocol version for QUIC ALPN
 pub const STOQ_ALPN: &[u8] = b"stoq/1.0";
@@ -41,6 +46,9 @@ pub mod frame_types {
 
     /// FALCON public key exchange frame type
     pub const FALCON_KEY: VarInt = VarInt::from_u32(0xfe000006);
+
+    /// W3C trace context frame for cross-hop span propagation
+    pub const TRACE_CONTEXT: VarInt = VarInt::from_u32(0xfe000007);
 }
 
 /// Transport parameter IDs for STOQ extensions
@@ -61,6 +69,63 @@ pub mod transport_params {
     pub const TOKEN_ALGORITHM: u64 = 0xfe04;
 }
 
+/// Application error codes carried in CONNECTION_CLOSE, RESET_STREAM and
+/// STOP_SENDING frames
+pub mod error_codes {
+    use quinn::VarInt;
+
+    /// Graceful close, no error
+    pub const NO_ERROR: VarInt = VarInt::from_u32(0x00);
+
+    /// Node is shutting down; reconnect elsewhere or later
+    pub const SHUTTING_DOWN: VarInt = VarInt::from_u32(0x01);
+
+    /// Peer is not authorized for the requested operation
+    pub const UNAUTHORIZED: VarInt = VarInt::from_u32(0x02);
+
+    /// Peer sent malformed or unexpected STOQ protocol data
+    pub const PROTOCOL_VIOLATION: VarInt = VarInt::from_u32(0x03);
+
+    /// Local failure unrelated to the peer's behaviour
+    pub const INTERNAL_ERROR: VarInt = VarInt::from_u32(0x04);
+
+    /// Operation was cancelled by the application
+    pub const CANCELLED: VarInt = VarInt::from_u32(0x05);
+
+    /// Node is overloaded and refuses new work
+    pub const OVERLOADED: VarInt = VarInt::from_u32(0x06);
+
+    /// Connection closed after being idle
+    pub const IDLE: VarInt = VarInt::from_u32(0x07);
+
+    /// Relayed stream would traverse more relays than `max_hops` allows
+    pub const HOP_LIMIT_EXCEEDED: VarInt = VarInt::from_u32(0x08);
+
+    /// Relayed stream's route revisits a hop it already traversed
+    pub const ROUTING_LOOP: VarInt = VarInt::from_u32(0x09);
+
+    /// Relay could not reach the next hop of a relayed stream
+    pub const NEXT_HOP_UNREACHABLE: VarInt = VarInt::from_u32(0x0a);
+
+    /// Symbolic name of a registered code, for logging
+    pub fn name(code: VarInt) -> Option<&'static str> {
+        match code.into_inner() {
+            0x00 => Some("NO_ERROR"),
+            0x01 => Some("SHUTTING_DOWN"),
+            0x02 => Some("UNAUTHORIZED"),
+            0x03 => Some("PROTOCOL_VIOLATION"),
+            0x04 => Some("INTERNAL_ERROR"),
+            0x05 => Some("CANCELLED"),
+            0x06 => Some("OVERLOADED"),
+            0x07 => Some("IDLE"),
+            0x08 => Some("HOP_LIMIT_EXCEEDED"),
+            0x09 => Some("ROUTING_LOOP"),
+            0x0a => Some("NEXT_HOP_UNREACHABLE"),
+            _ => None,
+        }
+    }
+}
+
 /// STOQ protocol handler for QUIC integration
 pub struct StoqProtocolHandler {
     /// Protocol extensions implementation
@@ -74,6 +139,9 @@ pub struct StoqProtocolHandler {
 
     /// Whether extensions are enabled
     extensions_enabled: bool,
+
+    /// Which frames must be sent and received in signed groups
+    frame_signing: FrameSigningPolicy,
 }
 
 impl StoqProtocolHandler {
@@ -88,9 +156,21 @@ impl StoqProtocolHandler {
             falcon_transport,
             max_shard_size,
             extensions_enabled: true,
+            frame_signing: FrameSigningPolicy::Off,
         }
     }
 
+    /// Require signed frame groups according to `policy`
+    pub fn with_frame_signing(mut self, policy: FrameSigningPolicy) -> Self {
+        self.frame_signing = policy;
+        self
+    }
+
+    /// Which frames must be signed
+    pub fn frame_signing(&self) -> FrameSigningPolicy {
+        self.frame_signing
+    }
+
     /// Encode a STOQ token frame
     pub fn encode_token_frame(&self, token: &PacketToken) -> Result<Bytes> {
         use crate::protocol::frames::{StoqFrame, TokenFrame};
@@ -100,13 +180,13 @@ impl StoqProtocolHandler {
             stream_id: None,
         });
 
-        frame.encode()
+        frame.encode().map_err(|e| StoqError::Protocol(e.to_string()))
     }
 
     /// Decode a STOQ token frame
     pub fn decode_token_frame(&self, mut data: Bytes) -> Result<PacketToken> {
         if data.len() < 48 { // 32 (hash) + 8 (seq) + 8 (timestamp)
-            return Err(anyhow!("Token frame too short: {} bytes", data.len()));
+            return Err(StoqError::Protocol(format!("Token frame too short: {} bytes", data.len())));
         }
 
         let mut hash = [0u8; 32];
@@ -130,13 +210,13 @@ impl StoqProtocolHandler {
             stream_id: None,
         });
 
-        frame.encode()
+        frame.encode().map_err(|e| StoqError::Protocol(e.to_string()))
     }
 
     /// Decode a STOQ shard metadata frame
     pub fn decode_shard_frame(&self, mut data: Bytes) -> Result<PacketShard> {
         if data.len() < 48 { // Minimum metadata size
-            return Err(anyhow!("Shard frame too short: {} bytes", data.len()));
+            return Err(StoqError::Protocol(format!("Shard frame too short: {} bytes", data.len())));
         }
 
         let shard_id = data.get_u32();
@@ -148,7 +228,7 @@ impl StoqProtocolHandler {
 
         let data_len = data.get_u32() as usize;
         if data.len() < data_len {
-            return Err(anyhow!("Shard data truncated"));
+            return Err(StoqError::Protocol("Shard data truncated".to_string()));
         }
 
         let shard_data = data.split_to(data_len);
@@ -172,13 +252,14 @@ impl StoqProtocolHandler {
 
             let frame = StoqFrame::FalconSignature(FalconSigFrame {
                 signature_data: exported,
-                key_id: "local".to_string(), // TODO: Use actual key ID
-                signed_frames: vec![frame_types::STOQ_TOKEN], // TODO: Track actual signed frames
+                key_id: falcon_guard.get_local_public_key().map(|key| key.id()).unwrap_or_default(),
+                // Signs opaque data rather than frames; see `encode_frame_group`
+                signed_frames: Vec::new(),
             });
 
-            frame.encode()
+            frame.encode().map_err(|e| StoqError::Protocol(e.to_string()))
         } else {
-            Err(anyhow!("FALCON transport not enabled"))
+            Err(StoqError::Unavailable("FALCON transport"))
         }
     }
 
@@ -186,27 +267,106 @@ impl StoqProtocolHandler {
     pub fn decode_falcon_frame(&self, mut data: Bytes) -> Result<FalconSignature> {
         if let Some(falcon) = &self.falcon_transport {
             if data.len() < 4 {
-                return Err(anyhow!("FALCON frame too short"));
+                return Err(StoqError::Protocol("FALCON frame too short".to_string()));
             }
 
             let sig_len = data.get_u32() as usize;
             if data.len() < sig_len {
-                return Err(anyhow!("FALCON signature truncated"));
+                return Err(StoqError::Protocol("FALCON signature truncated".to_string()));
             }
 
             let sig_data = data.split_to(sig_len);
             let falcon_guard = falcon.read();
             falcon_guard.import_signature(&sig_data)
+                .map_err(|e| StoqError::Falcon(e.to_string()))
         } else {
-            Err(anyhow!("FALCON transport not enabled"))
+            Err(StoqError::Unavailable("FALCON transport"))
+        }
+    }
+
+    /// Build a frame group, signed if the policy covers any of `frames`
+    ///
+    /// `frames` are encoded STOQ frames and `channel_binding` is exported from
+    /// the connection the group will travel on. Signing uses the local FALCON
+    /// key; see [`signing`] for the group format.
+    pub fn encode_frame_group(&self, frames: &[Bytes], channel_binding: &[u8]) -> Result<Bytes> {
+        if !self.needs_signature(frames) {
+            return signing::encode_group(frames, None, channel_binding);
+        }
+        let falcon = self.falcon_transport.as_ref().ok_or(StoqError::Unavailable("FALCON transport"))?;
+        signing::encode_group(frames, Some(&falcon.read()), channel_binding)
+    }
+
+    /// Whether the policy covers any of the encoded `frames`
+    fn needs_signature(&self, frames: &[Bytes]) -> bool {
+        frames.iter()
+            .filter_map(|frame| frames::decode_varint(&mut frame.clone()))
+            .any(|frame_type| self.frame_signing.covers(frame_type))
+    }
+
+    /// Verify a frame group and return its frames still encoded
+    ///
+    /// A signed group must be signed by `peer_key_id`, the key the peer proved
+    /// on the connection `channel_binding` was exported from. For groups
+    /// carrying frames [`frames::StoqFrame`] does not model, such as relay
+    /// headers.
+    pub fn verify_frame_group(&self, data: Bytes, channel_binding: &[u8], peer_key_id: Option<&str>) -> Result<Vec<Bytes>> {
+        let falcon = self.falcon_transport.as_ref().map(|falcon| falcon.read());
+        signing::verify_group(data, self.frame_signing, falcon.as_deref(), peer_key_id, channel_binding)
+    }
+
+    /// Verify a frame group as [`verify_frame_group`](Self::verify_frame_group)
+    /// does and return its frames
+    pub fn decode_frame_group(&self, data: Bytes, channel_binding: &[u8], peer_key_id: Option<&str>) -> Result<Vec<frames::StoqFrame>> {
+        let falcon = self.falcon_transport.as_ref().map(|falcon| falcon.read());
+        signing::decode_group(data, self.frame_signing, falcon.as_deref(), peer_key_id, channel_binding)
+    }
+
+    /// Verify and process every frame in a frame group
+    pub fn process_frame_group(&self, data: Bytes, channel_binding: &[u8], peer_key_id: Option<&str>) -> Result<()> {
+        for frame in self.decode_frame_group(data, channel_binding, peer_key_id)? {
+            self.handle_frame(frame)?;
         }
+        Ok(())
+    }
+
+    /// Proof of the local FALCON key for the peer on the other end of a
+    /// connection, or `None` without FALCON
+    ///
+    /// `side` is the local end; see [`signing`] for the proof.
+    pub fn encode_identity(&self, channel_binding: &[u8], side: quinn::Side) -> Result<Option<Bytes>> {
+        self.falcon_transport.as_ref()
+            .map(|falcon| signing::encode_identity(&falcon.read(), channel_binding, side))
+            .transpose()
+    }
+
+    /// Check a peer's identity proof and return the trusted key ID it proved
+    ///
+    /// `side` is the peer's end of the connection.
+    pub fn verify_identity(&self, data: Bytes, channel_binding: &[u8], side: quinn::Side) -> Result<String> {
+        let falcon = self.falcon_transport.as_ref().ok_or(StoqError::Unavailable("FALCON transport"))?;
+        signing::verify_identity(data, &falcon.read(), channel_binding, side)
     }
 
     /// Process incoming custom frame
+    ///
+    /// Frames the signing policy covers are refused here; they must arrive
+    /// through [`process_frame_group`](Self::process_frame_group).
     pub fn process_frame(&self, data: Bytes) -> Result<()> {
         use crate::protocol::frames::StoqFrame;
 
-        let frame = StoqFrame::decode(data)?;
+        let frame = StoqFrame::decode(data).map_err(|e| StoqError::Protocol(e.to_string()))?;
+        if self.frame_signing.covers(frame.frame_type()) {
+            return Err(StoqError::Protocol(format!(
+                "frame type {:#x} must arrive in a signed frame group",
+                frame.frame_type().into_inner()
+            )));
+        }
+        self.handle_frame(frame)
+    }
+
+    fn handle_frame(&self, frame: frames::StoqFrame) -> Result<()> {
+        use crate::protocol::frames::StoqFrame;
 
         match frame {
             StoqFrame::Token(token_frame) => {
@@ -221,10 +381,8 @@ impl StoqProtocolHandler {
                 // TODO: Store shard for reassembly
                 Ok(())
             }
-            StoqFrame::FalconSignature(_sig_frame) => {
-                debug!("Received FALCON signature");
-                // TODO: Verify signature
-                Ok(())
+            StoqFrame::FalconSignature(_) => {
+                Err(StoqError::Protocol("FALCON signature outside a frame group".to_string()))
             }
             _ => {
                 trace!("Received frame type: {:?}", frame.frame_type());
@@ -233,8 +391,14 @@ impl StoqProtocolHandler {
         }
     }
 
-    /// Apply STOQ extensions to outgoing data
-    pub fn apply_extensions(&self, data: &[u8]) -> Result<Vec<Bytes>> {
+    /// Seeding instructions for a packet, as chosen by the extensions
+    pub fn seed_info(&self, packet: &StoqPacket) -> Option<SeedInfo> {
+        self.extensions.get_seed_info(packet)
+    }
+
+    /// Apply STOQ extensions to outgoing data for the connection
+    /// `channel_binding` was exported from
+    pub fn apply_extensions(&self, data: &[u8], channel_binding: &[u8]) -> Result<Vec<Bytes>> {
         let mut frames = Vec::new();
 
         if self.extensions_enabled {
@@ -244,13 +408,19 @@ impl StoqProtocolHandler {
 
             // Add sharding if data is large
             if data.len() > self.max_shard_size {
-                let shards = self.extensions.shard_packet(data, self.max_shard_size)?;
+                let shards = self.extensions.shard_packet(data, self.max_shard_size)
+                    .map_err(|e| StoqError::Protocol(e.to_string()))?;
                 for shard in shards {
                     frames.push(self.encode_shard_frame(&shard)?);
                 }
             }
         }
 
+        // Frames the policy covers travel together in one signed group
+        if self.needs_signature(&frames) {
+            return Ok(vec![self.encode_frame_group(&frames, channel_binding)?]);
+        }
+
         Ok(frames)
     }
 
@@ -258,7 +428,8 @@ impl StoqProtocolHandler {
     pub fn falcon_sign(&self, data: &[u8]) -> Result<Option<Bytes>> {
         if let Some(falcon) = &self.falcon_transport {
             let falcon_guard = falcon.read();
-            let signature = falcon_guard.sign_handshake_data(data)?;
+            let signature = falcon_guard.sign_handshake_data(data)
+                .map_err(|e| StoqError::Falcon(e.to_string()))?;
             Ok(Some(self.encode_falcon_frame(&signature)?))
         } else {
             Ok(None)
@@ -307,6 +478,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_frame_signing_policy() {
+        use crate::transport::falcon::{FalconTransport, FalconVariant};
+
+        let mut falcon = FalconTransport::new(FalconVariant::Falcon512);
+        falcon.generate_local_keypair().unwrap();
+        let key = falcon.get_local_public_key().unwrap().clone();
+        let key_id = key.id();
+        // Trust our own key so the handler can verify what it signs
+        falcon.add_trusted_key(key.id(), key);
+        let falcon = Some(Arc::new(parking_lot::RwLock::new(falcon)));
+        let extensions = Arc::new(DefaultStoqExtensions::new());
+
+        let handler = StoqProtocolHandler::new(extensions.clone(), falcon.clone(), 1400)
+            .with_frame_signing(FrameSigningPolicy::All);
+        let binding = [7; 32];
+        let groups = handler.apply_extensions(b"signed payload", &binding).unwrap();
+        assert_eq!(groups.len(), 1);
+        let frames = handler.decode_frame_group(groups[0].clone(), &binding, Some(&key_id)).unwrap();
+        assert!(matches!(frames[0], frames::StoqFrame::Token(_)));
+        handler.process_frame_group(groups[0].clone(), &binding, Some(&key_id)).unwrap();
+        assert!(handler.process_frame_group(groups[0].clone(), &[8; 32], Some(&key_id)).is_err());
+
+        let proof = handler.encode_identity(&binding, quinn::Side::Client).unwrap().unwrap();
+        assert_eq!(handler.verify_identity(proof, &binding, quinn::Side::Client).unwrap(), key_id);
+
+        let token = handler.encode_token_frame(&extensions.tokenize_packet(b"unsigned")).unwrap();
+        assert!(handler.process_frame(token.clone()).is_err());
+        let routing = StoqProtocolHandler::new(extensions, falcon, 1400)
+            .with_frame_signing(FrameSigningPolicy::Routing);
+        routing.process_frame(token.clone()).unwrap();
+        let group = routing.encode_frame_group(&[token], &binding).unwrap();
+        assert_eq!(routing.decode_frame_group(group, &binding, None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_error_code_names() {
+        assert_eq!(error_codes::name(error_codes::SHUTTING_DOWN), Some("SHUTTING_DOWN"));
+        assert_eq!(error_codes::name(error_codes::UNAUTHORIZED), Some("UNAUTHORIZED"));
+        assert_eq!(error_codes::name(VarInt::from_u32(0xffff)), None);
+    }
+
     #[test]
     fn test_shard_frame_encoding() {
         let extensions = Arc::new(DefaultStoqExtensions::new());
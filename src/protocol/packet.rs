@@ -0,0 +1,503 @@
+//! StoqPacket wire format
+//!
+//! An encoded [`StoqPacket`] is a version byte followed by fields, each a QUIC
+//! varint tag, a varint length and that many bytes of value:
+//!
+//! | Tag | Field      | Value                                                        |
+//! |-----|------------|--------------------------------------------------------------|
+//! | 1   | `token`    | hash (32 bytes), sequence (u64), timestamp (u64)             |
+//! | 2   | `hops`     | varint count, then per hop: address (16), port (u16), timestamp (u64), map |
+//! | 3   | `seed_info`| varint count, then per node: address (16), port (u16), reliability (u8); replication factor (u32), priority (u8) |
+//! | 4   | `metadata` | map                                                          |
+//! | 5   | `data`     | the payload                                                  |
+//!
+//! A map is a varint count followed by entries of varint-length UTF-8 key and
+//! value, keys in ascending byte order. Integers are big-endian.
+//!
+//! The encoding is canonical so that signatures over it are stable: fields
+//! appear in ascending tag order and at most once, empty fields are omitted,
+//! map keys are sorted and unique, and varints use their shortest form. The
+//! decoder rejects anything else, except that fields with unknown tags are
+//! skipped so later versions can add them. Every count and length is checked
+//! against [`PacketLimits`] before anything is allocated.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use quinn::VarInt;
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+
+use super::frames;
+use crate::error::{Result, StoqError};
+use crate::extensions::{HopInfo, PacketToken, SeedInfo, SeedNode, SeedPriority, StoqPacket};
+
+/// Current packet format version
+pub const PACKET_VERSION: u8 = 1;
+
+/// Field tags
+mod tags {
+    pub const TOKEN: u64 = 1;
+    pub const HOPS: u64 = 2;
+    pub const SEED_INFO: u64 = 3;
+    pub const METADATA: u64 = 4;
+    pub const DATA: u64 = 5;
+}
+
+/// Size limits enforced when encoding and decoding packets
+#[derive(Debug, Clone)]
+pub struct PacketLimits {
+    /// Largest payload
+    pub max_data_len: usize,
+    /// Most hops in the hop chain
+    pub max_hops: usize,
+    /// Most seed nodes
+    pub max_seed_nodes: usize,
+    /// Most entries in one metadata map
+    pub max_metadata_entries: usize,
+    /// Longest metadata key or value
+    pub max_string_len: usize,
+}
+
+impl Default for PacketLimits {
+    fn default() -> Self {
+        Self {
+            max_data_len: 16 * 1024 * 1024,
+            max_hops: 64,
+            max_seed_nodes: 256,
+            max_metadata_entries: 64,
+            max_string_len: 1024,
+        }
+    }
+}
+
+fn malformed(reason: impl std::fmt::Display) -> StoqError {
+    StoqError::Protocol(format!("malformed packet: {}", reason))
+}
+
+fn over_limit(what: &str, len: usize, limit: usize) -> StoqError {
+    StoqError::Protocol(format!("packet {} of {} exceeds the limit of {}", what, len, limit))
+}
+
+fn check(what: &str, len: usize, limit: usize) -> Result<()> {
+    if len > limit {
+        return Err(over_limit(what, len, limit));
+    }
+    Ok(())
+}
+
+/// Encode `packet` in the current format
+pub fn encode(packet: &StoqPacket, limits: &PacketLimits) -> Result<Bytes> {
+    check("data", packet.data.len(), limits.max_data_len)?;
+    check("hop count", packet.hops.len(), limits.max_hops)?;
+
+    let mut buf = BytesMut::with_capacity(packet.data.len() + 64);
+    buf.put_u8(PACKET_VERSION);
+    let mut value = BytesMut::new();
+
+    if let Some(token) = &packet.token {
+        value.put_slice(&token.hash);
+        value.put_u64(token.sequence);
+        value.put_u64(token.timestamp);
+        put_field(&mut buf, tags::TOKEN, &value);
+        value.clear();
+    }
+
+    if !packet.hops.is_empty() {
+        put_len(&mut value, packet.hops.len());
+        for hop in &packet.hops {
+            value.put_slice(&hop.address.octets());
+            value.put_u16(hop.port);
+            value.put_u64(hop.timestamp);
+            put_map(&mut value, &hop.metadata, limits)?;
+        }
+        put_field(&mut buf, tags::HOPS, &value);
+        value.clear();
+    }
+
+    if let Some(seed_info) = &packet.seed_info {
+        check("seed node count", seed_info.seed_nodes.len(), limits.max_seed_nodes)?;
+        put_len(&mut value, seed_info.seed_nodes.len());
+        for node in &seed_info.seed_nodes {
+            value.put_slice(&node.address.octets());
+            value.put_u16(node.port);
+            value.put_u8(node.reliability);
+        }
+        value.put_u32(seed_info.replication_factor);
+        value.put_u8(match seed_info.priority {
+            SeedPriority::Low => 0,
+            SeedPriority::Normal => 1,
+            SeedPriority::High => 2,
+            SeedPriority::Critical => 3,
+        });
+        put_field(&mut buf, tags::SEED_INFO, &value);
+        value.clear();
+    }
+
+    if !packet.metadata.is_empty() {
+        put_map(&mut value, &packet.metadata, limits)?;
+        put_field(&mut buf, tags::METADATA, &value);
+    }
+
+    if !packet.data.is_empty() {
+        put_field(&mut buf, tags::DATA, &packet.data);
+    }
+
+    Ok(buf.freeze())
+}
+
+fn put_len(buf: &mut BytesMut, len: usize) {
+    // Limits keep every length far below the 2^62 varint ceiling
+    frames::encode_varint(buf, VarInt::from_u64(len as u64).expect("length fits in a varint"));
+}
+
+fn put_field(buf: &mut BytesMut, tag: u64, value: &[u8]) {
+    put_len(buf, tag as usize);
+    put_len(buf, value.len());
+    buf.put_slice(value);
+}
+
+fn put_map(buf: &mut BytesMut, map: &HashMap<String, String>, limits: &PacketLimits) -> Result<()> {
+    check("metadata entry count", map.len(), limits.max_metadata_entries)?;
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+    put_len(buf, entries.len());
+    for (key, value) in entries {
+        check("metadata string length", key.len().max(value.len()), limits.max_string_len)?;
+        put_len(buf, key.len());
+        buf.put_slice(key.as_bytes());
+        put_len(buf, value.len());
+        buf.put_slice(value.as_bytes());
+    }
+    Ok(())
+}
+
+/// Decode a packet, enforcing `limits`
+pub fn decode(mut data: Bytes, limits: &PacketLimits) -> Result<StoqPacket> {
+    if !data.has_remaining() {
+        return Err(malformed("empty"));
+    }
+    let version = data.get_u8();
+    if version != PACKET_VERSION {
+        return Err(StoqError::Protocol(format!("unsupported packet version {}", version)));
+    }
+
+    let mut packet = StoqPacket::new(Bytes::new());
+    let mut last_tag = 0;
+    while data.has_remaining() {
+        let tag = get_varint(&mut data)?;
+        if tag <= last_tag {
+            return Err(malformed(format!("field {} out of order", tag)));
+        }
+        last_tag = tag;
+        let len = get_len(&mut data)?;
+        let mut value = data.split_to(len);
+
+        match tag {
+            tags::TOKEN => packet.token = Some(get_token(&mut value)?),
+            tags::HOPS => packet.hops = get_hops(&mut value, limits)?,
+            tags::SEED_INFO => packet.seed_info = Some(get_seed_info(&mut value, limits)?),
+            tags::METADATA => {
+                packet.metadata = get_map(&mut value, limits)?;
+                if packet.metadata.is_empty() {
+                    return Err(malformed("empty metadata field"));
+                }
+            }
+            tags::DATA => {
+                check("data", len, limits.max_data_len)?;
+                if len == 0 {
+                    return Err(malformed("empty data field"));
+                }
+                packet.data = value.split_to(len);
+            }
+            _ => continue,
+        }
+        if value.has_remaining() {
+            return Err(malformed(format!("{} trailing bytes in field {}", value.remaining(), tag)));
+        }
+    }
+    Ok(packet)
+}
+
+/// Read a minimally encoded varint
+fn get_varint(data: &mut Bytes) -> Result<u64> {
+    let before = data.remaining();
+    let value = frames::decode_varint(data).ok_or_else(|| malformed("truncated varint"))?;
+    let minimal = match value.into_inner() {
+        0..0x40 => 1,
+        0x40..0x4000 => 2,
+        0x4000..0x4000_0000 => 4,
+        _ => 8,
+    };
+    if before - data.remaining() != minimal {
+        return Err(malformed("non-minimal varint"));
+    }
+    Ok(value.into_inner())
+}
+
+/// Read a length and check that many bytes follow
+fn get_len(data: &mut Bytes) -> Result<usize> {
+    let len = get_varint(data)?;
+    if len > data.remaining() as u64 {
+        return Err(malformed(format!("length {} overruns the {} bytes left", len, data.remaining())));
+    }
+    Ok(len as usize)
+}
+
+/// Read a count of items at least `item_len` bytes long, no more than `limit`
+fn get_count(data: &mut Bytes, what: &str, item_len: usize, limit: usize) -> Result<usize> {
+    let count = get_varint(data)?;
+    check(what, count.min(usize::MAX as u64) as usize, limit)?;
+    let count = count as usize;
+    if count.saturating_mul(item_len) > data.remaining() {
+        return Err(malformed(format!("{} of {} overruns the field", what, count)));
+    }
+    Ok(count)
+}
+
+fn need(data: &Bytes, len: usize, what: &str) -> Result<()> {
+    if data.remaining() < len {
+        return Err(malformed(format!("truncated {}", what)));
+    }
+    Ok(())
+}
+
+fn get_address(data: &mut Bytes) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    data.copy_to_slice(&mut octets);
+    Ipv6Addr::from(octets)
+}
+
+fn get_token(data: &mut Bytes) -> Result<PacketToken> {
+    need(data, 32 + 8 + 8, "token")?;
+    let mut hash = [0u8; 32];
+    data.copy_to_slice(&mut hash);
+    Ok(PacketToken { hash, sequence: data.get_u64(), timestamp: data.get_u64() })
+}
+
+fn get_hops(data: &mut Bytes, limits: &PacketLimits) -> Result<Vec<HopInfo>> {
+    const HOP_LEN: usize = 16 + 2 + 8 + 1;
+    let count = get_count(data, "hop count", HOP_LEN, limits.max_hops)?;
+    if count == 0 {
+        return Err(malformed("empty hops field"));
+    }
+    let mut hops = Vec::with_capacity(count);
+    for _ in 0..count {
+        need(data, HOP_LEN, "hop")?;
+        hops.push(HopInfo {
+            address: get_address(data),
+            port: data.get_u16(),
+            timestamp: data.get_u64(),
+            metadata: get_map(data, limits)?,
+        });
+    }
+    Ok(hops)
+}
+
+fn get_seed_info(data: &mut Bytes, limits: &PacketLimits) -> Result<SeedInfo> {
+    const NODE_LEN: usize = 16 + 2 + 1;
+    let count = get_count(data, "seed node count", NODE_LEN, limits.max_seed_nodes)?;
+    let mut seed_nodes = Vec::with_capacity(count);
+    for _ in 0..count {
+        seed_nodes.push(SeedNode {
+            address: get_address(data),
+            port: data.get_u16(),
+            reliability: data.get_u8(),
+        });
+    }
+    need(data, 4 + 1, "seed info")?;
+    let replication_factor = data.get_u32();
+    let priority = match data.get_u8() {
+        0 => SeedPriority::Low,
+        1 => SeedPriority::Normal,
+        2 => SeedPriority::High,
+        3 => SeedPriority::Critical,
+        other => return Err(malformed(format!("unknown seed priority {}", other))),
+    };
+    Ok(SeedInfo { seed_nodes, replication_factor, priority })
+}
+
+fn get_string(data: &mut Bytes, limits: &PacketLimits) -> Result<String> {
+    let len = get_len(data)?;
+    check("metadata string length", len, limits.max_string_len)?;
+    String::from_utf8(data.split_to(len).to_vec()).map_err(|_| malformed("metadata is not UTF-8"))
+}
+
+fn get_map(data: &mut Bytes, limits: &PacketLimits) -> Result<HashMap<String, String>> {
+    let count = get_count(data, "metadata entry count", 2, limits.max_metadata_entries)?;
+    let mut map = HashMap::with_capacity(count);
+    let mut last_key: Option<String> = None;
+    for _ in 0..count {
+        let key = get_string(data, limits)?;
+        if last_key.as_ref().is_some_and(|last| *last >= key) {
+            return Err(malformed("metadata keys out of order"));
+        }
+        let value = get_string(data, limits)?;
+        last_key = Some(key.clone());
+        map.insert(key, value);
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn sample() -> StoqPacket {
+        let mut packet = StoqPacket::new(Bytes::from_static(b"config blob"));
+        packet.token = Some(PacketToken { hash: [7; 32], sequence: 42, timestamp: 1_700_000_000 });
+        packet.hops.push(HopInfo {
+            address: "fd00::1".parse().unwrap(),
+            port: 9292,
+            timestamp: 1_700_000_000_123,
+            metadata: HashMap::from([("role".to_string(), "bastion".to_string())]),
+        });
+        packet.seed_info = Some(SeedInfo {
+            seed_nodes: vec![SeedNode { address: "fd00::2".parse().unwrap(), port: 9293, reliability: 80 }],
+            replication_factor: 1,
+            priority: SeedPriority::High,
+        });
+        for key in ["zone", "app", "build"] {
+            packet.metadata.insert(key.to_string(), format!("{}-value", key));
+        }
+        packet
+    }
+
+    fn limits() -> PacketLimits {
+        PacketLimits::default()
+    }
+
+    #[test]
+    fn test_roundtrip_is_canonical() {
+        let packet = sample();
+        let encoded = encode(&packet, &limits()).unwrap();
+        assert_eq!(encoded[0], PACKET_VERSION);
+
+        let decoded = decode(encoded.clone(), &limits()).unwrap();
+        assert_eq!(decoded.data, packet.data);
+        assert_eq!(decoded.token, packet.token);
+        assert_eq!(decoded.hops[0].metadata["role"], "bastion");
+        assert_eq!(decoded.seed_info.as_ref().unwrap().seed_nodes[0].reliability, 80);
+        assert_eq!(decoded.metadata, packet.metadata);
+
+        // The same contents in a differently built map encode identically
+        let mut rebuilt = sample();
+        rebuilt.metadata = HashMap::with_capacity(64);
+        for key in ["build", "app", "zone"] {
+            rebuilt.metadata.insert(key.to_string(), format!("{}-value", key));
+        }
+        assert_eq!(encode(&rebuilt, &limits()).unwrap(), encoded);
+        assert_eq!(encode(&decoded, &limits()).unwrap(), encoded);
+
+        let empty = encode(&StoqPacket::new(Bytes::new()), &limits()).unwrap();
+        assert_eq!(&empty[..], [PACKET_VERSION]);
+    }
+
+    #[test]
+    fn test_unknown_fields_are_skipped() {
+        let mut encoded = BytesMut::from(&encode(&sample(), &limits()).unwrap()[..]);
+        put_field(&mut encoded, 0x40, b"from the future");
+        let decoded = decode(encoded.freeze(), &limits()).unwrap();
+        assert_eq!(&decoded.data[..], b"config blob");
+    }
+
+    #[test]
+    fn test_rejects_non_canonical_input() {
+        let encoded = encode(&sample(), &limits()).unwrap();
+
+        let mut wrong_version = encoded.to_vec();
+        wrong_version[0] = 2;
+        assert!(decode(wrong_version.into(), &limits()).is_err());
+
+        // Data field before the metadata field
+        let mut out_of_order = BytesMut::from(&[PACKET_VERSION][..]);
+        put_field(&mut out_of_order, tags::DATA, b"x");
+        put_field(&mut out_of_order, tags::METADATA, &[1, 1, b'k', 1, b'v']);
+        assert!(decode(out_of_order.freeze(), &limits()).is_err());
+
+        let mut repeated = BytesMut::from(&[PACKET_VERSION][..]);
+        put_field(&mut repeated, tags::DATA, b"x");
+        put_field(&mut repeated, tags::DATA, b"y");
+        assert!(decode(repeated.freeze(), &limits()).is_err());
+
+        let mut unsorted = BytesMut::from(&[PACKET_VERSION][..]);
+        put_field(&mut unsorted, tags::METADATA, &[2, 1, b'b', 0, 1, b'a', 0]);
+        assert!(decode(unsorted.freeze(), &limits()).is_err());
+
+        // Tag 5 spelled as a two-byte varint
+        let long_varint = Bytes::from_static(&[PACKET_VERSION, 0x40, 0x05, 0x01, b'x']);
+        assert!(decode(long_varint, &limits()).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let strict = PacketLimits { max_data_len: 4, max_metadata_entries: 2, ..PacketLimits::default() };
+        assert!(encode(&sample(), &strict).is_err());
+
+        let encoded = encode(&sample(), &limits()).unwrap();
+        assert!(decode(encoded, &strict).is_err());
+
+        // A huge declared hop count is refused before allocating
+        let mut hops = BytesMut::from(&[PACKET_VERSION][..]);
+        put_field(&mut hops, tags::HOPS, &[0xbf, 0xff, 0xff, 0xff]);
+        assert!(decode(hops.freeze(), &limits()).is_err());
+    }
+
+    fn arb_map() -> impl Strategy<Value = HashMap<String, String>> {
+        prop::collection::hash_map(".{0,12}", ".{0,12}", 0..6)
+    }
+
+    fn arb_packet() -> impl Strategy<Value = StoqPacket> {
+        let hop = (any::<u128>(), any::<u16>(), any::<u64>(), arb_map()).prop_map(|(address, port, timestamp, metadata)| {
+            HopInfo { address: Ipv6Addr::from(address), port, timestamp, metadata }
+        });
+        let node = (any::<u128>(), any::<u16>(), any::<u8>())
+            .prop_map(|(address, port, reliability)| SeedNode { address: Ipv6Addr::from(address), port, reliability });
+        let seed = (prop::collection::vec(node, 0..4), any::<u32>(), 0u8..4).prop_map(|(seed_nodes, replication_factor, priority)| {
+            let priority = [SeedPriority::Low, SeedPriority::Normal, SeedPriority::High, SeedPriority::Critical][priority as usize].clone();
+            SeedInfo { seed_nodes, replication_factor, priority }
+        });
+        let token = (any::<[u8; 32]>(), any::<u64>(), any::<u64>())
+            .prop_map(|(hash, sequence, timestamp)| PacketToken { hash, sequence, timestamp });
+        (
+            prop::collection::vec(any::<u8>(), 0..512),
+            prop::option::of(token),
+            prop::collection::vec(hop, 0..4),
+            prop::option::of(seed),
+            arb_map(),
+        )
+            .prop_map(|(data, token, hops, seed_info, metadata)| StoqPacket { data: data.into(), token, hops, seed_info, metadata })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_roundtrip(packet in arb_packet()) {
+            let encoded = encode(&packet, &limits()).unwrap();
+            let decoded = decode(encoded.clone(), &limits()).unwrap();
+            prop_assert_eq!(&decoded.data, &packet.data);
+            prop_assert_eq!(&decoded.metadata, &packet.metadata);
+            prop_assert_eq!(decoded.hops.len(), packet.hops.len());
+            prop_assert_eq!(encode(&decoded, &limits()).unwrap(), encoded);
+        }
+
+        #[test]
+        fn prop_arbitrary_input_is_safe(data in prop::collection::vec(any::<u8>(), 0..256)) {
+            if let Ok(packet) = decode(data.into(), &limits()) {
+                let encoded = encode(&packet, &limits()).unwrap();
+                prop_assert_eq!(encode(&decode(encoded.clone(), &limits()).unwrap(), &limits()).unwrap(), encoded);
+            }
+        }
+
+        #[test]
+        fn prop_corrupted_packet_is_safe(packet in arb_packet(), flips in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..4), cut in any::<prop::sample::Index>()) {
+            let mut encoded = encode(&packet, &limits()).unwrap().to_vec();
+            for (index, byte) in flips {
+                let i = index.index(encoded.len());
+                encoded[i] ^= byte;
+            }
+            let _ = decode(Bytes::from(encoded.clone()), &limits());
+            encoded.truncate(cut.index(encoded.len() + 1));
+            let _ = decode(encoded.into(), &limits());
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Multi-hop relay header
+//!
+//! A relayed stream starts with a [`STOQ_HOP`](super::frame_types::STOQ_HOP)
+//! preamble naming the hops still ahead and the relays already traversed.
+//! Each relay takes the next hop off the route, appends its own [`HopFrame`]
+//! to the trail and forwards the rest of the stream unchanged, so the final
+//! receiver sees an empty route and the full trail. Relays stamp their trail
+//! entries with the Unix time in milliseconds.
+//!
+//! Wire format after the frame type: a varint body length, then `max_hops`
+//! (u8), the route (u8 count of address, port and SNI name entries) and the
+//! trail (u8 count of hop frame bodies). On the stream the header frame
+//! travels in a [frame group](super::signing) behind a `STOQ_HOP` marker, so
+//! it is signed whenever the frame signing policy covers hop frames.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::net::Ipv6Addr;
+
+use super::frame_types::STOQ_HOP;
+use super::frames::{self, HopFrame};
+use crate::error::{Result, StoqError};
+use crate::extensions::HopInfo;
+use crate::transport::Endpoint;
+
+/// Largest header body a relay will read
+pub const MAX_HEADER_LEN: usize = 16 * 1024;
+
+/// ALPN protocol a server prefers over [`STOQ_ALPN`](super::STOQ_ALPN) to
+/// accept relayed streams
+///
+/// Only streams on connections that agreed on it are checked for a header,
+/// so other peers' data is never mistaken for one.
+pub const HOP_ALPN: &[u8] = b"stoq/1.0+hop";
+
+/// Route and hop trail of a relayed stream
+#[derive(Debug, Clone)]
+pub struct RelayHeader {
+    /// Hops still ahead, the final receiver last
+    pub route: Vec<Endpoint>,
+    /// Relays already traversed, in order
+    pub trail: Vec<HopFrame>,
+    /// Most relays the stream may traverse
+    pub max_hops: u8,
+}
+
+impl RelayHeader {
+    /// Header for a stream that still has `route` ahead of it
+    pub fn new(route: Vec<Endpoint>, max_hops: u8) -> Self {
+        Self { route, trail: Vec::new(), max_hops }
+    }
+
+    /// Relays traversed so far
+    pub fn hop_count(&self) -> usize {
+        self.trail.len()
+    }
+
+    /// The traversed relays' hop information, in order
+    pub fn hops(&self) -> Vec<HopInfo> {
+        self.trail.iter().map(|frame| frame.hop.clone()).collect()
+    }
+
+    /// Record a relay, refusing to exceed `max_hops`
+    pub fn push_hop(&mut self, hop: HopInfo) -> Result<()> {
+        if self.trail.len() >= self.max_hops as usize {
+            return Err(StoqError::Protocol(format!("hop limit of {} reached", self.max_hops)));
+        }
+        self.trail.push(HopFrame {
+            hop,
+            hop_count: self.trail.len() as u8 + 1,
+            max_hops: self.max_hops,
+        });
+        Ok(())
+    }
+
+    /// Whether the trail already contains `address`:`port`
+    pub fn visited(&self, address: Ipv6Addr, port: u16) -> bool {
+        self.trail.iter().any(|frame| frame.hop.address == address && frame.hop.port == port)
+    }
+
+    /// Encode as a stream preamble
+    pub fn encode(&self) -> Result<Bytes> {
+        if self.route.len() > u8::MAX as usize || self.trail.len() > u8::MAX as usize {
+            return Err(StoqError::Protocol("relay route too long".to_string()));
+        }
+
+        let mut body = BytesMut::new();
+        body.put_u8(self.max_hops);
+        body.put_u8(self.route.len() as u8);
+        for hop in &self.route {
+            let name = hop.server_name.as_deref().unwrap_or("");
+            if name.len() > u8::MAX as usize {
+                return Err(StoqError::Protocol(format!("server name too long: {}", name)));
+            }
+            body.put_slice(&hop.address.octets());
+            body.put_u16(hop.port);
+            body.put_u8(name.len() as u8);
+            body.put_slice(name.as_bytes());
+        }
+        body.put_u8(self.trail.len() as u8);
+        for frame in &self.trail {
+            frames::encode_hop_frame(&mut body, frame)?;
+        }
+        if body.len() > MAX_HEADER_LEN {
+            return Err(StoqError::Protocol("relay header too long".to_string()));
+        }
+
+        let mut buf = BytesMut::new();
+        frames::encode_varint(&mut buf, STOQ_HOP);
+        frames::encode_varint(&mut buf, quinn::VarInt::from_u32(body.len() as u32));
+        buf.put_slice(&body);
+        Ok(buf.freeze())
+    }
+
+    /// Decode a header body (the bytes after the frame type and length)
+    pub fn decode_body(mut body: Bytes) -> Result<Self> {
+        let truncated = || StoqError::Protocol("relay header truncated".to_string());
+
+        if body.remaining() < 2 {
+            return Err(truncated());
+        }
+        let max_hops = body.get_u8();
+        let route_len = body.get_u8();
+        let mut route = Vec::with_capacity(route_len as usize);
+        for _ in 0..route_len {
+            if body.remaining() < 16 + 2 + 1 {
+                return Err(truncated());
+            }
+            let mut address = [0u8; 16];
+            body.copy_to_slice(&mut address);
+            let port = body.get_u16();
+            let name_len = body.get_u8() as usize;
+            if body.remaining() < name_len {
+                return Err(truncated());
+            }
+            let name = String::from_utf8(body.split_to(name_len).to_vec())
+                .map_err(|_| StoqError::Protocol("relay server name is not UTF-8".to_string()))?;
+            let mut hop = Endpoint::new(Ipv6Addr::from(address), port);
+            if !name.is_empty() {
+                hop = hop.with_server_name(name);
+            }
+            route.push(hop);
+        }
+
+        if !body.has_remaining() {
+            return Err(truncated());
+        }
+        let trail_len = body.get_u8();
+        let mut trail = Vec::with_capacity(trail_len as usize);
+        for _ in 0..trail_len {
+            let frame = frames::decode_hop_frame(&mut body)
+                .map_err(|e| StoqError::Protocol(format!("bad relay trail: {}", e)))?;
+            trail.push(frame);
+        }
+        if body.has_remaining() {
+            return Err(StoqError::Protocol("trailing bytes in relay header".to_string()));
+        }
+
+        Ok(Self { route, trail, max_hops })
+    }
+
+    /// Decode a header frame as produced by [`Self::encode`]
+    pub fn decode(frame: Bytes) -> Result<Self> {
+        match Self::split_preamble(frame) {
+            (Some(header), rest) if rest.is_empty() => Ok(header),
+            _ => Err(StoqError::Protocol("malformed relay header".to_string())),
+        }
+    }
+
+    /// Strip a leading relay header from stream data, if present
+    ///
+    /// Data that does not start with a well-formed header is returned unchanged.
+    pub fn split_preamble(data: Bytes) -> (Option<Self>, Bytes) {
+        let mut rest = data.clone();
+        if frames::decode_varint(&mut rest) != Some(STOQ_HOP) {
+            return (None, data);
+        }
+        let Some(len) = frames::decode_varint(&mut rest).map(|len| len.into_inner() as usize) else {
+            return (None, data);
+        };
+        if len > rest.len() {
+            return (None, data);
+        }
+        let body = rest.split_to(len);
+        match Self::decode_body(body) {
+            Ok(header) => (Some(header), rest),
+            Err(_) => (None, data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn hop(last: u16, port: u16) -> HopInfo {
+        HopInfo {
+            address: Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, last),
+            port,
+            timestamp: 1_700_000_000_000,
+            metadata: HashMap::from([("node".to_string(), format!("relay-{}", last))]),
+        }
+    }
+
+    #[test]
+    fn test_header_roundtrip() {
+        let route = vec![
+            Endpoint::new("fd00::2".parse().unwrap(), 9292).with_server_name("bastion".to_string()),
+            Endpoint::new("fd00::3".parse().unwrap(), 9293),
+        ];
+        let mut header = RelayHeader::new(route, 4);
+        header.push_hop(hop(1, 9292)).unwrap();
+
+        let mut data = BytesMut::from(&header.encode().unwrap()[..]);
+        data.extend_from_slice(b"payload");
+        let (parsed, rest) = RelayHeader::split_preamble(data.freeze());
+        let parsed = parsed.unwrap();
+
+        assert_eq!(&rest[..], b"payload");
+        assert_eq!(parsed.max_hops, 4);
+        assert_eq!(parsed.route.len(), 2);
+        assert_eq!(parsed.route[0].server_name.as_deref(), Some("bastion"));
+        assert_eq!(parsed.route[1].port, 9293);
+        assert_eq!(parsed.trail[0].hop_count, 1);
+        assert_eq!(parsed.hops()[0].metadata["node"], "relay-1");
+        assert!(parsed.visited(hop(1, 9292).address, 9292));
+        assert!(!parsed.visited(hop(1, 9292).address, 9293));
+    }
+
+    #[test]
+    fn test_hop_limit() {
+        let mut header = RelayHeader::new(Vec::new(), 2);
+        header.push_hop(hop(1, 1)).unwrap();
+        header.push_hop(hop(2, 1)).unwrap();
+        assert!(header.push_hop(hop(3, 1)).is_err());
+        assert_eq!(header.hop_count(), 2);
+        assert_eq!(header.trail[1].hop_count, 2);
+    }
+
+    #[test]
+    fn test_split_leaves_other_data() {
+        let (parsed, rest) = RelayHeader::split_preamble(Bytes::from_static(b"plain data"));
+        assert!(parsed.is_none());
+        assert_eq!(&rest[..], b"plain data");
+
+        // A truncated header is left alone rather than half-consumed
+        let encoded = RelayHeader::new(vec![Endpoint::new(Ipv6Addr::LOCALHOST, 1)], 1).encode().unwrap();
+        let truncated = encoded.slice(..encoded.len() - 1);
+        let (parsed, rest) = RelayHeader::split_preamble(truncated.clone());
+        assert!(parsed.is_none());
+        assert_eq!(rest, truncated);
+    }
+}
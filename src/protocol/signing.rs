@@ -0,0 +1,360 @@
+//! Signed frame groups
+//!
+//! A frame group carries STOQ frames to be acted on together, each prefixed
+//! with its varint length. A signed group opens with a
+//! [`FALCON_SIG`](super::frame_types::FALCON_SIG) frame whose `key_id` names
+//! the signer's key and whose `signed_frames` lists the type of every frame
+//! after it, in order. The signature covers [`SIGNING_CONTEXT`], the channel
+//! binding and the rest of the group exactly as sent, so frames cannot be
+//! altered, reordered, dropped or added without failing verification.
+//!
+//! The channel binding is keying material exported from the TLS session of
+//! the connection the group travels on, so a group signed for one connection
+//! fails verification on any other. Each peer proves its key on the
+//! connection with an identity proof: a `FALCON_SIG` frame signing
+//! [`IDENTITY_CONTEXT`], its side of the connection and the binding (the side
+//! stops a peer reflecting our own proof back at us). Receivers check the
+//! proof against their trusted FALCON keys (see
+//! [`FalconTransport::add_trusted_key`]), then accept only groups signed by
+//! the key it proved, and refuse unsigned frames their
+//! [`FrameSigningPolicy`] covers. Groups carry no sequence number: within a
+//! connection only the authenticated peer can send stream data, and replaying
+//! its own groups gains it nothing it could not send anyway.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use quinn::{Side, VarInt};
+use serde::{Deserialize, Serialize};
+
+use super::frame_types;
+use super::frames::{self, FalconSigFrame, StoqFrame};
+use crate::error::{Result, StoqError};
+use crate::transport::falcon::FalconTransport;
+
+/// Prefix of every signed frame group message, so the signatures cannot be
+/// replayed as signatures over anything else
+pub const SIGNING_CONTEXT: &[u8] = b"stoq frame group v1";
+
+/// Prefix of every identity proof message
+pub const IDENTITY_CONTEXT: &[u8] = b"stoq falcon identity v1";
+
+/// Most a signature frame adds to a group: a FALCON-1024 signature, its key
+/// ID and the frame type list, with room to spare
+pub const MAX_SIGNATURE_LEN: usize = 4 * 1024;
+
+/// Which frames must arrive in a signed group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FrameSigningPolicy {
+    /// Nothing needs signing; signatures that do arrive are still verified
+    #[default]
+    Off,
+    /// Hop and seed frames, which steer routing and replication
+    Routing,
+    /// Every STOQ frame
+    All,
+}
+
+impl FrameSigningPolicy {
+    /// Whether frames of `frame_type` must be signed
+    pub fn covers(&self, frame_type: VarInt) -> bool {
+        match self {
+            Self::Off => false,
+            Self::Routing => frame_type == frame_types::STOQ_HOP || frame_type == frame_types::STOQ_SEED,
+            Self::All => frame_type != frame_types::FALCON_SIG,
+        }
+    }
+}
+
+fn frame_type(frame: &Bytes) -> Result<VarInt> {
+    frames::decode_varint(&mut frame.clone()).ok_or_else(|| StoqError::Protocol("frame has no type".to_string()))
+}
+
+fn put_frame(buf: &mut BytesMut, frame: &[u8]) {
+    // Frames are built in memory, far below the 2^62 varint ceiling
+    frames::encode_varint(buf, VarInt::from_u64(frame.len() as u64).expect("frame length fits in a varint"));
+    buf.put_slice(frame);
+}
+
+fn take_frame(data: &mut Bytes) -> Result<Bytes> {
+    let len = frames::decode_varint(data)
+        .ok_or_else(|| StoqError::Protocol("frame group truncated".to_string()))?
+        .into_inner();
+    if len > data.remaining() as u64 {
+        return Err(StoqError::Protocol(format!("frame of {} bytes overruns the group", len)));
+    }
+    Ok(data.split_to(len as usize))
+}
+
+fn signed_message(channel_binding: &[u8], body: &[u8]) -> Vec<u8> {
+    [SIGNING_CONTEXT, &[channel_binding.len() as u8], channel_binding, body].concat()
+}
+
+fn identity_message(channel_binding: &[u8], side: Side) -> Vec<u8> {
+    [IDENTITY_CONTEXT, &[side.is_client() as u8, channel_binding.len() as u8], channel_binding].concat()
+}
+
+fn sign(signer: &FalconTransport, message: &[u8], signed_frames: Vec<VarInt>) -> Result<Bytes> {
+    let key_id = signer.get_local_public_key()
+        .map(|key| key.id())
+        .ok_or_else(|| StoqError::Falcon("no local key to sign frames with".to_string()))?;
+    let signature = signer.sign_handshake_data(message)
+        .map_err(|e| StoqError::Falcon(e.to_string()))?;
+    StoqFrame::FalconSignature(FalconSigFrame {
+        signature_data: signer.export_signature(&signature),
+        key_id,
+        signed_frames,
+    })
+    .encode()
+    .map_err(|e| StoqError::Protocol(e.to_string()))
+}
+
+fn check_signature(verifier: &FalconTransport, signature: &FalconSigFrame, message: &[u8]) -> Result<()> {
+    let imported = verifier.import_signature(&signature.signature_data)
+        .map_err(|e| StoqError::Falcon(e.to_string()))?;
+    let valid = verifier.verify_handshake_signature(&signature.key_id, &imported, message)
+        .map_err(|e| StoqError::Falcon(e.to_string()))?;
+    if !valid {
+        return Err(StoqError::Falcon(format!("signature by {} does not verify", signature.key_id)));
+    }
+    Ok(())
+}
+
+/// Whether a group opens with a signature
+pub fn is_signed(data: &Bytes) -> bool {
+    let mut data = data.clone();
+    take_frame(&mut data).is_ok_and(|first| frame_type(&first).is_ok_and(|frame_type| frame_type == frame_types::FALCON_SIG))
+}
+
+/// Prove `signer`'s local key to the peer on the `side` end of a connection
+pub fn encode_identity(signer: &FalconTransport, channel_binding: &[u8], side: Side) -> Result<Bytes> {
+    sign(signer, &identity_message(channel_binding, side), Vec::new())
+}
+
+/// Check the identity proof sent by the peer on the `side` end of a
+/// connection and return the trusted key ID it proved
+pub fn verify_identity(data: Bytes, verifier: &FalconTransport, channel_binding: &[u8], side: Side) -> Result<String> {
+    let signature = match StoqFrame::decode(data).map_err(|e| StoqError::Protocol(e.to_string()))? {
+        StoqFrame::FalconSignature(signature) if signature.signed_frames.is_empty() => signature,
+        _ => return Err(StoqError::Protocol("identity proof is not a bare signature frame".to_string())),
+    };
+    check_signature(verifier, &signature, &identity_message(channel_binding, side))?;
+    Ok(signature.key_id)
+}
+
+/// Build a group from encoded frames for the connection `channel_binding`
+/// was exported from, signed with `signer`'s local key if given
+pub fn encode_group(frames: &[Bytes], signer: Option<&FalconTransport>, channel_binding: &[u8]) -> Result<Bytes> {
+    let mut body = BytesMut::new();
+    let mut signed_frames = Vec::with_capacity(frames.len());
+    for frame in frames {
+        let frame_type = frame_type(frame)?;
+        if frame_type == frame_types::FALCON_SIG {
+            return Err(StoqError::Protocol("frame group cannot contain a signature frame".to_string()));
+        }
+        signed_frames.push(frame_type);
+        put_frame(&mut body, frame);
+    }
+    let Some(signer) = signer else {
+        return Ok(body.freeze());
+    };
+    let signature_frame = sign(signer, &signed_message(channel_binding, &body), signed_frames)?;
+
+    let mut group = BytesMut::with_capacity(signature_frame.len() + body.len() + 4);
+    put_frame(&mut group, &signature_frame);
+    group.put_slice(&body);
+    Ok(group.freeze())
+}
+
+/// Split a group into encoded frames, verifying its signature and enforcing `policy`
+///
+/// A signature must be by `peer_key_id`, the key the sender proved on the
+/// connection `channel_binding` was exported from.
+pub fn verify_group(
+    data: Bytes,
+    policy: FrameSigningPolicy,
+    verifier: Option<&FalconTransport>,
+    peer_key_id: Option<&str>,
+    channel_binding: &[u8],
+) -> Result<Vec<Bytes>> {
+    let mut body = data.clone();
+    let first = take_frame(&mut body)?;
+    let signature = if frame_type(&first)? == frame_types::FALCON_SIG {
+        match StoqFrame::decode(first).map_err(|e| StoqError::Protocol(e.to_string()))? {
+            StoqFrame::FalconSignature(signature) => Some(signature),
+            _ => unreachable!("decoded by frame type"),
+        }
+    } else {
+        body = data;
+        None
+    };
+
+    if let Some(signature) = &signature {
+        let verifier = verifier.ok_or(StoqError::Unavailable("FALCON transport"))?;
+        let peer_key_id = peer_key_id
+            .ok_or_else(|| StoqError::Falcon("peer has not proven a FALCON key".to_string()))?;
+        if signature.key_id != peer_key_id {
+            return Err(StoqError::Falcon(format!(
+                "frame group signed by {}, but the peer proved {}", signature.key_id, peer_key_id
+            )));
+        }
+        check_signature(verifier, signature, &signed_message(channel_binding, &body))?;
+    }
+
+    let mut frames = Vec::new();
+    let mut types = Vec::new();
+    while body.has_remaining() {
+        let frame = take_frame(&mut body)?;
+        let frame_type = frame_type(&frame)?;
+        if frame_type == frame_types::FALCON_SIG {
+            return Err(StoqError::Protocol("signature frame inside a frame group".to_string()));
+        }
+        if signature.is_none() && policy.covers(frame_type) {
+            return Err(StoqError::Protocol(format!("frame type {:#x} must be signed", frame_type.into_inner())));
+        }
+        types.push(frame_type);
+        frames.push(frame);
+    }
+    if signature.is_some_and(|signature| signature.signed_frames != types) {
+        return Err(StoqError::Protocol("signed frame types do not match the group".to_string()));
+    }
+    Ok(frames)
+}
+
+/// Split a group into frames, verifying its signature and enforcing `policy`
+pub fn decode_group(
+    data: Bytes,
+    policy: FrameSigningPolicy,
+    verifier: Option<&FalconTransport>,
+    peer_key_id: Option<&str>,
+    channel_binding: &[u8],
+) -> Result<Vec<StoqFrame>> {
+    verify_group(data, policy, verifier, peer_key_id, channel_binding)?
+        .into_iter()
+        .map(|frame| StoqFrame::decode(frame).map_err(|e| StoqError::Protocol(e.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extensions::{HopInfo, PacketToken, SeedInfo, SeedPriority};
+    use crate::protocol::frames::{HopFrame, SeedFrame, TokenFrame};
+    use crate::transport::falcon::FalconVariant;
+    use std::collections::HashMap;
+
+    /// A signer and a receiver that trusts it
+    fn peers() -> (FalconTransport, FalconTransport) {
+        let mut signer = FalconTransport::new(FalconVariant::Falcon512);
+        signer.generate_local_keypair().unwrap();
+        let key = signer.get_local_public_key().unwrap().clone();
+        let mut receiver = FalconTransport::new(FalconVariant::Falcon512);
+        receiver.add_trusted_key(key.id(), key);
+        (signer, receiver)
+    }
+
+    fn routing_frames() -> Vec<Bytes> {
+        let token = StoqFrame::Token(TokenFrame { token: PacketToken::new(b"payload", 1), stream_id: None });
+        let hop = StoqFrame::Hop(HopFrame {
+            hop: HopInfo { address: "fd00::1".parse().unwrap(), port: 9292, timestamp: 1, metadata: HashMap::new() },
+            hop_count: 1,
+            max_hops: 4,
+        });
+        let seed = StoqFrame::Seed(SeedFrame {
+            seed_info: SeedInfo { seed_nodes: Vec::new(), replication_factor: 1, priority: SeedPriority::High },
+            packet_id: [9; 32],
+        });
+        [token, hop, seed].iter().map(|frame| frame.encode().unwrap()).collect()
+    }
+
+    const BINDING: &[u8] = &[7; 32];
+
+    fn key_id(falcon: &FalconTransport) -> String {
+        falcon.get_local_public_key().unwrap().id()
+    }
+
+    #[test]
+    fn test_signed_group_verifies() {
+        let (signer, receiver) = peers();
+        let group = encode_group(&routing_frames(), Some(&signer), BINDING).unwrap();
+        assert!(is_signed(&group));
+        let frames = decode_group(group, FrameSigningPolicy::All, Some(&receiver), Some(&key_id(&signer)), BINDING).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(matches!(frames[1], StoqFrame::Hop(ref frame) if frame.hop.port == 9292));
+        assert!(matches!(frames[2], StoqFrame::Seed(ref frame) if frame.packet_id == [9; 32]));
+    }
+
+    #[test]
+    fn test_tampered_or_untrusted_group_is_refused() {
+        let (signer, receiver) = peers();
+        let signer_id = key_id(&signer);
+        let group = encode_group(&routing_frames(), Some(&signer), BINDING).unwrap();
+        let decode = |group, verifier| decode_group(group, FrameSigningPolicy::Off, verifier, Some(&signer_id), BINDING);
+
+        let mut tampered = group.to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decode(tampered.into(), Some(&receiver)).is_err());
+
+        // Dropping the last frame breaks both the signature and the type list
+        let frames = routing_frames();
+        let truncated = group.slice(..group.len() - frames[2].len() - 1);
+        assert!(decode(truncated, Some(&receiver)).is_err());
+
+        let stranger = FalconTransport::new(FalconVariant::Falcon512);
+        assert!(matches!(decode(group.clone(), Some(&stranger)), Err(StoqError::Falcon(_))));
+        assert!(matches!(decode(group, None), Err(StoqError::Unavailable(_))));
+    }
+
+    #[test]
+    fn test_group_is_bound_to_connection_and_peer() {
+        let (signer, mut receiver) = peers();
+        let mut other = FalconTransport::new(FalconVariant::Falcon512);
+        other.generate_local_keypair().unwrap();
+        let other_key = other.get_local_public_key().unwrap().clone();
+        receiver.add_trusted_key(other_key.id(), other_key);
+        let group = encode_group(&routing_frames(), Some(&signer), BINDING).unwrap();
+
+        // Replayed on another connection
+        let replayed = decode_group(group.clone(), FrameSigningPolicy::Off, Some(&receiver), Some(&key_id(&signer)), &[8; 32]);
+        assert!(matches!(replayed, Err(StoqError::Falcon(_))));
+        // Signed by a trusted key, but not the one the peer proved
+        let wrong_peer = decode_group(group.clone(), FrameSigningPolicy::Off, Some(&receiver), Some(&key_id(&other)), BINDING);
+        assert!(matches!(wrong_peer, Err(StoqError::Falcon(_))));
+        let unproven = decode_group(group, FrameSigningPolicy::Off, Some(&receiver), None, BINDING);
+        assert!(matches!(unproven, Err(StoqError::Falcon(_))));
+    }
+
+    #[test]
+    fn test_identity_proof() {
+        let (signer, receiver) = peers();
+        let proof = encode_identity(&signer, BINDING, Side::Client).unwrap();
+        assert_eq!(verify_identity(proof.clone(), &receiver, BINDING, Side::Client).unwrap(), key_id(&signer));
+
+        // Reflected back at its maker, or moved to another connection
+        assert!(verify_identity(proof.clone(), &receiver, BINDING, Side::Server).is_err());
+        assert!(verify_identity(proof.clone(), &receiver, &[8; 32], Side::Client).is_err());
+        let stranger = FalconTransport::new(FalconVariant::Falcon512);
+        assert!(verify_identity(proof, &stranger, BINDING, Side::Client).is_err());
+
+        // A group signature is not an identity proof
+        let group = encode_group(&routing_frames(), Some(&signer), BINDING).unwrap();
+        let signature = take_frame(&mut group.clone()).unwrap();
+        assert!(verify_identity(signature, &receiver, BINDING, Side::Client).is_err());
+    }
+
+    #[test]
+    fn test_policy_requires_signatures() {
+        let frames = routing_frames();
+        let decode = |group, policy| decode_group(group, policy, None, None, BINDING);
+        let unsigned = encode_group(&frames, None, BINDING).unwrap();
+        assert!(!is_signed(&unsigned));
+        assert_eq!(decode(unsigned.clone(), FrameSigningPolicy::Off).unwrap().len(), 3);
+        assert!(decode(unsigned, FrameSigningPolicy::Routing).is_err());
+
+        let token_only = encode_group(&frames[..1], None, BINDING).unwrap();
+        assert_eq!(decode(token_only.clone(), FrameSigningPolicy::Routing).unwrap().len(), 1);
+        assert!(decode(token_only, FrameSigningPolicy::All).is_err());
+
+        assert!(FrameSigningPolicy::Routing.covers(frame_types::STOQ_SEED));
+        assert!(!FrameSigningPolicy::Routing.covers(frame_types::STOQ_SHARD));
+        assert!(FrameSigningPolicy::All.covers(frame_types::STOQ_SHARD));
+    }
+}
@@ -0,0 +1,209 @@
+//! W3C trace context propagation
+//!
+//! Carries a `traceparent`/`tracestate` pair (W3C Trace Context Level 1)
+//! across STOQ hops in a [`TRACE_CONTEXT`](super::frame_types::TRACE_CONTEXT)
+//! frame sent at the start of a stream. STOQ only transports the context; the
+//! application's tracing exporter decides how to link spans with it.
+//!
+//! `tracing` has no notion of a remote parent, so the receiving `stoq.stream`
+//! span records the context as its `trace_id` and `parent_span_id` fields and
+//! is otherwise parented to its connection. An application that exports spans
+//! (e.g. through `tracing-opentelemetry`) installs a [`TraceParent`] hook with
+//! `StoqTransport::set_trace_parent`; once the context frame has been read,
+//! the stream span is reopened as a child of the span the hook returns, and
+//! follows from the connection's stream span.
+
+use bytes::{Bytes, BytesMut};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::frames::{self, StoqFrame};
+use crate::error::StoqError;
+
+/// Sampled flag in `trace-flags`
+pub const FLAG_SAMPLED: u8 = 0x01;
+
+/// W3C trace context identifying the remote parent span
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 16-byte trace identifier
+    pub trace_id: [u8; 16],
+    /// 8-byte identifier of the parent span
+    pub parent_id: [u8; 8],
+    /// Trace flags (bit 0 = sampled)
+    pub flags: u8,
+    /// Vendor-specific `tracestate` header value, if any
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// Create a context for a parent span
+    pub fn new(trace_id: [u8; 16], parent_id: [u8; 8], sampled: bool) -> Self {
+        Self {
+            trace_id,
+            parent_id,
+            flags: if sampled { FLAG_SAMPLED } else { 0 },
+            tracestate: None,
+        }
+    }
+
+    /// Attach a `tracestate` header value
+    pub fn with_tracestate(mut self, tracestate: String) -> Self {
+        self.tracestate = Some(tracestate);
+        self
+    }
+
+    /// Whether the parent was sampled
+    pub fn is_sampled(&self) -> bool {
+        self.flags & FLAG_SAMPLED != 0
+    }
+
+    /// Trace ID as 32 lowercase hex digits
+    pub fn trace_id_hex(&self) -> String {
+        hex::encode(self.trace_id)
+    }
+
+    /// Parent span ID as 16 lowercase hex digits
+    pub fn parent_id_hex(&self) -> String {
+        hex::encode(self.parent_id)
+    }
+
+    /// Format as a `traceparent` header value
+    pub fn traceparent(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-{}-{}-{:02x}", self.trace_id_hex(), self.parent_id_hex(), self.flags)
+    }
+}
+
+impl FromStr for TraceContext {
+    type Err = StoqError;
+
+    /// Parse a `traceparent` header value
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || StoqError::Protocol(format!("invalid traceparent: {}", s));
+        let parts: Vec<&str> = s.trim().split('-').collect();
+        if parts.len() < 4 {
+            return Err(invalid());
+        }
+
+        // Version ff is forbidden; future versions may append fields
+        let version = u8::from_str_radix(parts[0], 16).map_err(|_| invalid())?;
+        if parts[0].len() != 2 || version == 0xff || (version == 0 && parts.len() != 4) {
+            return Err(invalid());
+        }
+
+        let mut trace_id = [0u8; 16];
+        let mut parent_id = [0u8; 8];
+        hex::decode_to_slice(parts[1], &mut trace_id).map_err(|_| invalid())?;
+        hex::decode_to_slice(parts[2], &mut parent_id).map_err(|_| invalid())?;
+        if trace_id == [0; 16] || parent_id == [0; 8] {
+            return Err(invalid());
+        }
+        if parts[1].chars().any(|c| c.is_ascii_uppercase()) || parts[2].chars().any(|c| c.is_ascii_uppercase()) {
+            return Err(invalid());
+        }
+
+        if parts[3].len() != 2 {
+            return Err(invalid());
+        }
+        let flags = u8::from_str_radix(parts[3], 16).map_err(|_| invalid())?;
+
+        Ok(Self { trace_id, parent_id, flags, tracestate: None })
+    }
+}
+
+/// Opens the local span standing in for the remote parent in a peer's context
+///
+/// Typically the hook opens a span and sets its OpenTelemetry parent from the
+/// context, e.g. with `tracing-opentelemetry`'s `OpenTelemetrySpanExt::set_parent`.
+pub type TraceParent = Arc<dyn Fn(&TraceContext) -> tracing::Span + Send + Sync>;
+
+/// Encode a context as a stream preamble frame
+pub fn encode_preamble(context: &TraceContext) -> Bytes {
+    let mut buf = BytesMut::new();
+    frames::encode_varint(&mut buf, super::frame_types::TRACE_CONTEXT);
+    frames::encode_trace_context(&mut buf, context);
+    buf.freeze()
+}
+
+/// Strip a leading trace context frame from stream data, if present
+///
+/// Data that does not start with a well-formed frame is returned unchanged.
+pub fn split_preamble(data: Bytes) -> (Option<TraceContext>, Bytes) {
+    let mut rest = data.clone();
+    if frames::decode_varint(&mut rest) != Some(super::frame_types::TRACE_CONTEXT) {
+        return (None, data);
+    }
+    match frames::decode_trace_context(&mut rest) {
+        Ok(context) => (Some(context), rest),
+        Err(_) => (None, data),
+    }
+}
+
+impl From<TraceContext> for StoqFrame {
+    fn from(context: TraceContext) -> Self {
+        StoqFrame::TraceContext(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_traceparent_roundtrip() {
+        let context: TraceContext = TRACEPARENT.parse().unwrap();
+        assert!(context.is_sampled());
+        assert_eq!(context.parent_id_hex(), "00f067aa0ba902b7");
+        assert_eq!(context.traceparent(), TRACEPARENT);
+    }
+
+    #[test]
+    fn test_invalid_traceparent_rejected() {
+        for value in [
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f35-00f067aa0ba902b7-01",
+        ] {
+            assert!(value.parse::<TraceContext>().is_err(), "{}", value);
+        }
+        // Future versions may carry extra fields
+        assert!("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra".parse::<TraceContext>().is_ok());
+    }
+
+    #[test]
+    fn test_preamble_split() {
+        let context = TRACEPARENT.parse::<TraceContext>().unwrap().with_tracestate("congo=t61rcWkgMzE".to_string());
+        let mut data = BytesMut::from(&encode_preamble(&context)[..]);
+        data.extend_from_slice(b"payload");
+
+        let (parsed, rest) = split_preamble(data.freeze());
+        assert_eq!(parsed, Some(context));
+        assert_eq!(&rest[..], b"payload");
+
+        let (parsed, rest) = split_preamble(Bytes::from_static(b"plain data"));
+        assert!(parsed.is_none());
+        assert_eq!(&rest[..], b"plain data");
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let context: TraceContext = TRACEPARENT.parse().unwrap();
+        let encoded = StoqFrame::from(context.clone()).encode().unwrap();
+        match StoqFrame::decode(encoded).unwrap() {
+            StoqFrame::TraceContext(decoded) => assert_eq!(decoded, context),
+            other => panic!("unexpected frame: {:?}", other),
+        }
+    }
+}
@@ -1,15 +1,24 @@
 //! Adaptive connection optimization for live connections
 //! Provides real-time parameter adjustment based on network conditions
 
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::sync::atomic::{AtomicU64, AtomicBool, Ordering};
-use parking_lot::RwLock;
-use quinn::{Connection as QuinnConnection, TransportConfig, VarInt};
+use parking_lot::{Mutex, RwLock};
+use quinn::Connection as QuinnConnection;
 use tracing::{debug, info, warn, trace};
 use tokio::time::interval;
+use serde::{Serialize, Deserialize};
 
 use super::{NetworkTier, CongestionControl};
+use super::events::{ConnectionEvent, ConnectionEvents};
+use super::reconfigure::{self, ReconfigureReport};
+use super::policy::{AdaptationPolicy, HysteresisConfig, TieredPolicy};
+use super::estimator::{ConditionsEstimator, PathSample};
+
+/// Network condition samples kept per connection for the adaptation policy
+pub const CONDITION_HISTORY_LEN: usize = 32;
 
 /// Network condition metrics for adaptation decisions
 #[derive(Debug, Clone)]
@@ -62,10 +71,19 @@ pub struct AdaptiveConnection {
     parameters: Arc<RwLock<ConnectionParameters>>,
     /// Hysteresis state to prevent thrashing
     hysteresis: Arc<RwLock<HysteresisState>>,
+    /// Connection ID and event channel for `TierChanged` notifications
+    events: Option<(String, ConnectionEvents)>,
+    /// Decides tiers and parameters from the condition history
+    policy: RwLock<Arc<dyn AdaptationPolicy>>,
+    /// Recent network conditions, oldest first
+    history: RwLock<VecDeque<NetworkConditions>>,
+    /// Derives conditions from successive path statistics
+    estimator: Mutex<ConditionsEstimator>,
 }
 
 /// Connection-specific parameters that can be adjusted
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ConnectionParameters {
     /// Maximum stream window size
     pub stream_window: u64,
@@ -120,12 +138,13 @@ struct HysteresisState {
 
 impl Default for HysteresisState {
     fn default() -> Self {
+        let config = HysteresisConfig::default();
         Self {
             consecutive_count: 0,
             previous_tier: None,
             last_tier_change: Instant::now(),
-            min_tier_stability: Duration::from_secs(5), // 5 second minimum
-            required_consecutive: 3, // 3 consecutive measurements
+            min_tier_stability: config.min_stability,
+            required_consecutive: config.required_consecutive,
         }
     }
 }
@@ -144,45 +163,47 @@ impl AdaptiveConnection {
             adaptation_count: AtomicU64::new(0),
             parameters: Arc::new(RwLock::new(ConnectionParameters::default())),
             hysteresis: Arc::new(RwLock::new(HysteresisState::default())),
+            events: None,
+            policy: RwLock::new(Arc::new(TieredPolicy::default())),
+            history: RwLock::new(VecDeque::with_capacity(CONDITION_HISTORY_LEN)),
+            estimator: Mutex::new(ConditionsEstimator::default()),
         }
     }
 
-    /// Update network conditions from connection statistics
-    pub fn update_conditions(&self) {
-        let stats = self.connection.stats();
-        let mut conditions = self.conditions.write();
-
-        // Update RTT from path statistics
-        let path = stats.path;
-        conditions.rtt_ms = path.rtt.as_millis() as f64;
-
-        // Calculate jitter as RTT variance
-        if conditions.rtt_ms > 0.0 {
-            let prev_rtt = conditions.rtt_ms;
-            conditions.jitter_ms = (path.rtt.as_millis() as f64 - prev_rtt).abs();
-        }
+    /// Use the given adaptation policy
+    pub fn with_policy(self, policy: Arc<dyn AdaptationPolicy>) -> Self {
+        self.set_policy(policy);
+        self
+    }
 
-        // Update packet loss from frame statistics
-        let frame_stats = stats.frame_tx;
-        let total = frame_stats.acks + frame_stats.stream;
-        if total > 0 {
-            // Use retransmits as a proxy for loss
-            conditions.packet_loss = (frame_stats.path_response as f64 / total.max(1) as f64) * 100.0;
+    /// Replace the adaptation policy, taking its hysteresis settings
+    pub fn set_policy(&self, policy: Arc<dyn AdaptationPolicy>) {
+        let hysteresis = policy.hysteresis();
+        {
+            let mut state = self.hysteresis.write();
+            state.min_tier_stability = hysteresis.min_stability;
+            state.required_consecutive = hysteresis.required_consecutive;
         }
+        *self.policy.write() = policy;
+    }
 
-        // Update throughput estimate
-        let udp_stats = stats.udp_tx;
-        // Calculate throughput based on bytes transmitted
-        let duration = conditions.last_update.elapsed().as_secs_f64();
-        if duration > 0.0 {
-            let bytes_per_sec = udp_stats.bytes as f64 / duration;
-            conditions.throughput_mbps = (bytes_per_sec * 8.0) / 1_000_000.0;
-        }
+    /// Emit `TierChanged` events for this connection on the given channel
+    pub fn with_events(mut self, connection_id: String, events: ConnectionEvents) -> Self {
+        self.events = Some((connection_id, events));
+        self
+    }
 
-        // Track retransmissions (using datagrams as proxy)
-        conditions.retransmissions = udp_stats.datagrams;
+    /// Update network conditions from connection statistics
+    pub fn update_conditions(&self) {
+        let sample = PathSample::from_stats(&self.connection.stats(), Instant::now());
+        let mut conditions = self.conditions.write();
+        *conditions = self.estimator.lock().update(sample).clone();
 
-        conditions.last_update = Instant::now();
+        let mut history = self.history.write();
+        if history.len() == CONDITION_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(conditions.clone());
 
         debug!(
             "Updated network conditions: RTT={:.2}ms, loss={:.2}%, throughput={:.2}Mbps",
@@ -190,42 +211,13 @@ impl AdaptiveConnection {
         );
     }
 
-    /// Detect network tier based on current conditions
+    /// Detect network tier from recent conditions using the adaptation policy
     pub fn detect_tier(&self) -> NetworkTier {
-        let conditions = self.conditions.read();
-
-        // Use multiple heuristics to determine tier
-        let mut estimated_gbps = conditions.bandwidth_estimate / 1000.0;
-
-        // Adjust estimate based on actual throughput
-        if conditions.throughput_mbps > 0.0 {
-            estimated_gbps = (estimated_gbps + (conditions.throughput_mbps / 1000.0)) / 2.0;
-        }
-
-        // Penalize for high latency
-        if conditions.rtt_ms > 100.0 {
-            estimated_gbps *= 0.5; // Satellite/intercontinental
-        } else if conditions.rtt_ms > 50.0 {
-            estimated_gbps *= 0.7; // WAN
-        } else if conditions.rtt_ms > 20.0 {
-            estimated_gbps *= 0.9; // Metro
-        }
-
-        // Penalize for packet loss
-        if conditions.packet_loss > 5.0 {
-            estimated_gbps *= 0.3;
-        } else if conditions.packet_loss > 2.0 {
-            estimated_gbps *= 0.5;
-        } else if conditions.packet_loss > 0.5 {
-            estimated_gbps *= 0.8;
-        }
-
-        // Penalize for high jitter
-        if conditions.jitter_ms > 20.0 {
-            estimated_gbps *= 0.7;
+        let mut history = self.history.write();
+        if history.is_empty() {
+            history.push_back(self.conditions.read().clone());
         }
-
-        NetworkTier::from_gbps(estimated_gbps)
+        self.policy.read().detect_tier(history.make_contiguous())
     }
 
     /// Check if adaptation should trigger based on hysteresis
@@ -288,10 +280,10 @@ impl AdaptiveConnection {
         }
 
         // Update tier
-        {
+        let previous_tier = {
             let mut current = self.current_tier.write();
-            *current = detected_tier.clone();
-        }
+            std::mem::replace(&mut *current, detected_tier.clone())
+        };
 
         // Apply tier-specific parameters
         self.apply_tier_parameters(&detected_tier)?;
@@ -306,127 +298,47 @@ impl AdaptiveConnection {
             self.adaptation_count.load(Ordering::Relaxed)
         );
 
+        if let Some((connection_id, events)) = &self.events {
+            events.emit(ConnectionEvent::TierChanged {
+                connection_id: connection_id.clone(),
+                from: previous_tier,
+                to: detected_tier,
+            });
+        }
+
         Ok(())
     }
 
     /// Apply tier-specific parameters to the connection
     fn apply_tier_parameters(&self, tier: &NetworkTier) -> Result<(), anyhow::Error> {
         let mut params = self.parameters.write();
+        let previous = params.clone();
 
-        match tier {
-            NetworkTier::Slow { mbps } => {
-                // Conservative parameters for slow networks
-                params.stream_window = 256 * 1024; // 256KB
-                params.connection_window = 512 * 1024; // 512KB
-                params.max_streams = 10;
-                params.max_datagram_size = 1200;
-                params.keep_alive_interval = Some(Duration::from_secs(60));
-                params.idle_timeout = Duration::from_secs(300);
-                params.congestion_control = CongestionControl::NewReno;
-                params.send_buffer_size = 128 * 1024;
-                params.receive_buffer_size = 128 * 1024;
-
-                debug!("Applied slow network parameters ({}Mbps)", mbps);
-            }
-            NetworkTier::Home { mbps } => {
-                // Home broadband parameters
-                params.stream_window = 2 * 1024 * 1024; // 2MB
-                params.connection_window = 4 * 1024 * 1024; // 4MB
-                params.max_streams = 50;
-                params.max_datagram_size = 1500;
-                params.keep_alive_interval = Some(Duration::from_secs(45));
-                params.idle_timeout = Duration::from_secs(180);
-                params.congestion_control = CongestionControl::Cubic;
-                params.send_buffer_size = 1024 * 1024;
-                params.receive_buffer_size = 1024 * 1024;
-
-                debug!("Applied home network parameters ({}Mbps)", mbps);
-            }
-            NetworkTier::Standard { gbps } => {
-                // Standard gigabit parameters
-                params.stream_window = 8 * 1024 * 1024; // 8MB
-                params.connection_window = 16 * 1024 * 1024; // 16MB
-                params.max_streams = 100;
-                params.max_datagram_size = 9000; // Jumbo frames
-                params.keep_alive_interval = Some(Duration::from_secs(30));
-                params.idle_timeout = Duration::from_secs(120);
-                params.congestion_control = CongestionControl::Bbr2;
-                params.send_buffer_size = 4 * 1024 * 1024;
-                params.receive_buffer_size = 4 * 1024 * 1024;
-
-                debug!("Applied standard gigabit parameters ({}Gbps)", gbps);
-            }
-            NetworkTier::Performance { gbps } => {
-                // Performance network parameters
-                params.stream_window = 16 * 1024 * 1024; // 16MB
-                params.connection_window = 32 * 1024 * 1024; // 32MB
-                params.max_streams = 200;
-                params.max_datagram_size = 9000;
-                params.keep_alive_interval = Some(Duration::from_secs(20));
-                params.idle_timeout = Duration::from_secs(90);
-                params.congestion_control = CongestionControl::Bbr2;
-                params.send_buffer_size = 8 * 1024 * 1024;
-                params.receive_buffer_size = 8 * 1024 * 1024;
-
-                debug!("Applied performance network parameters ({}Gbps)", gbps);
-            }
-            NetworkTier::Enterprise { gbps } | NetworkTier::DataCenter { gbps } => {
-                // Maximum performance parameters
-                params.stream_window = 32 * 1024 * 1024; // 32MB
-                params.connection_window = 64 * 1024 * 1024; // 64MB
-                params.max_streams = 1000;
-                params.max_datagram_size = 9000;
-                params.keep_alive_interval = Some(Duration::from_secs(10));
-                params.idle_timeout = Duration::from_secs(60);
-                params.congestion_control = CongestionControl::Bbr2;
-                params.send_buffer_size = 16 * 1024 * 1024;
-                params.receive_buffer_size = 16 * 1024 * 1024;
-
-                debug!("Applied data center parameters ({}Gbps)", gbps);
-            }
-        }
+        *params = self.policy.read().parameters(tier, self.history.write().make_contiguous());
+        debug!("Applied {} tier parameters", tier.name());
 
         // Apply parameters to the actual connection
-        self.apply_to_connection(&params)?;
+        let report = self.apply_to_connection(&previous, &params);
+        debug!(
+            "Applied {:?} to live connection; reconnect needed for {:?}",
+            report.applied, report.requires_reconnect
+        );
 
         Ok(())
     }
 
     /// Apply parameters to the underlying QUIC connection
-    fn apply_to_connection(&self, params: &ConnectionParameters) -> Result<(), anyhow::Error> {
-        // Create new transport config with updated parameters
-        let mut transport_config = TransportConfig::default();
-
-        // Set flow control windows
-        transport_config.stream_receive_window(VarInt::from_u64(params.stream_window)?);
-        transport_config.receive_window(VarInt::from_u64(params.connection_window)?);
-
-        // Set stream limits
-        transport_config.max_concurrent_bidi_streams(VarInt::from_u32(params.max_streams));
-        transport_config.max_concurrent_uni_streams(VarInt::from_u32(params.max_streams / 2));
-
-        // Set datagram size - Quinn uses initial_mtu for this
-        transport_config.initial_mtu(params.max_datagram_size);
-
-        // Set timeouts
-        transport_config.max_idle_timeout(Some(params.idle_timeout.try_into()?));
-        if let Some(keep_alive) = params.keep_alive_interval {
-            transport_config.keep_alive_interval(Some(keep_alive));
+    ///
+    /// Stream limits and flow-control windows change in place; the remaining
+    /// parameters (including congestion control) are fixed at connection
+    /// setup and are only reported.
+    fn apply_to_connection(&self, previous: &ConnectionParameters, params: &ConnectionParameters) -> ReconfigureReport {
+        let mut report = ReconfigureReport::diff_parameters(previous, params);
+        if !report.applied.is_empty() {
+            reconfigure::apply_live_parameters(&self.connection, params);
+            report.connections_updated = 1;
         }
-
-        // Apply congestion control (this would need quinn support for dynamic changes)
-        // For now, we can only log the intended change
-        debug!("Would apply congestion control: {:?}", params.congestion_control);
-
-        // Apply the transport config to the connection
-        // Note: Quinn doesn't directly support updating transport config on live connections
-        // We'll need to implement this at the QUIC protocol level or use connection migration
-
-        // For now, we update what we can through the connection API
-        self.connection.set_max_concurrent_bi_streams(VarInt::from_u32(params.max_streams));
-        self.connection.set_max_concurrent_uni_streams(VarInt::from_u32(params.max_streams / 2));
-
-        Ok(())
+        report
     }
 
     /// Enable or disable adaptation
@@ -494,6 +406,8 @@ pub struct AdaptationManager {
     enabled: AtomicBool,
     /// Adaptation interval
     adaptation_interval: Duration,
+    /// Policy given to newly registered connections
+    policy: RwLock<Arc<dyn AdaptationPolicy>>,
 }
 
 impl AdaptationManager {
@@ -503,15 +417,40 @@ impl AdaptationManager {
             connections: Arc::new(dashmap::DashMap::new()),
             enabled: AtomicBool::new(true),
             adaptation_interval,
+            policy: RwLock::new(Arc::new(TieredPolicy::default())),
+        }
+    }
+
+    /// Use the given adaptation policy for connections
+    pub fn with_policy(self, policy: Arc<dyn AdaptationPolicy>) -> Self {
+        *self.policy.write() = policy;
+        self
+    }
+
+    /// Current adaptation policy
+    pub fn policy(&self) -> Arc<dyn AdaptationPolicy> {
+        self.policy.read().clone()
+    }
+
+    /// Switch all registered and future connections to a new policy
+    pub fn set_policy(&self, policy: Arc<dyn AdaptationPolicy>) {
+        for entry in self.connections.iter() {
+            entry.value().set_policy(policy.clone());
         }
+        *self.policy.write() = policy;
     }
 
     /// Register a connection for adaptive optimization
     pub fn register_connection(&self, id: String, connection: Arc<QuinnConnection>) {
-        let adaptive = Arc::new(AdaptiveConnection::new(connection));
+        let adaptive = Arc::new(AdaptiveConnection::new(connection).with_policy(self.policy()));
         self.connections.insert(id, adaptive);
     }
 
+    /// Register an existing adaptive connection wrapper
+    pub fn register_adaptive_connection(&self, id: String, connection: Arc<AdaptiveConnection>) {
+        self.connections.insert(id, connection);
+    }
+
     /// Unregister a connection
     pub fn unregister_connection(&self, id: &str) {
         self.connections.remove(id);
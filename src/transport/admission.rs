@@ -0,0 +1,559 @@
+//! Connection admission control
+//!
+//! Decides what to do with an incoming QUIC connection attempt before the
+//! handshake runs: accept it, refuse it, demand address validation with a
+//! Retry packet, or silently drop it. Checks are ordered cheapest first so
+//! unwanted peers cost as little as possible.
+//!
+//! Accepting an attempt reserves its place under the global and per-/64
+//! caps right away, so a burst of attempts that arrives together is capped
+//! even though their handshakes run concurrently and complete later.
+
+use std::net::{Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use dashmap::DashMap;
+use parking_lot::{Mutex, RwLock};
+use serde::{Serialize, Deserialize};
+use tracing::{debug, trace};
+
+use crate::error::StoqError;
+
+/// Upper bound on tracked rate-limit buckets before idle ones are pruned
+const MAX_TRACKED_PREFIXES: usize = 65_536;
+
+/// Buckets untouched for this long are pruned once the table is full
+const BUCKET_IDLE_EXPIRY: Duration = Duration::from_secs(60);
+
+/// IPv6 network prefix, e.g. `2001:db8::/32`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ipv6Prefix {
+    /// Network address
+    pub address: Ipv6Addr,
+    /// Prefix length in bits (0-128)
+    pub len: u8,
+}
+
+impl Ipv6Prefix {
+    /// Create a prefix, masking off host bits
+    pub fn new(address: Ipv6Addr, len: u8) -> Self {
+        let len = len.min(128);
+        Self {
+            address: Ipv6Addr::from(u128::from(address) & Self::mask(len)),
+            len,
+        }
+    }
+
+    /// Check whether an address falls inside this prefix
+    pub fn contains(&self, address: &Ipv6Addr) -> bool {
+        u128::from(*address) & Self::mask(self.len) == u128::from(self.address)
+    }
+
+    fn mask(len: u8) -> u128 {
+        if len == 0 {
+            0
+        } else {
+            u128::MAX << (128 - u32::from(len))
+        }
+    }
+}
+
+impl FromStr for Ipv6Prefix {
+    type Err = StoqError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, len),
+            None => (s, "128"),
+        };
+        let address = addr.parse::<Ipv6Addr>()
+            .map_err(|e| StoqError::Config(format!("invalid IPv6 prefix {}: {}", s, e)))?;
+        let len = len.parse::<u8>()
+            .ok()
+            .filter(|len| *len <= 128)
+            .ok_or_else(|| StoqError::Config(format!("invalid prefix length in {}", s)))?;
+        Ok(Self::new(address, len))
+    }
+}
+
+/// Admission control configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdmissionConfig {
+    /// Maximum concurrent inbound connections per source /64 (None = unlimited)
+    pub max_connections_per_prefix: Option<u32>,
+    /// New connection attempts per second allowed from one source prefix (None = unlimited)
+    pub prefix_rate_limit: Option<f64>,
+    /// Burst size for the per-prefix rate limit
+    pub prefix_rate_burst: u32,
+    /// Prefix length used to group sources for rate limiting
+    pub rate_limit_prefix_len: u8,
+    /// Inbound connection count above which unvalidated clients must complete
+    /// a Retry round trip (None = never)
+    pub retry_threshold: Option<u32>,
+    /// If non-empty, only sources inside these prefixes are admitted
+    pub allow: Vec<Ipv6Prefix>,
+    /// Sources inside these prefixes are always refused
+    pub deny: Vec<Ipv6Prefix>,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            max_connections_per_prefix: None,
+            prefix_rate_limit: None,
+            prefix_rate_burst: 10,
+            rate_limit_prefix_len: 64,
+            retry_threshold: None,
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+/// What to do with an incoming connection attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdmissionDecision {
+    /// Complete the handshake
+    Accept,
+    /// Reject with CONNECTION_REFUSED
+    Refuse,
+    /// Send a Retry packet to validate the source address first
+    Retry,
+    /// Drop silently without sending anything
+    Ignore,
+}
+
+/// Information about a connection attempt, passed to admission filters
+#[derive(Debug, Clone)]
+pub struct IncomingInfo {
+    /// Source address of the attempt
+    pub remote: SocketAddr,
+    /// Whether the source address was validated by a Retry token
+    pub address_validated: bool,
+    /// Inbound connections currently admitted, including handshakes in progress
+    pub active_connections: usize,
+}
+
+/// User callback consulted before the built-in limits
+///
+/// Returning [`AdmissionDecision::Accept`] lets the built-in checks decide.
+pub type AdmissionFilter = Arc<dyn Fn(&IncomingInfo) -> AdmissionDecision + Send + Sync>;
+
+/// Admission counters
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AdmissionStats {
+    /// Attempts allowed to complete the handshake
+    pub accepted: u64,
+    /// Attempts refused with CONNECTION_REFUSED
+    pub refused: u64,
+    /// Attempts sent a Retry packet
+    pub retried: u64,
+    /// Attempts dropped silently
+    pub ignored: u64,
+    /// Inbound connections currently admitted, including handshakes in progress
+    pub active: usize,
+    /// Admitted attempts whose handshake has not completed yet
+    pub handshaking: usize,
+}
+
+/// Token bucket for per-prefix rate limiting
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Limits in force, swapped as a whole on reconfiguration
+struct Limits {
+    config: AdmissionConfig,
+    max_connections: Option<u32>,
+}
+
+/// Admission controller applied to every incoming connection attempt
+pub struct AdmissionController {
+    limits: RwLock<Arc<Limits>>,
+    /// Held while a decision is made and its slot reserved, so concurrent
+    /// attempts cannot all pass the caps on the same count
+    admitting: Mutex<()>,
+    active: AtomicUsize,
+    handshaking: AtomicUsize,
+    per_prefix: DashMap<u64, usize>,
+    buckets: DashMap<u128, Mutex<TokenBucket>>,
+    filter: RwLock<Option<AdmissionFilter>>,
+    accepted: AtomicU64,
+    refused: AtomicU64,
+    retried: AtomicU64,
+    ignored: AtomicU64,
+}
+
+impl AdmissionController {
+    /// Create a controller with a global inbound connection cap
+    pub fn new(config: AdmissionConfig, max_connections: Option<u32>) -> Self {
+        Self {
+            limits: RwLock::new(Arc::new(Limits { config, max_connections })),
+            admitting: Mutex::new(()),
+            active: AtomicUsize::new(0),
+            handshaking: AtomicUsize::new(0),
+            per_prefix: DashMap::new(),
+            buckets: DashMap::new(),
+            filter: RwLock::new(None),
+            accepted: AtomicU64::new(0),
+            refused: AtomicU64::new(0),
+            retried: AtomicU64::new(0),
+            ignored: AtomicU64::new(0),
+        }
+    }
+
+    /// Replace the admission limits, effective from the next connection attempt
+    ///
+    /// Connections already admitted stay open even if the new limits would
+    /// refuse them.
+    pub fn reconfigure(&self, config: AdmissionConfig, max_connections: Option<u32>) {
+        *self.limits.write() = Arc::new(Limits { config, max_connections });
+        // Buckets are keyed by the old prefix length and sized by the old burst
+        self.buckets.clear();
+    }
+
+    /// Install or clear the user admission filter
+    pub fn set_filter(&self, filter: Option<AdmissionFilter>) {
+        *self.filter.write() = filter;
+    }
+
+    /// Decide what to do with a connection attempt
+    ///
+    /// An accepted attempt holds a slot under the caps from this point on:
+    /// confirm it with [`Self::connection_opened`] once the handshake
+    /// completes, or give it back with [`Self::connection_failed`].
+    pub fn evaluate(&self, remote: SocketAddr, address_validated: bool) -> AdmissionDecision {
+        let admitting = self.admitting.lock();
+        let decision = self.decide(remote, address_validated);
+        if let (AdmissionDecision::Accept, SocketAddr::V6(addr)) = (decision, remote) {
+            self.active.fetch_add(1, Ordering::Relaxed);
+            self.handshaking.fetch_add(1, Ordering::Relaxed);
+            *self.per_prefix.entry(prefix64(addr.ip())).or_insert(0) += 1;
+        }
+        drop(admitting);
+
+        let counter = match decision {
+            AdmissionDecision::Accept => &self.accepted,
+            AdmissionDecision::Refuse => &self.refused,
+            AdmissionDecision::Retry => &self.retried,
+            AdmissionDecision::Ignore => &self.ignored,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if decision != AdmissionDecision::Accept {
+            debug!("Admission {:?} for {}", decision, remote);
+        }
+        decision
+    }
+
+    fn decide(&self, remote: SocketAddr, address_validated: bool) -> AdmissionDecision {
+        // IPv4 and IPv4-mapped sources never get a handshake
+        let ip = match remote {
+            SocketAddr::V6(addr) if addr.ip().to_ipv4_mapped().is_none() => *addr.ip(),
+            _ => return AdmissionDecision::Refuse,
+        };
+        let limits = self.limits.read().clone();
+        let config = &limits.config;
+
+        if config.deny.iter().any(|prefix| prefix.contains(&ip)) {
+            return AdmissionDecision::Refuse;
+        }
+        if !config.allow.is_empty() && !config.allow.iter().any(|prefix| prefix.contains(&ip)) {
+            return AdmissionDecision::Refuse;
+        }
+
+        let active = self.active.load(Ordering::Relaxed);
+        if let Some(filter) = self.filter.read().as_ref() {
+            let info = IncomingInfo { remote, address_validated, active_connections: active };
+            match filter(&info) {
+                AdmissionDecision::Accept => {}
+                other => return other,
+            }
+        }
+
+        if !self.take_rate_token(config, &ip) {
+            return AdmissionDecision::Ignore;
+        }
+
+        if let Some(max) = limits.max_connections {
+            if active >= max as usize {
+                return AdmissionDecision::Refuse;
+            }
+        }
+        if let Some(max) = config.max_connections_per_prefix {
+            let count = self.per_prefix.get(&prefix64(&ip)).map(|c| *c).unwrap_or(0);
+            if count >= max as usize {
+                return AdmissionDecision::Refuse;
+            }
+        }
+
+        if let Some(threshold) = config.retry_threshold {
+            if !address_validated && active >= threshold as usize {
+                return AdmissionDecision::Retry;
+            }
+        }
+
+        AdmissionDecision::Accept
+    }
+
+    /// [`Self::evaluate`] an attempt, holding the slot of an accepted one in
+    /// a [`Reservation`] and otherwise returning the decision to apply
+    pub(crate) fn admit(self: &Arc<Self>, remote: SocketAddr, address_validated: bool) -> std::result::Result<Reservation, AdmissionDecision> {
+        let decision = self.evaluate(remote, address_validated);
+        match remote {
+            SocketAddr::V6(addr) if decision == AdmissionDecision::Accept => Ok(Reservation {
+                controller: self.clone(),
+                address: *addr.ip(),
+                confirmed: false,
+            }),
+            _ => Err(decision),
+        }
+    }
+
+    /// Consume a rate-limit token for the source prefix
+    fn take_rate_token(&self, config: &AdmissionConfig, ip: &Ipv6Addr) -> bool {
+        let Some(rate) = config.prefix_rate_limit else {
+            return true;
+        };
+
+        if self.buckets.len() >= MAX_TRACKED_PREFIXES {
+            self.buckets.retain(|_, bucket| bucket.lock().last_refill.elapsed() < BUCKET_IDLE_EXPIRY);
+        }
+
+        let key = u128::from(Ipv6Prefix::new(*ip, config.rate_limit_prefix_len).address);
+        let burst = f64::from(config.prefix_rate_burst.max(1));
+        let bucket = self.buckets.entry(key).or_insert_with(|| {
+            Mutex::new(TokenBucket { tokens: burst, last_refill: Instant::now() })
+        });
+        let mut bucket = bucket.lock();
+
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            trace!("Rate limit exceeded for prefix of {}", ip);
+            false
+        }
+    }
+
+    /// Record that an accepted attempt completed its handshake, keeping its slot
+    pub fn connection_opened(&self) {
+        self.handshaking.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Record that an accepted attempt's handshake failed, freeing its slot
+    pub fn connection_failed(&self, ip: &Ipv6Addr) {
+        self.handshaking.fetch_sub(1, Ordering::Relaxed);
+        self.release(ip);
+    }
+
+    /// Record that an admitted connection has closed
+    pub fn connection_closed(&self, ip: &Ipv6Addr) {
+        self.release(ip);
+    }
+
+    fn release(&self, ip: &Ipv6Addr) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        let key = prefix64(ip);
+        if let Some(mut count) = self.per_prefix.get_mut(&key) {
+            *count = count.saturating_sub(1);
+        }
+        self.per_prefix.remove_if(&key, |_, count| *count == 0);
+    }
+
+    /// Get admission counters
+    pub fn stats(&self) -> AdmissionStats {
+        AdmissionStats {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            refused: self.refused.load(Ordering::Relaxed),
+            retried: self.retried.load(Ordering::Relaxed),
+            ignored: self.ignored.load(Ordering::Relaxed),
+            active: self.active.load(Ordering::Relaxed),
+            handshaking: self.handshaking.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The slot [`AdmissionController::evaluate`] reserved for an accepted attempt
+///
+/// Dropped without [`Self::confirm`], it frees the slot, so handshakes that
+/// fail, end up on IPv4 or are abandoned with their task stop counting
+/// against the caps.
+pub(crate) struct Reservation {
+    controller: Arc<AdmissionController>,
+    address: Ipv6Addr,
+    confirmed: bool,
+}
+
+impl Reservation {
+    /// Keep the slot for the established connection
+    pub(crate) fn confirm(mut self) {
+        self.confirmed = true;
+        self.controller.connection_opened();
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.confirmed {
+            self.controller.connection_failed(&self.address);
+        }
+    }
+}
+
+/// Upper 64 bits of an address, identifying its /64
+fn prefix64(ip: &Ipv6Addr) -> u64 {
+    (u128::from(*ip) >> 64) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        SocketAddr::new(s.parse().unwrap(), 4433)
+    }
+
+    #[test]
+    fn test_prefix_parsing_and_matching() {
+        let prefix: Ipv6Prefix = "2001:db8:1::/48".parse().unwrap();
+        assert!(prefix.contains(&"2001:db8:1:2::5".parse().unwrap()));
+        assert!(!prefix.contains(&"2001:db8:2::1".parse().unwrap()));
+        assert!("2001:db8::/129".parse::<Ipv6Prefix>().is_err());
+    }
+
+    #[test]
+    fn test_ipv4_refused_before_handshake() {
+        let controller = AdmissionController::new(AdmissionConfig::default(), None);
+        assert_eq!(controller.evaluate(addr("127.0.0.1"), false), AdmissionDecision::Refuse);
+        assert_eq!(controller.evaluate(addr("::ffff:10.0.0.1"), false), AdmissionDecision::Refuse);
+        assert_eq!(controller.evaluate(addr("::1"), false), AdmissionDecision::Accept);
+    }
+
+    #[test]
+    fn test_allow_and_deny_lists() {
+        let config = AdmissionConfig {
+            allow: vec!["2001:db8::/32".parse().unwrap()],
+            deny: vec!["2001:db8:bad::/48".parse().unwrap()],
+            ..AdmissionConfig::default()
+        };
+        let controller = AdmissionController::new(config, None);
+        assert_eq!(controller.evaluate(addr("2001:db8:1::1"), false), AdmissionDecision::Accept);
+        assert_eq!(controller.evaluate(addr("2001:db8:bad::1"), false), AdmissionDecision::Refuse);
+        assert_eq!(controller.evaluate(addr("2001:db9::1"), false), AdmissionDecision::Refuse);
+    }
+
+    #[test]
+    fn test_global_and_prefix_caps() {
+        let config = AdmissionConfig {
+            max_connections_per_prefix: Some(1),
+            ..AdmissionConfig::default()
+        };
+        let controller = AdmissionController::new(config, Some(2));
+        let a: Ipv6Addr = "2001:db8:0:1::1".parse().unwrap();
+        let b: Ipv6Addr = "2001:db8:0:2::1".parse().unwrap();
+
+        // Slots are taken when the attempt is accepted, before its handshake completes
+        assert_eq!(controller.evaluate(addr("2001:db8:0:1::1"), false), AdmissionDecision::Accept);
+        assert_eq!(controller.evaluate(addr("2001:db8:0:1::2"), false), AdmissionDecision::Refuse);
+        assert_eq!(controller.evaluate(addr("2001:db8:0:2::1"), false), AdmissionDecision::Accept);
+        assert_eq!(controller.evaluate(addr("2001:db8:0:3::1"), false), AdmissionDecision::Refuse);
+        controller.connection_opened();
+        let stats = controller.stats();
+        assert_eq!((stats.active, stats.handshaking), (2, 1));
+
+        // A failed handshake frees its slot, a closed connection too
+        controller.connection_failed(&b);
+        assert_eq!(controller.evaluate(addr("2001:db8:0:2::2"), false), AdmissionDecision::Accept);
+        controller.connection_closed(&a);
+        assert_eq!(controller.evaluate(addr("2001:db8:0:1::2"), false), AdmissionDecision::Accept);
+        let stats = controller.stats();
+        assert_eq!((stats.active, stats.handshaking), (2, 2));
+    }
+
+    #[test]
+    fn test_reservation_is_freed_unless_confirmed() {
+        let config = AdmissionConfig {
+            max_connections_per_prefix: Some(1),
+            ..AdmissionConfig::default()
+        };
+        let controller = Arc::new(AdmissionController::new(config, None));
+
+        let reservation = controller.admit(addr("2001:db8::1"), false).ok().unwrap();
+        assert_eq!(controller.admit(addr("2001:db8::2"), false).err(), Some(AdmissionDecision::Refuse));
+        drop(reservation);
+        assert_eq!(controller.stats().active, 0);
+
+        controller.admit(addr("2001:db8::2"), false).ok().unwrap().confirm();
+        assert_eq!(controller.admit(addr("2001:db8::3"), false).err(), Some(AdmissionDecision::Refuse));
+        let stats = controller.stats();
+        assert_eq!((stats.active, stats.handshaking), (1, 0));
+
+        assert_eq!(controller.admit(addr("127.0.0.1"), false).err(), Some(AdmissionDecision::Refuse));
+    }
+
+    #[test]
+    fn test_reconfigure_applies_to_next_attempt() {
+        let controller = AdmissionController::new(AdmissionConfig::default(), None);
+        assert_eq!(controller.evaluate(addr("2001:db8:0:1::2"), false), AdmissionDecision::Accept);
+
+        controller.reconfigure(AdmissionConfig {
+            deny: vec!["2001:db8:0:2::/64".parse().unwrap()],
+            ..AdmissionConfig::default()
+        }, Some(1));
+        assert_eq!(controller.evaluate(addr("2001:db8:0:3::1"), false), AdmissionDecision::Refuse);
+        assert_eq!(controller.stats().active, 1);
+
+        controller.reconfigure(AdmissionConfig::default(), Some(3));
+        assert_eq!(controller.evaluate(addr("2001:db8:0:3::1"), false), AdmissionDecision::Accept);
+        assert_eq!(controller.evaluate(addr("2001:db8:0:2::1"), false), AdmissionDecision::Accept);
+    }
+
+    #[test]
+    fn test_prefix_rate_limit() {
+        let config = AdmissionConfig {
+            prefix_rate_limit: Some(0.001),
+            prefix_rate_burst: 2,
+            ..AdmissionConfig::default()
+        };
+        let controller = AdmissionController::new(config, None);
+        assert_eq!(controller.evaluate(addr("2001:db8::1"), false), AdmissionDecision::Accept);
+        assert_eq!(controller.evaluate(addr("2001:db8::2"), false), AdmissionDecision::Accept);
+        assert_eq!(controller.evaluate(addr("2001:db8::3"), false), AdmissionDecision::Ignore);
+        assert_eq!(controller.evaluate(addr("2001:db9::1"), false), AdmissionDecision::Accept);
+        assert_eq!(controller.stats().ignored, 1);
+    }
+
+    #[test]
+    fn test_retry_under_load() {
+        let config = AdmissionConfig {
+            retry_threshold: Some(1),
+            ..AdmissionConfig::default()
+        };
+        let controller = AdmissionController::new(config, None);
+        assert_eq!(controller.evaluate(addr("::1"), false), AdmissionDecision::Accept);
+        assert_eq!(controller.evaluate(addr("::1"), false), AdmissionDecision::Retry);
+        assert_eq!(controller.evaluate(addr("::1"), true), AdmissionDecision::Accept);
+    }
+
+    #[test]
+    fn test_user_filter() {
+        let controller = AdmissionController::new(AdmissionConfig::default(), None);
+        controller.set_filter(Some(Arc::new(|info: &IncomingInfo| {
+            if info.remote.port() == 4433 {
+                AdmissionDecision::Ignore
+            } else {
+                AdmissionDecision::Accept
+            }
+        })));
+        assert_eq!(controller.evaluate(addr("::1"), false), AdmissionDecision::Ignore);
+        assert_eq!(controller.evaluate("[::1]:5000".parse().unwrap(), false), AdmissionDecision::Accept);
+    }
+}
@@ -23,6 +23,9 @@ use sha2::{Sha256, Digest};
 use rsa::{RsaPrivateKey, pkcs8::EncodePrivateKey};
 use rand::rngs::OsRng;
 
+use crate::error::StoqError;
+use super::events::{ConnectionEvent, ConnectionEvents};
+
 /// Certificate manager configuration
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CertificateConfig {
@@ -125,6 +128,8 @@ pub struct CertificateManager {
     certificate_cache: Arc<DashMap<String, StoqNodeCertificate>>,
     /// TrustChain client (for production mode)
     trustchain_client: Option<Arc<TrustChainClient>>,
+    /// Lifecycle event channel notified on rotation
+    events: Option<ConnectionEvents>,
 }
 
 /// TrustChain client for certificate operations
@@ -212,7 +217,8 @@ impl TrustChainClient {
         send_stream.finish()?;
 
         // Read response
-        let response = recv_stream.read_to_end(64 * 1024).await?; // 64KB max
+        let response = recv_stream.read_to_end(64 * 1024).await // 64KB max
+            .map_err(|e| StoqError::from_read_to_end(e, 64 * 1024))?;
         let response_str = String::from_utf8(response)?;
 
         // Parse HTTP response
@@ -331,7 +337,8 @@ impl TrustChainClient {
         send_stream.finish()?;
 
         // Read response
-        let response = recv_stream.read_to_end(64 * 1024).await?;
+        let response = recv_stream.read_to_end(64 * 1024).await
+            .map_err(|e| StoqError::from_read_to_end(e, 64 * 1024))?;
         let response_str = String::from_utf8(response)?;
 
         // Parse HTTP response
@@ -427,7 +434,8 @@ impl TrustChainClient {
             Duration::from_secs(5),
             recv_stream.read_to_end(16 * 1024) // Limit to 16KB
         ).await
-        .map_err(|_| anyhow!("TrustChain response timeout"))??;
+        .map_err(|_| anyhow!("TrustChain response timeout"))?
+        .map_err(|e| StoqError::from_read_to_end(e, 16 * 1024))?;
 
         let response_str = String::from_utf8(response)?;
 
@@ -567,7 +575,7 @@ impl TrustChainClient {
 
 impl CertificateManager {
     /// Create new certificate manager
-    pub async fn new(config: CertificateConfig) -> Result<Self> {
+    pub async fn new(config: CertificateConfig) -> crate::error::Result<Self> {
         info!("Initializing STOQ certificate manager: {:?}", config.mode);
 
         let trustchain_client = match &config.mode {
@@ -578,7 +586,7 @@ impl CertificateManager {
                         config.node_id.clone(),
                     )))
                 } else {
-                    return Err(anyhow!("TrustChain endpoint required for production mode"));
+                    return Err(StoqError::Config("TrustChain endpoint required for production mode".to_string()));
                 }
             }
             CertificateMode::LocalhostTesting => None,
@@ -589,19 +597,27 @@ impl CertificateManager {
             current_certificate: Arc::new(RwLock::new(None)),
             certificate_cache: Arc::new(DashMap::new()),
             trustchain_client,
+            events: None,
         };
 
         // Initialize certificate
-        manager.initialize_certificate().await?;
+        manager.initialize_certificate().await.map_err(certificate_error)?;
 
         info!("STOQ certificate manager initialized successfully");
         Ok(manager)
     }
 
+    /// Emit `CertificateRotated` events on the given channel
+    pub fn with_events(mut self, events: ConnectionEvents) -> Self {
+        self.events = Some(events);
+        self
+    }
+
     /// Get server crypto configuration for QUIC
-    pub async fn server_crypto_config(&self) -> Result<rustls::ServerConfig> {
+    pub async fn server_crypto_config(&self) -> crate::error::Result<rustls::ServerConfig> {
         let cert_guard = self.current_certificate.read().await;
-        let cert = cert_guard.as_ref().ok_or_else(|| anyhow!("No certificate available"))?;
+        let cert = cert_guard.as_ref()
+            .ok_or_else(|| StoqError::Certificate("No certificate available".to_string()))?;
 
         let server_config = rustls::ServerConfig::builder()
             .with_no_client_auth()
@@ -615,7 +631,7 @@ impl CertificateManager {
     }
 
     /// Get client crypto configuration for QUIC
-    pub async fn client_crypto_config(&self) -> Result<rustls::ClientConfig> {
+    pub async fn client_crypto_config(&self) -> crate::error::Result<rustls::ClientConfig> {
         match self.config.mode {
             CertificateMode::LocalhostTesting => {
                 // For localhost testing, accept self-signed certificates
@@ -640,7 +656,7 @@ impl CertificateManager {
     }
 
     /// Validate certificate chain
-    pub async fn validate_certificate_chain(&self, cert_der: &[u8]) -> Result<bool> {
+    pub async fn validate_certificate_chain(&self, cert_der: &[u8]) -> crate::error::Result<bool> {
         let fingerprint = self.calculate_fingerprint(cert_der);
         let fingerprint_hex = hex::encode(fingerprint);
 
@@ -666,23 +682,24 @@ impl CertificateManager {
                 // For production, validate with TrustChain
                 if let Some(client) = &self.trustchain_client {
                     debug!("Certificate validation: TrustChain production mode");
-                    client.validate_certificate(cert_der).await
+                    client.validate_certificate(cert_der).await.map_err(certificate_error)
                 } else {
-                    Err(anyhow!("TrustChain client not available"))
+                    Err(StoqError::Unavailable("TrustChain client"))
                 }
             }
         }
     }
 
     /// Get current certificate fingerprint
-    pub async fn get_certificate_fingerprint(&self) -> Result<String> {
+    pub async fn get_certificate_fingerprint(&self) -> crate::error::Result<String> {
         let cert_guard = self.current_certificate.read().await;
-        let cert = cert_guard.as_ref().ok_or_else(|| anyhow!("No certificate available"))?;
+        let cert = cert_guard.as_ref()
+            .ok_or_else(|| StoqError::Certificate("No certificate available".to_string()))?;
         Ok(cert.fingerprint())
     }
 
     /// Check if certificate needs renewal and rotate if necessary
-    pub async fn check_and_rotate_certificate(&self) -> Result<bool> {
+    pub async fn check_and_rotate_certificate(&self) -> crate::error::Result<bool> {
         let needs_rotation = {
             let cert_guard = self.current_certificate.read().await;
             if let Some(cert) = cert_guard.as_ref() {
@@ -694,7 +711,7 @@ impl CertificateManager {
 
         if needs_rotation {
             info!("Certificate needs rotation, requesting new certificate");
-            self.rotate_certificate().await?;
+            self.rotate_certificate().await.map_err(certificate_error)?;
             Ok(true)
         } else {
             Ok(false)
@@ -795,6 +812,12 @@ impl CertificateManager {
         // Request new certificate (same as initialization)
         self.initialize_certificate().await?;
 
+        if let Some(events) = &self.events {
+            if let Some(cert) = self.current_certificate.read().await.as_ref() {
+                events.emit(ConnectionEvent::CertificateRotated { fingerprint: cert.fingerprint() });
+            }
+        }
+
         info!("Certificate rotation completed successfully");
         Ok(())
     }
@@ -807,6 +830,11 @@ impl CertificateManager {
     }
 }
 
+/// Convert an internal certificate failure into the public error type
+fn certificate_error(e: anyhow::Error) -> StoqError {
+    StoqError::Certificate(format!("{:#}", e))
+}
+
 /// Certificate verifier that accepts all certificates (for localhost testing only)
 #[derive(Debug)]
 struct AcceptAllVerifier;
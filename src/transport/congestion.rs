@@ -0,0 +1,112 @@
+//! Congestion controller selection
+//!
+//! Maps [`CongestionControl`] onto quinn's controller factories. The
+//! controller is chosen when a connection is established and stays fixed for
+//! its lifetime, so changing the algorithm (e.g. on a network tier change)
+//! only affects connections opened afterwards.
+
+use std::fmt;
+use std::sync::Arc;
+use quinn::congestion::{BbrConfig, ControllerFactory, CubicConfig, NewRenoConfig};
+use serde::{Serialize, Deserialize};
+
+use super::CongestionControl;
+
+/// Congestion controller tunables
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct CongestionConfig {
+    /// Initial congestion window in bytes (None = controller default)
+    pub initial_window: Option<u64>,
+    /// Custom controller used instead of the configured algorithm
+    #[serde(skip)]
+    pub factory: Option<Arc<dyn ControllerFactory + Send + Sync>>,
+}
+
+impl CongestionConfig {
+    /// Set the initial congestion window in bytes
+    pub fn with_initial_window(mut self, bytes: u64) -> Self {
+        self.initial_window = Some(bytes);
+        self
+    }
+
+    /// Use a custom controller factory, overriding the configured algorithm
+    pub fn with_factory(mut self, factory: Arc<dyn ControllerFactory + Send + Sync>) -> Self {
+        self.factory = Some(factory);
+        self
+    }
+
+    /// Controller factory for `algorithm` with these tunables applied
+    pub fn controller_factory(&self, algorithm: &CongestionControl) -> Arc<dyn ControllerFactory + Send + Sync> {
+        if let Some(factory) = &self.factory {
+            return factory.clone();
+        }
+
+        match algorithm {
+            CongestionControl::Bbr2 => {
+                let mut config = BbrConfig::default();
+                if let Some(window) = self.initial_window {
+                    config.initial_window(window);
+                }
+                Arc::new(config)
+            }
+            CongestionControl::Cubic => {
+                let mut config = CubicConfig::default();
+                if let Some(window) = self.initial_window {
+                    config.initial_window(window);
+                }
+                Arc::new(config)
+            }
+            CongestionControl::NewReno => {
+                let mut config = NewRenoConfig::default();
+                if let Some(window) = self.initial_window {
+                    config.initial_window(window);
+                }
+                Arc::new(config)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for CongestionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CongestionConfig")
+            .field("initial_window", &self.initial_window)
+            .field("factory", &self.factory.as_ref().map(|_| "custom"))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::Any;
+    use std::time::Instant;
+    use quinn::congestion::{Bbr, Cubic, NewReno};
+
+    fn build(config: &CongestionConfig, algorithm: CongestionControl) -> Box<dyn Any> {
+        config.controller_factory(&algorithm).build(Instant::now(), 1200).into_any()
+    }
+
+    #[test]
+    fn test_algorithm_selects_controller() {
+        let config = CongestionConfig::default();
+        assert!(build(&config, CongestionControl::Bbr2).is::<Bbr>());
+        assert!(build(&config, CongestionControl::Cubic).is::<Cubic>());
+        assert!(build(&config, CongestionControl::NewReno).is::<NewReno>());
+    }
+
+    #[test]
+    fn test_initial_window_applied() {
+        let config = CongestionConfig::default().with_initial_window(64 * 1024);
+        for algorithm in [CongestionControl::Bbr2, CongestionControl::Cubic, CongestionControl::NewReno] {
+            let controller = config.controller_factory(&algorithm).build(Instant::now(), 1200);
+            assert_eq!(controller.initial_window(), 64 * 1024, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_custom_factory_overrides_algorithm() {
+        let config = CongestionConfig::default().with_factory(Arc::new(NewRenoConfig::default()));
+        assert!(build(&config, CongestionControl::Bbr2).is::<NewReno>());
+    }
+}
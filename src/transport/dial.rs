@@ -0,0 +1,270 @@
+//! Multi-address dialing
+//!
+//! Peers advertise several IPv6 addresses (global, ULA, link-local). Dialing
+//! races the candidates Happy Eyeballs style (RFC 8305): attempts start one
+//! after another with a fixed stagger, a failed attempt starts the next one
+//! immediately, and the first completed handshake wins while the remaining
+//! attempts are cancelled. Winning addresses are remembered per peer and
+//! tried first on the next dial.
+
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::time::Duration;
+use dashmap::DashMap;
+use serde::{Serialize, Deserialize};
+use tokio::task::JoinSet;
+use tracing::{debug, info_span, warn, Instrument};
+
+use super::Endpoint;
+use crate::error::{Result, StoqError};
+
+/// Multi-address dialing configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DialConfig {
+    /// Delay before starting the next candidate while earlier ones are pending
+    pub attempt_delay: Duration,
+}
+
+impl Default for DialConfig {
+    fn default() -> Self {
+        Self {
+            attempt_delay: Duration::from_millis(250), // RFC 8305 recommended default
+        }
+    }
+}
+
+/// Remote peer reachable at several IPv6 addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiEndpoint {
+    /// Candidate addresses in preference order; link-local candidates carry
+    /// their interface index as the scope id
+    pub candidates: Vec<SocketAddrV6>,
+    /// Optional server name for SNI
+    pub server_name: Option<String>,
+}
+
+impl MultiEndpoint {
+    /// Create an endpoint with no candidates
+    pub fn new() -> Self {
+        Self {
+            candidates: Vec::new(),
+            server_name: None,
+        }
+    }
+
+    /// Add a candidate address
+    pub fn with_address(self, address: Ipv6Addr, port: u16) -> Self {
+        self.with_candidate(SocketAddrV6::new(address, port, 0, 0))
+    }
+
+    /// Add a scoped candidate address, e.g. link-local on interface `scope_id`
+    pub fn with_scoped_address(self, address: Ipv6Addr, port: u16, scope_id: u32) -> Self {
+        self.with_candidate(SocketAddrV6::new(address, port, 0, scope_id))
+    }
+
+    /// Add a candidate socket address, ignoring duplicates
+    pub fn with_candidate(mut self, candidate: SocketAddrV6) -> Self {
+        if !self.candidates.contains(&candidate) {
+            self.candidates.push(candidate);
+        }
+        self
+    }
+
+    /// Set server name for SNI
+    pub fn with_server_name(mut self, name: String) -> Self {
+        self.server_name = Some(name);
+        self
+    }
+
+    /// Key identifying this peer in the dial cache
+    fn cache_key(&self) -> String {
+        match &self.server_name {
+            Some(name) => name.clone(),
+            None => {
+                let mut addrs: Vec<String> = self.candidates.iter().map(ToString::to_string).collect();
+                addrs.sort();
+                addrs.join(",")
+            }
+        }
+    }
+}
+
+impl Default for MultiEndpoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Endpoint> for MultiEndpoint {
+    fn from(endpoint: Endpoint) -> Self {
+        Self {
+            candidates: vec![SocketAddrV6::new(endpoint.address, endpoint.port, 0, endpoint.scope_id)],
+            server_name: endpoint.server_name,
+        }
+    }
+}
+
+/// Check for fe80::/10 link-local addresses, which need a scope id to be dialable
+fn is_link_local(address: &Ipv6Addr) -> bool {
+    address.segments()[0] & 0xffc0 == 0xfe80
+}
+
+/// Remembers which candidate address last won for each peer
+#[derive(Debug, Default)]
+pub struct DialCache {
+    winners: DashMap<String, SocketAddrV6>,
+}
+
+impl DialCache {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Candidates in dial order: the previous winner first, then the rest in
+    /// advertised order. Link-local candidates without a scope id are dropped.
+    pub fn order(&self, endpoint: &MultiEndpoint) -> Vec<SocketAddrV6> {
+        let mut candidates: Vec<SocketAddrV6> = endpoint.candidates.iter()
+            .filter(|candidate| {
+                let dialable = !is_link_local(candidate.ip()) || candidate.scope_id() != 0;
+                if !dialable {
+                    warn!("Skipping link-local candidate {} without interface index", candidate);
+                }
+                dialable
+            })
+            .copied()
+            .collect();
+
+        if let Some(winner) = self.winners.get(&endpoint.cache_key()) {
+            if let Some(pos) = candidates.iter().position(|candidate| candidate == &*winner) {
+                let winner = candidates.remove(pos);
+                candidates.insert(0, winner);
+            }
+        }
+        candidates
+    }
+
+    /// Record the candidate that won a dial
+    pub fn record(&self, endpoint: &MultiEndpoint, winner: SocketAddrV6) {
+        self.winners.insert(endpoint.cache_key(), winner);
+    }
+
+    /// Previously winning address for a peer
+    pub fn winner(&self, endpoint: &MultiEndpoint) -> Option<SocketAddrV6> {
+        self.winners.get(&endpoint.cache_key()).map(|winner| *winner)
+    }
+}
+
+/// Race handshakes to `candidates`, returning the first to complete
+///
+/// Dropping the remaining attempts aborts their tasks, which closes the
+/// half-open connections.
+pub(crate) async fn race(
+    endpoint: &quinn::Endpoint,
+    client_config: &quinn::ClientConfig,
+    candidates: Vec<SocketAddrV6>,
+    server_name: &str,
+    attempt_delay: Duration,
+) -> Result<(quinn::Connection, SocketAddrV6)> {
+    let mut pending = candidates.into_iter().peekable();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+
+    match pending.next() {
+        Some(candidate) => spawn_attempt(&mut attempts, endpoint, client_config, candidate, server_name),
+        None => return Err(StoqError::Config("endpoint has no dialable candidate addresses".to_string())),
+    }
+
+    while !attempts.is_empty() {
+        tokio::select! {
+            Some(joined) = attempts.join_next() => {
+                match joined {
+                    Ok((candidate, Ok(connection))) => {
+                        debug!("Candidate {} won the dial race", candidate);
+                        return Ok((connection, candidate));
+                    }
+                    Ok((candidate, Err(e))) => {
+                        debug!("Candidate {} failed: {}", candidate, e);
+                        last_error = Some(e);
+                    }
+                    Err(e) => {
+                        last_error = Some(StoqError::Protocol(format!("dial attempt aborted: {}", e)));
+                    }
+                }
+                // A failure starts the next candidate without waiting
+                if let Some(candidate) = pending.next() {
+                    spawn_attempt(&mut attempts, endpoint, client_config, candidate, server_name);
+                }
+            }
+            _ = tokio::time::sleep(attempt_delay), if pending.peek().is_some() => {
+                if let Some(candidate) = pending.next() {
+                    spawn_attempt(&mut attempts, endpoint, client_config, candidate, server_name);
+                }
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| StoqError::Config("all candidate addresses failed".to_string())))
+}
+
+fn spawn_attempt(
+    attempts: &mut JoinSet<(SocketAddrV6, Result<quinn::Connection>)>,
+    endpoint: &quinn::Endpoint,
+    client_config: &quinn::ClientConfig,
+    candidate: SocketAddrV6,
+    server_name: &str,
+) {
+    debug!("Dialing candidate {}", candidate);
+    let endpoint = endpoint.clone();
+    let client_config = client_config.clone();
+    let server_name = server_name.to_string();
+    let span = info_span!("stoq.handshake", remote = %candidate);
+    attempts.spawn(async move {
+        let result = async {
+            let connecting = endpoint.connect_with(client_config, SocketAddr::V6(candidate), &server_name)?;
+            Ok(connecting.await?)
+        }.await;
+        (candidate, result)
+    }.instrument(span));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v6(s: &str) -> Ipv6Addr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_unscoped_link_local_skipped() {
+        let endpoint = MultiEndpoint::new()
+            .with_address(v6("fe80::1"), 9292)
+            .with_scoped_address(v6("fe80::2"), 9292, 3)
+            .with_address(v6("fd00::1"), 9292);
+        let order = DialCache::new().order(&endpoint);
+        assert_eq!(order.len(), 2);
+        assert_eq!(order[0].scope_id(), 3);
+        assert_eq!(*order[1].ip(), v6("fd00::1"));
+    }
+
+    #[test]
+    fn test_winner_dialed_first() {
+        let endpoint = MultiEndpoint::new()
+            .with_address(v6("2001:db8::1"), 9292)
+            .with_address(v6("fd00::1"), 9292)
+            .with_server_name("peer.example".to_string());
+        let cache = DialCache::new();
+        assert_eq!(*cache.order(&endpoint)[0].ip(), v6("2001:db8::1"));
+
+        cache.record(&endpoint, endpoint.candidates[1]);
+        let order = cache.order(&endpoint);
+        assert_eq!(order, vec![endpoint.candidates[1], endpoint.candidates[0]]);
+    }
+
+    #[test]
+    fn test_duplicate_candidates_ignored() {
+        let endpoint = MultiEndpoint::from(Endpoint::new(Ipv6Addr::LOCALHOST, 9292))
+            .with_address(Ipv6Addr::LOCALHOST, 9292);
+        assert_eq!(endpoint.candidates.len(), 1);
+    }
+}
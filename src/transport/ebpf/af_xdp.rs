@@ -1,21 +1,37 @@
-//! AF_XDP (Address Family XDP) zero-copy socket implementation
+//! AF_XDP (Address Family XDP) socket implementation
 //!
-//! Provides zero-copy packet I/O bypassing the kernel network stack
-//! for maximum performance with STOQ transport.
+//! Packet I/O through an AF_XDP socket bound to one device queue, bypassing
+//! the kernel network stack. Frames live in a UMEM area shared with the
+//! kernel and move through four single-producer rings:
+//! - fill: free frames handed to the kernel to receive into,
+//! - rx: frames the kernel received,
+//! - tx: frames to transmit,
+//! - completion: transmitted frames the kernel hands back.
+//!
+//! Traffic only reaches a socket once the interface's XDP program redirects
+//! it there, see [`XdpManager::redirect_to_socket`](super::xdp::XdpManager::redirect_to_socket).
+//! [`XdpUdpSocket`](super::udp::XdpUdpSocket) runs quinn over these sockets.
 
 use anyhow::{Result, anyhow};
 use std::sync::Arc;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use std::collections::HashMap;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::task::{Context, Poll, ready};
 use bytes::Bytes;
+use tokio::io::unix::AsyncFd;
 
-// AF_XDP would use libbpf-rs, but for now we'll use a simplified implementation
+use super::sys::{self, Mmap};
 
-/// AF_XDP socket for zero-copy packet I/O
+/// AF_XDP socket bound to one interface queue
+#[derive(Clone)]
 pub struct AfXdpSocket {
     interface: String,
     queue_id: u32,
     stats: Arc<RwLock<AfXdpStats>>,
+    xsk: Arc<Xsk>,
 }
 
 /// AF_XDP socket statistics
@@ -37,6 +53,8 @@ pub struct AfXdpManager {
     umem_config: UmemConfig,
     /// Ring configuration
     ring_config: RingConfig,
+    /// How sockets bind to their queue
+    bind_mode: XskBindMode,
 }
 
 /// UMEM (User Memory) configuration
@@ -44,7 +62,7 @@ pub struct AfXdpManager {
 pub struct UmemConfig {
     /// Number of frames in UMEM
     pub frame_count: u32,
-    /// Size of each frame
+    /// Size of each frame (power of two, 2048 up to the page size)
     pub frame_size: u32,
     /// Headroom for each frame
     pub frame_headroom: u32,
@@ -87,13 +105,43 @@ impl Default for RingConfig {
     }
 }
 
+/// How an AF_XDP socket shares frames with the driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum XskBindMode {
+    /// Zero-copy if the driver supports it, otherwise copy
+    #[default]
+    Auto,
+    /// The kernel copies frames in and out of UMEM; works on any interface
+    Copy,
+    /// The driver DMAs straight into UMEM
+    ZeroCopy,
+}
+
+impl XskBindMode {
+    /// `sockaddr_xdp` flags to try, in order
+    fn flags(self) -> &'static [u16] {
+        match self {
+            XskBindMode::Auto => &[libc::XDP_ZEROCOPY, libc::XDP_COPY],
+            XskBindMode::Copy => &[libc::XDP_COPY],
+            XskBindMode::ZeroCopy => &[libc::XDP_ZEROCOPY],
+        }
+    }
+}
+
 impl AfXdpManager {
     /// Create new AF_XDP manager
     pub fn new() -> Result<Self> {
+        Self::with_config(UmemConfig::default(), RingConfig::default(), XskBindMode::default())
+    }
+
+    /// Create a manager whose sockets use the given UMEM, rings and bind mode
+    pub fn with_config(umem_config: UmemConfig, ring_config: RingConfig, bind_mode: XskBindMode) -> Result<Self> {
+        validate_config(&umem_config, &ring_config)?;
         Ok(Self {
             sockets: Arc::new(RwLock::new(HashMap::new())),
-            umem_config: UmemConfig::default(),
-            ring_config: RingConfig::default(),
+            umem_config,
+            ring_config,
+            bind_mode,
         })
     }
 
@@ -106,30 +154,10 @@ impl AfXdpManager {
             return Err(anyhow!("Socket already exists for {}:{}", interface, queue_id));
         }
 
-        #[cfg(not(feature = "ebpf"))]
-        {
-            let socket = AfXdpSocket {
-                interface: interface.to_string(),
-                queue_id,
-                stats: Arc::new(RwLock::new(AfXdpStats::default())),
-            };
-
-            self.sockets.write().insert(socket_key, socket.clone());
-            return Ok(socket);
-        }
-
-        // Simplified AF_XDP socket creation (placeholder)
-        let af_xdp_socket = AfXdpSocket {
-            interface: interface.to_string(),
-            queue_id,
-            stats: Arc::new(RwLock::new(AfXdpStats::default())),
-        };
-
-        self.sockets.write().insert(socket_key.clone(), af_xdp_socket.clone());
-
-        tracing::info!("Created AF_XDP socket placeholder for {}:{}", interface, queue_id);
-        tracing::warn!("Note: Full AF_XDP implementation requires libbpf-rs integration");
+        let af_xdp_socket = AfXdpSocket::open(interface, queue_id, &self.umem_config, &self.ring_config, self.bind_mode)?;
+        self.sockets.write().insert(socket_key, af_xdp_socket.clone());
 
+        tracing::info!("Created AF_XDP socket for {}:{}", interface, queue_id);
         Ok(af_xdp_socket)
     }
 
@@ -159,42 +187,369 @@ impl AfXdpManager {
     }
 }
 
+fn validate_config(umem: &UmemConfig, rings: &RingConfig) -> Result<()> {
+    let page_size = 4096;
+    if !umem.frame_size.is_power_of_two() || umem.frame_size < 2048 || umem.frame_size > page_size {
+        return Err(anyhow!("UMEM frame size {} must be a power of two from 2048 to {}", umem.frame_size, page_size));
+    }
+    if umem.frame_headroom >= umem.frame_size / 2 {
+        return Err(anyhow!("UMEM headroom {} leaves too little of a {} byte frame", umem.frame_headroom, umem.frame_size));
+    }
+    for (name, size) in [("tx", rings.tx_size), ("rx", rings.rx_size), ("fill", rings.fill_size), ("completion", rings.comp_size)] {
+        if !size.is_power_of_two() {
+            return Err(anyhow!("AF_XDP {} ring size {} is not a power of two", name, size));
+        }
+    }
+    if umem.frame_count < 2 || rings.comp_size < rings.tx_size {
+        return Err(anyhow!("AF_XDP needs at least two frames and a completion ring as large as the TX ring"));
+    }
+    Ok(())
+}
+
+/// One of the socket's rings, mapped from the kernel
+///
+/// Userspace is the only producer of the fill and TX rings and the only
+/// consumer of the RX and completion rings.
+struct Ring<T> {
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    flags: *const AtomicU32,
+    entries: *mut T,
+    size: u32,
+    _map: Mmap,
+}
+
+// SAFETY: the ring memory is owned by the mapping, and each side of the
+// ring is only touched through `&mut self` under the socket's lock
+unsafe impl<T> Send for Ring<T> {}
+unsafe impl<T> Sync for Ring<T> {}
+
+impl<T: Copy> Ring<T> {
+    fn map(fd: BorrowedFd<'_>, offsets: &libc::xdp_ring_offset, page_offset: u64, size: u32) -> io::Result<Self> {
+        let map = Mmap::ring(fd, page_offset, offsets.desc as usize + size as usize * std::mem::size_of::<T>())?;
+        let base = map.as_ptr();
+        // SAFETY: the kernel reported these offsets inside the mapping
+        unsafe {
+            Ok(Self {
+                producer: base.add(offsets.producer as usize) as *const AtomicU32,
+                consumer: base.add(offsets.consumer as usize) as *const AtomicU32,
+                flags: base.add(offsets.flags as usize) as *const AtomicU32,
+                entries: base.add(offsets.desc as usize) as *mut T,
+                size,
+                _map: map,
+            })
+        }
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        // SAFETY: points into the live mapping, which the kernel updates atomically
+        unsafe { &*self.producer }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        // SAFETY: as for `producer`
+        unsafe { &*self.consumer }
+    }
+
+    fn needs_wakeup(&self) -> bool {
+        // SAFETY: as for `producer`
+        unsafe { &*self.flags }.load(Ordering::Relaxed) & libc::XDP_RING_NEED_WAKEUP != 0
+    }
+
+    /// Free slots, for a ring userspace produces
+    fn free(&self) -> u32 {
+        let used = self.producer().load(Ordering::Relaxed).wrapping_sub(self.consumer().load(Ordering::Acquire));
+        self.size - used
+    }
+
+    /// Queue as many `items` as fit, returning how many were queued
+    fn push(&mut self, items: &[T]) -> usize {
+        let count = items.len().min(self.free() as usize);
+        let producer = self.producer().load(Ordering::Relaxed);
+        for (i, item) in items[..count].iter().enumerate() {
+            let slot = producer.wrapping_add(i as u32) & (self.size - 1);
+            // SAFETY: the slot is free and inside the ring
+            unsafe { self.entries.add(slot as usize).write(*item) };
+        }
+        self.producer().store(producer.wrapping_add(count as u32), Ordering::Release);
+        count
+    }
+
+    /// Entries ready, for a ring userspace consumes
+    fn available(&self) -> u32 {
+        self.producer().load(Ordering::Acquire).wrapping_sub(self.consumer().load(Ordering::Relaxed))
+    }
+
+    /// Take up to `max` entries
+    fn pop(&mut self, max: usize, out: &mut Vec<T>) -> usize {
+        let count = (self.available() as usize).min(max);
+        let consumer = self.consumer().load(Ordering::Relaxed);
+        for i in 0..count {
+            let slot = consumer.wrapping_add(i as u32) & (self.size - 1);
+            // SAFETY: the kernel published the slot and will not reuse it until released
+            out.push(unsafe { self.entries.add(slot as usize).read() });
+        }
+        self.consumer().store(consumer.wrapping_add(count as u32), Ordering::Release);
+        count
+    }
+}
+
+struct XskState {
+    fill: Ring<u64>,
+    completion: Ring<u64>,
+    rx: Ring<libc::xdp_desc>,
+    tx: Ring<libc::xdp_desc>,
+    /// Frames owned by userspace, free for transmission
+    free_frames: Vec<u64>,
+    /// Scratch space for ring entries
+    addrs: Vec<u64>,
+    descs: Vec<libc::xdp_desc>,
+}
+
+impl XskState {
+    /// Take back frames the kernel finished transmitting
+    fn reap_completions(&mut self) {
+        let max = self.completion.available() as usize;
+        self.completion.pop(max, &mut self.free_frames);
+    }
+
+    fn tx_capacity(&self) -> usize {
+        self.free_frames.len().min(self.tx.free() as usize)
+    }
+}
+
+/// Kernel socket, UMEM and rings shared by clones of an [`AfXdpSocket`]
+struct Xsk {
+    /// Reactor registration, created on first async use
+    readiness: Mutex<Option<Arc<AsyncFd<RawFd>>>>,
+    state: Mutex<XskState>,
+    frame_size: u32,
+    umem: Mmap,
+    fd: OwnedFd,
+}
+
 impl AfXdpSocket {
-    /// Send packet using zero-copy (placeholder)
-    pub async fn send(&self, data: &[u8]) -> Result<()> {
-        // Simulate send - actual AF_XDP would use zero-copy
-        self.stats.write().packets_sent += 1;
-        self.stats.write().bytes_sent += data.len() as u64;
+    fn open(interface: &str, queue_id: u32, umem_config: &UmemConfig, rings: &RingConfig, mode: XskBindMode) -> Result<Self> {
+        validate_config(umem_config, rings)?;
+        let fd = sys::xsk_socket().map_err(|e| anyhow!("Failed to create AF_XDP socket: {}", e))?;
+        let frame_size = umem_config.frame_size;
+        let umem = Mmap::anonymous(umem_config.frame_count as usize * frame_size as usize, umem_config.use_huge_pages)
+            .map_err(|e| anyhow!("Failed to allocate UMEM: {}", e))?;
+        sys::xsk_register_umem(fd.as_fd(), &umem, frame_size, umem_config.frame_headroom)
+            .map_err(|e| anyhow!("Failed to register UMEM: {}", e))?;
+
+        for (ring, size) in [
+            (libc::XDP_UMEM_FILL_RING, rings.fill_size),
+            (libc::XDP_UMEM_COMPLETION_RING, rings.comp_size),
+            (libc::XDP_RX_RING, rings.rx_size),
+            (libc::XDP_TX_RING, rings.tx_size),
+        ] {
+            sys::xsk_ring_size(fd.as_fd(), ring, size).map_err(|e| anyhow!("Failed to size AF_XDP ring: {}", e))?;
+        }
+        let offsets = sys::xsk_mmap_offsets(fd.as_fd())?;
+        let mut state = XskState {
+            fill: Ring::map(fd.as_fd(), &offsets.fr, libc::XDP_UMEM_PGOFF_FILL_RING, rings.fill_size)?,
+            completion: Ring::map(fd.as_fd(), &offsets.cr, libc::XDP_UMEM_PGOFF_COMPLETION_RING, rings.comp_size)?,
+            rx: Ring::map(fd.as_fd(), &offsets.rx, libc::XDP_PGOFF_RX_RING as u64, rings.rx_size)?,
+            tx: Ring::map(fd.as_fd(), &offsets.tx, libc::XDP_PGOFF_TX_RING as u64, rings.tx_size)?,
+            free_frames: Vec::new(),
+            addrs: Vec::new(),
+            descs: Vec::new(),
+        };
 
-        tracing::trace!("AF_XDP send (simulated): {} bytes", data.len());
-        Ok(())
+        // Half the frames (at most a full fill ring) receive, the rest transmit
+        let frames: Vec<u64> = (0..umem_config.frame_count as u64).map(|i| i * frame_size as u64).collect();
+        let receive = (frames.len() / 2).min(rings.fill_size as usize);
+        state.fill.push(&frames[..receive]);
+        state.free_frames = frames[receive..].to_vec();
+
+        let ifindex = sys::if_index(interface).map_err(|e| anyhow!("Unknown interface {}: {}", interface, e))?;
+        let mut bound = Err(io::Error::from(io::ErrorKind::Unsupported));
+        for &flags in mode.flags() {
+            bound = sys::xsk_bind(fd.as_fd(), ifindex, queue_id, flags | libc::XDP_USE_NEED_WAKEUP);
+            if bound.is_ok() {
+                break;
+            }
+        }
+        bound.map_err(|e| anyhow!("Failed to bind AF_XDP socket to {}:{} in {:?} mode: {}", interface, queue_id, mode, e))?;
+
+        Ok(Self {
+            interface: interface.to_string(),
+            queue_id,
+            stats: Arc::new(RwLock::new(AfXdpStats::default())),
+            xsk: Arc::new(Xsk {
+                readiness: Mutex::new(None),
+                state: Mutex::new(state),
+                frame_size,
+                umem,
+                fd,
+            }),
+        })
     }
 
-    /// Receive packet using zero-copy (placeholder)
-    pub async fn receive(&self) -> Result<Bytes> {
-        // Simulate receive - actual AF_XDP would use zero-copy
-        self.stats.write().packets_received += 1;
+    /// Interface the socket is bound to
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    /// Device queue the socket is bound to
+    pub fn queue_id(&self) -> u32 {
+        self.queue_id
+    }
+
+    /// Largest frame that fits in a UMEM frame
+    pub fn max_frame_size(&self) -> usize {
+        self.xsk.frame_size as usize
+    }
+
+    /// Send one Ethernet frame, waiting for ring space
+    pub async fn send(&self, data: &[u8]) -> Result<()> {
+        self.send_batch(&[data]).await.map(drop)
+    }
 
-        tracing::trace!("AF_XDP receive (simulated)");
-        Ok(Bytes::new())
+    /// Receive one Ethernet frame, waiting until one arrives
+    pub async fn receive(&self) -> Result<Bytes> {
+        let mut frame = Bytes::new();
+        std::future::poll_fn(|cx| self.poll_recv_with(cx, 1, |data| frame = Bytes::copy_from_slice(data))).await?;
+        Ok(frame)
     }
 
-    /// Send multiple packets in batch for efficiency (placeholder)
+    /// Send Ethernet frames, waiting for ring space as needed
     pub async fn send_batch(&self, packets: &[&[u8]]) -> Result<usize> {
-        let count = packets.len();
-        self.stats.write().packets_sent += count as u64;
-        for packet in packets {
-            self.stats.write().bytes_sent += packet.len() as u64;
+        let mut sent = 0;
+        while sent < packets.len() {
+            sent += self.try_send_batch(&packets[sent..])?;
+            if sent < packets.len() {
+                std::future::poll_fn(|cx| self.poll_send_ready(cx)).await?;
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Receive up to `max_packets` frames, waiting until at least one arrives
+    pub async fn receive_batch(&self, max_packets: usize) -> Result<Vec<Bytes>> {
+        let mut frames = Vec::new();
+        std::future::poll_fn(|cx| self.poll_recv_with(cx, max_packets, |data| frames.push(Bytes::copy_from_slice(data)))).await?;
+        Ok(frames)
+    }
+
+    /// Queue as many frames as the TX ring and free frames allow, without waiting
+    pub fn try_send_batch(&self, packets: &[&[u8]]) -> io::Result<usize> {
+        if let Some(packet) = packets.iter().find(|p| p.len() > self.xsk.frame_size as usize) {
+            self.stats.write().invalid_descriptors += 1;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} byte frame exceeds the {} byte UMEM frame", packet.len(), self.xsk.frame_size),
+            ));
         }
 
-        tracing::trace!("AF_XDP batch send (simulated): {} packets", count);
+        let mut state = self.xsk.state.lock();
+        state.reap_completions();
+        let count = state.tx_capacity().min(packets.len());
+        let mut descs = std::mem::take(&mut state.descs);
+        descs.clear();
+        for packet in &packets[..count] {
+            let addr = state.free_frames.pop().expect("capacity checked");
+            // SAFETY: the frame is owned by userspace and `packet` fits in it
+            unsafe {
+                std::ptr::copy_nonoverlapping(packet.as_ptr(), self.xsk.umem.as_ptr().add(addr as usize), packet.len());
+            }
+            descs.push(libc::xdp_desc { addr, len: packet.len() as u32, options: 0 });
+        }
+        state.tx.push(&descs);
+        state.descs = descs;
+        drop(state);
+
+        if count > 0 {
+            sys::xsk_kick_tx(self.xsk.fd.as_fd())?;
+        }
+
+        let mut stats = self.stats.write();
+        stats.packets_sent += count as u64;
+        stats.bytes_sent += packets[..count].iter().map(|p| p.len() as u64).sum::<u64>();
+        if count < packets.len() {
+            stats.tx_ring_full += 1;
+        }
         Ok(count)
     }
 
-    /// Receive multiple packets in batch for efficiency (placeholder)
-    pub async fn receive_batch(&self, _max_packets: usize) -> Result<Vec<Bytes>> {
-        tracing::trace!("AF_XDP batch receive (simulated)");
-        Ok(Vec::new())
+    /// Pass up to `max` received frames to `f`, without waiting
+    pub fn recv_with(&self, max: usize, mut f: impl FnMut(&[u8])) -> usize {
+        let mut state = self.xsk.state.lock();
+        let mut descs = std::mem::take(&mut state.descs);
+        descs.clear();
+        let count = state.rx.pop(max, &mut descs);
+        let mut addrs = std::mem::take(&mut state.addrs);
+        addrs.clear();
+
+        let mut bytes = 0;
+        let mut invalid = 0;
+        let umem_len = self.xsk.umem.len();
+        for desc in &descs {
+            let (start, len) = (desc.addr as usize, desc.len as usize);
+            if start.checked_add(len).is_some_and(|end| end <= umem_len) {
+                // SAFETY: the kernel filled this range of a frame it handed back
+                f(unsafe { std::slice::from_raw_parts(self.xsk.umem.as_ptr().add(start), len) });
+                bytes += len as u64;
+            } else {
+                invalid += 1;
+            }
+            // Recycle the frame for receiving
+            addrs.push(desc.addr - desc.addr % self.xsk.frame_size as u64);
+        }
+        state.fill.push(&addrs);
+        if state.fill.needs_wakeup() {
+            sys::xsk_wake_rx(self.xsk.fd.as_fd());
+        }
+        state.descs = descs;
+        state.addrs = addrs;
+        drop(state);
+
+        let mut stats = self.stats.write();
+        stats.packets_received += count as u64;
+        stats.bytes_received += bytes;
+        stats.invalid_descriptors += invalid;
+        if count == 0 {
+            stats.rx_ring_empty += 1;
+        }
+        count
+    }
+
+    /// Receive into `f` once frames are available
+    pub fn poll_recv_with(&self, cx: &mut Context<'_>, max: usize, mut f: impl FnMut(&[u8])) -> Poll<io::Result<usize>> {
+        let readiness = self.readiness()?;
+        loop {
+            let mut guard = ready!(readiness.poll_read_ready(cx))?;
+            match self.recv_with(max, &mut f) {
+                0 => guard.clear_ready(),
+                count => return Poll::Ready(Ok(count)),
+            }
+        }
+    }
+
+    /// Ready once a frame can be queued for transmission
+    pub fn poll_send_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.xsk.state.lock().tx_capacity() == 0 {
+            // Completions only arrive once the kernel processes the TX ring
+            sys::xsk_kick_tx(self.xsk.fd.as_fd())?;
+            let mut state = self.xsk.state.lock();
+            state.reap_completions();
+            if state.tx_capacity() == 0 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn readiness(&self) -> io::Result<Arc<AsyncFd<RawFd>>> {
+        let mut readiness = self.xsk.readiness.lock();
+        if let Some(readiness) = &*readiness {
+            return Ok(readiness.clone());
+        }
+        let registered = Arc::new(AsyncFd::with_interest(self.xsk.fd.as_raw_fd(), tokio::io::Interest::READABLE)?);
+        *readiness = Some(registered.clone());
+        Ok(registered)
     }
 
     /// Get socket statistics
@@ -203,19 +558,26 @@ impl AfXdpSocket {
     }
 }
 
-impl Clone for AfXdpSocket {
-    fn clone(&self) -> Self {
-        Self {
-            interface: self.interface.clone(),
-            queue_id: self.queue_id,
-            stats: self.stats.clone(),
-        }
+impl AsFd for AfXdpSocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.xsk.fd.as_fd()
+    }
+}
+
+impl std::fmt::Debug for AfXdpSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AfXdpSocket")
+            .field("interface", &self.interface)
+            .field("queue_id", &self.queue_id)
+            .finish_non_exhaustive()
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use super::super::maps::{MapData, XskMap, XSK_MAP};
+    use std::os::fd::{AsRawFd, OwnedFd};
 
     #[test]
     fn test_umem_config_default() {
@@ -240,4 +602,108 @@ mod tests {
         let manager = AfXdpManager::new();
         assert!(manager.is_ok());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_config_validation() {
+        let umem = UmemConfig::default();
+        let rings = RingConfig::default();
+        assert!(validate_config(&umem, &rings).is_ok());
+        assert!(validate_config(&UmemConfig { frame_size: 3000, ..umem.clone() }, &rings).is_err());
+        assert!(validate_config(&UmemConfig { frame_size: 8192, ..umem.clone() }, &rings).is_err());
+        assert!(validate_config(&umem, &RingConfig { rx_size: 1000, ..rings.clone() }).is_err());
+        assert!(validate_config(&umem, &RingConfig { comp_size: 1024, ..rings }).is_err());
+    }
+
+    /// Small sockets for tests
+    pub(crate) fn test_manager() -> AfXdpManager {
+        let umem = UmemConfig { frame_count: 256, frame_size: 2048, ..Default::default() };
+        let rings = RingConfig { tx_size: 64, rx_size: 64, fill_size: 64, comp_size: 64 };
+        AfXdpManager::with_config(umem, rings, XskBindMode::Copy).unwrap()
+    }
+
+    /// Run `test` in a private network namespace with veth pair stoq0/stoq1
+    pub(crate) fn with_veth_pair(test: impl FnOnce() + Send + 'static) {
+        use std::process::Command;
+
+        std::thread::spawn(move || {
+            assert_eq!(unsafe { libc::unshare(libc::CLONE_NEWNET) }, 0, "{}", io::Error::last_os_error());
+            for args in [
+                "link add stoq0 address 02:00:00:00:00:01 type veth peer name stoq1 address 02:00:00:00:00:02",
+                "link set stoq0 up",
+                "link set stoq1 up",
+            ] {
+                let status = Command::new("ip").args(args.split_whitespace()).status().unwrap();
+                assert!(status.success(), "ip {}", args);
+            }
+            test();
+        })
+        .join()
+        .unwrap();
+    }
+
+    /// Minimal XDP program redirecting every frame to the socket in `map` for its queue
+    pub(crate) struct Redirect {
+        pub(crate) map: XskMap,
+        _link: OwnedFd,
+        _program: OwnedFd,
+    }
+
+    /// Attach a [`Redirect`] to `interface` in SKB mode, without needing clang
+    pub(crate) fn redirect_all(interface: &str) -> Redirect {
+        let map = Arc::new(MapData::create(XSK_MAP).unwrap());
+        let map_fd = map.as_fd().as_raw_fd();
+        // (opcode, dst | src << 4, offset, immediate)
+        let insns: [(u8, u8, i16, i32); 6] = [
+            (0x61, 0x12, 16, 0),     // r2 = ctx->rx_queue_index
+            (0x18, 0x11, 0, map_fd), // r1 = map (ld_imm64 with BPF_PSEUDO_MAP_FD)
+            (0x00, 0x00, 0, 0),
+            (0xb7, 0x03, 0, 2),     // r3 = XDP_PASS
+            (0x85, 0x00, 0, 51),    // call bpf_redirect_map
+            (0x95, 0x00, 0, 0),     // exit
+        ];
+        let bytecode: Vec<u8> = insns
+            .iter()
+            .flat_map(|&(code, regs, off, imm)| {
+                let mut insn = [code, regs, 0, 0, 0, 0, 0, 0];
+                insn[2..4].copy_from_slice(&off.to_le_bytes());
+                insn[4..8].copy_from_slice(&imm.to_le_bytes());
+                insn
+            })
+            .collect();
+        let program = sys::prog_load(sys::BPF_PROG_TYPE_XDP, "stoq_xsk_test", &bytecode, c"GPL").unwrap();
+        let ifindex = sys::if_index(interface).unwrap();
+        let link = sys::link_create_xdp(program.as_fd(), ifindex, sys::XDP_FLAGS_SKB_MODE).unwrap();
+        Redirect { map: XskMap::new(map).unwrap(), _link: link, _program: program }
+    }
+
+    #[test]
+    #[ignore = "requires root and iproute2"]
+    fn test_frames_over_veth_in_copy_mode() {
+        with_veth_pair(|| {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async {
+                let redirect = redirect_all("stoq1");
+                let mut manager = test_manager();
+                let receiver = manager.create_socket("stoq1", 0).unwrap();
+                redirect.map.set(0, receiver.as_fd()).unwrap();
+                let sender = manager.create_socket("stoq0", 0).unwrap();
+
+                let frames: Vec<Vec<u8>> = (0..8u8).map(|i| super::super::udp::tests::udp_frame(9292, &[i; 100])).collect();
+                let batch: Vec<&[u8]> = frames.iter().map(|f| f.as_slice()).collect();
+                assert_eq!(sender.send_batch(&batch).await.unwrap(), 8);
+
+                let mut received = Vec::new();
+                while received.len() < 8 {
+                    let frames = tokio::time::timeout(std::time::Duration::from_secs(5), receiver.receive_batch(16))
+                        .await
+                        .expect("frames redirected to the AF_XDP socket")
+                        .unwrap();
+                    received.extend(frames);
+                }
+                assert_eq!(received, frames);
+                assert_eq!(sender.get_stats().packets_sent, 8);
+                assert_eq!(receiver.get_stats().packets_received, 8);
+            });
+        });
+    }
+}
@@ -0,0 +1,338 @@
+//! eBPF and AF_XDP capability probe
+//!
+//! Works out whether this process can use the XDP fast path without relying
+//! on external tools or kernel version numbers:
+//! - the effective capability set comes from `/proc/self/status`,
+//! - the kernel release from `uname(2)`, parsed leniently because distros
+//!   append anything after the version,
+//! - XDP support by loading a trivial XDP program, and AF_XDP support by
+//!   creating an AF_XDP socket.
+//!
+//! Everything that stands in the way is listed in
+//! [`CapabilityReport::missing`] with an explanation.
+
+use std::fmt;
+use std::io;
+
+use super::sys;
+
+/// Linux capabilities the XDP fast path needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Attach XDP programs and load networking program types
+    NetAdmin,
+    /// Create AF_XDP sockets
+    NetRaw,
+    /// Everything below on kernels that predate CAP_BPF and CAP_PERFMON (5.8)
+    SysAdmin,
+    /// Unrestricted verifier pointer arithmetic
+    Perfmon,
+    /// Load programs and create maps
+    Bpf,
+}
+
+impl Capability {
+    /// Bit number in the capability sets (`linux/capability.h`)
+    pub fn bit(self) -> u32 {
+        match self {
+            Capability::NetAdmin => 12,
+            Capability::NetRaw => 13,
+            Capability::SysAdmin => 21,
+            Capability::Perfmon => 38,
+            Capability::Bpf => 39,
+        }
+    }
+
+    /// Name as in `capabilities(7)`
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::NetAdmin => "CAP_NET_ADMIN",
+            Capability::NetRaw => "CAP_NET_RAW",
+            Capability::SysAdmin => "CAP_SYS_ADMIN",
+            Capability::Perfmon => "CAP_PERFMON",
+            Capability::Bpf => "CAP_BPF",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A process capability set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CapabilitySet(u64);
+
+impl CapabilitySet {
+    /// Set from its bitmask
+    pub fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    /// Effective set of the current process
+    pub fn effective() -> io::Result<Self> {
+        let status = std::fs::read_to_string("/proc/self/status")?;
+        Self::from_proc_status(&status)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no CapEff line in /proc/self/status"))
+    }
+
+    /// Effective set from the contents of a `/proc/<pid>/status` file
+    pub fn from_proc_status(status: &str) -> Option<Self> {
+        let line = status.lines().find_map(|line| line.strip_prefix("CapEff:"))?;
+        u64::from_str_radix(line.trim(), 16).ok().map(Self)
+    }
+
+    /// Whether `capability` is in the set
+    pub fn contains(self, capability: Capability) -> bool {
+        self.0 & (1 << capability.bit()) != 0
+    }
+
+    /// Capabilities the XDP fast path needs that the set lacks
+    ///
+    /// CAP_SYS_ADMIN stands in for CAP_BPF and CAP_PERFMON, as it does on
+    /// kernels that predate them.
+    pub fn missing(self) -> Vec<Capability> {
+        let sys_admin = self.contains(Capability::SysAdmin);
+        [Capability::Bpf, Capability::Perfmon, Capability::NetAdmin, Capability::NetRaw]
+            .into_iter()
+            .filter(|&capability| match capability {
+                Capability::Bpf | Capability::Perfmon => !sys_admin && !self.contains(capability),
+                _ => !self.contains(capability),
+            })
+            .collect()
+    }
+}
+
+/// Kernel version from a release string
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KernelVersion {
+    /// Major version
+    pub major: u32,
+    /// Minor version
+    pub minor: u32,
+    /// Patch level, 0 if the release has none
+    pub patch: u32,
+}
+
+impl KernelVersion {
+    /// Parse a release such as `5.15.0-91-generic`, `6.8.0-rc3+` or `4.19`
+    pub fn parse(release: &str) -> Option<Self> {
+        let mut numbers = release.trim().splitn(3, '.').map(|part| {
+            let digits = part.find(|c: char| !c.is_ascii_digit()).unwrap_or(part.len());
+            part[..digits].parse::<u32>().ok()
+        });
+        let major = numbers.next()??;
+        let minor = numbers.next()??;
+        let patch = numbers.next().flatten().unwrap_or(0);
+        Some(Self { major, minor, patch })
+    }
+}
+
+impl fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Something standing in the way of XDP or AF_XDP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MissingRequirement {
+    /// Not running on Linux
+    NotLinux,
+    /// The effective capability set could not be read
+    CapabilitiesUnknown(String),
+    /// The effective capability set lacks a capability
+    Capability(Capability),
+    /// The kernel refused the probe XDP program
+    XdpProgramRejected(String),
+    /// The kernel refused to create an AF_XDP socket
+    AfXdpUnsupported(String),
+}
+
+impl fmt::Display for MissingRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissingRequirement::NotLinux => f.write_str("XDP and AF_XDP are only available on Linux"),
+            MissingRequirement::CapabilitiesUnknown(e) => {
+                write!(f, "could not read the effective capabilities from /proc/self/status: {}", e)
+            }
+            MissingRequirement::Capability(capability) => match capability {
+                Capability::Bpf => f.write_str("CAP_BPF (or CAP_SYS_ADMIN) is needed to load eBPF programs and create maps"),
+                Capability::Perfmon => f.write_str(
+                    "CAP_PERFMON (or CAP_SYS_ADMIN) is needed to lift the verifier's Spectre restrictions, \
+                     which can reject the XDP program's pointer arithmetic",
+                ),
+                Capability::NetAdmin => f.write_str(
+                    "CAP_NET_ADMIN is needed to load XDP programs and attach them to interfaces",
+                ),
+                Capability::NetRaw => f.write_str("CAP_NET_RAW is needed to create AF_XDP sockets"),
+                Capability::SysAdmin => f.write_str("CAP_SYS_ADMIN is needed on kernels without CAP_BPF"),
+            },
+            MissingRequirement::XdpProgramRejected(e) => write!(f, "the kernel refused to load a trivial XDP program: {}", e),
+            MissingRequirement::AfXdpUnsupported(e) => write!(f, "the kernel refused to create an AF_XDP socket: {}", e),
+        }
+    }
+}
+
+/// What the probe found
+#[derive(Debug, Clone)]
+pub struct CapabilityReport {
+    /// Kernel release as `uname -r` prints it
+    pub kernel_release: String,
+    /// Version parsed from the release, if it could be
+    pub kernel_version: Option<KernelVersion>,
+    /// Effective capability set, if it could be read
+    pub effective: Option<CapabilitySet>,
+    /// A trivial XDP program loaded
+    pub xdp_program_loads: bool,
+    /// An AF_XDP socket could be created
+    pub af_xdp_sockets: bool,
+    /// The BPF filesystem is mounted (only needed to pin objects)
+    pub bpf_fs_mounted: bool,
+    /// Everything that stands in the way, in the order found
+    pub missing: Vec<MissingRequirement>,
+}
+
+impl CapabilityReport {
+    /// Probe the running kernel and process
+    pub fn probe() -> Self {
+        let mut missing = Vec::new();
+        if !cfg!(target_os = "linux") {
+            missing.push(MissingRequirement::NotLinux);
+        }
+
+        let kernel_release = sys::kernel_release().unwrap_or_else(|_| "unknown".to_string());
+        let kernel_version = KernelVersion::parse(&kernel_release);
+
+        let effective = match CapabilitySet::effective() {
+            Ok(effective) => {
+                missing.extend(effective.missing().into_iter().map(MissingRequirement::Capability));
+                Some(effective)
+            }
+            Err(e) => {
+                missing.push(MissingRequirement::CapabilitiesUnknown(e.to_string()));
+                None
+            }
+        };
+
+        let (xdp_program_loads, af_xdp_sockets) = probe_kernel(&mut missing);
+
+        Self {
+            kernel_release,
+            kernel_version,
+            effective,
+            xdp_program_loads,
+            af_xdp_sockets,
+            bpf_fs_mounted: std::path::Path::new("/sys/fs/bpf").exists(),
+            missing,
+        }
+    }
+
+    /// Whether the effective set is known to contain `capability`
+    pub fn has(&self, capability: Capability) -> bool {
+        self.effective.is_some_and(|effective| effective.contains(capability))
+    }
+
+    /// XDP programs can be loaded and attached
+    pub fn xdp_available(&self) -> bool {
+        self.xdp_program_loads && self.has(Capability::NetAdmin)
+    }
+
+    /// AF_XDP sockets can be created and fed by XDP
+    pub fn af_xdp_available(&self) -> bool {
+        self.xdp_available() && self.af_xdp_sockets
+    }
+}
+
+impl fmt::Display for CapabilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "kernel {}: XDP {}, AF_XDP {}", self.kernel_release,
+            if self.xdp_available() { "available" } else { "unavailable" },
+            if self.af_xdp_available() { "available" } else { "unavailable" })?;
+        for requirement in &self.missing {
+            write!(f, "; {}", requirement)?;
+        }
+        Ok(())
+    }
+}
+
+/// `r0 = XDP_PASS; exit`
+const PROBE_PROGRAM: [u8; 16] = [
+    0xb7, 0x00, 0, 0, 2, 0, 0, 0,
+    0x95, 0x00, 0, 0, 0, 0, 0, 0,
+];
+
+/// Load the probe program and create an AF_XDP socket
+fn probe_kernel(missing: &mut Vec<MissingRequirement>) -> (bool, bool) {
+    let xdp = sys::prog_load(sys::BPF_PROG_TYPE_XDP, "stoq_probe", &PROBE_PROGRAM, c"GPL")
+        .map_err(|e| missing.push(MissingRequirement::XdpProgramRejected(e.to_string())))
+        .is_ok();
+    let af_xdp = sys::xsk_socket()
+        .map_err(|e| missing.push(MissingRequirement::AfXdpUnsupported(e.to_string())))
+        .is_ok();
+    (xdp, af_xdp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernel_version_parse() {
+        let version = |major, minor, patch| Some(KernelVersion { major, minor, patch });
+        assert_eq!(KernelVersion::parse("5.15.0-91-generic"), version(5, 15, 0));
+        assert_eq!(KernelVersion::parse("6.18.44-fc-v139"), version(6, 18, 44));
+        assert_eq!(KernelVersion::parse("6.8.0-rc3+"), version(6, 8, 0));
+        assert_eq!(KernelVersion::parse("4.19\n"), version(4, 19, 0));
+        assert_eq!(KernelVersion::parse("4.18.0-513.el8.x86_64"), version(4, 18, 0));
+        assert_eq!(KernelVersion::parse("5.10.0_custom"), version(5, 10, 0));
+        assert_eq!(KernelVersion::parse("Linux version 5.10.0"), None);
+        assert_eq!(KernelVersion::parse("unknown"), None);
+        assert!(version(5, 8, 0) > version(4, 19, 300));
+    }
+
+    #[test]
+    fn test_capability_set_from_proc_status() {
+        let status = |cap_eff: &str| format!("Name:\tstoq\nCapInh:\t0000000000000000\nCapPrm:\t{0}\nCapEff:\t{0}\n", cap_eff);
+
+        let root = CapabilitySet::from_proc_status(&status("000001ffffffffff")).unwrap();
+        assert!(root.contains(Capability::Bpf));
+        assert!(root.missing().is_empty());
+
+        let unprivileged = CapabilitySet::from_proc_status(&status("0000000000000000")).unwrap();
+        assert_eq!(
+            unprivileged.missing(),
+            vec![Capability::Bpf, Capability::Perfmon, Capability::NetAdmin, Capability::NetRaw]
+        );
+
+        // CAP_NET_ADMIN and CAP_NET_RAW only
+        let network = CapabilitySet::from_proc_status(&status("0000000000003000")).unwrap();
+        assert_eq!(network.missing(), vec![Capability::Bpf, Capability::Perfmon]);
+
+        // CAP_SYS_ADMIN covers CAP_BPF and CAP_PERFMON
+        let legacy = CapabilitySet::from_bits(1 << 21 | 1 << 12 | 1 << 13);
+        assert!(legacy.missing().is_empty());
+
+        assert_eq!(CapabilitySet::from_proc_status("Name:\tstoq\n"), None);
+    }
+
+    #[test]
+    fn test_probe_explains_what_is_unavailable() {
+        let report = CapabilityReport::probe();
+
+        if cfg!(target_os = "linux") {
+            assert!(report.kernel_version.is_some(), "{}", report.kernel_release);
+        }
+        if !report.af_xdp_available() {
+            assert!(!report.missing.is_empty(), "{}", report);
+        }
+        assert_eq!(
+            report.xdp_program_loads,
+            !report.missing.iter().any(|m| matches!(m, MissingRequirement::XdpProgramRejected(_))),
+            "{}",
+            report
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! DDoS mitigation in the XDP fast path
+//!
+//! Sheds attack traffic before it reaches quinn:
+//! - per source /64 packet-rate limits (GCRA token buckets in an LRU map),
+//! - QUIC Initial packets in datagrams smaller than the 1200 bytes RFC 9000
+//!   requires of clients,
+//! - long-header packets with a version outside an allowlist,
+//! - in "under attack" mode, any packet whose destination connection ID the
+//!   local endpoint did not issue.
+//!
+//! The last check needs the endpoint's connection IDs in the kernel, which
+//! [`ConnectionIdTracker`] provides by issuing them itself.
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use quinn::{ConnectionId, ConnectionIdGenerator};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::maps::{BpfHashMap, CidKey, XdpConfigEntry, CID_MAP, CONFIG_MIN_INITIAL, CONFIG_UNDER_ATTACK, CONFIG_VERSION_ALLOWLIST};
+
+/// QUIC version 1 (RFC 9000)
+pub const QUIC_V1: u32 = 0x0000_0001;
+/// QUIC version 2 (RFC 9369)
+pub const QUIC_V2: u32 = 0x6b33_43cf;
+/// Smallest UDP payload allowed to carry a client Initial (RFC 9000 §14.1)
+pub const QUIC_MIN_INITIAL_SIZE: u32 = 1200;
+/// Length of the connection IDs [`ConnectionIdTracker`] issues
+pub const TRACKED_CID_LEN: usize = 8;
+
+/// XDP DDoS mitigation settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DdosConfig {
+    /// Sustained packets per second admitted from one source /64 (0 = unlimited)
+    pub rate_limit_pps: u32,
+    /// Packets a source /64 may send back to back above the sustained rate
+    pub rate_limit_burst: u32,
+    /// Drop QUIC Initial packets in datagrams below 1200 bytes
+    pub drop_short_initials: bool,
+    /// Long-header versions admitted (empty = any version)
+    pub allowed_versions: Vec<u32>,
+    /// Only admit packets to connection IDs issued by the local endpoint
+    pub under_attack: bool,
+}
+
+impl Default for DdosConfig {
+    fn default() -> Self {
+        Self {
+            rate_limit_pps: 0,
+            rate_limit_burst: 64,
+            drop_short_initials: true,
+            allowed_versions: Vec::new(),
+            under_attack: false,
+        }
+    }
+}
+
+impl DdosConfig {
+    /// Fill the DDoS fields of a `config_map` entry, keeping the filter fields
+    pub fn apply(&self, entry: &mut XdpConfigEntry) {
+        if self.rate_limit_pps == 0 {
+            entry.rate_interval_ns = 0;
+            entry.rate_burst_ns = 0;
+        } else {
+            let interval = Duration::from_secs(1).as_nanos() as u64 / self.rate_limit_pps as u64;
+            entry.rate_interval_ns = interval;
+            entry.rate_burst_ns = interval * self.rate_limit_burst.saturating_sub(1) as u64;
+        }
+
+        let mut flags = entry.flags & !(CONFIG_MIN_INITIAL | CONFIG_VERSION_ALLOWLIST | CONFIG_UNDER_ATTACK);
+        if self.drop_short_initials {
+            flags |= CONFIG_MIN_INITIAL;
+        }
+        if !self.allowed_versions.is_empty() {
+            flags |= CONFIG_VERSION_ALLOWLIST;
+        }
+        if self.under_attack {
+            flags |= CONFIG_UNDER_ATTACK;
+        }
+        entry.flags = flags;
+        entry.cid_len = TRACKED_CID_LEN as u32;
+    }
+}
+
+struct TrackerState {
+    /// Most recently issued IDs, replayed when a program is loaded
+    recent: VecDeque<CidKey>,
+    /// `cid_map` of the loaded program
+    map: Option<BpfHashMap<CidKey, u32>>,
+}
+
+/// Records the connection IDs the local endpoint issues so XDP can admit them
+///
+/// Install [`generator_factory`](Self::generator_factory) in the endpoint's
+/// `EndpointConfig`. Retired IDs are not removed; the LRU `cid_map` ages
+/// them out.
+pub struct ConnectionIdTracker {
+    state: Mutex<TrackerState>,
+}
+
+impl ConnectionIdTracker {
+    /// Create a tracker not yet bound to a program
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(TrackerState {
+                recent: VecDeque::new(),
+                map: None,
+            }),
+        })
+    }
+
+    /// Record an issued connection ID
+    pub fn record(&self, cid: &[u8]) {
+        let Some(key) = CidKey::new(cid) else { return };
+        let mut state = self.state.lock();
+        if let Some(map) = &state.map {
+            if let Err(e) = map.insert(&key, &1) {
+                tracing::warn!("Failed to publish connection ID to XDP: {}", e);
+            }
+        }
+        if state.recent.len() == CID_MAP.max_entries as usize {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(key);
+    }
+
+    /// Whether `cid` was issued and is still remembered
+    pub fn contains(&self, cid: &[u8]) -> bool {
+        CidKey::new(cid).is_some_and(|key| self.state.lock().recent.contains(&key))
+    }
+
+    /// Publish issued IDs to `map`, now and as they are issued
+    pub fn bind(&self, map: BpfHashMap<CidKey, u32>) -> Result<()> {
+        let mut state = self.state.lock();
+        for key in &state.recent {
+            map.insert(key, &1)?;
+        }
+        state.map = Some(map);
+        Ok(())
+    }
+
+    /// Factory for `EndpointConfig::cid_generator`
+    pub fn generator_factory(self: &Arc<Self>) -> impl Fn() -> Box<dyn ConnectionIdGenerator> + Send + Sync + 'static {
+        let tracker = self.clone();
+        move || Box::new(TrackingGenerator { tracker: tracker.clone() })
+    }
+}
+
+/// Random connection IDs, each recorded with the tracker
+struct TrackingGenerator {
+    tracker: Arc<ConnectionIdTracker>,
+}
+
+impl ConnectionIdGenerator for TrackingGenerator {
+    fn generate_cid(&mut self) -> ConnectionId {
+        let bytes: [u8; TRACKED_CID_LEN] = rand::random();
+        self.tracker.record(&bytes);
+        ConnectionId::new(&bytes)
+    }
+
+    fn cid_len(&self) -> usize {
+        TRACKED_CID_LEN
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::maps::CONFIG_CONNTRACK;
+
+    #[test]
+    fn test_apply_keeps_filter_fields() {
+        let mut entry = XdpConfigEntry { port: 9292, flags: CONFIG_CONNTRACK | CONFIG_UNDER_ATTACK, ..Default::default() };
+        let config = DdosConfig {
+            rate_limit_pps: 1000,
+            rate_limit_burst: 10,
+            allowed_versions: vec![QUIC_V1],
+            ..Default::default()
+        };
+        config.apply(&mut entry);
+
+        assert_eq!(entry.port, 9292);
+        assert_eq!(entry.flags, CONFIG_CONNTRACK | CONFIG_MIN_INITIAL | CONFIG_VERSION_ALLOWLIST);
+        assert_eq!(entry.rate_interval_ns, 1_000_000);
+        assert_eq!(entry.rate_burst_ns, 9_000_000);
+        assert_eq!(entry.cid_len, TRACKED_CID_LEN as u32);
+
+        DdosConfig { drop_short_initials: false, ..Default::default() }.apply(&mut entry);
+        assert_eq!(entry.flags, CONFIG_CONNTRACK);
+        assert_eq!(entry.rate_interval_ns, 0);
+    }
+
+    #[test]
+    fn test_generator_records_issued_ids() {
+        let tracker = ConnectionIdTracker::new();
+        let mut generator = tracker.generator_factory()();
+        let cid = generator.generate_cid();
+
+        assert_eq!(cid.len(), TRACKED_CID_LEN);
+        assert_eq!(generator.cid_len(), TRACKED_CID_LEN);
+        assert!(tracker.contains(&cid));
+        assert!(!tracker.contains(&[0; TRACKED_CID_LEN]));
+    }
+}
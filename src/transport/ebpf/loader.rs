@@ -4,11 +4,43 @@
 //! for the STOQ transport layer.
 
 use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
 use std::fs;
 
-// eBPF loader would use aya
+use object::{Object, ObjectSection, ObjectSymbol, RelocationTarget, SymbolKind};
+
+use super::ddos::{QUIC_MIN_INITIAL_SIZE, QUIC_V1, QUIC_V2};
+use super::maps::{
+    BpfArray, BpfHashMap, CidKey, ConnInfo, ConnKey, FilterKey, MapData, PerCpuArray, XdpConfigEntry, XdpStatsEntry, XskMap,
+    CONFIG_CONNTRACK, CONFIG_DROP_IPV4, CONFIG_MATCH_ADDR, CONFIG_MIN_INITIAL, CONFIG_QUIC_ONLY, CONFIG_UNDER_ATTACK,
+    CONFIG_VERSION_ALLOWLIST, CONFIG_XSK_REDIRECT, CONN_STATE_ESTABLISHED, CONN_STATE_HANDSHAKE, MAX_CID_LEN, XDP_MAPS,
+};
+use super::sys;
+
+/// ELF section holding the XDP program
+pub const XDP_SECTION: &str = "xdp/stoq_filter";
+/// Name the XDP program is loaded under
+const XDP_PROGRAM_NAME: &str = "stoq_xdp_filter";
+/// `BPF_LD | BPF_IMM | BPF_DW`, the two-slot 64-bit immediate load
+const BPF_LD_IMM64: u8 = 0x18;
+
+/// ELF needs its headers 8-byte aligned to be parsed in place
+#[repr(C, align(8))]
+struct Aligned<T: ?Sized>(T);
+
+static PREBUILT_XDP_ELF: &Aligned<[u8]> = &Aligned(*include_bytes!("programs/stoq_xdp.o"));
+
+/// The checked-in build of `programs/xdp.c`, loaded when clang is not installed
+///
+/// `programs/xdp.c` is [`ProgramGenerator::generate_xdp_source`] at the time
+/// the object was built; a unit test fails when the two drift apart.
+pub static PREBUILT_XDP_OBJECT: &[u8] = &PREBUILT_XDP_ELF.0;
 
 /// eBPF program source locations
 pub struct EbpfSources {
@@ -33,8 +65,8 @@ impl Default for EbpfSources {
 /// eBPF program loader
 pub struct EbpfLoader {
     sources: EbpfSources,
-    /// Whether programs are loaded
-    programs_loaded: bool,
+    /// Loaded XDP program, if any
+    xdp_program: Option<Arc<XdpProgram>>,
 }
 
 impl EbpfLoader {
@@ -47,7 +79,7 @@ impl EbpfLoader {
     pub fn with_sources(sources: EbpfSources) -> Self {
         Self {
             sources,
-            programs_loaded: false,
+            xdp_program: None,
         }
     }
 
@@ -61,9 +93,16 @@ impl EbpfLoader {
             return Err(anyhow!("clang not found. Install with: apt install clang llvm"));
         }
 
-        // Compile XDP program
+        // Compile XDP program, generating the source if none is provided
         let xdp_output = self.sources.output_dir.join("stoq_xdp.o");
-        self.compile_program(&self.sources.xdp_source, &xdp_output)?;
+        let xdp_source = if self.sources.xdp_source.exists() {
+            self.sources.xdp_source.clone()
+        } else {
+            let generated = self.sources.output_dir.join("stoq_xdp.c");
+            ProgramGenerator::save_source(&ProgramGenerator::generate_xdp_source(), &generated)?;
+            generated
+        };
+        self.compile_program(&xdp_source, &xdp_output)?;
 
         // Compile TC program if provided
         if let Some(tc_source) = &self.sources.tc_source {
@@ -83,6 +122,8 @@ impl EbpfLoader {
             return Ok(());
         }
 
+        // <asm/types.h> lives under the multiarch include directory on Debian
+        let multiarch_include = format!("-I/usr/include/{}-linux-gnu", std::env::consts::ARCH);
         let status = Command::new("clang")
             .args(&[
                 "-O2",
@@ -93,6 +134,7 @@ impl EbpfLoader {
                 output.to_str().unwrap(),
                 "-I/usr/include",
                 "-I/usr/include/bpf",
+                &multiarch_include,
                 "-D__TARGET_ARCH_x86",
             ])
             .status()?;
@@ -114,30 +156,41 @@ impl EbpfLoader {
     }
 
     /// Load compiled eBPF programs
+    ///
+    /// Uses the object in the output directory, compiling it first if clang
+    /// is installed, and [`PREBUILT_XDP_OBJECT`] otherwise.
     pub fn load(&mut self) -> Result<()> {
-        // Check if compiled programs exist
         let xdp_path = self.sources.output_dir.join("stoq_xdp.o");
 
+        if !xdp_path.exists() && !Self::check_clang() {
+            self.xdp_program = Some(Arc::new(XdpProgram::from_elf(PREBUILT_XDP_OBJECT)?));
+            tracing::info!("clang not found, loaded the prebuilt XDP program");
+            return Ok(());
+        }
+
         if !xdp_path.exists() {
-            // Try to compile if not exists
             self.compile()?;
 
             if !xdp_path.exists() {
-                tracing::warn!("eBPF bytecode not found. Run 'make ebpf' to compile programs.");
-                return Ok(());
+                return Err(anyhow!("eBPF bytecode {:?} not found after compilation", xdp_path));
             }
         }
 
-        // In a real implementation, we would load the bytecode with aya
-        self.programs_loaded = true;
+        let program = XdpProgram::from_elf(&fs::read(&xdp_path)?)?;
+        self.xdp_program = Some(Arc::new(program));
 
-        tracing::info!("eBPF programs loaded (placeholder)");
+        tracing::info!("eBPF programs loaded from {:?}", xdp_path);
         Ok(())
     }
 
     /// Check if programs are loaded
     pub fn are_programs_loaded(&self) -> bool {
-        self.programs_loaded
+        self.xdp_program.is_some()
+    }
+
+    /// The loaded XDP program
+    pub fn xdp_program(&self) -> Option<Arc<XdpProgram>> {
+        self.xdp_program.clone()
     }
 
     /// Verify eBPF program before loading
@@ -166,22 +219,267 @@ impl EbpfLoader {
     }
 }
 
-/// eBPF program template generator
-pub struct ProgramGenerator;
+/// Outcome of running a program against a test frame
+#[derive(Debug, Clone)]
+pub struct TestRun {
+    /// XDP action the program returned (`XDP_DROP` = 1, `XDP_PASS` = 2, ...)
+    pub action: u32,
+    /// Average run time per repetition
+    pub duration: Duration,
+    /// Frame as left by the program
+    pub output: Vec<u8>,
+}
 
-impl ProgramGenerator {
-    /// Generate XDP program source code
-    pub fn generate_xdp_source() -> String {
-        r#"
+/// The STOQ XDP program loaded into the kernel together with its maps
+///
+/// Loading does not attach it anywhere; see
+/// [`XdpManager`](super::xdp::XdpManager) for that. The kernel objects are
+/// released when this is dropped and no link still references them.
+#[derive(Debug)]
+pub struct XdpProgram {
+    fd: OwnedFd,
+    maps: HashMap<&'static str, Arc<MapData>>,
+}
+
+impl XdpProgram {
+    /// Create the maps, patch their descriptors into the program and load it
+    ///
+    /// `elf` is the object clang produced from
+    /// [`ProgramGenerator::generate_xdp_source`]. Maps are created from
+    /// [`XDP_MAPS`] rather than parsed from BTF.
+    pub fn from_elf(elf: &[u8]) -> Result<Self> {
+        let mut object = XdpObject::parse(elf)?;
+
+        let maps = XDP_MAPS
+            .iter()
+            .map(|spec| Ok((spec.name, Arc::new(MapData::create(*spec)?))))
+            .collect::<Result<HashMap<_, _>>>()?;
+        object.relocate(|name| maps.get(name).map(|map| map.as_fd()))?;
+
+        let fd = sys::prog_load(sys::BPF_PROG_TYPE_XDP, XDP_PROGRAM_NAME, &object.insns, &object.license)
+            .map_err(|e| anyhow!("Failed to load XDP program: {}", e))?;
+
+        Ok(Self { fd, maps })
+    }
+
+    fn map(&self, name: &str) -> Result<Arc<MapData>> {
+        self.maps.get(name).cloned().ok_or_else(|| anyhow!("Map {} not loaded", name))
+    }
+
+    /// Filter settings read by the program on every packet
+    pub fn config_map(&self) -> Result<BpfArray<XdpConfigEntry>> {
+        BpfArray::new(self.map("config_map")?)
+    }
+
+    /// Per address pair action overrides
+    pub fn filter_map(&self) -> Result<BpfHashMap<FilterKey, u32>> {
+        BpfHashMap::new(self.map("filter_map")?)
+    }
+
+    /// Per-CPU program counters
+    pub fn stats_map(&self) -> Result<PerCpuArray<XdpStatsEntry>> {
+        PerCpuArray::new(self.map("stats_map")?)
+    }
+
+    /// GCRA arrival times per source /64, keyed by the prefix bytes
+    pub fn rate_map(&self) -> Result<BpfHashMap<u64, u64>> {
+        BpfHashMap::new(self.map("rate_map")?)
+    }
+
+    /// Admitted QUIC versions when the allowlist is enabled
+    pub fn version_map(&self) -> Result<BpfHashMap<u32, u32>> {
+        BpfHashMap::new(self.map("version_map")?)
+    }
+
+    /// Connection IDs admitted while under attack
+    pub fn cid_map(&self) -> Result<BpfHashMap<CidKey, u32>> {
+        BpfHashMap::new(self.map("cid_map")?)
+    }
+
+    /// AF_XDP sockets that STOQ datagrams are redirected to
+    pub fn xsk_map(&self) -> Result<XskMap> {
+        XskMap::new(self.map("xsks_map")?)
+    }
+
+    /// Per-flow counters of STOQ traffic
+    pub fn connection_map(&self) -> Result<BpfHashMap<ConnKey, ConnInfo>> {
+        BpfHashMap::new(self.map("connection_map")?)
+    }
+
+    /// Run the program on `frame` via `BPF_PROG_TEST_RUN`
+    ///
+    /// Map side effects are real: counters and connection entries are
+    /// updated exactly as for a received packet.
+    pub fn test_run(&self, frame: &[u8]) -> Result<TestRun> {
+        let output = sys::prog_test_run(self.fd.as_fd(), frame, 1)
+            .map_err(|e| anyhow!("BPF_PROG_TEST_RUN failed: {}", e))?;
+        Ok(TestRun {
+            action: output.retval,
+            duration: Duration::from_nanos(output.duration_ns as u64),
+            output: output.data,
+        })
+    }
+}
+
+impl AsFd for XdpProgram {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// The XDP section of a clang-built object, ready to have map fds patched in
+struct XdpObject {
+    /// Instructions of [`XDP_SECTION`]
+    insns: Vec<u8>,
+    /// Offset of each map load in `insns` and the map it refers to
+    relocations: Vec<(usize, String)>,
+    license: CString,
+}
+
+impl XdpObject {
+    /// Extract the program and its map relocations
+    ///
+    /// Only loads of maps defined in `.maps` can be relocated; calls to
+    /// functions that were not inlined are refused.
+    fn parse(elf: &[u8]) -> Result<Self> {
+        let file = object::File::parse(elf)?;
+        let section = file
+            .section_by_name(XDP_SECTION)
+            .ok_or_else(|| anyhow!("Object has no {} section", XDP_SECTION))?;
+        let maps_section = file.section_by_name(".maps").map(|maps| maps.index());
+
+        let mut relocations = Vec::new();
+        for (offset, relocation) in section.relocations() {
+            let RelocationTarget::Symbol(index) = relocation.target() else {
+                return Err(anyhow!("Unsupported relocation at offset {}", offset));
+            };
+            let symbol = file.symbol_by_index(index)?;
+            match symbol.section_index() {
+                Some(index) if Some(index) == maps_section => {
+                    relocations.push((offset as usize, symbol.name()?.to_string()));
+                }
+                Some(index) => {
+                    let target = file.section_by_index(index)?;
+                    return Err(anyhow!(
+                        "Instruction at offset {} refers to {} in {}; only map loads are supported, \
+                         so functions must be inlined (__always_inline)",
+                        offset,
+                        if symbol.kind() == SymbolKind::Section { "code" } else { symbol.name()? },
+                        target.name()?,
+                    ));
+                }
+                None => return Err(anyhow!("Program references undefined symbol {}", symbol.name()?)),
+            }
+        }
+
+        let license = match file.section_by_name("license") {
+            Some(section) => CStr::from_bytes_until_nul(section.data()?)?.to_owned(),
+            None => c"GPL".to_owned(),
+        };
+
+        Ok(Self { insns: section.data()?.to_vec(), relocations, license })
+    }
+
+    /// Patch the fd `map_fd` gives for each referenced map into its load
+    fn relocate<'fd>(&mut self, map_fd: impl Fn(&str) -> Option<BorrowedFd<'fd>>) -> Result<()> {
+        for (offset, name) in &self.relocations {
+            let fd = map_fd(name).ok_or_else(|| anyhow!("Program references unknown map {}", name))?;
+            relocate_map_fd(&mut self.insns, *offset, fd)?;
+        }
+        Ok(())
+    }
+}
+
+/// Point the 64-bit immediate load at `offset` to the map behind `fd`
+fn relocate_map_fd(insns: &mut [u8], offset: usize, fd: BorrowedFd<'_>) -> Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let insn = insns
+        .get_mut(offset..offset + 16)
+        .ok_or_else(|| anyhow!("Relocation offset {} outside program", offset))?;
+    if insn[0] != BPF_LD_IMM64 {
+        return Err(anyhow!("Relocation at offset {} is not a 64-bit immediate load", offset));
+    }
+    insn[1] = (insn[1] & 0x0f) | (sys::BPF_PSEUDO_MAP_FD << 4);
+    insn[4..8].copy_from_slice(&fd.as_raw_fd().to_le_bytes());
+    insn[12..16].fill(0);
+    Ok(())
+}
+
+const XDP_PRELUDE: &str = r#"
 #include <linux/bpf.h>
 #include <linux/if_ether.h>
+#include <linux/in.h>
 #include <linux/ip.h>
 #include <linux/ipv6.h>
 #include <linux/udp.h>
 #include <bpf/bpf_helpers.h>
 #include <bpf/bpf_endian.h>
 
-#define STOQ_PORT 9292
+/* Constants generated from their Rust definitions */
+"#;
+
+const XDP_PROGRAM: &str = r#"
+#ifndef IPPROTO_ICMPV6
+#define IPPROTO_ICMPV6 58
+#endif
+
+struct conn_key {
+    __u8 src_ip[16];
+    __u8 dst_ip[16];
+    __u16 src_port;
+    __u16 dst_port;
+};
+
+struct conn_info {
+    __u64 packets;
+    __u64 bytes;
+    __u64 last_seen;
+    __u64 first_seen;
+    __u32 state;
+    __u32 _pad;
+};
+
+struct xdp_stats {
+    __u64 packets_passed;
+    __u64 packets_dropped;
+    __u64 packets_redirected;
+    __u64 bytes_processed;
+    __u64 rate_limited;
+    __u64 short_initials;
+    __u64 bad_versions;
+    __u64 unknown_cids;
+    __u64 frame_sizes[5];
+};
+
+struct filter_key {
+    __u8 src_ip[16];
+    __u8 dst_ip[16];
+};
+
+struct xdp_config {
+    __u32 bind_addr[4];      /* network byte order, matched if STOQ_CFG_MATCH_ADDR */
+    __u16 port;              /* host byte order, 0 = nothing is STOQ traffic */
+    __u16 flags;
+    __u32 max_packet_size;
+    __u64 rate_interval_ns;  /* credit per packet, 0 = no rate limit */
+    __u64 rate_burst_ns;     /* how far a source may run ahead */
+    __u32 cid_len;           /* short-header destination CID length */
+    __u32 _pad;
+};
+
+struct cid_key {
+    __u8 len;
+    __u8 cid[MAX_CID_LEN];
+};
+
+/* Filter settings, written from user space */
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, __u32);
+    __type(value, struct xdp_config);
+} config_map SEC(".maps");
 
 /* Connection tracking map */
 struct {
@@ -207,30 +505,97 @@ struct {
     __type(value, __u32);
 } filter_map SEC(".maps");
 
-struct conn_key {
-    __u8 src_ip[16];
-    __u8 dst_ip[16];
-    __u16 src_port;
-    __u16 dst_port;
-};
+/* GCRA theoretical arrival time per source /64 */
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, 65536);
+    __type(key, __u64);
+    __type(value, __u64);
+} rate_map SEC(".maps");
 
-struct conn_info {
-    __u64 packets;
-    __u64 bytes;
-    __u64 last_seen;
-};
+/* Admitted QUIC versions */
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 64);
+    __type(key, __u32);
+    __type(value, __u32);
+} version_map SEC(".maps");
 
-struct xdp_stats {
-    __u64 packets_passed;
-    __u64 packets_dropped;
-    __u64 packets_redirected;
-    __u64 bytes_processed;
-};
+/* AF_XDP socket per receive queue */
+struct {
+    __uint(type, BPF_MAP_TYPE_XSKMAP);
+    __uint(max_entries, 64);
+    __type(key, __u32);
+    __type(value, __u32);
+} xsks_map SEC(".maps");
 
-struct filter_key {
-    __u8 src_ip[16];
-    __u8 dst_ip[16];
-};
+/* Connection IDs issued by the local endpoint */
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, 65536);
+    __type(key, struct cid_key);
+    __type(value, __u32);
+} cid_map SEC(".maps");
+
+static __always_inline int addr_eq(const __u32 *a, const struct in6_addr *b) {
+    return a[0] == b->in6_u.u6_addr32[0] && a[1] == b->in6_u.u6_addr32[1] &&
+           a[2] == b->in6_u.u6_addr32[2] && a[3] == b->in6_u.u6_addr32[3];
+}
+
+/* Non-STOQ IPv6 traffic passes unless the filter is QUIC-only */
+static __always_inline int not_stoq(const struct xdp_config *cfg, struct xdp_stats *stats) {
+    if (cfg->flags & STOQ_CFG_QUIC_ONLY) {
+        stats->packets_dropped++;
+        return XDP_DROP;
+    }
+    return XDP_PASS;
+}
+
+static __always_inline int drop_packet(struct xdp_stats *stats, __u64 *reason) {
+    stats->packets_dropped++;
+    if (reason)
+        (*reason)++;
+    return XDP_DROP;
+}
+
+/* Token bucket as GCRA: each packet pushes the arrival time forward */
+static __always_inline int rate_limited(const struct xdp_config *cfg, const struct ipv6hdr *ip6) {
+    if (!cfg->rate_interval_ns)
+        return 0;
+
+    __u64 prefix;
+    __builtin_memcpy(&prefix, &ip6->saddr, sizeof(prefix));
+    __u64 now = bpf_ktime_get_ns();
+
+    __u64 *tat = bpf_map_lookup_elem(&rate_map, &prefix);
+    if (!tat) {
+        __u64 next = now + cfg->rate_interval_ns;
+        bpf_map_update_elem(&rate_map, &prefix, &next, BPF_ANY);
+        return 0;
+    }
+
+    __u64 start = *tat > now ? *tat : now;
+    if (start - now > cfg->rate_burst_ns)
+        return 1;
+    *tat = start + cfg->rate_interval_ns;
+    return 0;
+}
+
+static __always_inline int cid_known(const __u8 *cid, __u32 len, void *data_end) {
+    struct cid_key key = {};
+    if (len > MAX_CID_LEN)
+        return 0;
+    key.len = len;
+
+    for (int i = 0; i < MAX_CID_LEN; i++) {
+        if (i >= len)
+            break;
+        if ((void *)(cid + i + 1) > data_end)
+            return 0;
+        key.cid[i] = cid[i];
+    }
+    return bpf_map_lookup_elem(&cid_map, &key) != 0;
+}
 
 SEC("xdp/stoq_filter")
 int stoq_xdp_filter(struct xdp_md *ctx) {
@@ -238,59 +603,121 @@ int stoq_xdp_filter(struct xdp_md *ctx) {
     void *data_end = (void *)(long)ctx->data_end;
 
     struct ethhdr *eth = data;
+    struct xdp_config *cfg;
     struct xdp_stats *stats;
     __u32 key = 0;
 
-    /* Get per-CPU stats */
+    cfg = bpf_map_lookup_elem(&config_map, &key);
     stats = bpf_map_lookup_elem(&stats_map, &key);
-    if (!stats)
+    if (!cfg || !stats)
         return XDP_PASS;
 
-    /* Verify ethernet header */
+    /* Leave anything we cannot parse to the kernel */
     if (data + sizeof(*eth) > data_end)
-        return XDP_DROP;
+        return XDP_PASS;
 
-    /* Only process IPv6 packets */
-    if (bpf_ntohs(eth->h_proto) != ETH_P_IPV6) {
-        stats->packets_dropped++;
-        return XDP_DROP;
-    }
+    /* ARP and other non-IP protocols always pass; IPv4 only on request */
+    __u16 proto = bpf_ntohs(eth->h_proto);
+    if (proto == ETH_P_IP && (cfg->flags & STOQ_CFG_DROP_IPV4))
+        return drop_packet(stats, 0);
+    if (proto != ETH_P_IPV6)
+        return XDP_PASS;
 
     struct ipv6hdr *ip6 = data + sizeof(*eth);
     if (data + sizeof(*eth) + sizeof(*ip6) > data_end)
-        return XDP_DROP;
+        return XDP_PASS;
 
-    /* Only process UDP packets */
-    if (ip6->nexthdr != IPPROTO_UDP)
+    /* Neighbour discovery must keep working even when QUIC-only */
+    if (ip6->nexthdr == IPPROTO_ICMPV6)
         return XDP_PASS;
+    if (ip6->nexthdr != IPPROTO_UDP)
+        return not_stoq(cfg, stats);
 
     struct udphdr *udp = data + sizeof(*eth) + sizeof(*ip6);
     if (data + sizeof(*eth) + sizeof(*ip6) + sizeof(*udp) > data_end)
-        return XDP_DROP;
+        return XDP_PASS;
 
-    /* Check if it's STOQ traffic (port 9292) */
-    if (bpf_ntohs(udp->dest) != STOQ_PORT && bpf_ntohs(udp->source) != STOQ_PORT)
+    /* STOQ traffic is addressed to our port, or comes from a peer's STOQ port */
+    if (cfg->port == 0 ||
+        (bpf_ntohs(udp->dest) != cfg->port && bpf_ntohs(udp->source) != cfg->port))
+        return not_stoq(cfg, stats);
+    if ((cfg->flags & STOQ_CFG_MATCH_ADDR) && !addr_eq(cfg->bind_addr, &ip6->daddr))
+        return not_stoq(cfg, stats);
+
+    __u64 len = data_end - data;
+    if (len < 64)
+        stats->frame_sizes[0]++;
+    else if (len < 256)
+        stats->frame_sizes[1]++;
+    else if (len < 1024)
+        stats->frame_sizes[2]++;
+    else if (len <= 1500)
+        stats->frame_sizes[3]++;
+    else
+        stats->frame_sizes[4]++;
+    if (len > cfg->max_packet_size)
+        return drop_packet(stats, 0);
+
+    if (rate_limited(cfg, ip6))
+        return drop_packet(stats, &stats->rate_limited);
+
+    /* QUIC header checks */
+    __u8 *quic = (void *)(udp + 1);
+    if ((void *)(quic + 1) > data_end)
         return XDP_PASS;
 
+    if (quic[0] & 0x80) {
+        /* Long header: flags, version, DCID length, DCID */
+        if ((void *)(quic + 6) > data_end)
+            return XDP_PASS;
+        __u32 version = ((__u32)quic[1] << 24) | ((__u32)quic[2] << 16) | ((__u32)quic[3] << 8) | quic[4];
+
+        /* Version negotiation (version 0) is always allowed through */
+        if (version && (cfg->flags & STOQ_CFG_VERSION_ALLOWLIST) && !bpf_map_lookup_elem(&version_map, &version))
+            return drop_packet(stats, &stats->bad_versions);
+
+        /* Clients must pad datagrams carrying Initials to 1200 bytes */
+        __u8 type = (quic[0] & 0x30) >> 4;
+        int initial = (version == QUIC_V1 && type == 0) || (version == QUIC_V2 && type == 1);
+        if (initial && (cfg->flags & STOQ_CFG_MIN_INITIAL) && bpf_ntohs(udp->dest) == cfg->port &&
+            bpf_ntohs(udp->len) - sizeof(*udp) < QUIC_MIN_INITIAL_SIZE)
+            return drop_packet(stats, &stats->short_initials);
+
+        if ((cfg->flags & STOQ_CFG_UNDER_ATTACK) && !cid_known(quic + 6, quic[5], data_end))
+            return drop_packet(stats, &stats->unknown_cids);
+    } else if ((cfg->flags & STOQ_CFG_UNDER_ATTACK) && !cid_known(quic + 1, cfg->cid_len, data_end)) {
+        return drop_packet(stats, &stats->unknown_cids);
+    }
+
     /* Update connection tracking */
-    struct conn_key conn = {};
-    __builtin_memcpy(conn.src_ip, &ip6->saddr, 16);
-    __builtin_memcpy(conn.dst_ip, &ip6->daddr, 16);
-    conn.src_port = udp->source;
-    conn.dst_port = udp->dest;
-
-    struct conn_info *info = bpf_map_lookup_elem(&connection_map, &conn);
-    if (info) {
-        info->packets++;
-        info->bytes += data_end - data;
-        info->last_seen = bpf_ktime_get_ns();
-    } else {
-        struct conn_info new_info = {
-            .packets = 1,
-            .bytes = data_end - data,
-            .last_seen = bpf_ktime_get_ns()
-        };
-        bpf_map_update_elem(&connection_map, &conn, &new_info, BPF_ANY);
+    if (cfg->flags & STOQ_CFG_CONNTRACK) {
+        struct conn_key conn = {};
+        __builtin_memcpy(conn.src_ip, &ip6->saddr, 16);
+        __builtin_memcpy(conn.dst_ip, &ip6->daddr, 16);
+        conn.src_port = udp->source;
+        conn.dst_port = udp->dest;
+
+        /* 1-RTT packets use the short header once the handshake is done */
+        __u32 state = (quic[0] & 0x80) ? CONN_STATE_HANDSHAKE : CONN_STATE_ESTABLISHED;
+        __u64 now = bpf_ktime_get_ns();
+
+        struct conn_info *info = bpf_map_lookup_elem(&connection_map, &conn);
+        if (info) {
+            info->packets++;
+            info->bytes += len;
+            info->last_seen = now;
+            if (state == CONN_STATE_ESTABLISHED)
+                info->state = state;
+        } else {
+            struct conn_info new_info = {
+                .packets = 1,
+                .bytes = len,
+                .last_seen = now,
+                .first_seen = now,
+                .state = state
+            };
+            bpf_map_update_elem(&connection_map, &conn, &new_info, BPF_ANY);
+        }
     }
 
     /* Check filter rules */
@@ -302,8 +729,7 @@ int stoq_xdp_filter(struct xdp_md *ctx) {
     if (action) {
         switch (*action) {
             case 1: /* DROP */
-                stats->packets_dropped++;
-                return XDP_DROP;
+                return drop_packet(stats, 0);
             case 3: /* REDIRECT */
                 stats->packets_redirected++;
                 return XDP_REDIRECT;
@@ -312,15 +738,56 @@ int stoq_xdp_filter(struct xdp_md *ctx) {
         }
     }
 
+    /* Datagrams for our port go to this queue's AF_XDP socket, if it has one */
+    __u32 queue = ctx->rx_queue_index;
+    if ((cfg->flags & STOQ_CFG_XSK_REDIRECT) && bpf_ntohs(udp->dest) == cfg->port &&
+        bpf_map_lookup_elem(&xsks_map, &queue)) {
+        stats->packets_redirected++;
+        stats->bytes_processed += len;
+        return bpf_redirect_map(&xsks_map, queue, XDP_PASS);
+    }
+
     /* Update statistics and pass packet */
     stats->packets_passed++;
-    stats->bytes_processed += data_end - data;
+    stats->bytes_processed += len;
 
     return XDP_PASS;
 }
 
 char _license[] SEC("license") = "GPL";
-"#.to_string()
+"#;
+
+/// eBPF program template generator
+pub struct ProgramGenerator;
+
+impl ProgramGenerator {
+    /// Generate XDP program source code
+    ///
+    /// The program has no built-in port or address: it reads them, along with
+    /// the [`XdpFilterConfig`](super::xdp::XdpFilterConfig) switches, from
+    /// `config_map` on every packet. Until that map is written no traffic
+    /// counts as STOQ and every frame passes.
+    pub fn generate_xdp_source() -> String {
+        let defines: String = [
+            ("STOQ_CFG_QUIC_ONLY", CONFIG_QUIC_ONLY as u32),
+            ("STOQ_CFG_DROP_IPV4", CONFIG_DROP_IPV4 as u32),
+            ("STOQ_CFG_CONNTRACK", CONFIG_CONNTRACK as u32),
+            ("STOQ_CFG_MATCH_ADDR", CONFIG_MATCH_ADDR as u32),
+            ("STOQ_CFG_MIN_INITIAL", CONFIG_MIN_INITIAL as u32),
+            ("STOQ_CFG_VERSION_ALLOWLIST", CONFIG_VERSION_ALLOWLIST as u32),
+            ("STOQ_CFG_UNDER_ATTACK", CONFIG_UNDER_ATTACK as u32),
+            ("STOQ_CFG_XSK_REDIRECT", CONFIG_XSK_REDIRECT as u32),
+            ("CONN_STATE_HANDSHAKE", CONN_STATE_HANDSHAKE),
+            ("CONN_STATE_ESTABLISHED", CONN_STATE_ESTABLISHED),
+            ("MAX_CID_LEN", MAX_CID_LEN as u32),
+            ("QUIC_V1", QUIC_V1),
+            ("QUIC_V2", QUIC_V2),
+            ("QUIC_MIN_INITIAL_SIZE", QUIC_MIN_INITIAL_SIZE),
+        ]
+        .iter()
+        .map(|(name, value)| format!("#define {} {:#x}\n", name, value))
+        .collect();
+        [XDP_PRELUDE, &defines, XDP_PROGRAM].concat()
     }
 
     /// Generate TC (Traffic Control) program source
@@ -359,12 +826,98 @@ mod tests {
 
     #[test]
     fn test_program_generation() {
-        let xdp_source = ProgramGenerator::generate_xdp_source();
-        assert!(xdp_source.contains("stoq_xdp_filter"));
-        assert!(xdp_source.contains("XDP_PASS"));
-
         let tc_source = ProgramGenerator::generate_tc_source();
         assert!(tc_source.contains("stoq_tc_filter"));
         assert!(tc_source.contains("TC_ACT_OK"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_checked_in_xdp_source_is_current() {
+        assert!(
+            include_str!("programs/xdp.c") == ProgramGenerator::generate_xdp_source(),
+            "programs/xdp.c is stale; rebuild it and stoq_xdp.o with \
+             `cargo run --example regenerate_xdp --features ebpf`"
+        );
+    }
+
+    #[test]
+    fn test_prebuilt_object_relocates_only_its_maps() {
+        let object = XdpObject::parse(PREBUILT_XDP_OBJECT).unwrap();
+        let mut referenced: Vec<&str> = object.relocations.iter().map(|(_, name)| name.as_str()).collect();
+        referenced.sort_unstable();
+        referenced.dedup();
+        let mut maps: Vec<&str> = XDP_MAPS.iter().map(|spec| spec.name).collect();
+        maps.sort_unstable();
+        assert_eq!(referenced, maps);
+        assert_eq!(object.license.as_bytes(), b"GPL");
+    }
+
+    #[test]
+    fn test_prebuilt_object_relocation() {
+        use std::os::fd::AsRawFd;
+
+        // A distinct fd stands in for each map
+        let files: HashMap<&str, fs::File> = XDP_MAPS
+            .iter()
+            .map(|spec| (spec.name, fs::File::open("/dev/null").unwrap()))
+            .collect();
+        let mut object = XdpObject::parse(PREBUILT_XDP_OBJECT).unwrap();
+        let original = object.insns.clone();
+        object.relocate(|name| files.get(name).map(|file| file.as_fd())).unwrap();
+
+        for (offset, name) in &object.relocations {
+            let insn = &object.insns[*offset..*offset + 16];
+            assert_eq!(insn[0], BPF_LD_IMM64, "{} at {}", name, offset);
+            assert_eq!(insn[1] >> 4, sys::BPF_PSEUDO_MAP_FD, "{} at {}", name, offset);
+            assert_eq!(i32::from_le_bytes(insn[4..8].try_into().unwrap()), files[name.as_str()].as_raw_fd());
+            assert_eq!(insn[12..16], [0; 4]);
+        }
+        // Only the map loads are touched
+        for (index, (before, after)) in original.iter().zip(&object.insns).enumerate() {
+            if !object.relocations.iter().any(|(offset, _)| (*offset..*offset + 16).contains(&index)) {
+                assert_eq!(before, after, "byte {}", index);
+            }
+        }
+
+        let mut object = XdpObject::parse(PREBUILT_XDP_OBJECT).unwrap();
+        let err = object
+            .relocate(|name| files.get(name).filter(|_| name != "cid_map").map(|file| file.as_fd()))
+            .unwrap_err();
+        assert!(err.to_string().contains("cid_map"), "{}", err);
+    }
+
+    #[test]
+    fn test_out_of_line_calls_are_refused() {
+        let elf = Aligned(*include_bytes!("programs/out_of_line_call.o"));
+        let err = XdpObject::parse(&elf.0).err().unwrap();
+        assert!(err.to_string().contains("must be inlined"), "{}", err);
+        assert!(err.to_string().contains(".text"), "{}", err);
+    }
+
+    #[test]
+    #[ignore = "requires root"]
+    fn test_prebuilt_object_passes_verifier() {
+        let program = XdpProgram::from_elf(PREBUILT_XDP_OBJECT).unwrap();
+        // Unconfigured, every frame passes
+        assert_eq!(program.test_run(&[0u8; 64]).unwrap().action, 2);
+    }
+
+    #[test]
+    fn test_relocate_map_fd() {
+        use std::os::fd::AsRawFd;
+
+        let file = fs::File::open("/dev/null").unwrap();
+        // r1 = 0 ll (two slots), then exit
+        let mut insns = vec![
+            0x18, 0x01, 0, 0, 0, 0, 0, 0,
+            0x00, 0x00, 0, 0, 0, 0, 0, 0,
+            0x95, 0x00, 0, 0, 0, 0, 0, 0,
+        ];
+        relocate_map_fd(&mut insns, 0, file.as_fd()).unwrap();
+        assert_eq!(insns[1], 0x11); // dst r1, src BPF_PSEUDO_MAP_FD
+        assert_eq!(i32::from_le_bytes(insns[4..8].try_into().unwrap()), file.as_raw_fd());
+
+        assert!(relocate_map_fd(&mut insns, 16, file.as_fd()).is_err());
+        assert!(relocate_map_fd(&mut insns, 24, file.as_fd()).is_err());
+    }
+}
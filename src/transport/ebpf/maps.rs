@@ -0,0 +1,530 @@
+//! Typed access to the STOQ XDP program's kernel maps
+//!
+//! Key and value types mirror the structs in
+//! [`ProgramGenerator::generate_xdp_source`](super::loader::ProgramGenerator::generate_xdp_source)
+//! byte for byte, so reads and writes go straight through `bpf(2)`.
+
+use anyhow::{Result, anyhow};
+use std::marker::PhantomData;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+use std::sync::Arc;
+
+use super::sys;
+
+/// Plain-old-data types that can be copied to and from map memory
+///
+/// # Safety
+///
+/// Implementors must be `repr(C)` with no padding bytes and valid for any
+/// bit pattern.
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+
+fn as_bytes<T: Pod>(value: &T) -> &[u8] {
+    // SAFETY: Pod types have no padding, so every byte is initialised
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>()) }
+}
+
+fn from_bytes<T: Pod>(bytes: &[u8]) -> T {
+    assert!(bytes.len() >= std::mem::size_of::<T>());
+    // SAFETY: length checked above, and Pod types accept any bit pattern
+    unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
+/// Connection tracking key (`struct conn_key`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ConnKey {
+    /// Source IPv6 address
+    pub src_ip: [u8; 16],
+    /// Destination IPv6 address
+    pub dst_ip: [u8; 16],
+    /// Source UDP port, network byte order
+    pub src_port: u16,
+    /// Destination UDP port, network byte order
+    pub dst_port: u16,
+}
+
+/// Per-connection counters (`struct conn_info`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnInfo {
+    /// Packets seen
+    pub packets: u64,
+    /// Frame bytes seen
+    pub bytes: u64,
+    /// `bpf_ktime_get_ns` of the last packet
+    pub last_seen: u64,
+    /// `bpf_ktime_get_ns` of the first packet
+    pub first_seen: u64,
+    /// `CONN_STATE_*` value
+    pub state: u32,
+    /// Explicit tail padding
+    pub _pad: u32,
+}
+
+/// `conn_info.state`: only long-header (handshake) packets seen
+pub const CONN_STATE_HANDSHAKE: u32 = 0;
+/// `conn_info.state`: a short-header (1-RTT) packet was seen
+pub const CONN_STATE_ESTABLISHED: u32 = 1;
+
+/// Filter rule key (`struct filter_key`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FilterKey {
+    /// Source IPv6 address
+    pub src_ip: [u8; 16],
+    /// Destination IPv6 address
+    pub dst_ip: [u8; 16],
+}
+
+/// One CPU's slot of the statistics map (`struct xdp_stats`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XdpStatsEntry {
+    /// Packets passed to the network stack
+    pub packets_passed: u64,
+    /// Packets dropped
+    pub packets_dropped: u64,
+    /// Packets redirected
+    pub packets_redirected: u64,
+    /// Bytes of passed STOQ packets
+    pub bytes_processed: u64,
+    /// Drops by the per-source rate limit
+    pub rate_limited: u64,
+    /// Drops of QUIC Initials in short datagrams
+    pub short_initials: u64,
+    /// Drops of versions outside the allowlist
+    pub bad_versions: u64,
+    /// Drops of unknown connection IDs while under attack
+    pub unknown_cids: u64,
+    /// STOQ frames by size: <64, 64-255, 256-1023, 1024-1500 and >1500 bytes
+    pub frame_sizes: [u64; 5],
+}
+
+/// `xdp_config.flags`: drop non-STOQ IPv6 traffic (neighbour discovery excepted)
+pub const CONFIG_QUIC_ONLY: u16 = 1 << 0;
+/// `xdp_config.flags`: drop IPv4 frames
+pub const CONFIG_DROP_IPV4: u16 = 1 << 1;
+/// `xdp_config.flags`: record STOQ flows in `connection_map`
+pub const CONFIG_CONNTRACK: u16 = 1 << 2;
+/// `xdp_config.flags`: only count traffic to `bind_addr` as STOQ
+pub const CONFIG_MATCH_ADDR: u16 = 1 << 3;
+/// `xdp_config.flags`: drop QUIC Initials in datagrams below 1200 bytes
+pub const CONFIG_MIN_INITIAL: u16 = 1 << 4;
+/// `xdp_config.flags`: drop long-header versions missing from `version_map`
+pub const CONFIG_VERSION_ALLOWLIST: u16 = 1 << 5;
+/// `xdp_config.flags`: drop packets whose destination CID is not in `cid_map`
+pub const CONFIG_UNDER_ATTACK: u16 = 1 << 6;
+/// `xdp_config.flags`: redirect datagrams for `port` to the queue's AF_XDP socket
+pub const CONFIG_XSK_REDIRECT: u16 = 1 << 7;
+
+/// Longest QUIC connection ID (RFC 9000)
+pub const MAX_CID_LEN: usize = 20;
+
+/// Filter settings the program reads on every packet (`struct xdp_config`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XdpConfigEntry {
+    /// Local address STOQ is bound to
+    pub bind_addr: [u8; 16],
+    /// STOQ UDP port, host byte order (0 matches nothing)
+    pub port: u16,
+    /// `CONFIG_*` bits
+    pub flags: u16,
+    /// Larger STOQ frames are dropped
+    pub max_packet_size: u32,
+    /// Nanoseconds of credit one packet costs a source /64 (0 = no rate limit)
+    pub rate_interval_ns: u64,
+    /// Credit a source /64 may run ahead by, i.e. its burst
+    pub rate_burst_ns: u64,
+    /// Length of the destination CID in short-header packets
+    pub cid_len: u32,
+    /// Explicit tail padding
+    pub _pad: u32,
+}
+
+/// Connection ID key (`struct cid_key`), zero padded
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CidKey {
+    /// Connection ID length
+    pub len: u8,
+    /// Connection ID bytes
+    pub cid: [u8; MAX_CID_LEN],
+}
+
+impl CidKey {
+    /// Key for `cid`, or None if it is longer than QUIC allows
+    pub fn new(cid: &[u8]) -> Option<Self> {
+        if cid.len() > MAX_CID_LEN {
+            return None;
+        }
+        let mut key = Self { len: cid.len() as u8, ..Default::default() };
+        key.cid[..cid.len()].copy_from_slice(cid);
+        Some(key)
+    }
+}
+
+unsafe impl Pod for XdpConfigEntry {}
+unsafe impl Pod for CidKey {}
+unsafe impl Pod for ConnKey {}
+unsafe impl Pod for ConnInfo {}
+unsafe impl Pod for FilterKey {}
+unsafe impl Pod for XdpStatsEntry {}
+
+/// Map definition matching a `SEC(".maps")` entry of the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapSpec {
+    /// Symbol name in the object file
+    pub name: &'static str,
+    /// `bpf_map_type`
+    pub map_type: u32,
+    /// Key size in bytes
+    pub key_size: u32,
+    /// Value size in bytes (per CPU for per-CPU maps)
+    pub value_size: u32,
+    /// Maximum number of entries
+    pub max_entries: u32,
+}
+
+const fn size<T>() -> u32 {
+    std::mem::size_of::<T>() as u32
+}
+
+/// `config_map`: the single [`XdpConfigEntry`]
+pub const CONFIG_MAP: MapSpec = MapSpec {
+    name: "config_map",
+    map_type: sys::BPF_MAP_TYPE_ARRAY,
+    key_size: size::<u32>(),
+    value_size: size::<XdpConfigEntry>(),
+    max_entries: 1,
+};
+
+/// `connection_map`: per-flow packet and byte counters
+pub const CONNECTION_MAP: MapSpec = MapSpec {
+    name: "connection_map",
+    map_type: sys::BPF_MAP_TYPE_HASH,
+    key_size: size::<ConnKey>(),
+    value_size: size::<ConnInfo>(),
+    max_entries: 65536,
+};
+
+/// `stats_map`: per-CPU program counters
+pub const STATS_MAP: MapSpec = MapSpec {
+    name: "stats_map",
+    map_type: sys::BPF_MAP_TYPE_PERCPU_ARRAY,
+    key_size: size::<u32>(),
+    value_size: size::<XdpStatsEntry>(),
+    max_entries: 1,
+};
+
+/// `filter_map`: per address pair [`XdpAction`](super::xdp::XdpAction) overrides
+pub const FILTER_MAP: MapSpec = MapSpec {
+    name: "filter_map",
+    map_type: sys::BPF_MAP_TYPE_HASH,
+    key_size: size::<FilterKey>(),
+    value_size: size::<u32>(),
+    max_entries: 10000,
+};
+
+/// `rate_map`: GCRA theoretical arrival time per source /64
+pub const RATE_MAP: MapSpec = MapSpec {
+    name: "rate_map",
+    map_type: sys::BPF_MAP_TYPE_LRU_HASH,
+    key_size: size::<u64>(),
+    value_size: size::<u64>(),
+    max_entries: 65536,
+};
+
+/// `version_map`: admitted QUIC versions when the allowlist is on
+pub const VERSION_MAP: MapSpec = MapSpec {
+    name: "version_map",
+    map_type: sys::BPF_MAP_TYPE_HASH,
+    key_size: size::<u32>(),
+    value_size: size::<u32>(),
+    max_entries: 64,
+};
+
+/// `cid_map`: connection IDs issued by the local endpoint
+pub const CID_MAP: MapSpec = MapSpec {
+    name: "cid_map",
+    map_type: sys::BPF_MAP_TYPE_LRU_HASH,
+    key_size: size::<CidKey>(),
+    value_size: size::<u32>(),
+    max_entries: 65536,
+};
+
+/// `xsks_map`: AF_XDP socket per receive queue
+pub const XSK_MAP: MapSpec = MapSpec {
+    name: "xsks_map",
+    map_type: sys::BPF_MAP_TYPE_XSKMAP,
+    key_size: size::<u32>(),
+    value_size: size::<u32>(),
+    max_entries: 64,
+};
+
+/// Every map the XDP program declares
+pub const XDP_MAPS: [MapSpec; 8] = [CONFIG_MAP, CONNECTION_MAP, STATS_MAP, FILTER_MAP, RATE_MAP, VERSION_MAP, CID_MAP, XSK_MAP];
+
+/// A kernel map created from a [`MapSpec`]
+#[derive(Debug)]
+pub struct MapData {
+    spec: MapSpec,
+    fd: OwnedFd,
+}
+
+impl MapData {
+    /// Create the map in the kernel
+    pub fn create(spec: MapSpec) -> Result<Self> {
+        let fd = sys::map_create(spec.name, spec.map_type, spec.key_size, spec.value_size, spec.max_entries)
+            .map_err(|e| anyhow!("Failed to create map {}: {}", spec.name, e))?;
+        Ok(Self { spec, fd })
+    }
+
+    /// Definition the map was created from
+    pub fn spec(&self) -> &MapSpec {
+        &self.spec
+    }
+
+    fn check<K, V>(&self, map_types: &[u32]) -> Result<()> {
+        if !map_types.contains(&self.spec.map_type) || self.spec.key_size != size::<K>() || self.spec.value_size != size::<V>() {
+            return Err(anyhow!("Map {} does not match the requested key/value types", self.spec.name));
+        }
+        Ok(())
+    }
+}
+
+impl AsFd for MapData {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// Typed view of a `BPF_MAP_TYPE_HASH` or `BPF_MAP_TYPE_LRU_HASH` map
+pub struct BpfHashMap<K, V> {
+    map: Arc<MapData>,
+    _types: PhantomData<(K, V)>,
+}
+
+impl<K: Pod, V: Pod> BpfHashMap<K, V> {
+    /// Wrap a hash map whose key and value sizes match `K` and `V`
+    pub fn new(map: Arc<MapData>) -> Result<Self> {
+        map.check::<K, V>(&[sys::BPF_MAP_TYPE_HASH, sys::BPF_MAP_TYPE_LRU_HASH])?;
+        Ok(Self { map, _types: PhantomData })
+    }
+
+    /// Value stored for `key`
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        let mut value = vec![0u8; size::<V>() as usize];
+        let found = sys::map_lookup(self.map.as_fd(), as_bytes(key), &mut value)
+            .map_err(|e| anyhow!("Lookup in {} failed: {}", self.map.spec.name, e))?;
+        Ok(found.then(|| from_bytes(&value)))
+    }
+
+    /// Insert or replace the value for `key`
+    pub fn insert(&self, key: &K, value: &V) -> Result<()> {
+        sys::map_update(self.map.as_fd(), as_bytes(key), as_bytes(value), sys::BPF_ANY)
+            .map_err(|e| anyhow!("Update of {} failed: {}", self.map.spec.name, e))
+    }
+
+    /// Remove `key`, returning whether it was present
+    pub fn remove(&self, key: &K) -> Result<bool> {
+        sys::map_delete(self.map.as_fd(), as_bytes(key))
+            .map_err(|e| anyhow!("Delete from {} failed: {}", self.map.spec.name, e))
+    }
+
+    /// All keys currently in the map
+    pub fn keys(&self) -> Result<Vec<K>> {
+        let mut keys = Vec::new();
+        let mut next = vec![0u8; size::<K>() as usize];
+        loop {
+            let previous = keys.last().map(as_bytes);
+            let more = sys::map_next_key(self.map.as_fd(), previous, &mut next)
+                .map_err(|e| anyhow!("Iterating {} failed: {}", self.map.spec.name, e))?;
+            if !more {
+                return Ok(keys);
+            }
+            keys.push(from_bytes(&next));
+        }
+    }
+
+    /// All entries currently in the map
+    ///
+    /// The program keeps running while this iterates, so entries deleted in
+    /// between are skipped.
+    pub fn entries(&self) -> Result<Vec<(K, V)>> {
+        let mut entries = Vec::new();
+        for key in self.keys()? {
+            if let Some(value) = self.get(&key)? {
+                entries.push((key, value));
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Typed view of a `BPF_MAP_TYPE_ARRAY` map
+pub struct BpfArray<V> {
+    map: Arc<MapData>,
+    _types: PhantomData<V>,
+}
+
+impl<V: Pod> BpfArray<V> {
+    /// Wrap an array whose value size matches `V`
+    pub fn new(map: Arc<MapData>) -> Result<Self> {
+        map.check::<u32, V>(&[sys::BPF_MAP_TYPE_ARRAY])?;
+        Ok(Self { map, _types: PhantomData })
+    }
+
+    /// Value at `index`
+    pub fn get(&self, index: u32) -> Result<V> {
+        let mut value = vec![0u8; size::<V>() as usize];
+        let found = sys::map_lookup(self.map.as_fd(), as_bytes(&index), &mut value)
+            .map_err(|e| anyhow!("Lookup in {} failed: {}", self.map.spec.name, e))?;
+        if !found {
+            return Err(anyhow!("Index {} out of bounds for {}", index, self.map.spec.name));
+        }
+        Ok(from_bytes(&value))
+    }
+
+    /// Overwrite the value at `index`
+    pub fn set(&self, index: u32, value: &V) -> Result<()> {
+        sys::map_update(self.map.as_fd(), as_bytes(&index), as_bytes(value), sys::BPF_ANY)
+            .map_err(|e| anyhow!("Update of {} failed: {}", self.map.spec.name, e))
+    }
+}
+
+/// View of a `BPF_MAP_TYPE_XSKMAP` map, keyed by receive queue
+pub struct XskMap {
+    map: Arc<MapData>,
+}
+
+impl XskMap {
+    /// Wrap an XSKMAP
+    pub fn new(map: Arc<MapData>) -> Result<Self> {
+        map.check::<u32, u32>(&[sys::BPF_MAP_TYPE_XSKMAP])?;
+        Ok(Self { map })
+    }
+
+    /// Deliver redirected packets from `queue` to `socket`
+    pub fn set(&self, queue: u32, socket: BorrowedFd<'_>) -> Result<()> {
+        let fd = socket.as_raw_fd() as u32;
+        sys::map_update(self.map.as_fd(), as_bytes(&queue), as_bytes(&fd), sys::BPF_ANY)
+            .map_err(|e| anyhow!("Update of {} failed: {}", self.map.spec.name, e))
+    }
+
+    /// Stop redirecting `queue`; returns false if no socket was set
+    pub fn clear(&self, queue: u32) -> Result<bool> {
+        sys::map_delete(self.map.as_fd(), as_bytes(&queue))
+            .map_err(|e| anyhow!("Delete from {} failed: {}", self.map.spec.name, e))
+    }
+}
+
+/// Typed view of a `BPF_MAP_TYPE_PERCPU_ARRAY` map
+pub struct PerCpuArray<V> {
+    map: Arc<MapData>,
+    cpus: usize,
+    _types: PhantomData<V>,
+}
+
+impl<V: Pod> PerCpuArray<V> {
+    /// Wrap a per-CPU array whose value size matches `V`
+    pub fn new(map: Arc<MapData>) -> Result<Self> {
+        map.check::<u32, V>(&[sys::BPF_MAP_TYPE_PERCPU_ARRAY])?;
+        let cpus = sys::possible_cpus()?;
+        Ok(Self { map, cpus, _types: PhantomData })
+    }
+
+    /// Per-CPU values are padded to 8 bytes in the syscall buffer
+    fn stride() -> usize {
+        (size::<V>() as usize).next_multiple_of(8)
+    }
+
+    /// One value per possible CPU at `index`
+    pub fn get(&self, index: u32) -> Result<Vec<V>> {
+        let mut buf = vec![0u8; self.cpus * Self::stride()];
+        let found = sys::map_lookup(self.map.as_fd(), as_bytes(&index), &mut buf)
+            .map_err(|e| anyhow!("Lookup in {} failed: {}", self.map.spec.name, e))?;
+        if !found {
+            return Err(anyhow!("Index {} out of bounds for {}", index, self.map.spec.name));
+        }
+        Ok(buf.chunks_exact(Self::stride()).map(from_bytes).collect())
+    }
+
+    /// Overwrite every CPU's value at `index`
+    pub fn set(&self, index: u32, values: &[V]) -> Result<()> {
+        if values.len() != self.cpus {
+            return Err(anyhow!("Expected {} per-CPU values, got {}", self.cpus, values.len()));
+        }
+        let mut buf = vec![0u8; self.cpus * Self::stride()];
+        for (chunk, value) in buf.chunks_exact_mut(Self::stride()).zip(values) {
+            chunk[..size::<V>() as usize].copy_from_slice(as_bytes(value));
+        }
+        sys::map_update(self.map.as_fd(), as_bytes(&index), &buf, sys::BPF_ANY)
+            .map_err(|e| anyhow!("Update of {} failed: {}", self.map.spec.name, e))
+    }
+
+    /// Number of possible CPUs, i.e. values per index
+    pub fn cpus(&self) -> usize {
+        self.cpus
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layouts_match_program() {
+        // Sizes of the C structs in the generated program
+        assert_eq!(CONNECTION_MAP.key_size, 36);
+        assert_eq!(CONNECTION_MAP.value_size, 40);
+        assert_eq!(STATS_MAP.value_size, 104);
+        assert_eq!(FILTER_MAP.key_size, 32);
+        assert_eq!(CONFIG_MAP.value_size, 48);
+        assert_eq!(CID_MAP.key_size, 21);
+        assert_eq!(std::mem::align_of::<ConnKey>(), 2);
+    }
+
+    #[test]
+    fn test_pod_round_trip() {
+        let key = ConnKey { src_ip: [1; 16], dst_ip: [2; 16], src_port: 9292u16.to_be(), dst_port: 443u16.to_be() };
+        assert_eq!(from_bytes::<ConnKey>(as_bytes(&key)), key);
+    }
+
+    #[test]
+    #[ignore = "requires CAP_BPF"]
+    fn test_kernel_map_access() {
+        let filters = BpfHashMap::<FilterKey, u32>::new(Arc::new(MapData::create(FILTER_MAP).unwrap())).unwrap();
+        let key = FilterKey { src_ip: [1; 16], dst_ip: [2; 16] };
+        assert_eq!(filters.get(&key).unwrap(), None);
+        filters.insert(&key, &1).unwrap();
+        assert_eq!(filters.get(&key).unwrap(), Some(1));
+        assert_eq!(filters.entries().unwrap(), vec![(key, 1)]);
+        assert!(filters.remove(&key).unwrap());
+        assert!(!filters.remove(&key).unwrap());
+
+        let stats = PerCpuArray::<XdpStatsEntry>::new(Arc::new(MapData::create(STATS_MAP).unwrap())).unwrap();
+        let values: Vec<_> = (0..stats.cpus() as u64).map(|cpu| XdpStatsEntry { packets_passed: cpu, ..Default::default() }).collect();
+        stats.set(0, &values).unwrap();
+        assert_eq!(stats.get(0).unwrap(), values);
+        assert!(stats.get(1).is_err());
+
+        let config = BpfArray::<XdpConfigEntry>::new(Arc::new(MapData::create(CONFIG_MAP).unwrap())).unwrap();
+        assert_eq!(config.get(0).unwrap(), XdpConfigEntry::default());
+        let entry = XdpConfigEntry { port: 9292, flags: CONFIG_CONNTRACK, ..Default::default() };
+        config.set(0, &entry).unwrap();
+        assert_eq!(config.get(0).unwrap(), entry);
+
+        let cids = BpfHashMap::<CidKey, u32>::new(Arc::new(MapData::create(CID_MAP).unwrap())).unwrap();
+        let cid = CidKey::new(&[7; 8]).unwrap();
+        cids.insert(&cid, &1).unwrap();
+        assert_eq!(cids.keys().unwrap(), vec![cid]);
+
+        // Key and value sizes are checked against the map
+        assert!(BpfHashMap::<ConnKey, ConnInfo>::new(filters.map.clone()).is_err());
+    }
+}
@@ -2,6 +2,10 @@
 //!
 //! Collects kernel-level metrics for transport performance monitoring
 //! including packet counts, latency measurements, and connection tracking.
+//!
+//! Once an XDP program is attached, packet, size, DDoS and connection metrics
+//! come from its `stats_map` and `connection_map`; until then they reflect
+//! what userspace reports.
 
 use anyhow::Result;
 use std::sync::Arc;
@@ -9,7 +13,13 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 use parking_lot::RwLock;
 
-// eBPF map types would normally come from aya
+use super::loader::XdpProgram;
+use super::maps::{ConnInfo, ConnKey, CONN_STATE_ESTABLISHED};
+use super::sys;
+use super::xdp::XdpStats;
+
+/// Default idle time after which a `connection_map` entry is aged out
+pub const DEFAULT_CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
 
 /// eBPF metrics collected at kernel level
 #[derive(Debug, Default, Clone)]
@@ -24,6 +34,8 @@ pub struct EbpfMetrics {
     pub cpu_metrics: CpuMetrics,
     /// Memory metrics
     pub memory_metrics: MemoryMetrics,
+    /// XDP DDoS mitigation drops
+    pub ddos_metrics: DdosMetrics,
 }
 
 /// Packet-level metrics from eBPF
@@ -31,6 +43,8 @@ pub struct EbpfMetrics {
 pub struct PacketMetrics {
     /// Total packets processed
     pub total_packets: u64,
+    /// Total bytes processed
+    pub total_bytes: u64,
     /// Packets by size distribution
     pub size_distribution: SizeDistribution,
     /// Packets per second (current rate)
@@ -74,15 +88,18 @@ pub struct ConnectionMetrics {
 }
 
 /// Connection state distribution
+///
+/// QUIC encrypts `CONNECTION_CLOSE`, so the kernel infers state from header
+/// forms and idleness.
 #[derive(Debug, Default, Clone)]
 pub struct StateDistribution {
-    /// Handshaking
+    /// Handshaking: only long-header packets seen
     pub handshaking: u64,
-    /// Established
+    /// Established: short-header packets seen recently
     pub established: u64,
-    /// Closing
+    /// Closing: idle for over half the idle timeout
     pub closing: u64,
-    /// Time-wait
+    /// Time-wait (QUIC has none; always 0 from kernel data)
     pub time_wait: u64,
 }
 
@@ -152,6 +169,37 @@ pub struct MemoryMetrics {
     pub memcpy_ops: u64,
 }
 
+/// Packets shed by XDP DDoS mitigation
+#[derive(Debug, Default, Clone)]
+pub struct DdosMetrics {
+    /// Dropped by the per-source /64 rate limit
+    pub rate_limited: u64,
+    /// QUIC Initials dropped for arriving in datagrams below 1200 bytes
+    pub short_initials: u64,
+    /// Long-header packets dropped for a version outside the allowlist
+    pub bad_versions: u64,
+    /// Packets dropped under attack for an unknown destination connection ID
+    pub unknown_cids: u64,
+}
+
+impl From<&XdpStats> for DdosMetrics {
+    fn from(stats: &XdpStats) -> Self {
+        Self {
+            rate_limited: stats.rate_limited,
+            short_initials: stats.short_initials,
+            bad_versions: stats.bad_versions,
+            unknown_cids: stats.unknown_cids,
+        }
+    }
+}
+
+impl DdosMetrics {
+    /// All mitigation drops
+    pub fn total(&self) -> u64 {
+        self.rate_limited + self.short_initials + self.bad_versions + self.unknown_cids
+    }
+}
+
 /// eBPF metrics collector
 pub struct EbpfMetricsCollector {
     /// Current metrics
@@ -165,6 +213,20 @@ pub struct EbpfMetricsCollector {
     /// Atomic counters for lock-free updates
     packet_counter: Arc<AtomicU64>,
     byte_counter: Arc<AtomicU64>,
+    /// XDP program whose maps back the metrics
+    program: RwLock<Option<Arc<XdpProgram>>>,
+    /// Idle time after which `connection_map` entries are aged out
+    idle_timeout: Duration,
+}
+
+/// One read of the XDP program's maps
+struct KernelSample {
+    /// Statistics summed across CPUs
+    stats: XdpStats,
+    /// `connection_map` contents
+    connections: Vec<(ConnKey, ConnInfo)>,
+    /// `bpf_ktime_get_ns` time of the read
+    now_ns: u64,
 }
 
 impl EbpfMetricsCollector {
@@ -177,26 +239,43 @@ impl EbpfMetricsCollector {
             interval: Duration::from_secs(1),
             packet_counter: Arc::new(AtomicU64::new(0)),
             byte_counter: Arc::new(AtomicU64::new(0)),
+            program: RwLock::new(None),
+            idle_timeout: DEFAULT_CONNECTION_IDLE_TIMEOUT,
         })
     }
 
+    /// Age out `connection_map` entries idle for longer than `idle_timeout`
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Read metrics from `program`'s maps from now on
+    pub fn attach_program(&self, program: Arc<XdpProgram>) {
+        *self.program.write() = Some(program);
+    }
+
     /// Collect current metrics from eBPF maps
     pub fn collect(&self) -> EbpfMetrics {
         let now = Instant::now();
+        let program = self.program.read().clone();
         let mut metrics = self.current.write();
 
         // Calculate rates
         let elapsed = now.duration_since(*self.last_collection.read()).as_secs_f64();
-        if elapsed > 0.0 {
+        if let Some(program) = program {
+            if let Err(e) = self.read_kernel(&program, &mut metrics, elapsed) {
+                tracing::debug!("Failed to read eBPF metrics maps: {}", e);
+            }
+        } else if elapsed > 0.0 {
             let packets = self.packet_counter.load(Ordering::Relaxed);
             let bytes = self.byte_counter.load(Ordering::Relaxed);
 
             let prev = self.previous.read();
             let packet_diff = packets.saturating_sub(prev.packet_metrics.total_packets);
-            let byte_diff = bytes.saturating_sub(
-                (prev.packet_metrics.bytes_per_second * elapsed) as u64
-            );
+            let byte_diff = bytes.saturating_sub(prev.packet_metrics.total_bytes);
 
+            metrics.packet_metrics.total_bytes = bytes;
             metrics.packet_metrics.packets_per_second = packet_diff as f64 / elapsed;
             metrics.packet_metrics.bytes_per_second = byte_diff as f64 / elapsed;
         }
@@ -208,6 +287,85 @@ impl EbpfMetricsCollector {
         metrics.clone()
     }
 
+    /// Fold the program's maps into `metrics` and age out idle connections
+    fn read_kernel(&self, program: &XdpProgram, metrics: &mut EbpfMetrics, elapsed: f64) -> Result<()> {
+        let connection_map = program.connection_map()?;
+        let sample = KernelSample {
+            stats: XdpStats::from_entries(&program.stats_map()?.get(0)?),
+            connections: connection_map.entries()?,
+            now_ns: sys::ktime_ns(),
+        };
+
+        let idle_ns = self.idle_timeout.as_nanos() as u64;
+        for key in self.apply_kernel_sample(metrics, &sample, elapsed) {
+            // Skip entries the program refreshed since they were read
+            let still_idle = connection_map.get(&key)?
+                .is_some_and(|info| sys::ktime_ns().saturating_sub(info.last_seen) > idle_ns);
+            if still_idle {
+                connection_map.remove(&key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Update `metrics` from a kernel sample, returning the expired connections
+    fn apply_kernel_sample(&self, metrics: &mut EbpfMetrics, sample: &KernelSample, elapsed: f64) -> Vec<ConnKey> {
+        let stats = &sample.stats;
+        let packets = &mut metrics.packet_metrics;
+        let total = stats.packets_passed + stats.packets_dropped + stats.packets_redirected;
+        if elapsed > 0.0 {
+            packets.packets_per_second = total.saturating_sub(packets.total_packets) as f64 / elapsed;
+            packets.bytes_per_second = stats.bytes_processed.saturating_sub(packets.total_bytes) as f64 / elapsed;
+        }
+        packets.total_packets = total;
+        packets.total_bytes = stats.bytes_processed;
+        packets.kernel_drops = stats.packets_dropped;
+        let [tiny, small, medium, large, jumbo] = stats.frame_sizes;
+        packets.size_distribution = SizeDistribution { tiny, small, medium, large, jumbo };
+        metrics.ddos_metrics = DdosMetrics::from(stats);
+
+        let idle_ns = self.idle_timeout.as_nanos() as u64;
+        let closing_ns = idle_ns / 2;
+        let window_start = sample.now_ns.saturating_sub((elapsed * 1e9) as u64);
+        let connections = &mut metrics.connection_metrics;
+        let mut states = StateDistribution::default();
+        let mut new_connections = 0;
+        let mut expired = Vec::new();
+
+        for (key, info) in &sample.connections {
+            let established = info.state == CONN_STATE_ESTABLISHED;
+            let idle = sample.now_ns.saturating_sub(info.last_seen);
+            if idle > idle_ns {
+                // Connections that never left the handshake failed
+                if established {
+                    connections.closed_connections += 1;
+                } else {
+                    connections.failed_connections += 1;
+                }
+                expired.push(*key);
+                continue;
+            }
+
+            if info.first_seen > window_start {
+                new_connections += 1;
+            }
+            if idle > closing_ns {
+                states.closing += 1;
+            } else if established {
+                states.established += 1;
+            } else {
+                states.handshaking += 1;
+            }
+        }
+
+        connections.active_connections = (sample.connections.len() - expired.len()) as u64;
+        if elapsed > 0.0 {
+            connections.new_connections_per_sec = new_connections as f64 / elapsed;
+        }
+        connections.state_distribution = states;
+        expired
+    }
+
     /// Update packet counter (called from XDP program via map)
     pub fn update_packet_count(&self, count: u64) {
         self.packet_counter.store(count, Ordering::Relaxed);
@@ -281,6 +439,11 @@ impl EbpfMetricsCollector {
         metrics.memory_metrics.ring_utilization = ring_util;
     }
 
+    /// Update DDoS mitigation counters from the XDP program's statistics
+    pub fn update_ddos_counters(&self, stats: &XdpStats) {
+        self.current.write().ddos_metrics = DdosMetrics::from(stats);
+    }
+
     /// Record zero-copy operation
     pub fn record_zero_copy(&self) {
         self.current.write().memory_metrics.zero_copy_ops += 1;
@@ -381,6 +544,11 @@ impl std::fmt::Display for EbpfMetrics {
         write!(f, "  Memory: {} zero-copy, {} memcpy ops\n",
             self.memory_metrics.zero_copy_ops,
             self.memory_metrics.memcpy_ops)?;
+        writeln!(f, "  DDoS drops: {} rate-limited, {} short Initials, {} bad versions, {} unknown CIDs",
+            self.ddos_metrics.rate_limited,
+            self.ddos_metrics.short_initials,
+            self.ddos_metrics.bad_versions,
+            self.ddos_metrics.unknown_cids)?;
         Ok(())
     }
 }
@@ -429,4 +597,85 @@ mod tests {
         assert_eq!(metrics.packet_metrics.size_distribution.jumbo, 1);
         assert_eq!(metrics.packet_metrics.total_packets, 5);
     }
+
+    #[test]
+    fn test_ddos_counters() {
+        let collector = EbpfMetricsCollector::new().unwrap();
+        let stats = XdpStats {
+            packets_dropped: 10,
+            rate_limited: 4,
+            short_initials: 3,
+            bad_versions: 2,
+            unknown_cids: 1,
+            ..Default::default()
+        };
+        collector.update_ddos_counters(&stats);
+
+        let metrics = collector.collect();
+        assert_eq!(metrics.ddos_metrics.rate_limited, 4);
+        assert_eq!(metrics.ddos_metrics.unknown_cids, 1);
+        assert_eq!(metrics.ddos_metrics.total(), 10);
+        assert!(metrics.to_string().contains("4 rate-limited"));
+    }
+
+    fn connection(port: u16, first_seen_s: u64, last_seen_s: u64, state: u32) -> (ConnKey, ConnInfo) {
+        let key = ConnKey { src_port: port.to_be(), ..Default::default() };
+        let info = ConnInfo {
+            packets: 1,
+            first_seen: first_seen_s * 1_000_000_000,
+            last_seen: last_seen_s * 1_000_000_000,
+            state,
+            ..Default::default()
+        };
+        (key, info)
+    }
+
+    #[test]
+    fn test_kernel_sample() {
+        use super::super::maps::CONN_STATE_HANDSHAKE;
+
+        let collector = EbpfMetricsCollector::new().unwrap().with_idle_timeout(Duration::from_secs(60));
+        let mut metrics = EbpfMetrics::default();
+        metrics.packet_metrics.total_packets = 100;
+        metrics.packet_metrics.total_bytes = 10_000;
+
+        let sample = KernelSample {
+            stats: XdpStats {
+                packets_passed: 250,
+                packets_dropped: 50,
+                bytes_processed: 30_000,
+                rate_limited: 20,
+                frame_sizes: [1, 2, 3, 4, 5],
+                ..Default::default()
+            },
+            connections: vec![
+                connection(1, 999, 1000, CONN_STATE_ESTABLISHED),
+                connection(2, 500, 995, CONN_STATE_HANDSHAKE),
+                connection(3, 100, 950, CONN_STATE_ESTABLISHED),
+                connection(4, 100, 900, CONN_STATE_ESTABLISHED),
+                connection(5, 100, 910, CONN_STATE_HANDSHAKE),
+            ],
+            now_ns: 1000 * 1_000_000_000,
+        };
+        let expired = collector.apply_kernel_sample(&mut metrics, &sample, 2.0);
+
+        let packets = &metrics.packet_metrics;
+        assert_eq!(packets.total_packets, 300);
+        assert_eq!(packets.kernel_drops, 50);
+        assert_eq!(packets.packets_per_second, 100.0);
+        assert_eq!(packets.bytes_per_second, 10_000.0);
+        assert_eq!(packets.size_distribution.jumbo, 5);
+        assert_eq!(metrics.ddos_metrics.rate_limited, 20);
+
+        // Idle past the timeout is aged out; past half of it is closing
+        let connections = &metrics.connection_metrics;
+        assert_eq!(expired.len(), 2);
+        assert_eq!(connections.closed_connections, 1);
+        assert_eq!(connections.failed_connections, 1);
+        assert_eq!(connections.active_connections, 3);
+        assert_eq!(connections.new_connections_per_sec, 0.5);
+        assert_eq!(connections.state_distribution.established, 1);
+        assert_eq!(connections.state_distribution.handshaking, 1);
+        assert_eq!(connections.state_distribution.closing, 1);
+    }
 }
\ No newline at end of file
@@ -6,14 +6,24 @@
 //! - Transport-level metrics collection in kernel
 //! - Connection tracking and load balancing
 
+#[cfg(feature = "ebpf")]
+pub mod capabilities;
 #[cfg(feature = "ebpf")]
 pub mod xdp;
 #[cfg(feature = "ebpf")]
+pub mod ddos;
+#[cfg(feature = "ebpf")]
 pub mod af_xdp;
 #[cfg(feature = "ebpf")]
 pub mod metrics;
 #[cfg(feature = "ebpf")]
+pub mod udp;
+#[cfg(feature = "ebpf")]
 pub mod loader;
+#[cfg(feature = "ebpf")]
+pub mod maps;
+#[cfg(feature = "ebpf")]
+mod sys;
 
 use anyhow::{Result, anyhow};
 use std::sync::Arc;
@@ -26,76 +36,28 @@ pub struct EbpfCapabilities {
     pub xdp_available: bool,
     /// AF_XDP support available
     pub af_xdp_available: bool,
-    /// Kernel version string
+    /// Kernel release string
     pub kernel_version: String,
     /// CAP_NET_ADMIN available
     pub has_cap_net_admin: bool,
     /// BPF filesystem mounted
     pub bpf_fs_mounted: bool,
+    /// Probe details, including why anything is unavailable
+    pub report: capabilities::CapabilityReport,
 }
 
 impl EbpfCapabilities {
     /// Detect eBPF capabilities on current system
     pub fn detect() -> Self {
-        let kernel_version = Self::get_kernel_version();
-        let has_cap_net_admin = Self::check_cap_net_admin();
-        let bpf_fs_mounted = Self::check_bpf_fs();
-
-        // XDP requires kernel 4.8+, AF_XDP requires kernel 4.18+
-        let (major, minor) = Self::parse_kernel_version(&kernel_version);
-        let xdp_available = major > 4 || (major == 4 && minor >= 8);
-        let af_xdp_available = major > 4 || (major == 4 && minor >= 18);
-
+        let report = capabilities::CapabilityReport::probe();
         Self {
-            xdp_available: xdp_available && has_cap_net_admin && bpf_fs_mounted,
-            af_xdp_available: af_xdp_available && has_cap_net_admin && bpf_fs_mounted,
-            kernel_version,
-            has_cap_net_admin,
-            bpf_fs_mounted,
-        }
-    }
-
-    fn get_kernel_version() -> String {
-        std::fs::read_to_string("/proc/version")
-            .unwrap_or_else(|_| "Unknown".to_string())
-    }
-
-    fn parse_kernel_version(version: &str) -> (u32, u32) {
-        // Parse version like "Linux version 5.10.0-..."
-        let parts: Vec<&str> = version.split_whitespace().collect();
-        if parts.len() > 2 {
-            let version_parts: Vec<&str> = parts[2].split('.').collect();
-            if version_parts.len() >= 2 {
-                let major = version_parts[0].parse().unwrap_or(0);
-                let minor = version_parts[1].parse().unwrap_or(0);
-                return (major, minor);
-            }
+            xdp_available: report.xdp_available(),
+            af_xdp_available: report.af_xdp_available(),
+            kernel_version: report.kernel_release.clone(),
+            has_cap_net_admin: report.has(capabilities::Capability::NetAdmin),
+            bpf_fs_mounted: report.bpf_fs_mounted,
+            report,
         }
-        (0, 0)
-    }
-
-    fn check_cap_net_admin() -> bool {
-        // Check if we have CAP_NET_ADMIN capability
-        #[cfg(target_os = "linux")]
-        {
-            use std::process::Command;
-
-            Command::new("capsh")
-                .args(&["--print"])
-                .output()
-                .map(|output| {
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    stdout.contains("cap_net_admin")
-                })
-                .unwrap_or(false)
-        }
-
-        #[cfg(not(target_os = "linux"))]
-        false
-    }
-
-    fn check_bpf_fs() -> bool {
-        std::path::Path::new("/sys/fs/bpf").exists()
     }
 }
 
@@ -121,12 +83,7 @@ impl EbpfTransport {
     pub fn new() -> Result<Self> {
         let capabilities = EbpfCapabilities::detect();
 
-        tracing::info!("eBPF capabilities detected: {:?}", capabilities);
-
-        if !capabilities.has_cap_net_admin {
-            tracing::warn!("CAP_NET_ADMIN not available, eBPF features disabled");
-            tracing::warn!("To enable eBPF, run with: sudo setcap cap_net_admin+ep <binary>");
-        }
+        tracing::info!("eBPF capabilities detected: {}", capabilities.report);
 
         #[cfg(not(feature = "ebpf"))]
         {
@@ -139,6 +96,10 @@ impl EbpfTransport {
 
         #[cfg(feature = "ebpf")]
         {
+            for requirement in &capabilities.report.missing {
+                tracing::warn!("eBPF requirement not met: {}", requirement);
+            }
+
             let mut xdp_manager = None;
             let mut af_xdp_manager = None;
             let mut metrics_collector = None;
@@ -214,7 +175,11 @@ impl EbpfTransport {
     #[cfg(feature = "ebpf")]
     pub fn attach_xdp(&self, interface: &str) -> Result<()> {
         if let Some(xdp) = &self.xdp_manager {
-            xdp.write().attach(interface)?;
+            let mut xdp = xdp.write();
+            xdp.attach(interface)?;
+            if let (Some(collector), Some(program)) = (&self.metrics_collector, xdp.program()) {
+                collector.attach_program(program.clone());
+            }
             tracing::info!("XDP program attached to interface {}", interface);
             Ok(())
         } else {
@@ -227,6 +192,36 @@ impl EbpfTransport {
         Err(anyhow!("eBPF feature not compiled"))
     }
 
+    /// Set the XDP DDoS mitigation controls
+    #[cfg(feature = "ebpf")]
+    pub fn configure_ddos(&self, config: &ddos::DdosConfig) -> Result<()> {
+        if let Some(xdp) = &self.xdp_manager {
+            xdp.write().configure_ddos(config)
+        } else {
+            Err(anyhow!("XDP not available"))
+        }
+    }
+
+    /// Admit the connection IDs `tracker` records while under attack
+    #[cfg(feature = "ebpf")]
+    pub fn track_connection_ids(&self, tracker: Arc<ddos::ConnectionIdTracker>) -> Result<()> {
+        if let Some(xdp) = &self.xdp_manager {
+            xdp.write().track_connection_ids(tracker)
+        } else {
+            Err(anyhow!("XDP not available"))
+        }
+    }
+
+    /// Point the XDP filter at the transport's port and bind address
+    #[cfg(feature = "ebpf")]
+    pub fn configure_xdp(&self, filter: &xdp::XdpFilterConfig, port: u16, bind_address: std::net::Ipv6Addr) -> Result<()> {
+        if let Some(xdp) = &self.xdp_manager {
+            xdp.write().configure(filter, port, bind_address)
+        } else {
+            Err(anyhow!("XDP not available"))
+        }
+    }
+
     /// Create AF_XDP socket for zero-copy
     #[cfg(feature = "ebpf")]
     pub fn create_af_xdp_socket(&self, interface: &str, queue_id: u32) -> Result<af_xdp::AfXdpSocket> {
@@ -237,6 +232,20 @@ impl EbpfTransport {
         }
     }
 
+    /// Create an AF_XDP socket and have XDP redirect STOQ datagrams on its queue to it
+    ///
+    /// Attaches the XDP program to `interface` first if needed.
+    #[cfg(feature = "ebpf")]
+    pub fn redirect_to_af_xdp(&self, interface: &str, queue_id: u32) -> Result<af_xdp::AfXdpSocket> {
+        let xdp = self.xdp_manager.as_ref().ok_or_else(|| anyhow!("XDP not available"))?;
+        let socket = self.create_af_xdp_socket(interface, queue_id)?;
+        if !xdp.read().is_attached(interface) {
+            self.attach_xdp(interface)?;
+        }
+        xdp.write().redirect_to_socket(&socket)?;
+        Ok(socket)
+    }
+
     #[cfg(not(feature = "ebpf"))]
     pub fn create_af_xdp_socket(&self, _interface: &str, _queue_id: u32) -> Result<()> {
         Err(anyhow!("eBPF feature not compiled"))
@@ -284,14 +293,10 @@ mod tests {
 
     #[test]
     fn test_kernel_version_parsing() {
-        let version = "Linux version 5.10.0-generic";
-        let (major, minor) = EbpfCapabilities::parse_kernel_version(version);
-        assert_eq!(major, 5);
-        assert_eq!(minor, 10);
-
-        let version = "Linux version 4.18.0-generic";
-        let (major, minor) = EbpfCapabilities::parse_kernel_version(version);
-        assert_eq!(major, 4);
-        assert_eq!(minor, 18);
+        let version = capabilities::KernelVersion::parse("5.10.0-generic").unwrap();
+        assert_eq!((version.major, version.minor), (5, 10));
+
+        let version = capabilities::KernelVersion::parse("4.18.0-generic").unwrap();
+        assert_eq!((version.major, version.minor), (4, 18));
     }
-}
\ No newline at end of file
+}
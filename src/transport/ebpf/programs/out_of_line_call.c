@@ -0,0 +1,19 @@
+/*
+ * Test object for the XDP loader: the program calls a function clang keeps
+ * out of line, which the loader must refuse rather than load.
+ *
+ * clang -O2 -target bpf -c out_of_line_call.c -o out_of_line_call.o
+ */
+#include <linux/bpf.h>
+#include <bpf/bpf_helpers.h>
+
+static __attribute__((noinline)) int classify(struct xdp_md *ctx) {
+    return ctx->data_end - ctx->data > 64 ? XDP_DROP : XDP_PASS;
+}
+
+SEC("xdp/stoq_filter")
+int stoq_xdp_filter(struct xdp_md *ctx) {
+    return classify(ctx);
+}
+
+char _license[] SEC("license") = "GPL";
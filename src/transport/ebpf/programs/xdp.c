@@ -0,0 +1,361 @@
+
+#include <linux/bpf.h>
+#include <linux/if_ether.h>
+#include <linux/in.h>
+#include <linux/ip.h>
+#include <linux/ipv6.h>
+#include <linux/udp.h>
+#include <bpf/bpf_helpers.h>
+#include <bpf/bpf_endian.h>
+
+/* Constants generated from their Rust definitions */
+#define STOQ_CFG_QUIC_ONLY 0x1
+#define STOQ_CFG_DROP_IPV4 0x2
+#define STOQ_CFG_CONNTRACK 0x4
+#define STOQ_CFG_MATCH_ADDR 0x8
+#define STOQ_CFG_MIN_INITIAL 0x10
+#define STOQ_CFG_VERSION_ALLOWLIST 0x20
+#define STOQ_CFG_UNDER_ATTACK 0x40
+#define STOQ_CFG_XSK_REDIRECT 0x80
+#define CONN_STATE_HANDSHAKE 0x0
+#define CONN_STATE_ESTABLISHED 0x1
+#define MAX_CID_LEN 0x14
+#define QUIC_V1 0x1
+#define QUIC_V2 0x6b3343cf
+#define QUIC_MIN_INITIAL_SIZE 0x4b0
+
+#ifndef IPPROTO_ICMPV6
+#define IPPROTO_ICMPV6 58
+#endif
+
+struct conn_key {
+    __u8 src_ip[16];
+    __u8 dst_ip[16];
+    __u16 src_port;
+    __u16 dst_port;
+};
+
+struct conn_info {
+    __u64 packets;
+    __u64 bytes;
+    __u64 last_seen;
+    __u64 first_seen;
+    __u32 state;
+    __u32 _pad;
+};
+
+struct xdp_stats {
+    __u64 packets_passed;
+    __u64 packets_dropped;
+    __u64 packets_redirected;
+    __u64 bytes_processed;
+    __u64 rate_limited;
+    __u64 short_initials;
+    __u64 bad_versions;
+    __u64 unknown_cids;
+    __u64 frame_sizes[5];
+};
+
+struct filter_key {
+    __u8 src_ip[16];
+    __u8 dst_ip[16];
+};
+
+struct xdp_config {
+    __u32 bind_addr[4];      /* network byte order, matched if STOQ_CFG_MATCH_ADDR */
+    __u16 port;              /* host byte order, 0 = nothing is STOQ traffic */
+    __u16 flags;
+    __u32 max_packet_size;
+    __u64 rate_interval_ns;  /* credit per packet, 0 = no rate limit */
+    __u64 rate_burst_ns;     /* how far a source may run ahead */
+    __u32 cid_len;           /* short-header destination CID length */
+    __u32 _pad;
+};
+
+struct cid_key {
+    __u8 len;
+    __u8 cid[MAX_CID_LEN];
+};
+
+/* Filter settings, written from user space */
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, __u32);
+    __type(value, struct xdp_config);
+} config_map SEC(".maps");
+
+/* Connection tracking map */
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 65536);
+    __type(key, struct conn_key);
+    __type(value, struct conn_info);
+} connection_map SEC(".maps");
+
+/* Per-CPU statistics */
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, __u32);
+    __type(value, struct xdp_stats);
+} stats_map SEC(".maps");
+
+/* Filter rules map */
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 10000);
+    __type(key, struct filter_key);
+    __type(value, __u32);
+} filter_map SEC(".maps");
+
+/* GCRA theoretical arrival time per source /64 */
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, 65536);
+    __type(key, __u64);
+    __type(value, __u64);
+} rate_map SEC(".maps");
+
+/* Admitted QUIC versions */
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 64);
+    __type(key, __u32);
+    __type(value, __u32);
+} version_map SEC(".maps");
+
+/* AF_XDP socket per receive queue */
+struct {
+    __uint(type, BPF_MAP_TYPE_XSKMAP);
+    __uint(max_entries, 64);
+    __type(key, __u32);
+    __type(value, __u32);
+} xsks_map SEC(".maps");
+
+/* Connection IDs issued by the local endpoint */
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, 65536);
+    __type(key, struct cid_key);
+    __type(value, __u32);
+} cid_map SEC(".maps");
+
+static __always_inline int addr_eq(const __u32 *a, const struct in6_addr *b) {
+    return a[0] == b->in6_u.u6_addr32[0] && a[1] == b->in6_u.u6_addr32[1] &&
+           a[2] == b->in6_u.u6_addr32[2] && a[3] == b->in6_u.u6_addr32[3];
+}
+
+/* Non-STOQ IPv6 traffic passes unless the filter is QUIC-only */
+static __always_inline int not_stoq(const struct xdp_config *cfg, struct xdp_stats *stats) {
+    if (cfg->flags & STOQ_CFG_QUIC_ONLY) {
+        stats->packets_dropped++;
+        return XDP_DROP;
+    }
+    return XDP_PASS;
+}
+
+static __always_inline int drop_packet(struct xdp_stats *stats, __u64 *reason) {
+    stats->packets_dropped++;
+    if (reason)
+        (*reason)++;
+    return XDP_DROP;
+}
+
+/* Token bucket as GCRA: each packet pushes the arrival time forward */
+static __always_inline int rate_limited(const struct xdp_config *cfg, const struct ipv6hdr *ip6) {
+    if (!cfg->rate_interval_ns)
+        return 0;
+
+    __u64 prefix;
+    __builtin_memcpy(&prefix, &ip6->saddr, sizeof(prefix));
+    __u64 now = bpf_ktime_get_ns();
+
+    __u64 *tat = bpf_map_lookup_elem(&rate_map, &prefix);
+    if (!tat) {
+        __u64 next = now + cfg->rate_interval_ns;
+        bpf_map_update_elem(&rate_map, &prefix, &next, BPF_ANY);
+        return 0;
+    }
+
+    __u64 start = *tat > now ? *tat : now;
+    if (start - now > cfg->rate_burst_ns)
+        return 1;
+    *tat = start + cfg->rate_interval_ns;
+    return 0;
+}
+
+static __always_inline int cid_known(const __u8 *cid, __u32 len, void *data_end) {
+    struct cid_key key = {};
+    if (len > MAX_CID_LEN)
+        return 0;
+    key.len = len;
+
+    for (int i = 0; i < MAX_CID_LEN; i++) {
+        if (i >= len)
+            break;
+        if ((void *)(cid + i + 1) > data_end)
+            return 0;
+        key.cid[i] = cid[i];
+    }
+    return bpf_map_lookup_elem(&cid_map, &key) != 0;
+}
+
+SEC("xdp/stoq_filter")
+int stoq_xdp_filter(struct xdp_md *ctx) {
+    void *data = (void *)(long)ctx->data;
+    void *data_end = (void *)(long)ctx->data_end;
+
+    struct ethhdr *eth = data;
+    struct xdp_config *cfg;
+    struct xdp_stats *stats;
+    __u32 key = 0;
+
+    cfg = bpf_map_lookup_elem(&config_map, &key);
+    stats = bpf_map_lookup_elem(&stats_map, &key);
+    if (!cfg || !stats)
+        return XDP_PASS;
+
+    /* Leave anything we cannot parse to the kernel */
+    if (data + sizeof(*eth) > data_end)
+        return XDP_PASS;
+
+    /* ARP and other non-IP protocols always pass; IPv4 only on request */
+    __u16 proto = bpf_ntohs(eth->h_proto);
+    if (proto == ETH_P_IP && (cfg->flags & STOQ_CFG_DROP_IPV4))
+        return drop_packet(stats, 0);
+    if (proto != ETH_P_IPV6)
+        return XDP_PASS;
+
+    struct ipv6hdr *ip6 = data + sizeof(*eth);
+    if (data + sizeof(*eth) + sizeof(*ip6) > data_end)
+        return XDP_PASS;
+
+    /* Neighbour discovery must keep working even when QUIC-only */
+    if (ip6->nexthdr == IPPROTO_ICMPV6)
+        return XDP_PASS;
+    if (ip6->nexthdr != IPPROTO_UDP)
+        return not_stoq(cfg, stats);
+
+    struct udphdr *udp = data + sizeof(*eth) + sizeof(*ip6);
+    if (data + sizeof(*eth) + sizeof(*ip6) + sizeof(*udp) > data_end)
+        return XDP_PASS;
+
+    /* STOQ traffic is addressed to our port, or comes from a peer's STOQ port */
+    if (cfg->port == 0 ||
+        (bpf_ntohs(udp->dest) != cfg->port && bpf_ntohs(udp->source) != cfg->port))
+        return not_stoq(cfg, stats);
+    if ((cfg->flags & STOQ_CFG_MATCH_ADDR) && !addr_eq(cfg->bind_addr, &ip6->daddr))
+        return not_stoq(cfg, stats);
+
+    __u64 len = data_end - data;
+    if (len < 64)
+        stats->frame_sizes[0]++;
+    else if (len < 256)
+        stats->frame_sizes[1]++;
+    else if (len < 1024)
+        stats->frame_sizes[2]++;
+    else if (len <= 1500)
+        stats->frame_sizes[3]++;
+    else
+        stats->frame_sizes[4]++;
+    if (len > cfg->max_packet_size)
+        return drop_packet(stats, 0);
+
+    if (rate_limited(cfg, ip6))
+        return drop_packet(stats, &stats->rate_limited);
+
+    /* QUIC header checks */
+    __u8 *quic = (void *)(udp + 1);
+    if ((void *)(quic + 1) > data_end)
+        return XDP_PASS;
+
+    if (quic[0] & 0x80) {
+        /* Long header: flags, version, DCID length, DCID */
+        if ((void *)(quic + 6) > data_end)
+            return XDP_PASS;
+        __u32 version = ((__u32)quic[1] << 24) | ((__u32)quic[2] << 16) | ((__u32)quic[3] << 8) | quic[4];
+
+        /* Version negotiation (version 0) is always allowed through */
+        if (version && (cfg->flags & STOQ_CFG_VERSION_ALLOWLIST) && !bpf_map_lookup_elem(&version_map, &version))
+            return drop_packet(stats, &stats->bad_versions);
+
+        /* Clients must pad datagrams carrying Initials to 1200 bytes */
+        __u8 type = (quic[0] & 0x30) >> 4;
+        int initial = (version == QUIC_V1 && type == 0) || (version == QUIC_V2 && type == 1);
+        if (initial && (cfg->flags & STOQ_CFG_MIN_INITIAL) && bpf_ntohs(udp->dest) == cfg->port &&
+            bpf_ntohs(udp->len) - sizeof(*udp) < QUIC_MIN_INITIAL_SIZE)
+            return drop_packet(stats, &stats->short_initials);
+
+        if ((cfg->flags & STOQ_CFG_UNDER_ATTACK) && !cid_known(quic + 6, quic[5], data_end))
+            return drop_packet(stats, &stats->unknown_cids);
+    } else if ((cfg->flags & STOQ_CFG_UNDER_ATTACK) && !cid_known(quic + 1, cfg->cid_len, data_end)) {
+        return drop_packet(stats, &stats->unknown_cids);
+    }
+
+    /* Update connection tracking */
+    if (cfg->flags & STOQ_CFG_CONNTRACK) {
+        struct conn_key conn = {};
+        __builtin_memcpy(conn.src_ip, &ip6->saddr, 16);
+        __builtin_memcpy(conn.dst_ip, &ip6->daddr, 16);
+        conn.src_port = udp->source;
+        conn.dst_port = udp->dest;
+
+        /* 1-RTT packets use the short header once the handshake is done */
+        __u32 state = (quic[0] & 0x80) ? CONN_STATE_HANDSHAKE : CONN_STATE_ESTABLISHED;
+        __u64 now = bpf_ktime_get_ns();
+
+        struct conn_info *info = bpf_map_lookup_elem(&connection_map, &conn);
+        if (info) {
+            info->packets++;
+            info->bytes += len;
+            info->last_seen = now;
+            if (state == CONN_STATE_ESTABLISHED)
+                info->state = state;
+        } else {
+            struct conn_info new_info = {
+                .packets = 1,
+                .bytes = len,
+                .last_seen = now,
+                .first_seen = now,
+                .state = state
+            };
+            bpf_map_update_elem(&connection_map, &conn, &new_info, BPF_ANY);
+        }
+    }
+
+    /* Check filter rules */
+    struct filter_key filter = {};
+    __builtin_memcpy(filter.src_ip, &ip6->saddr, 16);
+    __builtin_memcpy(filter.dst_ip, &ip6->daddr, 16);
+
+    __u32 *action = bpf_map_lookup_elem(&filter_map, &filter);
+    if (action) {
+        switch (*action) {
+            case 1: /* DROP */
+                return drop_packet(stats, 0);
+            case 3: /* REDIRECT */
+                stats->packets_redirected++;
+                return XDP_REDIRECT;
+            default:
+                break;
+        }
+    }
+
+    /* Datagrams for our port go to this queue's AF_XDP socket, if it has one */
+    __u32 queue = ctx->rx_queue_index;
+    if ((cfg->flags & STOQ_CFG_XSK_REDIRECT) && bpf_ntohs(udp->dest) == cfg->port &&
+        bpf_map_lookup_elem(&xsks_map, &queue)) {
+        stats->packets_redirected++;
+        stats->bytes_processed += len;
+        return bpf_redirect_map(&xsks_map, queue, XDP_PASS);
+    }
+
+    /* Update statistics and pass packet */
+    stats->packets_passed++;
+    stats->bytes_processed += len;
+
+    return XDP_PASS;
+}
+
+char _license[] SEC("license") = "GPL";
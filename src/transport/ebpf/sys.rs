@@ -0,0 +1,873 @@
+//! Raw `bpf(2)` syscall wrappers
+//!
+//! The handful of bpf commands STOQ needs: map creation and element access,
+//! program loading, `BPF_PROG_TEST_RUN` and XDP attachment through a bpf link.
+//! Attribute structs mirror the matching members of `union bpf_attr` and carry
+//! explicit padding so every byte passed to the kernel is initialised.
+//! These stand in for aya, which the offline build cannot depend on; see
+//! "Loading Without aya" in `EBPF_STATUS.md`.
+//!
+//! Also the AF_XDP socket setup calls (UMEM registration, ring sizing and
+//! mapping, binding and wakeups) and the rtnetlink IPv6 route and neighbour
+//! lookups AF_XDP senders need to address Ethernet frames.
+
+use std::ffi::CStr;
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+
+const BPF_MAP_CREATE: libc::c_long = 0;
+const BPF_MAP_LOOKUP_ELEM: libc::c_long = 1;
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+const BPF_MAP_DELETE_ELEM: libc::c_long = 3;
+const BPF_MAP_GET_NEXT_KEY: libc::c_long = 4;
+const BPF_PROG_LOAD: libc::c_long = 5;
+const BPF_PROG_TEST_RUN: libc::c_long = 10;
+const BPF_LINK_CREATE: libc::c_long = 28;
+
+pub(crate) const BPF_MAP_TYPE_HASH: u32 = 1;
+pub(crate) const BPF_MAP_TYPE_ARRAY: u32 = 2;
+pub(crate) const BPF_MAP_TYPE_PERCPU_ARRAY: u32 = 6;
+pub(crate) const BPF_MAP_TYPE_LRU_HASH: u32 = 9;
+pub(crate) const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+pub(crate) const BPF_PROG_TYPE_XDP: u32 = 6;
+/// `bpf_attach_type` for XDP links
+const BPF_XDP: u32 = 37;
+/// Create or update an element
+pub(crate) const BPF_ANY: u64 = 0;
+/// `src_reg` marking a 64-bit immediate load as a map file descriptor
+pub(crate) const BPF_PSEUDO_MAP_FD: u8 = 1;
+
+pub(crate) const XDP_FLAGS_SKB_MODE: u32 = 1 << 1;
+pub(crate) const XDP_FLAGS_DRV_MODE: u32 = 1 << 2;
+pub(crate) const XDP_FLAGS_HW_MODE: u32 = 1 << 3;
+
+/// Verifier log size used when a load fails and is retried for diagnostics
+const VERIFIER_LOG_SIZE: usize = 1 << 20;
+
+#[repr(C)]
+#[derive(Default)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+    inner_map_fd: u32,
+    numa_node: u32,
+    map_name: [u8; 16],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct MapElemAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+    prog_ifindex: u32,
+    expected_attach_type: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct TestRunAttr {
+    prog_fd: u32,
+    retval: u32,
+    data_size_in: u32,
+    data_size_out: u32,
+    data_in: u64,
+    data_out: u64,
+    repeat: u32,
+    duration: u32,
+    ctx_size_in: u32,
+    ctx_size_out: u32,
+    ctx_in: u64,
+    ctx_out: u64,
+    flags: u32,
+    cpu: u32,
+    batch_size: u32,
+    _pad: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct LinkCreateAttr {
+    prog_fd: u32,
+    target_ifindex: u32,
+    attach_type: u32,
+    flags: u32,
+}
+
+/// Result of a `BPF_PROG_TEST_RUN`
+pub(crate) struct TestRunOutput {
+    pub retval: u32,
+    pub duration_ns: u32,
+    pub data: Vec<u8>,
+}
+
+fn bpf<T>(cmd: libc::c_long, attr: &mut T) -> io::Result<libc::c_long> {
+    // SAFETY: `attr` is a fully initialised repr(C) prefix of `union bpf_attr`
+    // for `cmd`, and any pointers it holds outlive the call.
+    let ret = unsafe {
+        libc::syscall(libc::SYS_bpf, cmd, attr as *mut T, std::mem::size_of::<T>() as libc::c_uint)
+    };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn bpf_fd<T>(cmd: libc::c_long, attr: &mut T) -> io::Result<OwnedFd> {
+    let fd = bpf(cmd, attr)? as RawFd;
+    // SAFETY: the kernel returned a new file descriptor that nothing else owns
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Kernel object names are at most 15 bytes plus the terminator
+fn object_name(name: &str) -> [u8; 16] {
+    let mut buf = [0u8; 16];
+    for (dst, src) in buf.iter_mut().zip(name.bytes().take(15)) {
+        *dst = src;
+    }
+    buf
+}
+
+fn fd_u32(fd: BorrowedFd<'_>) -> u32 {
+    fd.as_raw_fd() as u32
+}
+
+pub(crate) fn map_create(name: &str, map_type: u32, key_size: u32, value_size: u32, max_entries: u32) -> io::Result<OwnedFd> {
+    let mut attr = MapCreateAttr {
+        map_type,
+        key_size,
+        value_size,
+        max_entries,
+        map_name: object_name(name),
+        ..Default::default()
+    };
+    bpf_fd(BPF_MAP_CREATE, &mut attr)
+}
+
+/// Look up `key`, filling `value`; returns false if the key is absent
+pub(crate) fn map_lookup(fd: BorrowedFd<'_>, key: &[u8], value: &mut [u8]) -> io::Result<bool> {
+    let mut attr = MapElemAttr {
+        map_fd: fd_u32(fd),
+        key: key.as_ptr() as u64,
+        value: value.as_mut_ptr() as u64,
+        ..Default::default()
+    };
+    match bpf(BPF_MAP_LOOKUP_ELEM, &mut attr) {
+        Ok(_) => Ok(true),
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn map_update(fd: BorrowedFd<'_>, key: &[u8], value: &[u8], flags: u64) -> io::Result<()> {
+    let mut attr = MapElemAttr {
+        map_fd: fd_u32(fd),
+        key: key.as_ptr() as u64,
+        value: value.as_ptr() as u64,
+        flags,
+        ..Default::default()
+    };
+    bpf(BPF_MAP_UPDATE_ELEM, &mut attr).map(drop)
+}
+
+/// Delete `key`; returns false if the key was absent
+pub(crate) fn map_delete(fd: BorrowedFd<'_>, key: &[u8]) -> io::Result<bool> {
+    let mut attr = MapElemAttr {
+        map_fd: fd_u32(fd),
+        key: key.as_ptr() as u64,
+        ..Default::default()
+    };
+    match bpf(BPF_MAP_DELETE_ELEM, &mut attr) {
+        Ok(_) => Ok(true),
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Key following `key` (or the first key for None); returns false at the end
+pub(crate) fn map_next_key(fd: BorrowedFd<'_>, key: Option<&[u8]>, next: &mut [u8]) -> io::Result<bool> {
+    let mut attr = MapElemAttr {
+        map_fd: fd_u32(fd),
+        key: key.map_or(0, |key| key.as_ptr() as u64),
+        value: next.as_mut_ptr() as u64,
+        ..Default::default()
+    };
+    match bpf(BPF_MAP_GET_NEXT_KEY, &mut attr) {
+        Ok(_) => Ok(true),
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Load a program, retrying with the verifier log enabled if it is rejected
+pub(crate) fn prog_load(prog_type: u32, name: &str, insns: &[u8], license: &CStr) -> io::Result<OwnedFd> {
+    let mut attr = ProgLoadAttr {
+        prog_type,
+        insn_cnt: (insns.len() / 8) as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        prog_name: object_name(name),
+        ..Default::default()
+    };
+    let error = match bpf_fd(BPF_PROG_LOAD, &mut attr) {
+        Ok(fd) => return Ok(fd),
+        Err(e) => e,
+    };
+
+    let mut log = vec![0u8; VERIFIER_LOG_SIZE];
+    attr.log_level = 1;
+    attr.log_size = log.len() as u32;
+    attr.log_buf = log.as_mut_ptr() as u64;
+    if let Ok(fd) = bpf_fd(BPF_PROG_LOAD, &mut attr) {
+        return Ok(fd);
+    }
+    let log = CStr::from_bytes_until_nul(&log)
+        .map(|log| log.to_string_lossy().trim().to_string())
+        .unwrap_or_default();
+    if log.is_empty() {
+        Err(error)
+    } else {
+        Err(io::Error::new(error.kind(), format!("{}; verifier log:\n{}", error, log)))
+    }
+}
+
+/// Run a program once per `repeat` against `data` without attaching it
+pub(crate) fn prog_test_run(fd: BorrowedFd<'_>, data: &[u8], repeat: u32) -> io::Result<TestRunOutput> {
+    // XDP programs may grow the frame by up to the headroom the kernel reserves
+    let mut out = vec![0u8; data.len() + 256];
+    let mut attr = TestRunAttr {
+        prog_fd: fd_u32(fd),
+        data_size_in: data.len() as u32,
+        data_size_out: out.len() as u32,
+        data_in: data.as_ptr() as u64,
+        data_out: out.as_mut_ptr() as u64,
+        repeat,
+        ..Default::default()
+    };
+    bpf(BPF_PROG_TEST_RUN, &mut attr)?;
+    out.truncate(attr.data_size_out as usize);
+    Ok(TestRunOutput {
+        retval: attr.retval,
+        duration_ns: attr.duration,
+        data: out,
+    })
+}
+
+/// Attach an XDP program to an interface; the link detaches when dropped
+pub(crate) fn link_create_xdp(prog: BorrowedFd<'_>, ifindex: u32, flags: u32) -> io::Result<OwnedFd> {
+    let mut attr = LinkCreateAttr {
+        prog_fd: fd_u32(prog),
+        target_ifindex: ifindex,
+        attach_type: BPF_XDP,
+        flags,
+    };
+    bpf_fd(BPF_LINK_CREATE, &mut attr)
+}
+
+/// Kernel release as `uname -r` prints it
+pub(crate) fn kernel_release() -> io::Result<String> {
+    // SAFETY: utsname is plain data
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    // SAFETY: uname fills the struct with NUL-terminated strings
+    cvt(unsafe { libc::uname(&mut name) })?;
+    // SAFETY: `release` is NUL-terminated within the array
+    let release = unsafe { CStr::from_ptr(name.release.as_ptr()) };
+    Ok(release.to_string_lossy().into_owned())
+}
+
+pub(crate) fn if_index(interface: &str) -> io::Result<u32> {
+    let name = std::ffi::CString::new(interface)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "interface name contains NUL"))?;
+    // SAFETY: `name` is a valid NUL-terminated string
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Memory mapping, unmapped when dropped
+pub(crate) struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: the mapping is plain memory owned by this value; callers
+// synchronise access to its contents
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Private anonymous memory, optionally backed by huge pages
+    pub(crate) fn anonymous(len: usize, huge_pages: bool) -> io::Result<Self> {
+        let mut flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_POPULATE;
+        if huge_pages {
+            flags |= libc::MAP_HUGETLB;
+        }
+        Self::map(len, flags, -1, 0)
+    }
+
+    /// A ring of an AF_XDP socket at its `XDP_*_PGOFF` offset
+    pub(crate) fn ring(fd: BorrowedFd<'_>, offset: u64, len: usize) -> io::Result<Self> {
+        Self::map(len, libc::MAP_SHARED | libc::MAP_POPULATE, fd.as_raw_fd(), offset as libc::off_t)
+    }
+
+    fn map(len: usize, flags: libc::c_int, fd: RawFd, offset: libc::off_t) -> io::Result<Self> {
+        // SAFETY: a fresh mapping does not alias any Rust-owned memory
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, flags, fd, offset)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr: ptr as *mut u8, len })
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and `len` describe a mapping this value owns
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+pub(crate) fn xsk_socket() -> io::Result<OwnedFd> {
+    // SAFETY: plain socket(2) call
+    let fd = cvt(unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) })?;
+    // SAFETY: the kernel returned a new file descriptor that nothing else owns
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn xsk_setsockopt<T>(fd: BorrowedFd<'_>, name: libc::c_int, value: &T) -> io::Result<()> {
+    // SAFETY: `value` is a repr(C) option value of the size passed
+    cvt(unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_XDP,
+            name,
+            value as *const T as *const libc::c_void,
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    })
+    .map(drop)
+}
+
+/// Register `umem` as the socket's packet buffer, split into `chunk_size` frames
+pub(crate) fn xsk_register_umem(fd: BorrowedFd<'_>, umem: &Mmap, chunk_size: u32, headroom: u32) -> io::Result<()> {
+    let reg = libc::xdp_umem_reg {
+        addr: umem.as_ptr() as u64,
+        len: umem.len() as u64,
+        chunk_size,
+        headroom,
+        flags: 0,
+        tx_metadata_len: 0,
+    };
+    xsk_setsockopt(fd, libc::XDP_UMEM_REG, &reg)
+}
+
+/// Size one of the `XDP_*_RING` rings
+pub(crate) fn xsk_ring_size(fd: BorrowedFd<'_>, ring: libc::c_int, entries: u32) -> io::Result<()> {
+    xsk_setsockopt(fd, ring, &entries)
+}
+
+pub(crate) fn xsk_mmap_offsets(fd: BorrowedFd<'_>) -> io::Result<libc::xdp_mmap_offsets> {
+    // SAFETY: xdp_mmap_offsets is plain integers, valid when zeroed
+    let mut offsets: libc::xdp_mmap_offsets = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of_val(&offsets) as libc::socklen_t;
+    // SAFETY: `offsets` is writable for `len` bytes
+    cvt(unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_XDP,
+            libc::XDP_MMAP_OFFSETS,
+            &mut offsets as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    })?;
+    if len as usize != std::mem::size_of_val(&offsets) {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "kernel without AF_XDP ring flags"));
+    }
+    Ok(offsets)
+}
+
+/// Bind the socket to a device queue with `XDP_COPY`/`XDP_ZEROCOPY` style `flags`
+pub(crate) fn xsk_bind(fd: BorrowedFd<'_>, ifindex: u32, queue_id: u32, flags: u16) -> io::Result<()> {
+    let addr = libc::sockaddr_xdp {
+        sxdp_family: libc::AF_XDP as u16,
+        sxdp_flags: flags,
+        sxdp_ifindex: ifindex,
+        sxdp_queue_id: queue_id,
+        sxdp_shared_umem_fd: 0,
+    };
+    // SAFETY: `addr` is a valid sockaddr_xdp of the size passed
+    cvt(unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            &addr as *const _ as *const libc::sockaddr,
+            std::mem::size_of_val(&addr) as libc::socklen_t,
+        )
+    })
+    .map(drop)
+}
+
+/// Ask the kernel to process the TX ring
+pub(crate) fn xsk_kick_tx(fd: BorrowedFd<'_>) -> io::Result<()> {
+    // SAFETY: a zero-length send reads no memory
+    let ret = unsafe { libc::sendto(fd.as_raw_fd(), std::ptr::null(), 0, libc::MSG_DONTWAIT, std::ptr::null(), 0) };
+    match ret {
+        0.. => Ok(()),
+        // The ring is being drained already, or frames are still in flight
+        _ => match io::Error::last_os_error() {
+            e if matches!(e.raw_os_error(), Some(libc::EAGAIN | libc::EBUSY | libc::ENOBUFS)) => Ok(()),
+            e => Err(e),
+        },
+    }
+}
+
+/// Ask the kernel to refill RX from the fill ring
+pub(crate) fn xsk_wake_rx(fd: BorrowedFd<'_>) {
+    // SAFETY: a zero-length receive writes no memory
+    unsafe {
+        libc::recvfrom(fd.as_raw_fd(), std::ptr::null_mut(), 0, libc::MSG_DONTWAIT, std::ptr::null_mut(), std::ptr::null_mut())
+    };
+}
+
+/// Hardware address of an interface, as seen from the calling thread's namespace
+pub(crate) fn interface_mac(interface: &str) -> io::Result<[u8; 6]> {
+    // SAFETY: ifreq is plain data
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    if interface.len() >= request.ifr_name.len() || interface.contains('\0') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "bad interface name"));
+    }
+    for (dst, src) in request.ifr_name.iter_mut().zip(interface.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    // SAFETY: plain socket creation
+    let fd = cvt(unsafe { libc::socket(libc::AF_INET6, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) })?;
+    // SAFETY: `fd` was just created and is owned here
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    // SAFETY: SIOCGIFHWADDR reads the name and fills the address of `request`
+    cvt(unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCGIFHWADDR, &mut request) })?;
+    // SAFETY: the ioctl filled ifru_hwaddr
+    let address = unsafe { request.ifr_ifru.ifru_hwaddr.sa_data };
+    Ok(std::array::from_fn(|i| address[i] as u8))
+}
+
+const RTM_NEWROUTE: u16 = 24;
+const RTM_GETROUTE: u16 = 26;
+const RTM_NEWNEIGH: u16 = 28;
+const RTM_GETNEIGH: u16 = 30;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 1;
+const NLM_F_DUMP: u16 = 0x300;
+const NDA_DST: u16 = 1;
+const NDA_LLADDR: u16 = 2;
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+const NUD_INCOMPLETE: u16 = 0x01;
+const NUD_FAILED: u16 = 0x20;
+const NLMSG_HDRLEN: usize = 16;
+const NDMSG_LEN: usize = 12;
+const RTMSG_LEN: usize = 12;
+
+/// `nlmsghdr` followed by `ndmsg`
+#[repr(C)]
+#[derive(Default)]
+struct NeighDumpRequest {
+    len: u32,
+    kind: u16,
+    flags: u16,
+    seq: u32,
+    pid: u32,
+    family: u8,
+    _pad1: u8,
+    _pad2: u16,
+    ifindex: i32,
+    state: u16,
+    ndm_flags: u8,
+    ndm_type: u8,
+}
+
+/// An IPv6 neighbour's address and link-layer address
+pub(crate) type Neighbour = ([u8; 16], [u8; 6]);
+
+/// `nlmsghdr` followed by `rtmsg` and the `RTA_DST` and `RTA_OIF` attributes
+#[repr(C)]
+#[derive(Default)]
+struct RouteGetRequest {
+    len: u32,
+    kind: u16,
+    flags: u16,
+    seq: u32,
+    pid: u32,
+    family: u8,
+    dst_len: u8,
+    src_len: u8,
+    tos: u8,
+    table: u8,
+    protocol: u8,
+    scope: u8,
+    rtm_type: u8,
+    rtm_flags: u32,
+    dst_attr_len: u16,
+    dst_attr_type: u16,
+    dst: [u8; 16],
+    oif_attr_len: u16,
+    oif_attr_type: u16,
+    oif: u32,
+}
+
+/// Open a route netlink socket and send `request` on it
+fn netlink_request<T>(request: &T) -> io::Result<OwnedFd> {
+    // SAFETY: plain socket(2) call
+    let fd = cvt(unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) })?;
+    // SAFETY: the kernel returned a new file descriptor that nothing else owns
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    // SAFETY: `request` is readable for its size; the kernel fills in the sender
+    cvt(unsafe {
+        libc::send(fd.as_raw_fd(), request as *const T as *const libc::c_void, std::mem::size_of::<T>(), 0) as libc::c_int
+    })?;
+    Ok(fd)
+}
+
+fn netlink_recv(fd: &OwnedFd, buf: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: `buf` is writable for its length
+    let len = unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(len as usize)
+}
+
+/// IPv6 neighbours on `ifindex` with a resolved link-layer address
+pub(crate) fn ipv6_neighbours(ifindex: u32) -> io::Result<Vec<Neighbour>> {
+    let fd = netlink_request(&NeighDumpRequest {
+        len: std::mem::size_of::<NeighDumpRequest>() as u32,
+        kind: RTM_GETNEIGH,
+        flags: NLM_F_REQUEST | NLM_F_DUMP,
+        seq: 1,
+        family: libc::AF_INET6 as u8,
+        ..Default::default()
+    })?;
+
+    let mut neighbours = Vec::new();
+    let mut buf = vec![0u8; 32 * 1024];
+    loop {
+        let len = netlink_recv(&fd, &mut buf)?;
+        if parse_neighbours(&buf[..len], ifindex, &mut neighbours)? {
+            return Ok(neighbours);
+        }
+    }
+}
+
+/// Where the kernel would send IPv6 packets for a destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Route {
+    /// Outgoing interface
+    pub(crate) ifindex: u32,
+    /// Router to hand the packet to, or `None` when the destination is on-link
+    pub(crate) gateway: Option<[u8; 16]>,
+}
+
+/// Look up the route to `destination` leaving through `ifindex`, as `ip -6 route get` does
+pub(crate) fn ipv6_route(destination: [u8; 16], ifindex: u32) -> io::Result<Route> {
+    let fd = netlink_request(&RouteGetRequest {
+        len: std::mem::size_of::<RouteGetRequest>() as u32,
+        kind: RTM_GETROUTE,
+        flags: NLM_F_REQUEST,
+        seq: 1,
+        family: libc::AF_INET6 as u8,
+        dst_len: 128,
+        dst_attr_len: 20,
+        dst_attr_type: RTA_DST,
+        dst: destination,
+        oif_attr_len: 8,
+        oif_attr_type: RTA_OIF,
+        oif: ifindex,
+        ..Default::default()
+    })?;
+
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let len = netlink_recv(&fd, &mut buf)?;
+        if let Some(route) = parse_route(&buf[..len])? {
+            return Ok(route);
+        }
+    }
+}
+
+fn read_u16(buf: &[u8], at: usize) -> u16 {
+    u16::from_ne_bytes([buf[at], buf[at + 1]])
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "truncated netlink message")
+}
+
+/// Call `f` with the type and payload of every `rtattr` in `attrs`
+fn for_each_attr(mut attrs: &[u8], mut f: impl FnMut(u16, &[u8])) -> io::Result<()> {
+    while attrs.len() >= 4 {
+        let attr_len = read_u16(attrs, 0) as usize;
+        if attr_len < 4 || attr_len > attrs.len() {
+            return Err(truncated());
+        }
+        f(read_u16(attrs, 2), &attrs[4..attr_len]);
+        attrs = &attrs[((attr_len + 3) & !3).min(attrs.len())..];
+    }
+    Ok(())
+}
+
+/// Call `f` with the type and body of every message in one netlink datagram
+/// until it returns `Some`, failing on an error report
+fn for_each_message<T>(mut buf: &[u8], mut f: impl FnMut(u16, &[u8]) -> io::Result<Option<T>>) -> io::Result<Option<T>> {
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = u32::from_ne_bytes(buf[..4].try_into().unwrap()) as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            return Err(truncated());
+        }
+        let message = &buf[NLMSG_HDRLEN..len];
+        let kind = read_u16(buf, 4);
+        if kind == NLMSG_ERROR {
+            let errno = message.get(..4).ok_or_else(truncated)?;
+            let errno = i32::from_ne_bytes(errno.try_into().unwrap());
+            if errno != 0 {
+                return Err(io::Error::from_raw_os_error(-errno));
+            }
+        } else if let Some(done) = f(kind, message)? {
+            return Ok(Some(done));
+        }
+        buf = &buf[((len + 3) & !3).min(buf.len())..];
+    }
+    Ok(None)
+}
+
+/// Collect neighbours from one netlink datagram; true once the dump is done
+fn parse_neighbours(buf: &[u8], ifindex: u32, out: &mut Vec<Neighbour>) -> io::Result<bool> {
+    let done = for_each_message(buf, |kind, message| {
+        match kind {
+            NLMSG_DONE => return Ok(Some(())),
+            RTM_NEWNEIGH if message.len() >= NDMSG_LEN => {
+                let family = message[0];
+                let index = i32::from_ne_bytes(message[4..8].try_into().unwrap()) as u32;
+                let state = read_u16(message, 8);
+                let mut dst = None;
+                let mut lladdr = None;
+                for_each_attr(&message[NDMSG_LEN..], |kind, payload| match kind {
+                    NDA_DST => dst = <[u8; 16]>::try_from(payload).ok(),
+                    NDA_LLADDR => lladdr = <[u8; 6]>::try_from(payload).ok(),
+                    _ => {}
+                })?;
+
+                let usable = state & (NUD_INCOMPLETE | NUD_FAILED) == 0;
+                if let (true, true, Some(dst), Some(lladdr)) = (family == libc::AF_INET6 as u8, index == ifindex && usable, dst, lladdr) {
+                    out.push((dst, lladdr));
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    })?;
+    Ok(done.is_some())
+}
+
+/// The route in a reply to [`ipv6_route`], once it has arrived
+fn parse_route(buf: &[u8]) -> io::Result<Option<Route>> {
+    for_each_message(buf, |kind, message| {
+        if kind != RTM_NEWROUTE || message.len() < RTMSG_LEN {
+            return Ok(None);
+        }
+        let mut route = Route { ifindex: 0, gateway: None };
+        for_each_attr(&message[RTMSG_LEN..], |kind, payload| match kind {
+            RTA_OIF => route.ifindex = <[u8; 4]>::try_from(payload).map_or(0, u32::from_ne_bytes),
+            RTA_GATEWAY => route.gateway = <[u8; 16]>::try_from(payload).ok(),
+            _ => {}
+        })?;
+        Ok(Some(route))
+    })
+}
+
+/// Current `CLOCK_MONOTONIC` time, the clock of `bpf_ktime_get_ns`
+pub(crate) fn ktime_ns() -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: `ts` is a valid timespec for the kernel to fill
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// Number of possible CPUs, which sizes per-CPU map values
+pub(crate) fn possible_cpus() -> io::Result<usize> {
+    let list = std::fs::read_to_string("/sys/devices/system/cpu/possible")?;
+    parse_cpu_list(&list)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("bad CPU list {:?}", list)))
+}
+
+/// Highest CPU in a list like `0-3,8-11` plus one
+fn parse_cpu_list(list: &str) -> Option<usize> {
+    list.trim()
+        .split(',')
+        .map(|range| range.rsplit('-').next().and_then(|last| last.parse::<usize>().ok()))
+        .try_fold(0, |max, last| Some(max.max(last? + 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attr_layouts() {
+        assert_eq!(std::mem::size_of::<MapCreateAttr>(), 44);
+        assert_eq!(std::mem::size_of::<MapElemAttr>(), 32);
+        assert_eq!(std::mem::size_of::<ProgLoadAttr>(), 72);
+        assert_eq!(std::mem::size_of::<TestRunAttr>(), 80);
+        assert_eq!(std::mem::size_of::<LinkCreateAttr>(), 16);
+        assert_eq!(std::mem::size_of::<NeighDumpRequest>(), NLMSG_HDRLEN + NDMSG_LEN);
+        assert_eq!(std::mem::size_of::<RouteGetRequest>(), NLMSG_HDRLEN + RTMSG_LEN + 20 + 8);
+    }
+
+    #[test]
+    fn test_interface_mac() {
+        assert_eq!(interface_mac("lo").unwrap(), [0; 6]);
+        assert!(interface_mac("stoq-missing").is_err());
+    }
+
+    /// RTM_NEWNEIGH message as the kernel sends it
+    fn neighbour_message(ifindex: i32, state: u16, dst: [u8; 16], lladdr: [u8; 6]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&[0; 4]);
+        message.extend_from_slice(&RTM_NEWNEIGH.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend_from_slice(&[libc::AF_INET6 as u8, 0, 0, 0]);
+        message.extend_from_slice(&ifindex.to_ne_bytes());
+        message.extend_from_slice(&state.to_ne_bytes());
+        message.extend_from_slice(&[0, 0]);
+        message.extend_from_slice(&20u16.to_ne_bytes());
+        message.extend_from_slice(&NDA_DST.to_ne_bytes());
+        message.extend_from_slice(&dst);
+        message.extend_from_slice(&10u16.to_ne_bytes());
+        message.extend_from_slice(&NDA_LLADDR.to_ne_bytes());
+        message.extend_from_slice(&lladdr);
+        message.extend_from_slice(&[0, 0]);
+        let len = message.len() as u32;
+        message[..4].copy_from_slice(&len.to_ne_bytes());
+        message
+    }
+
+    #[test]
+    fn test_parse_neighbours() {
+        let reachable = 0x02;
+        let mut buf = neighbour_message(3, reachable, [1; 16], [2; 6]);
+        buf.extend(neighbour_message(4, reachable, [3; 16], [4; 6]));
+        buf.extend(neighbour_message(3, NUD_FAILED, [5; 16], [6; 6]));
+
+        let mut neighbours = Vec::new();
+        assert!(!parse_neighbours(&buf, 3, &mut neighbours).unwrap());
+        assert_eq!(neighbours, vec![([1; 16], [2; 6])]);
+
+        let mut done = vec![0u8; NLMSG_HDRLEN + 4];
+        done[..4].copy_from_slice(&((NLMSG_HDRLEN + 4) as u32).to_ne_bytes());
+        done[4..6].copy_from_slice(&NLMSG_DONE.to_ne_bytes());
+        assert!(parse_neighbours(&done, 3, &mut neighbours).unwrap());
+        assert!(parse_neighbours(&buf[..10], 3, &mut neighbours).is_ok());
+        assert!(parse_neighbours(&buf[..30], 3, &mut neighbours).is_err());
+    }
+
+    #[test]
+    fn test_ipv6_neighbours_dump() {
+        // Loopback neighbours, if any, have an all-zero link-layer address
+        let neighbours = ipv6_neighbours(if_index("lo").unwrap()).unwrap();
+        assert!(neighbours.iter().all(|(_, lladdr)| *lladdr == [0; 6]));
+    }
+
+    /// RTM_NEWROUTE message out of `ifindex`, through `gateway` if given
+    fn route_message(ifindex: u32, gateway: Option<[u8; 16]>) -> Vec<u8> {
+        let mut message = vec![0; NLMSG_HDRLEN + RTMSG_LEN];
+        message[4..6].copy_from_slice(&RTM_NEWROUTE.to_ne_bytes());
+        message[NLMSG_HDRLEN] = libc::AF_INET6 as u8;
+        message.extend_from_slice(&8u16.to_ne_bytes());
+        message.extend_from_slice(&RTA_OIF.to_ne_bytes());
+        message.extend_from_slice(&ifindex.to_ne_bytes());
+        if let Some(gateway) = gateway {
+            message.extend_from_slice(&20u16.to_ne_bytes());
+            message.extend_from_slice(&RTA_GATEWAY.to_ne_bytes());
+            message.extend_from_slice(&gateway);
+        }
+        let len = message.len() as u32;
+        message[..4].copy_from_slice(&len.to_ne_bytes());
+        message
+    }
+
+    #[test]
+    fn test_parse_route() {
+        assert_eq!(parse_route(&route_message(3, None)).unwrap(), Some(Route { ifindex: 3, gateway: None }));
+        assert_eq!(
+            parse_route(&route_message(3, Some([7; 16]))).unwrap(),
+            Some(Route { ifindex: 3, gateway: Some([7; 16]) })
+        );
+
+        let mut unreachable = vec![0u8; NLMSG_HDRLEN + 4];
+        unreachable[..4].copy_from_slice(&((NLMSG_HDRLEN + 4) as u32).to_ne_bytes());
+        unreachable[4..6].copy_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        unreachable[NLMSG_HDRLEN..].copy_from_slice(&(-libc::ENETUNREACH).to_ne_bytes());
+        let error = parse_route(&unreachable).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::ENETUNREACH));
+    }
+
+    #[test]
+    fn test_ipv6_route_lookup() {
+        let lo = if_index("lo").unwrap();
+        let route = ipv6_route(std::net::Ipv6Addr::LOCALHOST.octets(), lo).unwrap();
+        assert_eq!(route, Route { ifindex: lo, gateway: None });
+    }
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0\n"), Some(1));
+        assert_eq!(parse_cpu_list("0-7\n"), Some(8));
+        assert_eq!(parse_cpu_list("0-3,8-11"), Some(12));
+        assert_eq!(parse_cpu_list("garbage"), None);
+    }
+
+    #[test]
+    fn test_object_name_truncates() {
+        assert_eq!(&object_name("stoq_xdp_filter_long")[..15], b"stoq_xdp_filter");
+        assert_eq!(object_name("stoq_xdp_filter_long")[15], 0);
+    }
+}
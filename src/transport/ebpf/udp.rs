@@ -0,0 +1,518 @@
+//! UDP over AF_XDP for quinn
+//!
+//! [`XdpUdpSocket`] implements quinn's [`AsyncUdpSocket`] on an
+//! [`AfXdpSocket`], building and checking the Ethernet, IPv6 and UDP headers
+//! the kernel stack would otherwise handle, so a `quinn::Endpoint` runs over
+//! it unchanged.
+//!
+//! Next-hop MAC addresses come from frames the peer sent, from
+//! [`XdpUdpSocket::add_neighbour`], or from the kernel: the kernel's route to
+//! the destination names the gateway for off-link peers, and the neighbour
+//! table gives the MAC of the gateway or on-link peer. VLAN tags, IPv6
+//! extension headers and fragments are not supported.
+
+use anyhow::{Result, anyhow};
+use parking_lot::{Mutex, RwLock};
+use quinn::udp::{EcnCodepoint, RecvMeta, Transmit};
+use quinn::{AsyncUdpSocket, UdpPoller};
+use std::collections::HashMap;
+use std::io::{self, IoSliceMut};
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use std::time::{Duration, Instant};
+
+use super::af_xdp::AfXdpSocket;
+use super::sys;
+
+const ETH_HEADER_LEN: usize = 14;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+/// Ethernet, IPv6 and UDP headers in front of every datagram
+pub const HEADERS_LEN: usize = ETH_HEADER_LEN + IPV6_HEADER_LEN + UDP_HEADER_LEN;
+const ETH_P_IPV6: u16 = 0x86dd;
+const IPPROTO_UDP: u8 = 17;
+const HOP_LIMIT: u8 = 64;
+/// Minimum time between reads of the kernel route and neighbour tables on a miss
+const NEIGHBOUR_REFRESH: Duration = Duration::from_secs(1);
+
+/// quinn socket sending and receiving UDP datagrams through AF_XDP
+pub struct XdpUdpSocket {
+    socket: AfXdpSocket,
+    local: SocketAddrV6,
+    local_mac: [u8; 6],
+    ifindex: u32,
+    /// Next-hop MAC address per IPv6 neighbour
+    neighbours: RwLock<HashMap<Ipv6Addr, [u8; 6]>>,
+    /// Next-hop address per destination, or `None` if the kernel has no route
+    /// out of this interface; cleared when the neighbour table is re-read
+    routes: RwLock<HashMap<Ipv6Addr, Option<Ipv6Addr>>>,
+    /// When the kernel neighbour table was last read
+    neighbours_read: Mutex<Option<Instant>>,
+    /// Frames being built for transmission
+    scratch: Mutex<Vec<u8>>,
+}
+
+impl XdpUdpSocket {
+    /// Carry UDP for `local` over `socket`
+    ///
+    /// `local` must be a concrete address and port: there is no kernel
+    /// socket to pick them.
+    pub fn new(socket: AfXdpSocket, local: SocketAddrV6) -> Result<Self> {
+        if local.ip().is_unspecified() || local.port() == 0 {
+            return Err(anyhow!("AF_XDP UDP needs a concrete local address, got {}", local));
+        }
+        let local_mac = sys::interface_mac(socket.interface())
+            .map_err(|e| anyhow!("Failed to read the MAC address of {}: {}", socket.interface(), e))?;
+        let ifindex = sys::if_index(socket.interface())?;
+
+        let udp = Self {
+            socket,
+            local,
+            local_mac,
+            ifindex,
+            neighbours: RwLock::new(HashMap::new()),
+            routes: RwLock::new(HashMap::new()),
+            neighbours_read: Mutex::new(None),
+            scratch: Mutex::new(Vec::new()),
+        };
+        udp.read_neighbours();
+        Ok(udp)
+    }
+
+    /// Use `mac` as the next hop for `address`
+    pub fn add_neighbour(&self, address: Ipv6Addr, mac: [u8; 6]) {
+        self.neighbours.write().insert(address, mac);
+    }
+
+    /// The underlying AF_XDP socket
+    pub fn af_xdp_socket(&self) -> &AfXdpSocket {
+        &self.socket
+    }
+
+    fn read_neighbours(&self) {
+        *self.neighbours_read.lock() = Some(Instant::now());
+        self.routes.write().clear();
+        match sys::ipv6_neighbours(self.ifindex) {
+            Ok(neighbours) => {
+                let mut known = self.neighbours.write();
+                for (address, mac) in neighbours {
+                    known.insert(Ipv6Addr::from(address), mac);
+                }
+            }
+            Err(e) => tracing::debug!("Failed to read the IPv6 neighbour table: {}", e),
+        }
+    }
+
+    /// The gateway for `destination`, or `destination` itself when on-link
+    fn route(&self, destination: &Ipv6Addr) -> Option<Ipv6Addr> {
+        if let Some(next) = self.routes.read().get(destination) {
+            return *next;
+        }
+        let next = match sys::ipv6_route(destination.octets(), self.ifindex) {
+            Ok(route) if route.ifindex == self.ifindex => Some(route.gateway.map_or(*destination, Ipv6Addr::from)),
+            Ok(route) => {
+                tracing::debug!("Route to {} leaves through interface {}, not {}", destination, route.ifindex, self.ifindex);
+                None
+            }
+            Err(e) => {
+                tracing::debug!("No route to {}: {}", destination, e);
+                None
+            }
+        };
+        self.routes.write().insert(*destination, next);
+        next
+    }
+
+    fn next_hop(&self, destination: &Ipv6Addr) -> Option<[u8; 6]> {
+        if destination.is_multicast() {
+            let octets = destination.octets();
+            return Some([0x33, 0x33, octets[12], octets[13], octets[14], octets[15]]);
+        }
+        if let Some(mac) = self.neighbours.read().get(destination) {
+            return Some(*mac);
+        }
+        let via_route = || {
+            let next = self.route(destination)?;
+            self.neighbours.read().get(&next).copied()
+        };
+        if let Some(mac) = via_route() {
+            return Some(mac);
+        }
+        let stale = self.neighbours_read.lock().is_none_or(|at| at.elapsed() >= NEIGHBOUR_REFRESH);
+        if !stale {
+            return None;
+        }
+        self.read_neighbours();
+        via_route()
+    }
+
+    /// Check a received frame, returning the datagram if it is for us
+    fn accept<'a>(&self, frame: &'a [u8]) -> Option<Datagram<'a>> {
+        let datagram = parse_frame(frame)?;
+        if datagram.destination != self.local {
+            return None;
+        }
+        let source = *datagram.source.ip();
+        if self.neighbours.read().get(&source) != Some(&datagram.source_mac) {
+            self.add_neighbour(source, datagram.source_mac);
+        }
+        Some(datagram)
+    }
+}
+
+impl std::fmt::Debug for XdpUdpSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("XdpUdpSocket")
+            .field("socket", &self.socket)
+            .field("local", &self.local)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncUdpSocket for XdpUdpSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(XdpPoller { socket: self })
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let SocketAddr::V6(destination) = transmit.destination else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "AF_XDP UDP is IPv6 only"));
+        };
+        let source_ip = match transmit.src_ip {
+            Some(std::net::IpAddr::V6(ip)) => ip,
+            _ => *self.local.ip(),
+        };
+        let source = SocketAddrV6::new(source_ip, self.local.port(), 0, 0);
+        let destination_mac = self.next_hop(destination.ip()).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::HostUnreachable, format!("no route or neighbour entry for {}", destination.ip()))
+        })?;
+        let ecn = transmit.ecn.map_or(0, |ecn| ecn as u8);
+
+        let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len()).max(1);
+        let mut scratch = self.scratch.lock();
+        scratch.clear();
+        let mut frames = Vec::new();
+        for segment in transmit.contents.chunks(segment_size) {
+            let start = scratch.len();
+            build_frame(&mut scratch, self.local_mac, destination_mac, source, destination, ecn, segment);
+            frames.push(start..scratch.len());
+        }
+        let frames: Vec<&[u8]> = frames.into_iter().map(|range| &scratch[range]).collect();
+
+        match self.socket.try_send_batch(&frames)? {
+            0 => Err(io::ErrorKind::WouldBlock.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<io::Result<usize>> {
+        let max = bufs.len().min(meta.len());
+        loop {
+            let mut count = 0;
+            ready!(self.socket.poll_recv_with(cx, max, |frame| {
+                let Some(datagram) = self.accept(frame) else { return };
+                let buf = &mut bufs[count];
+                let len = datagram.payload.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram.payload[..len]);
+                meta[count] = RecvMeta {
+                    addr: SocketAddr::V6(datagram.source),
+                    len,
+                    stride: len,
+                    ecn: EcnCodepoint::from_bits(datagram.ecn),
+                    dst_ip: Some((*datagram.destination.ip()).into()),
+                };
+                count += 1;
+            }))?;
+            // Frames that were not ours do not count as a receive
+            if count > 0 {
+                return Poll::Ready(Ok(count));
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(SocketAddr::V6(self.local))
+    }
+
+    fn may_fragment(&self) -> bool {
+        false
+    }
+}
+
+/// Waits for TX ring space on behalf of quinn
+#[derive(Debug)]
+struct XdpPoller {
+    socket: Arc<XdpUdpSocket>,
+}
+
+impl UdpPoller for XdpPoller {
+    fn poll_writable(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.socket.socket.poll_send_ready(cx)
+    }
+}
+
+/// A UDP datagram inside a received frame
+#[derive(Debug, PartialEq)]
+struct Datagram<'a> {
+    source_mac: [u8; 6],
+    source: SocketAddrV6,
+    destination: SocketAddrV6,
+    ecn: u8,
+    payload: &'a [u8],
+}
+
+/// Append an Ethernet + IPv6 + UDP frame carrying `payload` to `buf`
+fn build_frame(
+    buf: &mut Vec<u8>,
+    source_mac: [u8; 6],
+    destination_mac: [u8; 6],
+    source: SocketAddrV6,
+    destination: SocketAddrV6,
+    ecn: u8,
+    payload: &[u8],
+) {
+    let udp_len = (UDP_HEADER_LEN + payload.len()) as u16;
+    buf.extend_from_slice(&destination_mac);
+    buf.extend_from_slice(&source_mac);
+    buf.extend_from_slice(&ETH_P_IPV6.to_be_bytes());
+
+    // Version 6, traffic class carrying only the ECN bits, no flow label
+    buf.extend_from_slice(&[0x60, (ecn & 0x3) << 4, 0, 0]);
+    buf.extend_from_slice(&udp_len.to_be_bytes());
+    buf.extend_from_slice(&[IPPROTO_UDP, HOP_LIMIT]);
+    buf.extend_from_slice(&source.ip().octets());
+    buf.extend_from_slice(&destination.ip().octets());
+
+    let udp_start = buf.len();
+    buf.extend_from_slice(&source.port().to_be_bytes());
+    buf.extend_from_slice(&destination.port().to_be_bytes());
+    buf.extend_from_slice(&udp_len.to_be_bytes());
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(payload);
+
+    let checksum = udp_checksum(source.ip(), destination.ip(), &buf[udp_start..]);
+    buf[udp_start + 6..udp_start + 8].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// Parse an Ethernet + IPv6 + UDP frame, rejecting bad lengths and checksums
+fn parse_frame(frame: &[u8]) -> Option<Datagram<'_>> {
+    if frame.len() < HEADERS_LEN || u16::from_be_bytes([frame[12], frame[13]]) != ETH_P_IPV6 {
+        return None;
+    }
+    let ip = &frame[ETH_HEADER_LEN..];
+    if ip[0] >> 4 != 6 || ip[6] != IPPROTO_UDP {
+        return None;
+    }
+    let payload_len = u16::from_be_bytes([ip[4], ip[5]]) as usize;
+    let udp = ip[IPV6_HEADER_LEN..].get(..payload_len)?;
+    let udp_len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+    if udp_len < UDP_HEADER_LEN || udp_len != payload_len {
+        return None;
+    }
+
+    let source_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&ip[8..24]).unwrap());
+    let destination_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&ip[24..40]).unwrap());
+    // A zero checksum is not allowed over IPv6; a valid one sums to zero
+    if udp[6..8] == [0, 0] || udp_checksum(&source_ip, &destination_ip, udp) != 0 {
+        return None;
+    }
+
+    Some(Datagram {
+        source_mac: frame[6..12].try_into().unwrap(),
+        source: SocketAddrV6::new(source_ip, u16::from_be_bytes([udp[0], udp[1]]), 0, 0),
+        destination: SocketAddrV6::new(destination_ip, u16::from_be_bytes([udp[2], udp[3]]), 0, 0),
+        ecn: (u16::from_be_bytes([ip[0], ip[1]]) >> 4) as u8 & 0x3,
+        payload: &udp[UDP_HEADER_LEN..],
+    })
+}
+
+/// RFC 8200 §8.1 UDP checksum over the IPv6 pseudo-header and `udp`
+///
+/// Returns the value to store for a segment whose checksum field is zero,
+/// or zero when verifying a segment with its checksum in place.
+fn udp_checksum(source: &Ipv6Addr, destination: &Ipv6Addr, udp: &[u8]) -> u16 {
+    let mut sum: u64 = 0;
+    let mut add = |bytes: &[u8]| {
+        for chunk in bytes.chunks(2) {
+            sum += u16::from_be_bytes([chunk[0], chunk.get(1).copied().unwrap_or(0)]) as u64;
+        }
+    };
+    add(&source.octets());
+    add(&destination.octets());
+    add(&(udp.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, IPPROTO_UDP]);
+    add(udp);
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    match !(sum as u16) {
+        // Transmitted as all ones (RFC 768)
+        0 if udp[6..8] == [0, 0] => 0xffff,
+        checksum => checksum,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const CLIENT_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+    const SERVER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+    fn client() -> SocketAddrV6 {
+        SocketAddrV6::new("fd00::1".parse().unwrap(), 40000, 0, 0)
+    }
+
+    fn server(port: u16) -> SocketAddrV6 {
+        SocketAddrV6::new("fd00::2".parse().unwrap(), port, 0, 0)
+    }
+
+    /// Frame from the client (stoq0 side) to the server (stoq1 side)
+    pub(crate) fn udp_frame(port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        build_frame(&mut frame, CLIENT_MAC, SERVER_MAC, client(), server(port), 0, payload);
+        frame
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let mut frame = Vec::new();
+        build_frame(&mut frame, CLIENT_MAC, SERVER_MAC, client(), server(9292), EcnCodepoint::Ect0 as u8, b"stoq!");
+        assert_eq!(frame.len(), HEADERS_LEN + 5);
+        assert_eq!(&frame[..6], &SERVER_MAC);
+
+        let datagram = parse_frame(&frame).unwrap();
+        assert_eq!(datagram, Datagram {
+            source_mac: CLIENT_MAC,
+            source: client(),
+            destination: server(9292),
+            ecn: EcnCodepoint::Ect0 as u8,
+            payload: b"stoq!",
+        });
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_frames() {
+        let frame = udp_frame(9292, b"payload");
+        assert!(parse_frame(&frame[..frame.len() - 1]).is_none());
+
+        let mut corrupted = frame.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(parse_frame(&corrupted).is_none());
+
+        let mut no_checksum = frame.clone();
+        no_checksum[HEADERS_LEN - 2..HEADERS_LEN].copy_from_slice(&[0, 0]);
+        assert!(parse_frame(&no_checksum).is_none());
+
+        let mut ipv4 = frame;
+        ipv4[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        assert!(parse_frame(&ipv4).is_none());
+    }
+
+    #[test]
+    fn test_udp_checksum_matches_known_value() {
+        // fd00::1 port 40000 -> fd00::2 port 9292 carrying "stoq", per RFC 1071
+        let frame = udp_frame(9292, b"stoq");
+        assert_eq!(u16::from_be_bytes([frame[HEADERS_LEN - 2], frame[HEADERS_LEN - 1]]), 0x625f);
+    }
+
+    #[test]
+    #[ignore = "requires root and iproute2"]
+    fn test_off_link_peers_go_through_the_gateway() {
+        use super::super::af_xdp::tests::{test_manager, with_veth_pair};
+        use std::process::Command;
+
+        with_veth_pair(|| {
+            for args in [
+                "-6 addr add fd00::1/64 dev stoq0 nodad",
+                "-6 route add fd01::/64 via fd00::2 dev stoq0",
+                "-6 neigh add fd00::2 lladdr 02:00:00:00:00:02 dev stoq0 nud permanent",
+            ] {
+                let status = Command::new("ip").args(args.split_whitespace()).status().unwrap();
+                assert!(status.success(), "ip {}", args);
+            }
+            let mut manager = test_manager();
+            let udp = XdpUdpSocket::new(manager.create_socket("stoq0", 0).unwrap(), client()).unwrap();
+
+            assert_eq!(udp.next_hop(&"fd01::5".parse().unwrap()), Some(SERVER_MAC));
+            assert_eq!(udp.next_hop(&"fd00::2".parse().unwrap()), Some(SERVER_MAC));
+            // No route out of stoq0
+            assert_eq!(udp.next_hop(&"fd02::5".parse().unwrap()), None);
+        });
+    }
+
+    #[test]
+    #[ignore = "requires root and iproute2"]
+    fn test_quinn_over_af_xdp_on_veth() {
+        use super::super::af_xdp::tests::{redirect_all, test_manager, with_veth_pair};
+        use std::os::fd::AsFd;
+        use std::process::Command;
+
+        with_veth_pair(|| {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async {
+                let redirects = [redirect_all("stoq0"), redirect_all("stoq1")];
+                let mut manager = test_manager();
+                let client_xsk = manager.create_socket("stoq0", 0).unwrap();
+                let server_xsk = manager.create_socket("stoq1", 0).unwrap();
+                redirects[0].map.set(0, client_xsk.as_fd()).unwrap();
+                redirects[1].map.set(0, server_xsk.as_fd()).unwrap();
+
+                // The client finds the server in the kernel neighbour table;
+                // the server learns the client from its first datagram
+                let status = Command::new("ip")
+                    .args("-6 neigh add fd00::2 lladdr 02:00:00:00:00:02 dev stoq0 nud permanent".split_whitespace())
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+                let client_udp = Arc::new(XdpUdpSocket::new(client_xsk, client()).unwrap());
+                let server_udp = Arc::new(XdpUdpSocket::new(server_xsk, server(4433)).unwrap());
+
+                let cert = rcgen::generate_simple_self_signed(vec!["stoq.test".to_string()]).unwrap();
+                let cert_der = rustls::pki_types::CertificateDer::from(cert.cert.der().to_vec());
+                let key = rustls::pki_types::PrivateKeyDer::try_from(cert.key_pair.serialize_der()).unwrap();
+                let server_config = quinn::ServerConfig::with_single_cert(vec![cert_der.clone()], key).unwrap();
+                let mut roots = rustls::RootCertStore::empty();
+                roots.add(cert_der).unwrap();
+                let client_config = quinn::ClientConfig::with_root_certificates(Arc::new(roots)).unwrap();
+
+                let runtime = Arc::new(quinn::TokioRuntime);
+                let server_endpoint = quinn::Endpoint::new_with_abstract_socket(
+                    quinn::EndpointConfig::default(), Some(server_config), server_udp, runtime.clone(),
+                ).unwrap();
+                let client_endpoint = quinn::Endpoint::new_with_abstract_socket(
+                    quinn::EndpointConfig::default(), None, client_udp.clone(), runtime,
+                ).unwrap();
+
+                let accept = tokio::spawn(async move {
+                    let connection = server_endpoint.accept().await.unwrap().await.unwrap();
+                    let (mut send, mut recv) = connection.accept_bi().await.unwrap();
+                    let request = recv.read_to_end(1 << 20).await.unwrap();
+                    send.write_all(&request).await.unwrap();
+                    send.finish().unwrap();
+                    connection.closed().await;
+                });
+
+                let connect = async {
+                    let connection = client_endpoint.connect_with(client_config, server(4433).into(), "stoq.test").unwrap().await.unwrap();
+                    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+                    let request: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+                    send.write_all(&request).await.unwrap();
+                    send.finish().unwrap();
+                    let response = recv.read_to_end(1 << 20).await.unwrap();
+                    assert_eq!(response, request);
+                    connection.close(0u32.into(), b"done");
+                };
+                tokio::time::timeout(Duration::from_secs(20), connect).await.expect("echo over AF_XDP");
+                accept.await.unwrap();
+
+                let stats = client_udp.af_xdp_socket().get_stats();
+                assert!(stats.packets_sent > 70, "{:?}", stats);
+                assert!(stats.packets_received > 0, "{:?}", stats);
+            });
+        });
+    }
+}
@@ -5,10 +5,19 @@
 
 use anyhow::{Result, anyhow};
 use std::collections::HashMap;
+use std::net::Ipv6Addr;
 use std::sync::Arc;
+use std::os::fd::{AsFd, OwnedFd};
 use parking_lot::RwLock;
 
-// eBPF types would normally come from aya, but we'll use placeholders for now
+use super::af_xdp::AfXdpSocket;
+use super::ddos::{ConnectionIdTracker, DdosConfig};
+use super::loader::{EbpfLoader, XdpProgram};
+use super::maps::{
+    FilterKey, XdpConfigEntry, XdpStatsEntry, CONFIG_CONNTRACK, CONFIG_DROP_IPV4, CONFIG_MATCH_ADDR, CONFIG_QUIC_ONLY,
+    CONFIG_XSK_REDIRECT,
+};
+use super::sys;
 
 /// XDP action to take on packets
 #[repr(u32)]
@@ -28,6 +37,16 @@ pub struct XdpManager {
     attached: Arc<RwLock<HashMap<String, AttachedProgram>>>,
     /// Statistics from XDP programs
     stats: Arc<RwLock<XdpStats>>,
+    /// Loaded program, shared by every attachment
+    program: Option<Arc<XdpProgram>>,
+    /// Filter settings written to the program's `config_map`
+    settings: XdpConfigEntry,
+    /// DDoS mitigation settings merged into `config_map`
+    ddos: DdosConfig,
+    /// Source of the connection IDs admitted while under attack
+    cid_tracker: Option<Arc<ConnectionIdTracker>>,
+    /// Whether STOQ datagrams are redirected to AF_XDP sockets
+    xsk_redirect: bool,
     /// Whether XDP is available
     available: bool,
 }
@@ -36,6 +55,8 @@ pub struct XdpManager {
 struct AttachedProgram {
     interface: String,
     attach_mode: XdpAttachMode,
+    /// bpf link; closing it detaches the program
+    _link: OwnedFd,
 }
 
 /// XDP attach mode
@@ -49,6 +70,16 @@ pub enum XdpAttachMode {
     Offload,
 }
 
+impl XdpAttachMode {
+    fn flags(self) -> u32 {
+        match self {
+            XdpAttachMode::Native => sys::XDP_FLAGS_DRV_MODE,
+            XdpAttachMode::Generic => sys::XDP_FLAGS_SKB_MODE,
+            XdpAttachMode::Offload => sys::XDP_FLAGS_HW_MODE,
+        }
+    }
+}
+
 /// XDP program statistics
 #[derive(Debug, Default, Clone)]
 pub struct XdpStats {
@@ -56,16 +87,50 @@ pub struct XdpStats {
     pub packets_dropped: u64,
     pub packets_redirected: u64,
     pub bytes_processed: u64,
+    /// Drops by the per-source rate limit (included in `packets_dropped`)
+    pub rate_limited: u64,
+    /// Drops of QUIC Initials in short datagrams
+    pub short_initials: u64,
+    /// Drops of versions outside the allowlist
+    pub bad_versions: u64,
+    /// Drops of unknown connection IDs while under attack
+    pub unknown_cids: u64,
+    /// STOQ frames by size: <64, 64-255, 256-1023, 1024-1500 and >1500 bytes
+    pub frame_sizes: [u64; 5],
+}
+
+impl XdpStats {
+    /// Aggregate the per-CPU slots of `stats_map`
+    pub fn from_entries(entries: &[XdpStatsEntry]) -> Self {
+        let mut stats = Self::default();
+        for entry in entries {
+            stats.packets_passed += entry.packets_passed;
+            stats.packets_dropped += entry.packets_dropped;
+            stats.packets_redirected += entry.packets_redirected;
+            stats.bytes_processed += entry.bytes_processed;
+            stats.rate_limited += entry.rate_limited;
+            stats.short_initials += entry.short_initials;
+            stats.bad_versions += entry.bad_versions;
+            stats.unknown_cids += entry.unknown_cids;
+            for (total, count) in stats.frame_sizes.iter_mut().zip(entry.frame_sizes) {
+                *total += count;
+            }
+        }
+        stats
+    }
 }
 
 impl XdpManager {
-    /// Create new XDP manager and load eBPF programs
+    /// Create new XDP manager; the program is loaded on first attach
+    ///
+    /// Until [`configure`](Self::configure) is called the program treats no
+    /// traffic as STOQ and passes every frame.
     pub fn new() -> Result<Self> {
         // Check if eBPF is theoretically available
         let available = Self::check_ebpf_support();
 
         if available {
-            tracing::info!("XDP support detected (placeholder implementation)");
+            tracing::info!("XDP support detected");
         } else {
             tracing::warn!("XDP not available on this system");
         }
@@ -73,10 +138,132 @@ impl XdpManager {
         Ok(Self {
             attached: Arc::new(RwLock::new(HashMap::new())),
             stats: Arc::new(RwLock::new(XdpStats::default())),
+            program: None,
+            settings: XdpConfigEntry::default(),
+            ddos: DdosConfig::default(),
+            cid_tracker: None,
+            xsk_redirect: false,
             available,
         })
     }
 
+    /// Create a manager around an already loaded program
+    pub fn with_program(program: Arc<XdpProgram>) -> Self {
+        Self {
+            attached: Arc::new(RwLock::new(HashMap::new())),
+            stats: Arc::new(RwLock::new(XdpStats::default())),
+            program: Some(program),
+            settings: XdpConfigEntry::default(),
+            ddos: DdosConfig::default(),
+            cid_tracker: None,
+            xsk_redirect: false,
+            available: true,
+        }
+    }
+
+    /// Compile and load the XDP program if not already loaded
+    pub fn load(&mut self) -> Result<Arc<XdpProgram>> {
+        if let Some(program) = &self.program {
+            return Ok(program.clone());
+        }
+
+        let mut loader = EbpfLoader::new();
+        loader.load()?;
+        let program = loader.xdp_program().ok_or_else(|| anyhow!("XDP program was not loaded"))?;
+        self.write_config(&program)?;
+        if let Some(tracker) = &self.cid_tracker {
+            tracker.bind(program.cid_map()?)?;
+        }
+        self.program = Some(program.clone());
+        Ok(program)
+    }
+
+    /// Write the filter and DDoS settings to the program's maps
+    fn write_config(&self, program: &XdpProgram) -> Result<()> {
+        // Versions go in before the allowlist flag can take effect
+        let versions = program.version_map()?;
+        for version in versions.keys()? {
+            if !self.ddos.allowed_versions.contains(&version) {
+                versions.remove(&version)?;
+            }
+        }
+        for version in &self.ddos.allowed_versions {
+            versions.insert(version, &1)?;
+        }
+
+        let mut entry = self.settings;
+        self.ddos.apply(&mut entry);
+        if self.xsk_redirect {
+            entry.flags |= CONFIG_XSK_REDIRECT;
+        }
+        program.config_map()?.set(0, &entry)
+    }
+
+    /// Set the filter switches and the STOQ port and bind address to match
+    ///
+    /// Takes effect immediately on a loaded program, and otherwise when it
+    /// is loaded.
+    pub fn configure(&mut self, filter: &XdpFilterConfig, port: u16, bind_address: Ipv6Addr) -> Result<()> {
+        self.settings = filter.program_config(port, bind_address);
+        if let Some(program) = &self.program {
+            self.write_config(program)?;
+        }
+        tracing::debug!("XDP filter configured for [{}]:{} ({:?})", bind_address, port, filter);
+        Ok(())
+    }
+
+    /// Set the DDoS mitigation controls
+    pub fn configure_ddos(&mut self, config: &DdosConfig) -> Result<()> {
+        self.ddos = config.clone();
+        if let Some(program) = &self.program {
+            self.write_config(program)?;
+        }
+        tracing::info!("XDP DDoS mitigation configured: {:?}", config);
+        Ok(())
+    }
+
+    /// Redirect STOQ datagrams arriving on `socket`'s queue to it
+    ///
+    /// The program must be attached to the socket's interface for anything
+    /// to arrive.
+    pub fn redirect_to_socket(&mut self, socket: &AfXdpSocket) -> Result<()> {
+        let program = self.load()?;
+        program.xsk_map()?.set(socket.queue_id(), socket.as_fd())?;
+        if !self.xsk_redirect {
+            self.xsk_redirect = true;
+            self.write_config(&program)?;
+        }
+        tracing::info!("Redirecting STOQ datagrams on {}:{} to AF_XDP", socket.interface(), socket.queue_id());
+        Ok(())
+    }
+
+    /// Enter or leave "under attack" mode, admitting only known connection IDs
+    pub fn set_under_attack(&mut self, under_attack: bool) -> Result<()> {
+        if under_attack && self.cid_tracker.is_none() {
+            return Err(anyhow!("Under attack mode needs the endpoint's connection IDs; call track_connection_ids first"));
+        }
+        let config = DdosConfig { under_attack, ..self.ddos.clone() };
+        self.configure_ddos(&config)
+    }
+
+    /// Admit the connection IDs `tracker` records when under attack
+    pub fn track_connection_ids(&mut self, tracker: Arc<ConnectionIdTracker>) -> Result<()> {
+        if let Some(program) = &self.program {
+            tracker.bind(program.cid_map()?)?;
+        }
+        self.cid_tracker = Some(tracker);
+        Ok(())
+    }
+
+    /// The loaded program, for direct map access and test runs
+    pub fn program(&self) -> Option<&Arc<XdpProgram>> {
+        self.program.as_ref()
+    }
+
+    fn loaded(&self) -> Result<&Arc<XdpProgram>> {
+        self.program.as_ref().ok_or_else(|| anyhow!("XDP program not loaded"))
+    }
+
     fn check_ebpf_support() -> bool {
         // Check for basic eBPF support indicators
         #[cfg(target_os = "linux")]
@@ -101,24 +288,38 @@ impl XdpManager {
             return Err(anyhow!("XDP not available on this system"));
         }
 
-        // Placeholder implementation - actual attachment would use aya/libbpf
+        if self.attached.read().contains_key(interface) {
+            return Err(anyhow!("XDP program already attached to {}", interface));
+        }
+
+        let program = self.load()?;
+        let ifindex = sys::if_index(interface)
+            .map_err(|e| anyhow!("Unknown interface {}: {}", interface, e))?;
+        let link = sys::link_create_xdp(program.as_fd(), ifindex, mode.flags())
+            .map_err(|e| anyhow!("Failed to attach XDP to {} in {:?} mode: {}", interface, mode, e))?;
+
         let attached_prog = AttachedProgram {
             interface: interface.to_string(),
             attach_mode: mode,
+            _link: link,
         };
 
         self.attached.write().insert(interface.to_string(), attached_prog);
 
-        tracing::info!("XDP program attached to {} in {:?} mode (simulated)", interface, mode);
-        tracing::warn!("Note: Actual XDP attachment requires aya/libbpf integration");
+        tracing::info!("XDP program attached to {} in {:?} mode", interface, mode);
 
         Ok(())
     }
 
+    /// Whether the program is attached to `interface`
+    pub fn is_attached(&self, interface: &str) -> bool {
+        self.attached.read().contains_key(interface)
+    }
+
     /// Detach XDP program from interface
     pub fn detach(&mut self, interface: &str) -> Result<()> {
-        if self.attached.write().remove(interface).is_some() {
-            tracing::info!("XDP program detached from {} (simulated)", interface);
+        if let Some(attached) = self.attached.write().remove(interface) {
+            tracing::info!("XDP program detached from {} ({:?} mode)", attached.interface, attached.attach_mode);
         }
         Ok(())
     }
@@ -131,41 +332,41 @@ impl XdpManager {
     }
 
     /// Update connection filter rules
-    pub fn update_filter(&mut self, _src_ip: [u8; 16], _dst_ip: [u8; 16], _action: XdpAction) -> Result<()> {
-        #[cfg(feature = "ebpf")]
-        {
-            // Filter rule updates would be implemented here with proper map access
-            // For now, this is a placeholder
-            tracing::debug!("Filter rule update (not yet implemented)");
-        }
+    pub fn update_filter(&mut self, src_ip: [u8; 16], dst_ip: [u8; 16], action: XdpAction) -> Result<()> {
+        self.loaded()?.filter_map()?.insert(&FilterKey { src_ip, dst_ip }, &(action as u32))?;
+        tracing::debug!("Filter rule {:?} set for {:02x?} -> {:02x?}", action, src_ip, dst_ip);
         Ok(())
     }
 
+    /// Remove a filter rule, returning whether one existed
+    pub fn remove_filter(&mut self, src_ip: [u8; 16], dst_ip: [u8; 16]) -> Result<bool> {
+        self.loaded()?.filter_map()?.remove(&FilterKey { src_ip, dst_ip })
+    }
+
     /// Get current statistics from XDP programs
     pub fn get_stats(&self) -> XdpStats {
         self.stats.read().clone()
     }
 
-    /// Update statistics from eBPF maps
+    /// Update statistics from eBPF maps, summing the per-CPU counters
     pub fn update_stats(&mut self) -> Result<()> {
-        #[cfg(feature = "ebpf")]
-        {
-            // Statistics update would be implemented here with proper map access
-            // For now, use placeholder stats
-            tracing::debug!("Stats update (not yet implemented)");
-        }
+        let per_cpu = self.loaded()?.stats_map()?.get(0)?;
+        *self.stats.write() = XdpStats::from_entries(&per_cpu);
         Ok(())
     }
 }
 
 /// XDP filter configuration
+///
+/// The defaults only observe STOQ traffic; everything else on the interface
+/// passes untouched.
 #[derive(Debug, Clone)]
 pub struct XdpFilterConfig {
-    /// Allow only QUIC packets (UDP port 9292)
+    /// Drop IPv6 traffic that is not STOQ (ICMPv6 always passes)
     pub filter_quic_only: bool,
-    /// Drop non-IPv6 packets
+    /// Drop IPv4 packets (ARP and other protocols always pass)
     pub drop_ipv4: bool,
-    /// Maximum packet size to process
+    /// Drop STOQ frames larger than this
     pub max_packet_size: usize,
     /// Enable connection tracking
     pub enable_connection_tracking: bool,
@@ -174,18 +375,48 @@ pub struct XdpFilterConfig {
 impl Default for XdpFilterConfig {
     fn default() -> Self {
         Self {
-            filter_quic_only: true,
-            drop_ipv4: true,
+            filter_quic_only: false,
+            drop_ipv4: false,
             max_packet_size: 65535,
             enable_connection_tracking: true,
         }
     }
 }
 
+impl XdpFilterConfig {
+    /// `config_map` entry for STOQ on `port`, bound to `bind_address`
+    ///
+    /// An unspecified bind address matches STOQ traffic to any local address.
+    pub fn program_config(&self, port: u16, bind_address: Ipv6Addr) -> XdpConfigEntry {
+        let mut flags = 0;
+        if self.filter_quic_only {
+            flags |= CONFIG_QUIC_ONLY;
+        }
+        if self.drop_ipv4 {
+            flags |= CONFIG_DROP_IPV4;
+        }
+        if self.enable_connection_tracking {
+            flags |= CONFIG_CONNTRACK;
+        }
+        if !bind_address.is_unspecified() {
+            flags |= CONFIG_MATCH_ADDR;
+        }
+
+        XdpConfigEntry {
+            bind_addr: bind_address.octets(),
+            port,
+            flags,
+            max_packet_size: self.max_packet_size.min(u32::MAX as usize) as u32,
+            ..Default::default()
+        }
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::ddos::{QUIC_MIN_INITIAL_SIZE, QUIC_V1, QUIC_V2};
 
     #[test]
     fn test_xdp_manager_creation() {
@@ -196,8 +427,293 @@ mod tests {
     #[test]
     fn test_filter_config_default() {
         let config = XdpFilterConfig::default();
-        assert!(config.filter_quic_only);
-        assert!(config.drop_ipv4);
+        assert!(!config.filter_quic_only);
+        assert!(!config.drop_ipv4);
         assert_eq!(config.max_packet_size, 65535);
     }
+
+    #[test]
+    fn test_program_config_from_filter() {
+        let entry = XdpFilterConfig::default().program_config(4433, Ipv6Addr::UNSPECIFIED);
+        assert_eq!(entry.port, 4433);
+        assert_eq!(entry.flags, CONFIG_CONNTRACK);
+        assert_eq!(entry.max_packet_size, 65535);
+
+        let filter = XdpFilterConfig {
+            filter_quic_only: true,
+            drop_ipv4: true,
+            enable_connection_tracking: false,
+            ..Default::default()
+        };
+        let entry = filter.program_config(9292, Ipv6Addr::from(DST));
+        assert_eq!(entry.flags, CONFIG_QUIC_ONLY | CONFIG_DROP_IPV4 | CONFIG_MATCH_ADDR);
+        assert_eq!(entry.bind_addr, DST);
+    }
+
+    const SRC: [u8; 16] = [0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    const DST: [u8; 16] = [0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+
+    /// Ethernet + IPv6 + UDP frame to `dst_port` with a small payload
+    fn ipv6_udp_frame(dst_port: u16) -> Vec<u8> {
+        udp_frame(SRC, dst_port, b"stoq")
+    }
+
+    /// Ethernet + IPv6 + UDP frame from `src` to DST
+    fn udp_frame(src: [u8; 16], dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let udp_len = (8 + payload.len()) as u16;
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1]);
+        frame.extend_from_slice(&0x86ddu16.to_be_bytes());
+        frame.extend_from_slice(&[0x60, 0, 0, 0]);
+        frame.extend_from_slice(&udp_len.to_be_bytes());
+        frame.extend_from_slice(&[17, 64]); // next header UDP, hop limit
+        frame.extend_from_slice(&src);
+        frame.extend_from_slice(&DST);
+        frame.extend_from_slice(&40000u16.to_be_bytes());
+        frame.extend_from_slice(&dst_port.to_be_bytes());
+        frame.extend_from_slice(&udp_len.to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    #[ignore = "requires root"]
+    fn test_prog_test_run_applies_filter() {
+        let mut manager = XdpManager::new().unwrap();
+        let program = manager.load().unwrap();
+        let frame = ipv6_udp_frame(9292);
+
+        // Unconfigured, nothing counts as STOQ traffic
+        assert_eq!(program.test_run(&frame).unwrap().action, XdpAction::Pass as u32);
+        assert!(program.connection_map().unwrap().keys().unwrap().is_empty());
+
+        manager.configure(&XdpFilterConfig::default(), 9292, Ipv6Addr::UNSPECIFIED).unwrap();
+        assert_eq!(program.test_run(&frame).unwrap().action, XdpAction::Pass as u32);
+
+        manager.update_filter(SRC, DST, XdpAction::Drop).unwrap();
+        assert_eq!(program.test_run(&frame).unwrap().action, XdpAction::Drop as u32);
+        assert!(manager.remove_filter(SRC, DST).unwrap());
+        assert_eq!(program.test_run(&frame).unwrap().action, XdpAction::Pass as u32);
+
+        manager.update_stats().unwrap();
+        let stats = manager.get_stats();
+        assert_eq!(stats.packets_passed, 2);
+        assert_eq!(stats.packets_dropped, 1);
+        assert_eq!(stats.bytes_processed, 2 * frame.len() as u64);
+
+        let connections = program.connection_map().unwrap().entries().unwrap();
+        assert_eq!(connections.len(), 1);
+        let (key, info) = connections[0];
+        assert_eq!((key.src_ip, key.dst_ip, u16::from_be(key.dst_port)), (SRC, DST, 9292));
+        assert_eq!(info.packets, 3);
+    }
+
+    #[test]
+    #[ignore = "requires root"]
+    fn test_prog_test_run_follows_config() {
+        let mut manager = XdpManager::new().unwrap();
+        let program = manager.load().unwrap();
+        let pass = XdpAction::Pass as u32;
+        let drop = XdpAction::Drop as u32;
+
+        let mut ipv4 = ipv6_udp_frame(9292);
+        ipv4[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        let mut arp = ipv4.clone();
+        arp[12..14].copy_from_slice(&0x0806u16.to_be_bytes());
+        let other_port = ipv6_udp_frame(53);
+
+        // By default non-STOQ traffic passes untouched
+        manager.configure(&XdpFilterConfig::default(), 4433, Ipv6Addr::UNSPECIFIED).unwrap();
+        for frame in [&ipv4, &arp, &other_port, &ipv6_udp_frame(9292)] {
+            assert_eq!(program.test_run(frame).unwrap().action, pass);
+        }
+        assert!(program.connection_map().unwrap().keys().unwrap().is_empty());
+
+        let strict = XdpFilterConfig { filter_quic_only: true, drop_ipv4: true, ..Default::default() };
+        manager.configure(&strict, 53, Ipv6Addr::UNSPECIFIED).unwrap();
+        assert_eq!(program.test_run(&ipv4).unwrap().action, drop);
+        assert_eq!(program.test_run(&arp).unwrap().action, pass);
+        assert_eq!(program.test_run(&other_port).unwrap().action, pass);
+        assert_eq!(program.test_run(&ipv6_udp_frame(9292)).unwrap().action, drop);
+
+        // Bound to another address, traffic to DST is not ours
+        manager.configure(&strict, 53, Ipv6Addr::LOCALHOST).unwrap();
+        assert_eq!(program.test_run(&other_port).unwrap().action, drop);
+        manager.configure(&strict, 53, Ipv6Addr::from(DST)).unwrap();
+        assert_eq!(program.test_run(&other_port).unwrap().action, pass);
+
+        let small = XdpFilterConfig { max_packet_size: 32, ..Default::default() };
+        manager.configure(&small, 53, Ipv6Addr::UNSPECIFIED).unwrap();
+        assert_eq!(program.test_run(&other_port).unwrap().action, drop);
+    }
+
+    /// QUIC long-header packet of `version` and `packet_type`, padded to `size`
+    fn long_header(version: u32, packet_type: u8, dcid: &[u8], size: usize) -> Vec<u8> {
+        let mut packet = vec![0xc0 | (packet_type << 4)];
+        packet.extend_from_slice(&version.to_be_bytes());
+        packet.push(dcid.len() as u8);
+        packet.extend_from_slice(dcid);
+        packet.resize(size.max(packet.len()), 0);
+        packet
+    }
+
+    #[test]
+    #[ignore = "requires root"]
+    fn test_prog_test_run_rate_limits_per_prefix() {
+        let mut manager = XdpManager::new().unwrap();
+        let program = manager.load().unwrap();
+        manager.configure(&XdpFilterConfig::default(), 9292, Ipv6Addr::UNSPECIFIED).unwrap();
+        manager.configure_ddos(&DdosConfig { rate_limit_pps: 1, rate_limit_burst: 4, ..Default::default() }).unwrap();
+
+        let mut same_prefix = SRC;
+        same_prefix[15] = 7;
+        let mut other_prefix = SRC;
+        other_prefix[7] = 1;
+
+        for _ in 0..4 {
+            assert_eq!(program.test_run(&ipv6_udp_frame(9292)).unwrap().action, XdpAction::Pass as u32);
+        }
+        assert_eq!(program.test_run(&udp_frame(same_prefix, 9292, b"stoq")).unwrap().action, XdpAction::Drop as u32);
+        assert_eq!(program.test_run(&udp_frame(other_prefix, 9292, b"stoq")).unwrap().action, XdpAction::Pass as u32);
+
+        manager.update_stats().unwrap();
+        assert_eq!(manager.get_stats().rate_limited, 1);
+        assert_eq!(manager.get_stats().packets_dropped, 1);
+    }
+
+    #[test]
+    #[ignore = "requires root"]
+    fn test_prog_test_run_checks_quic_headers() {
+        let mut manager = XdpManager::new().unwrap();
+        let program = manager.load().unwrap();
+        let pass = XdpAction::Pass as u32;
+        let drop = XdpAction::Drop as u32;
+        manager.configure(&XdpFilterConfig::default(), 9292, Ipv6Addr::UNSPECIFIED).unwrap();
+
+        let dcid = [1, 2, 3, 4, 5, 6, 7, 8];
+        let run = |payload: &[u8]| program.test_run(&udp_frame(SRC, 9292, payload)).unwrap().action;
+
+        // Initials must be padded to 1200 bytes; Handshake packets need not be
+        let size = QUIC_MIN_INITIAL_SIZE as usize;
+        assert_eq!(run(&long_header(QUIC_V1, 0, &dcid, size - 1)), drop);
+        assert_eq!(run(&long_header(QUIC_V1, 0, &dcid, size)), pass);
+        assert_eq!(run(&long_header(QUIC_V1, 2, &dcid, 64)), pass);
+        assert_eq!(run(&long_header(QUIC_V2, 1, &dcid, 64)), drop);
+
+        let versions = DdosConfig { allowed_versions: vec![QUIC_V1], ..Default::default() };
+        manager.configure_ddos(&versions).unwrap();
+        assert_eq!(run(&long_header(QUIC_V1, 2, &dcid, 64)), pass);
+        assert_eq!(run(&long_header(QUIC_V2, 2, &dcid, 64)), drop);
+        assert_eq!(run(&long_header(0, 0, &dcid, 64)), pass);
+        assert_eq!(program.version_map().unwrap().keys().unwrap(), vec![QUIC_V1]);
+
+        // Only connection IDs the endpoint issued get through under attack
+        assert!(manager.set_under_attack(true).is_err());
+        let tracker = ConnectionIdTracker::new();
+        manager.track_connection_ids(tracker.clone()).unwrap();
+        tracker.record(&dcid);
+        manager.set_under_attack(true).unwrap();
+
+        let mut short = vec![0x40];
+        short.extend_from_slice(&dcid);
+        short.extend_from_slice(b"payload");
+        assert_eq!(run(&short), pass);
+        assert_eq!(run(&long_header(QUIC_V1, 2, &dcid, 64)), pass);
+        short[1] ^= 0xff;
+        assert_eq!(run(&short), drop);
+        assert_eq!(run(&long_header(QUIC_V1, 0, &[9; 8], size)), drop);
+
+        manager.update_stats().unwrap();
+        let stats = manager.get_stats();
+        assert_eq!(stats.short_initials, 2);
+        assert_eq!(stats.bad_versions, 1);
+        assert_eq!(stats.unknown_cids, 2);
+        assert_eq!(stats.rate_limited, 0);
+    }
+
+    #[test]
+    #[ignore = "requires root"]
+    fn test_prog_test_run_feeds_metrics() {
+        use super::super::metrics::EbpfMetricsCollector;
+
+        let mut manager = XdpManager::new().unwrap();
+        let program = manager.load().unwrap();
+        manager.configure(&XdpFilterConfig::default(), 9292, Ipv6Addr::UNSPECIFIED).unwrap();
+        let collector = EbpfMetricsCollector::new().unwrap().with_idle_timeout(std::time::Duration::from_millis(50));
+        collector.attach_program(program.clone());
+
+        let initial = long_header(QUIC_V1, 0, &[1; 8], QUIC_MIN_INITIAL_SIZE as usize);
+        program.test_run(&udp_frame(SRC, 9292, &initial)).unwrap();
+        let mut other = SRC;
+        other[15] = 9;
+        program.test_run(&udp_frame(other, 9292, &initial)).unwrap();
+        program.test_run(&udp_frame(other, 9292, &[0x40; 16])).unwrap();
+
+        let metrics = collector.collect();
+        assert_eq!(metrics.packet_metrics.total_packets, 3);
+        assert_eq!(metrics.packet_metrics.size_distribution.large, 2);
+        assert_eq!(metrics.packet_metrics.size_distribution.small, 1);
+        assert_eq!(metrics.connection_metrics.active_connections, 2);
+        assert_eq!(metrics.connection_metrics.state_distribution.handshaking, 1);
+        assert_eq!(metrics.connection_metrics.state_distribution.established, 1);
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let metrics = collector.collect();
+        assert_eq!(metrics.connection_metrics.active_connections, 0);
+        assert_eq!(metrics.connection_metrics.closed_connections, 1);
+        assert_eq!(metrics.connection_metrics.failed_connections, 1);
+        assert!(program.connection_map().unwrap().keys().unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore = "requires root and iproute2"]
+    fn test_generic_xdp_on_veth() {
+        use std::process::Command;
+
+        // Private network namespace for this thread and the commands it spawns
+        std::thread::spawn(|| {
+            assert_eq!(unsafe { libc::unshare(libc::CLONE_NEWNET) }, 0, "{}", std::io::Error::last_os_error());
+            for args in ["link add stoq0 type veth peer name stoq1", "link set stoq0 up", "link set stoq1 up"] {
+                let status = Command::new("ip").args(args.split_whitespace()).status().unwrap();
+                assert!(status.success(), "ip {}", args);
+            }
+
+            let mut manager = XdpManager::new().unwrap();
+            manager.configure(&XdpFilterConfig::default(), 9292, Ipv6Addr::UNSPECIFIED).unwrap();
+            manager.attach_with_mode("stoq1", XdpAttachMode::Generic).unwrap();
+
+            // Raw frames out of stoq0 arrive on stoq1 without touching routing
+            let socket = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) };
+            assert!(socket >= 0, "{}", std::io::Error::last_os_error());
+            let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+            addr.sll_family = libc::AF_PACKET as u16;
+            addr.sll_ifindex = sys::if_index("stoq0").unwrap() as i32;
+            let frame = ipv6_udp_frame(9292);
+            for _ in 0..10 {
+                let sent = unsafe {
+                    libc::sendto(
+                        socket,
+                        frame.as_ptr() as *const libc::c_void,
+                        frame.len(),
+                        0,
+                        &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                        std::mem::size_of::<libc::sockaddr_ll>() as u32,
+                    )
+                };
+                assert_eq!(sent, frame.len() as isize, "{}", std::io::Error::last_os_error());
+            }
+            unsafe { libc::close(socket) };
+            std::thread::sleep(std::time::Duration::from_millis(100));
+
+            manager.update_stats().unwrap();
+            assert!(manager.get_stats().packets_passed >= 10);
+            let connections = manager.program().unwrap().connection_map().unwrap().entries().unwrap();
+            assert!(connections.iter().any(|(key, info)| u16::from_be(key.dst_port) == 9292 && info.packets >= 10));
+
+            manager.detach("stoq1").unwrap();
+        })
+        .join()
+        .unwrap();
+    }
 }
\ No newline at end of file
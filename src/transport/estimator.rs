@@ -0,0 +1,269 @@
+//! Network condition estimation from QUIC path statistics
+//!
+//! Turns successive cumulative [`quinn::ConnectionStats`] snapshots into
+//! [`NetworkConditions`]:
+//!
+//! - RTT and packet loss are EWMA-smoothed; loss is lost vs sent packets
+//!   between samples.
+//! - Jitter follows RFC 3550 §6.4.1 (`J += (|D| - J) / 16`) with `D` the
+//!   change in RTT between samples.
+//! - Bandwidth is a windowed max of the delivery rate (bytes sent minus bytes
+//!   lost, or bytes received if higher), in the style of BBR. Samples that did
+//!   not fill the congestion window are application-limited and only raise the
+//!   estimate, so an idle connection keeps its last (or default) estimate.
+//!
+//! The estimator is driven by [`PathSample`]s carrying their own timestamps,
+//! so recorded traces can be replayed deterministically.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+
+use super::adaptive::NetworkConditions;
+
+/// Estimator tunables
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EstimatorConfig {
+    /// Weight of a new sample in RTT, loss and throughput averages
+    pub ewma_alpha: f64,
+    /// How long a delivery-rate sample stays in the max-bandwidth filter
+    pub bandwidth_window: Duration,
+}
+
+impl Default for EstimatorConfig {
+    fn default() -> Self {
+        Self {
+            ewma_alpha: 0.125, // Same gain as TCP SRTT (RFC 6298)
+            bandwidth_window: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Cumulative path counters at one point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathSample {
+    /// When the counters were read
+    pub at: Instant,
+    /// Smoothed RTT reported by the QUIC stack
+    pub rtt: Duration,
+    /// Congestion window in bytes
+    pub cwnd: u64,
+    /// Packets sent
+    pub sent_packets: u64,
+    /// Packets declared lost
+    pub lost_packets: u64,
+    /// UDP payload bytes sent
+    pub sent_bytes: u64,
+    /// Bytes in packets declared lost
+    pub lost_bytes: u64,
+    /// UDP payload bytes received
+    pub received_bytes: u64,
+}
+
+impl PathSample {
+    /// Read the counters of a QUIC connection
+    pub fn from_stats(stats: &quinn::ConnectionStats, at: Instant) -> Self {
+        Self {
+            at,
+            rtt: stats.path.rtt,
+            cwnd: stats.path.cwnd,
+            sent_packets: stats.path.sent_packets,
+            lost_packets: stats.path.lost_packets,
+            sent_bytes: stats.udp_tx.bytes,
+            lost_bytes: stats.path.lost_bytes,
+            received_bytes: stats.udp_rx.bytes,
+        }
+    }
+}
+
+/// Windowed max filter over timestamped samples
+#[derive(Debug, Default)]
+struct MaxFilter {
+    /// Decreasing values, oldest first
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl MaxFilter {
+    fn update(&mut self, at: Instant, value: f64, window: Duration) {
+        while self.samples.back().is_some_and(|&(_, v)| v <= value) {
+            self.samples.pop_back();
+        }
+        self.samples.push_back((at, value));
+        self.expire(at, window);
+    }
+
+    fn expire(&mut self, now: Instant, window: Duration) {
+        // Keep the newest sample so an idle link holds its last estimate
+        while self.samples.len() > 1
+            && self.samples.front().is_some_and(|&(at, _)| now.saturating_duration_since(at) > window)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    fn max(&self) -> Option<f64> {
+        self.samples.front().map(|&(_, v)| v)
+    }
+}
+
+/// Derives [`NetworkConditions`] from successive [`PathSample`]s
+#[derive(Debug)]
+pub struct ConditionsEstimator {
+    config: EstimatorConfig,
+    previous: Option<PathSample>,
+    conditions: NetworkConditions,
+    bandwidth: MaxFilter,
+}
+
+impl ConditionsEstimator {
+    /// Create an estimator starting from default conditions
+    pub fn new(config: EstimatorConfig) -> Self {
+        Self {
+            config,
+            previous: None,
+            conditions: NetworkConditions::default(),
+            bandwidth: MaxFilter::default(),
+        }
+    }
+
+    /// Current estimate
+    pub fn conditions(&self) -> &NetworkConditions {
+        &self.conditions
+    }
+
+    /// Fold in a new sample and return the updated estimate
+    ///
+    /// Samples must be fed in time order; the first one only sets the RTT
+    /// and the baseline for the deltas.
+    pub fn update(&mut self, sample: PathSample) -> &NetworkConditions {
+        let alpha = self.config.ewma_alpha;
+        let ewma = |average: f64, value: f64| average + alpha * (value - average);
+        let rtt_ms = sample.rtt.as_secs_f64() * 1000.0;
+
+        let previous = match self.previous.replace(sample) {
+            Some(previous) => previous,
+            None => {
+                self.conditions.rtt_ms = rtt_ms;
+                self.conditions.retransmissions = sample.lost_packets;
+                self.conditions.last_update = sample.at;
+                return &self.conditions;
+            }
+        };
+
+        let elapsed = sample.at.saturating_duration_since(previous.at).as_secs_f64();
+        let conditions = &mut self.conditions;
+
+        // RFC 3550 jitter uses the previous sample before smoothing RTT
+        let previous_rtt_ms = previous.rtt.as_secs_f64() * 1000.0;
+        conditions.jitter_ms += ((rtt_ms - previous_rtt_ms).abs() - conditions.jitter_ms) / 16.0;
+        conditions.rtt_ms = ewma(conditions.rtt_ms, rtt_ms);
+
+        let sent = sample.sent_packets.saturating_sub(previous.sent_packets);
+        let lost = sample.lost_packets.saturating_sub(previous.lost_packets);
+        if sent > 0 {
+            let loss = (lost.min(sent) as f64 / sent as f64) * 100.0;
+            conditions.packet_loss = ewma(conditions.packet_loss, loss);
+        }
+        conditions.retransmissions = sample.lost_packets;
+
+        if elapsed > 0.0 {
+            let delivered = sample.sent_bytes.saturating_sub(previous.sent_bytes)
+                .saturating_sub(sample.lost_bytes.saturating_sub(previous.lost_bytes));
+            let received = sample.received_bytes.saturating_sub(previous.received_bytes);
+            let bytes = delivered.max(received);
+            let rate_mbps = bytes as f64 * 8.0 / elapsed / 1_000_000.0;
+            conditions.throughput_mbps = ewma(conditions.throughput_mbps, rate_mbps);
+
+            // A sender that could not fill its window per RTT is app-limited
+            let rtts = (elapsed / sample.rtt.as_secs_f64().max(1e-3)).max(1.0);
+            let app_limited = (bytes as f64) < sample.cwnd as f64 * rtts;
+            let window = self.config.bandwidth_window;
+            if !app_limited || rate_mbps > conditions.bandwidth_estimate {
+                self.bandwidth.update(sample.at, rate_mbps, window);
+            } else {
+                self.bandwidth.expire(sample.at, window);
+            }
+            if let Some(max) = self.bandwidth.max() {
+                conditions.bandwidth_estimate = max;
+            }
+        }
+
+        conditions.last_update = sample.at;
+        &self.conditions
+    }
+}
+
+impl Default for ConditionsEstimator {
+    fn default() -> Self {
+        Self::new(EstimatorConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recorded trace row: (ms since start, rtt ms, cwnd, sent pkts, lost pkts, sent bytes, lost bytes)
+    type TraceRow = (u64, u64, u64, u64, u64, u64, u64);
+
+    fn replay(estimator: &mut ConditionsEstimator, start: Instant, trace: &[TraceRow]) -> NetworkConditions {
+        for &(ms, rtt, cwnd, sent_packets, lost_packets, sent_bytes, lost_bytes) in trace {
+            estimator.update(PathSample {
+                at: start + Duration::from_millis(ms),
+                rtt: Duration::from_millis(rtt),
+                cwnd,
+                sent_packets,
+                lost_packets,
+                sent_bytes,
+                lost_bytes,
+                received_bytes: 0,
+            });
+        }
+        estimator.conditions().clone()
+    }
+
+    #[test]
+    fn test_loss_from_packet_deltas() {
+        let mut estimator = ConditionsEstimator::new(EstimatorConfig { ewma_alpha: 1.0, ..Default::default() });
+        let conditions = replay(&mut estimator, Instant::now(), &[
+            (0, 20, 12_000, 1_000, 0, 0, 0),
+            (1_000, 20, 12_000, 2_000, 50, 1_200_000, 60_000),
+        ]);
+        assert!((conditions.packet_loss - 5.0).abs() < 1e-9);
+        assert_eq!(conditions.retransmissions, 50);
+    }
+
+    #[test]
+    fn test_jitter_follows_rfc3550() {
+        let mut estimator = ConditionsEstimator::default();
+        let trace: Vec<TraceRow> = (0..200)
+            .map(|i| (i * 100, if i % 2 == 0 { 40 } else { 60 }, 12_000, i * 10, 0, 0, 0))
+            .collect();
+        let conditions = replay(&mut estimator, Instant::now(), &trace);
+
+        // RTT alternating by 20 ms converges to a 20 ms jitter around a 50 ms mean
+        assert!((conditions.jitter_ms - 20.0).abs() < 0.5, "jitter {}", conditions.jitter_ms);
+        assert!((conditions.rtt_ms - 50.0).abs() < 10.0, "rtt {}", conditions.rtt_ms);
+    }
+
+    #[test]
+    fn test_bandwidth_window_max() {
+        let start = Instant::now();
+        let mut estimator = ConditionsEstimator::default();
+        // Saturated 80 Mbps (a 100 kB window every 10 ms) for 3 s
+        let mut trace: Vec<TraceRow> = (0..=3).map(|s| (s * 1_000, 10, 100_000, s * 7_000, 0, s * 10_000_000, 0)).collect();
+        let conditions = replay(&mut estimator, start, &trace);
+        assert!((conditions.bandwidth_estimate - 80.0).abs() < 1e-6);
+
+        // App-limited trickle does not lower the estimate, even past the window
+        trace = (4..=20).map(|s| (s * 1_000, 10, 1_000_000, 21_000 + s, 0, 30_000_000 + s * 1_000, 0)).collect();
+        let conditions = replay(&mut estimator, start, &trace);
+        assert!((conditions.bandwidth_estimate - 80.0).abs() < 1e-6);
+        assert!(conditions.throughput_mbps < 80.0);
+
+        // A saturated but slower link replaces the old maximum once it expires
+        trace = (21..=40).map(|s| (s * 1_000, 10, 20_000, 30_000 + s, 0, 30_020_000 + (s - 20) * 2_000_000, 0)).collect();
+        let conditions = replay(&mut estimator, start, &trace);
+        assert!((conditions.bandwidth_estimate - 16.0).abs() < 1e-6, "bw {}", conditions.bandwidth_estimate);
+    }
+}
@@ -0,0 +1,140 @@
+//! Connection lifecycle events
+//!
+//! Typed notifications delivered over a `tokio::sync::broadcast` channel so
+//! control planes can react to connection state changes without polling.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use quinn::VarInt;
+use tokio::sync::broadcast;
+
+use super::NetworkTier;
+use crate::error::ErrorKind;
+
+/// Number of events buffered per subscriber before the slowest one lags
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Which side initiated a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionDirection {
+    /// Established by [`super::StoqTransport::connect`]
+    Outbound,
+    /// Established by [`super::StoqTransport::accept`]
+    Inbound,
+}
+
+/// Lifecycle event for a STOQ connection
+#[derive(Debug, Clone)]
+pub enum ConnectionEvent {
+    /// Handshake completed and the connection is ready for streams
+    Established {
+        /// Connection identifier, as returned by `Connection::id`
+        connection_id: String,
+        /// Remote socket address
+        remote: SocketAddr,
+        /// Which side initiated the connection
+        direction: ConnectionDirection,
+    },
+    /// Peer address changed (QUIC connection migration)
+    Migrated {
+        /// Connection identifier
+        connection_id: String,
+        /// Previous remote address
+        from: SocketAddr,
+        /// New remote address
+        to: SocketAddr,
+    },
+    /// Adaptive optimization moved the connection to a new network tier
+    TierChanged {
+        /// Connection identifier
+        connection_id: String,
+        /// Tier before adaptation
+        from: NetworkTier,
+        /// Tier after adaptation
+        to: NetworkTier,
+    },
+    /// Local certificate was rotated
+    CertificateRotated {
+        /// SHA-256 fingerprint of the new certificate
+        fingerprint: String,
+    },
+    /// No packets were exchanged for half of the idle timeout
+    Idle {
+        /// Connection identifier
+        connection_id: String,
+        /// How long the connection has been quiet
+        idle_for: Duration,
+    },
+    /// Connection closed, locally or by the peer
+    Closed {
+        /// Connection identifier
+        connection_id: String,
+        /// Classification of the close reason
+        kind: ErrorKind,
+        /// Application error code, if the peer supplied one
+        code: Option<VarInt>,
+        /// Human-readable close reason
+        reason: String,
+    },
+}
+
+/// Broadcast sender for connection events, shared by transport components
+#[derive(Debug, Clone)]
+pub struct ConnectionEvents {
+    sender: broadcast::Sender<ConnectionEvent>,
+}
+
+impl ConnectionEvents {
+    /// Create a new event channel
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self { sender }
+    }
+
+    /// Subscribe to events emitted after this call
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Emit an event to all current subscribers
+    pub fn emit(&self, event: ConnectionEvent) {
+        // No subscribers is not an error
+        let _ = self.sender.send(event);
+    }
+
+    /// Number of active subscribers
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+impl Default for ConnectionEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_events_reach_subscribers() {
+        let events = ConnectionEvents::new();
+        let mut rx = events.subscribe();
+
+        events.emit(ConnectionEvent::CertificateRotated { fingerprint: "abcd".to_string() });
+
+        match rx.recv().await.unwrap() {
+            ConnectionEvent::CertificateRotated { fingerprint } => assert_eq!(fingerprint, "abcd"),
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_emit_without_subscribers() {
+        let events = ConnectionEvents::new();
+        events.emit(ConnectionEvent::CertificateRotated { fingerprint: String::new() });
+        assert_eq!(events.subscriber_count(), 0);
+    }
+}
@@ -106,6 +106,11 @@ impl FalconPublicKey {
         hasher.update(&self.key_data);
         hasher.finalize().into()
     }
+
+    /// Identifier peers know this key by: its key ID, or the hex fingerprint
+    pub fn id(&self) -> String {
+        self.key_id.clone().unwrap_or_else(|| hex::encode(self.fingerprint()))
+    }
 }
 
 /// FALCON private key for signing
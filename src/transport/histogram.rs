@@ -0,0 +1,323 @@
+//! Lock-free log-bucketed histogram
+//!
+//! Values are counted in buckets whose width grows with magnitude: each
+//! power-of-two range is split into `2^SUB_BUCKET_BITS` linear sub-buckets,
+//! bounding the relative error of any quantile to about 3%. Recording is a
+//! single relaxed atomic increment on a per-thread shard, so hot paths never
+//! contend on a lock or allocate. Snapshots are plain bucket arrays that can be
+//! merged, e.g. to combine per-connection histograms.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use crossbeam::utils::CachePadded;
+
+/// Linear sub-buckets per power of two, as a bit count
+const SUB_BUCKET_BITS: u32 = 5;
+
+/// Number of linear sub-buckets per power of two
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+
+/// Largest recordable value, as a bit count; larger values are clamped
+const MAX_VALUE_BITS: u32 = 40;
+
+/// Largest recordable value
+pub const MAX_VALUE: u64 = (1 << MAX_VALUE_BITS) - 1;
+
+/// Total bucket count
+const BUCKET_COUNT: usize = (MAX_VALUE_BITS - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS;
+
+/// Recording shards; threads are spread across them round-robin
+const SHARD_COUNT: usize = 8;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD_INDEX: Cell<usize> = Cell::new(NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARD_COUNT);
+}
+
+fn bucket_index(value: u64) -> usize {
+    let value = value.min(MAX_VALUE);
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> shift) as usize - SUB_BUCKETS;
+    (shift as usize + 1) * SUB_BUCKETS + sub_bucket
+}
+
+/// Inclusive value range covered by a bucket
+fn bucket_range(index: usize) -> (u64, u64) {
+    if index < SUB_BUCKETS {
+        return (index as u64, index as u64);
+    }
+    let shift = (index / SUB_BUCKETS - 1) as u32;
+    let sub_bucket = (index % SUB_BUCKETS + SUB_BUCKETS) as u64;
+    let low = sub_bucket << shift;
+    (low, low + (1 << shift) - 1)
+}
+
+struct Shard {
+    buckets: Box<[AtomicU64]>,
+    count: AtomicU64,
+    sum: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Shard {
+    fn new() -> Self {
+        Self {
+            buckets: (0..BUCKET_COUNT).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Concurrent histogram of `u64` values (e.g. latencies in microseconds)
+pub struct Histogram {
+    shards: Box<[CachePadded<Shard>]>,
+}
+
+impl Histogram {
+    /// Create an empty histogram
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARD_COUNT).map(|_| CachePadded::new(Shard::new())).collect(),
+        }
+    }
+
+    /// Record one value, clamped to [`MAX_VALUE`]
+    pub fn record(&self, value: u64) {
+        let value = value.min(MAX_VALUE);
+        let shard = &self.shards[SHARD_INDEX.with(Cell::get)];
+        shard.buckets[bucket_index(value)].fetch_add(1, Ordering::Relaxed);
+        shard.count.fetch_add(1, Ordering::Relaxed);
+        shard.sum.fetch_add(value, Ordering::Relaxed);
+        shard.min.fetch_min(value, Ordering::Relaxed);
+        shard.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    /// Add every value of a snapshot to this histogram
+    pub fn merge(&self, snapshot: &HistogramSnapshot) {
+        let shard = &self.shards[SHARD_INDEX.with(Cell::get)];
+        for (bucket, count) in shard.buckets.iter().zip(&snapshot.buckets) {
+            if *count > 0 {
+                bucket.fetch_add(*count, Ordering::Relaxed);
+            }
+        }
+        shard.count.fetch_add(snapshot.count, Ordering::Relaxed);
+        shard.sum.fetch_add(snapshot.sum, Ordering::Relaxed);
+        if !snapshot.is_empty() {
+            shard.min.fetch_min(snapshot.min, Ordering::Relaxed);
+            shard.max.fetch_max(snapshot.max, Ordering::Relaxed);
+        }
+    }
+
+    /// Copy the current contents
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        self.collect(|counter| counter.load(Ordering::Relaxed), |min| min.load(Ordering::Relaxed), |max| max.load(Ordering::Relaxed))
+    }
+
+    /// Take the current contents and reset to empty
+    ///
+    /// Each bucket is swapped out atomically, so every recorded value lands
+    /// in exactly one snapshot even while other threads keep recording.
+    pub fn snapshot_and_reset(&self) -> HistogramSnapshot {
+        self.collect(
+            |counter| counter.swap(0, Ordering::Relaxed),
+            |min| min.swap(u64::MAX, Ordering::Relaxed),
+            |max| max.swap(0, Ordering::Relaxed),
+        )
+    }
+
+    fn collect(
+        &self,
+        take: impl Fn(&AtomicU64) -> u64,
+        take_min: impl Fn(&AtomicU64) -> u64,
+        take_max: impl Fn(&AtomicU64) -> u64,
+    ) -> HistogramSnapshot {
+        let mut snapshot = HistogramSnapshot::default();
+        for shard in self.shards.iter() {
+            for (total, bucket) in snapshot.buckets.iter_mut().zip(shard.buckets.iter()) {
+                *total += take(bucket);
+            }
+            snapshot.count += take(&shard.count);
+            snapshot.sum += take(&shard.sum);
+            snapshot.min = snapshot.min.min(take_min(&shard.min));
+            snapshot.max = snapshot.max.max(take_max(&shard.max));
+        }
+        snapshot
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Point-in-time copy of a [`Histogram`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for HistogramSnapshot {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; BUCKET_COUNT],
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+}
+
+impl HistogramSnapshot {
+    /// Number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Whether no values were recorded
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Sum of recorded values
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Mean of recorded values (0 when empty)
+    pub fn mean(&self) -> u64 {
+        self.sum.checked_div(self.count).unwrap_or(0)
+    }
+
+    /// Smallest recorded value (0 when empty)
+    pub fn min(&self) -> u64 {
+        if self.is_empty() { 0 } else { self.min }
+    }
+
+    /// Largest recorded value (0 when empty)
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Value at quantile `q` in `[0, 1]` (0 when empty)
+    ///
+    /// Returns the midpoint of the bucket holding the value, clamped to the
+    /// observed min and max.
+    pub fn quantile(&self, q: f64) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let (low, high) = bucket_range(index);
+                return (low + (high - low) / 2).clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Add another snapshot's values to this one
+    pub fn merge(&mut self, other: &HistogramSnapshot) {
+        for (total, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *total += count;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_bucket_ranges_are_contiguous() {
+        let mut expected_low = 0;
+        for index in 0..BUCKET_COUNT {
+            let (low, high) = bucket_range(index);
+            assert_eq!(low, expected_low, "bucket {}", index);
+            assert_eq!(bucket_index(low), index);
+            assert_eq!(bucket_index(high), index);
+            expected_low = high + 1;
+        }
+        assert_eq!(expected_low, MAX_VALUE + 1);
+    }
+
+    #[test]
+    fn test_quantiles_within_error_bound() {
+        let histogram = Histogram::new();
+        for value in 1..=10_000u64 {
+            histogram.record(value * 100);
+        }
+        let snapshot = histogram.snapshot();
+
+        for (q, exact) in [(0.5, 500_000.0), (0.9, 900_000.0), (0.99, 990_000.0), (0.999, 999_000.0)] {
+            let estimate = snapshot.quantile(q) as f64;
+            assert!((estimate - exact).abs() / exact < 0.04, "q={} estimate={}", q, estimate);
+        }
+        assert_eq!(snapshot.quantile(0.0), 100);
+        assert_eq!(snapshot.quantile(1.0), 1_000_000);
+        assert_eq!(snapshot.mean(), 500_050);
+    }
+
+    #[test]
+    fn test_snapshot_and_reset_loses_nothing() {
+        let histogram = Arc::new(Histogram::new());
+        let writers: Vec<_> = (0..4).map(|_| {
+            let histogram = histogram.clone();
+            std::thread::spawn(move || {
+                for value in 0..10_000 {
+                    histogram.record(value);
+                }
+            })
+        }).collect();
+
+        let mut total = HistogramSnapshot::default();
+        while writers.iter().any(|w| !w.is_finished()) {
+            total.merge(&histogram.snapshot_and_reset());
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        total.merge(&histogram.snapshot_and_reset());
+
+        assert_eq!(total.count(), 40_000);
+        assert_eq!(total.sum(), 4 * (0..10_000u64).sum::<u64>());
+        assert!(histogram.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_merge_combines_histograms() {
+        let a = Histogram::new();
+        let b = Histogram::new();
+        a.record(10);
+        b.record(30);
+        a.merge(&b.snapshot());
+
+        let snapshot = a.snapshot();
+        assert_eq!(snapshot.count(), 2);
+        assert_eq!((snapshot.min(), snapshot.max()), (10, 30));
+        assert_eq!(snapshot.quantile(0.5), 10);
+    }
+}
@@ -8,11 +8,17 @@
 
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Instant, Duration};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use parking_lot::RwLock;
 use serde::{Serialize, Deserialize};
 use std::sync::Arc;
 
+use crate::error::ErrorKind;
+use super::histogram::{Histogram, HistogramSnapshot};
+
+/// Completed reporting intervals kept for sparklines
+pub const INTERVAL_HISTORY_LEN: usize = 60;
+
 /// Core transport metrics with native collection
 pub struct TransportMetrics {
     // Basic counters
@@ -27,61 +33,35 @@ pub struct TransportMetrics {
     shards_reassembled: AtomicU64,
     hop_routes_processed: AtomicU64,
 
-    // Performance metrics
-    latency_samples: Arc<RwLock<LatencyTracker>>,
+    // Path metrics rolled up from per-connection QUIC stats
+    lost_packets: AtomicU64,
+    congestion_events: AtomicU64,
+    retransmitted_bytes: AtomicU64,
+    datagrams_dropped: AtomicU64,
+
+    // Performance metrics (latencies in microseconds)
+    latency: Histogram,
+    interval_latency: Histogram,
     error_counts: Arc<RwLock<ErrorMetrics>>,
 
     // Timing
     start_time: Instant,
-    last_reset: Arc<RwLock<Instant>>,
+    interval: RwLock<IntervalState>,
 }
 
-/// Latency tracking with percentiles
-struct LatencyTracker {
-    samples: VecDeque<u64>, // Microseconds
-    max_samples: usize,
-    sum: u64,
-    count: u64,
+/// Counter values at the start of the current reporting interval
+#[derive(Clone, Copy)]
+struct CounterBaseline {
+    at: Instant,
+    bytes_sent: u64,
+    bytes_received: u64,
+    packets_tokenized: u64,
+    connections_established: u64,
 }
 
-impl LatencyTracker {
-    fn new(max_samples: usize) -> Self {
-        Self {
-            samples: VecDeque::with_capacity(max_samples),
-            max_samples,
-            sum: 0,
-            count: 0,
-        }
-    }
-
-    fn record(&mut self, latency_us: u64) {
-        if self.samples.len() >= self.max_samples {
-            if let Some(old) = self.samples.pop_front() {
-                self.sum = self.sum.saturating_sub(old);
-            }
-        }
-        self.samples.push_back(latency_us);
-        self.sum += latency_us;
-        self.count += 1;
-    }
-
-    fn average(&self) -> u64 {
-        if self.samples.is_empty() {
-            0
-        } else {
-            self.sum / self.samples.len() as u64
-        }
-    }
-
-    fn percentile(&self, p: f64) -> u64 {
-        if self.samples.is_empty() {
-            return 0;
-        }
-        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
-        sorted.sort_unstable();
-        let idx = ((sorted.len() as f64 - 1.0) * p / 100.0) as usize;
-        sorted[idx]
-    }
+struct IntervalState {
+    baseline: CounterBaseline,
+    history: VecDeque<IntervalMetrics>,
 }
 
 /// Error tracking metrics
@@ -92,6 +72,7 @@ struct ErrorMetrics {
     sharding_errors: u64,
     reassembly_errors: u64,
     token_validation_failures: u64,
+    connection_closes: HashMap<ErrorKind, u64>,
 }
 
 impl TransportMetrics {
@@ -105,10 +86,24 @@ impl TransportMetrics {
             packets_sharded: AtomicU64::new(0),
             shards_reassembled: AtomicU64::new(0),
             hop_routes_processed: AtomicU64::new(0),
-            latency_samples: Arc::new(RwLock::new(LatencyTracker::new(10000))),
+            lost_packets: AtomicU64::new(0),
+            congestion_events: AtomicU64::new(0),
+            retransmitted_bytes: AtomicU64::new(0),
+            datagrams_dropped: AtomicU64::new(0),
+            latency: Histogram::new(),
+            interval_latency: Histogram::new(),
             error_counts: Arc::new(RwLock::new(ErrorMetrics::default())),
             start_time: Instant::now(),
-            last_reset: Arc::new(RwLock::new(Instant::now())),
+            interval: RwLock::new(IntervalState {
+                baseline: CounterBaseline {
+                    at: Instant::now(),
+                    bytes_sent: 0,
+                    bytes_received: 0,
+                    packets_tokenized: 0,
+                    connections_established: 0,
+                },
+                history: VecDeque::with_capacity(INTERVAL_HISTORY_LEN),
+            }),
         }
     }
 
@@ -149,7 +144,46 @@ impl TransportMetrics {
     // Performance metrics
     pub fn record_latency(&self, latency: Duration) {
         let latency_us = latency.as_micros() as u64;
-        self.latency_samples.write().record(latency_us);
+        self.latency.record(latency_us);
+        self.interval_latency.record(latency_us);
+    }
+
+    /// Snapshot of all latency samples since start, in microseconds
+    pub fn latency_histogram(&self) -> HistogramSnapshot {
+        self.latency.snapshot()
+    }
+
+    /// Roll a connection stats sample into the transport totals
+    ///
+    /// `previous` is the last sample recorded for the same connection, so only
+    /// the growth since then is added. The sample's RTT feeds the latency
+    /// percentiles.
+    pub fn record_connection_stats(&self, previous: Option<&ConnectionStats>, current: &ConnectionStats) {
+        let delta = |f: fn(&ConnectionStats) -> u64| {
+            f(current).saturating_sub(previous.map(f).unwrap_or(0))
+        };
+        self.lost_packets.fetch_add(delta(|s| s.lost_packets), Ordering::Relaxed);
+        self.congestion_events.fetch_add(delta(|s| s.congestion_events), Ordering::Relaxed);
+        self.retransmitted_bytes.fetch_add(delta(|s| s.retransmitted_bytes), Ordering::Relaxed);
+        self.datagrams_dropped.fetch_add(delta(|s| s.datagrams_dropped), Ordering::Relaxed);
+        if !current.rtt.is_zero() {
+            self.record_latency(current.rtt);
+        }
+    }
+
+    /// Count a connection close by its classified reason
+    pub fn record_connection_close_kind(&self, kind: ErrorKind) {
+        *self.error_counts.write().connection_closes.entry(kind).or_insert(0) += 1;
+    }
+
+    /// Connection closes so far, grouped by reason
+    pub fn connection_closes_by_kind(&self) -> Vec<(ErrorKind, u64)> {
+        let mut closes: Vec<_> = self.error_counts.read().connection_closes
+            .iter()
+            .map(|(kind, count)| (*kind, *count))
+            .collect();
+        closes.sort_by_key(|(kind, _)| kind.as_str());
+        closes
     }
 
     pub fn record_connection_failure(&self) {
@@ -185,7 +219,7 @@ impl TransportMetrics {
             0.0
         };
 
-        let avg_latency_us = self.latency_samples.read().average();
+        let avg_latency_us = self.latency.snapshot().mean();
 
         crate::TransportStats {
             bytes_sent,
@@ -200,42 +234,65 @@ impl TransportMetrics {
     /// Get detailed protocol metrics for monitoring dashboard
     pub fn get_protocol_metrics(&self) -> ProtocolMetrics {
         let errors = self.error_counts.read();
-        let latency = self.latency_samples.read();
+        let latency = self.latency.snapshot();
 
         ProtocolMetrics {
             packets_tokenized: self.packets_tokenized.load(Ordering::Relaxed),
             packets_sharded: self.packets_sharded.load(Ordering::Relaxed),
             shards_reassembled: self.shards_reassembled.load(Ordering::Relaxed),
             hop_routes_processed: self.hop_routes_processed.load(Ordering::Relaxed),
-            avg_latency_us: latency.average(),
-            p50_latency_us: latency.percentile(50.0),
-            p95_latency_us: latency.percentile(95.0),
-            p99_latency_us: latency.percentile(99.0),
+            avg_latency_us: latency.mean(),
+            p50_latency_us: latency.quantile(0.50),
+            p95_latency_us: latency.quantile(0.95),
+            p99_latency_us: latency.quantile(0.99),
             connection_failures: errors.connection_failures,
             packet_drops: errors.packet_drops,
             sharding_errors: errors.sharding_errors,
             reassembly_errors: errors.reassembly_errors,
             token_validation_failures: errors.token_validation_failures,
+            lost_packets: self.lost_packets.load(Ordering::Relaxed),
+            congestion_events: self.congestion_events.load(Ordering::Relaxed),
+            retransmitted_bytes: self.retransmitted_bytes.load(Ordering::Relaxed),
+            datagrams_dropped: self.datagrams_dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    fn counter_baseline(&self) -> CounterBaseline {
+        CounterBaseline {
+            at: Instant::now(),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            packets_tokenized: self.packets_tokenized.load(Ordering::Relaxed),
+            connections_established: self.connections_established.load(Ordering::Relaxed),
         }
     }
 
-    /// Reset non-cumulative metrics (for periodic reporting)
-    pub fn reset_interval_metrics(&self) {
-        *self.last_reset.write() = Instant::now();
+    /// Close the current reporting interval and start a new one
+    ///
+    /// The closed interval is appended to the history (see
+    /// [`interval_history`](Self::interval_history)) and returned.
+    pub fn reset_interval_metrics(&self) -> IntervalMetrics {
+        let mut interval = self.interval.write();
+        let now = self.counter_baseline();
+        let completed = IntervalMetrics::between(&interval.baseline, &now, &self.interval_latency.snapshot_and_reset());
+
+        if interval.history.len() == INTERVAL_HISTORY_LEN {
+            interval.history.pop_front();
+        }
+        interval.history.push_back(completed.clone());
+        interval.baseline = now;
+        completed
     }
 
-    /// Get metrics since last reset
+    /// Get metrics for the current interval so far
     pub fn get_interval_metrics(&self) -> IntervalMetrics {
-        let elapsed = self.last_reset.read().elapsed().as_secs_f64();
-        let bytes_sent = self.bytes_sent.load(Ordering::Relaxed);
-        let bytes_received = self.bytes_received.load(Ordering::Relaxed);
+        let baseline = self.interval.read().baseline;
+        IntervalMetrics::between(&baseline, &self.counter_baseline(), &self.interval_latency.snapshot())
+    }
 
-        IntervalMetrics {
-            duration_secs: elapsed,
-            throughput_gbps: ((bytes_sent + bytes_received) as f64 * 8.0) / (elapsed * 1_000_000_000.0),
-            packets_per_sec: self.packets_tokenized.load(Ordering::Relaxed) as f64 / elapsed,
-            connections_per_sec: self.connections_established.load(Ordering::Relaxed) as f64 / elapsed,
-        }
+    /// Completed intervals, oldest first (at most [`INTERVAL_HISTORY_LEN`])
+    pub fn interval_history(&self) -> Vec<IntervalMetrics> {
+        self.interval.read().history.iter().cloned().collect()
     }
 }
 
@@ -255,13 +312,187 @@ pub struct ProtocolMetrics {
     pub sharding_errors: u64,
     pub reassembly_errors: u64,
     pub token_validation_failures: u64,
+    /// Packets declared lost across all connections
+    pub lost_packets: u64,
+    /// Congestion events across all connections
+    pub congestion_events: u64,
+    /// Bytes declared lost and retransmitted across all connections
+    pub retransmitted_bytes: u64,
+    /// Outgoing datagrams dropped across all connections
+    pub datagrams_dropped: u64,
+}
+
+/// Locally tracked per-connection counters that QUIC stats don't cover
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionCounters {
+    pub(crate) streams_opened: AtomicU64,
+    pub(crate) streams_accepted: AtomicU64,
+    pub(crate) datagrams_dropped: AtomicU64,
+}
+
+/// Per-connection statistics, sampled from the QUIC path state
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    /// Smoothed round-trip time
+    pub rtt: Duration,
+    /// Congestion window in bytes
+    pub cwnd: u64,
+    /// Congestion events (window reductions)
+    pub congestion_events: u64,
+    /// Packets sent
+    pub sent_packets: u64,
+    /// Packets declared lost
+    pub lost_packets: u64,
+    /// Bytes declared lost and scheduled for retransmission
+    pub retransmitted_bytes: u64,
+    /// Current path MTU
+    pub current_mtu: u16,
+    /// Outgoing datagrams that could not be sent
+    pub datagrams_dropped: u64,
+    /// Bidirectional streams opened locally
+    pub streams_opened: u64,
+    /// Bidirectional streams accepted from the peer
+    pub streams_accepted: u64,
+    /// UDP payload bytes sent, including QUIC overhead
+    pub bytes_sent: u64,
+    /// UDP payload bytes received, including QUIC overhead
+    pub bytes_received: u64,
+    /// UDP datagrams sent
+    pub datagrams_sent: u64,
+    /// UDP datagrams received
+    pub datagrams_received: u64,
+}
+
+impl ConnectionStats {
+    pub(crate) fn sample(stats: &quinn::ConnectionStats, counters: &ConnectionCounters) -> Self {
+        Self {
+            rtt: stats.path.rtt,
+            cwnd: stats.path.cwnd,
+            congestion_events: stats.path.congestion_events,
+            sent_packets: stats.path.sent_packets,
+            lost_packets: stats.path.lost_packets,
+            retransmitted_bytes: stats.path.lost_bytes,
+            current_mtu: stats.path.current_mtu,
+            datagrams_dropped: counters.datagrams_dropped.load(Ordering::Relaxed),
+            streams_opened: counters.streams_opened.load(Ordering::Relaxed),
+            streams_accepted: counters.streams_accepted.load(Ordering::Relaxed),
+            bytes_sent: stats.udp_tx.bytes,
+            bytes_received: stats.udp_rx.bytes,
+            datagrams_sent: stats.udp_tx.datagrams,
+            datagrams_received: stats.udp_rx.datagrams,
+        }
+    }
 }
 
 /// Interval-based metrics for rate calculations
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IntervalMetrics {
     pub duration_secs: f64,
+    /// Bytes sent during the interval
+    pub bytes_sent: u64,
+    /// Bytes received during the interval
+    pub bytes_received: u64,
+    /// Connections established during the interval
+    pub connections_established: u64,
     pub throughput_gbps: f64,
     pub packets_per_sec: f64,
     pub connections_per_sec: f64,
-}
\ No newline at end of file
+    /// Median latency recorded during the interval
+    pub p50_latency_us: u64,
+    /// 99th percentile latency recorded during the interval
+    pub p99_latency_us: u64,
+}
+
+impl IntervalMetrics {
+    fn between(start: &CounterBaseline, end: &CounterBaseline, latency: &HistogramSnapshot) -> Self {
+        let elapsed = end.at.saturating_duration_since(start.at).as_secs_f64();
+        let rate = |delta: u64| if elapsed > 0.0 { delta as f64 / elapsed } else { 0.0 };
+
+        let bytes_sent = end.bytes_sent.saturating_sub(start.bytes_sent);
+        let bytes_received = end.bytes_received.saturating_sub(start.bytes_received);
+        let connections_established = end.connections_established.saturating_sub(start.connections_established);
+
+        Self {
+            duration_secs: elapsed,
+            bytes_sent,
+            bytes_received,
+            connections_established,
+            throughput_gbps: rate((bytes_sent + bytes_received) * 8) / 1_000_000_000.0,
+            packets_per_sec: rate(end.packets_tokenized.saturating_sub(start.packets_tokenized)),
+            connections_per_sec: rate(connections_established),
+            p50_latency_us: latency.quantile(0.50),
+            p99_latency_us: latency.quantile(0.99),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_stats_rollup_adds_deltas() {
+        let metrics = TransportMetrics::new();
+        let first = ConnectionStats {
+            rtt: Duration::from_millis(10),
+            lost_packets: 3,
+            retransmitted_bytes: 3000,
+            ..ConnectionStats::default()
+        };
+        let second = ConnectionStats {
+            rtt: Duration::from_millis(20),
+            lost_packets: 5,
+            retransmitted_bytes: 4000,
+            congestion_events: 1,
+            ..ConnectionStats::default()
+        };
+
+        metrics.record_connection_stats(None, &first);
+        metrics.record_connection_stats(Some(&first), &second);
+
+        let protocol = metrics.get_protocol_metrics();
+        assert_eq!(protocol.lost_packets, 5);
+        assert_eq!(protocol.retransmitted_bytes, 4000);
+        assert_eq!(protocol.congestion_events, 1);
+        assert!(protocol.p50_latency_us.abs_diff(10_000) < 10_000 * 3 / 100);
+        assert_eq!(protocol.p99_latency_us, 20_000);
+        assert_eq!(protocol.avg_latency_us, 15_000);
+    }
+
+    #[test]
+    fn test_interval_latency_resets() {
+        let metrics = TransportMetrics::new();
+        metrics.record_latency(Duration::from_millis(5));
+        metrics.record_latency(Duration::from_millis(5));
+
+        assert!(metrics.get_interval_metrics().p99_latency_us > 0);
+        assert!(metrics.reset_interval_metrics().p99_latency_us > 0);
+        assert_eq!(metrics.get_interval_metrics().p99_latency_us, 0);
+
+        // Cumulative latency is kept across intervals
+        assert_eq!(metrics.latency_histogram().count(), 2);
+    }
+
+    #[test]
+    fn test_interval_metrics_report_deltas() {
+        let metrics = TransportMetrics::new();
+        metrics.record_bytes_sent(1_000);
+        metrics.record_connection_established();
+        let first = metrics.reset_interval_metrics();
+        assert_eq!((first.bytes_sent, first.connections_established), (1_000, 1));
+
+        metrics.record_bytes_sent(250);
+        metrics.record_bytes_received(50);
+        let current = metrics.get_interval_metrics();
+        assert_eq!((current.bytes_sent, current.bytes_received), (250, 50));
+        assert_eq!(current.connections_established, 0);
+        assert_eq!(current.connections_per_sec, 0.0);
+
+        for _ in 0..INTERVAL_HISTORY_LEN + 5 {
+            metrics.reset_interval_metrics();
+        }
+        let history = metrics.interval_history();
+        assert_eq!(history.len(), INTERVAL_HISTORY_LEN);
+        assert!(history.iter().all(|interval| interval.bytes_sent == 0));
+    }
+}
@@ -3,37 +3,75 @@
 use async_trait::async_trait;
 use quinn::{self, TransportConfig as QuinnTransportConfig, VarInt};
 // Certificate types imported elsewhere
-use std::net::{SocketAddr, Ipv6Addr};
+use std::net::{SocketAddr, SocketAddrV6, Ipv6Addr};
 use std::sync::Arc;
 use std::time::Duration;
 use socket2;
-use anyhow::{Result, anyhow};
-use bytes::{Bytes, BytesMut, BufMut};
+use crate::error::{Result, StoqError};
+use bytes::{Bytes, BufMut};
 use parking_lot::{RwLock, Mutex};
 use dashmap::DashMap;
-use tracing::{info, debug, warn};
+use tracing::{info, debug, warn, debug_span, info_span, Instrument, Span};
 use serde::{Serialize, Deserialize};
-use std::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::collections::VecDeque;
+use tokio::task::JoinSet;
 // Simplified memory management - no unsafe operations
 
 pub mod certificates;
 pub mod streams;
 pub mod metrics;
+pub mod histogram;
 pub mod falcon;
 pub mod adaptive;
+pub mod events;
+pub mod admission;
+pub mod dial;
+pub mod pool;
+pub mod openmetrics;
+pub mod congestion;
+pub mod reconfigure;
+pub mod policy;
+pub mod estimator;
+pub mod relay;
+pub mod seed;
+mod signing;
+#[cfg(test)]
+mod testing;
 #[cfg(feature = "ebpf")]
 pub mod ebpf;
 
 use certificates::CertificateManager;
-use metrics::TransportMetrics;
-pub use metrics::{ProtocolMetrics, IntervalMetrics};
+use metrics::{TransportMetrics, ConnectionCounters};
+pub use metrics::{ProtocolMetrics, IntervalMetrics, ConnectionStats, INTERVAL_HISTORY_LEN};
+pub use histogram::{Histogram, HistogramSnapshot};
 use falcon::{FalconTransport, FalconVariant};
 use adaptive::{AdaptiveConnection, AdaptationManager};
+pub use events::{ConnectionEvent, ConnectionDirection, ConnectionEvents};
+pub use admission::{AdmissionConfig, AdmissionDecision, AdmissionStats, IncomingInfo, Ipv6Prefix};
+use admission::{AdmissionController, Reservation};
+pub use dial::{DialConfig, MultiEndpoint};
+use dial::DialCache;
+use signing::ConnectionSigning;
+pub use pool::{MemoryPool, MemoryPoolStats, PooledBuffer};
+pub use openmetrics::{MetricsServer, OpenMetricsEncoder};
+pub use congestion::CongestionConfig;
+pub use reconfigure::ReconfigureReport;
+pub use policy::{AdaptationPolicy, HysteresisConfig, TieredPolicy, TieredPolicyConfig};
+pub use estimator::{ConditionsEstimator, EstimatorConfig, PathSample};
+pub use relay::{RelayConfig, RelayHandle};
+pub use seed::{SeedConfig, SeedReport};
 
 // Protocol integration
-use crate::protocol::{StoqProtocolHandler, handshake::StoqHandshakeExtension};
-use crate::extensions::DefaultStoqExtensions;
+use crate::protocol::{StoqProtocolHandler, STOQ_ALPN, error_codes, handshake::StoqHandshakeExtension};
+use crate::extensions::{DefaultStoqExtensions, HopInfo, SeedInfo, StoqPacket};
+use crate::protocol::frames::SeedFrame;
+use crate::protocol::relay::{RelayHeader, HOP_ALPN};
+pub use crate::protocol::signing::FrameSigningPolicy;
+#[cfg(feature = "trace-context")]
+use crate::protocol::trace_context;
+#[cfg(feature = "trace-context")]
+pub use crate::protocol::trace_context::{TraceContext, TraceParent};
 
 /// Network tier classification for adaptive configuration
 #[derive(Debug, Clone)]
@@ -65,6 +103,18 @@ impl NetworkTier {
             _ => NetworkTier::Slow { mbps },
         }
     }
+
+    /// Stable lowercase tier name, suitable for logs and metric labels
+    pub fn name(&self) -> &'static str {
+        match self {
+            NetworkTier::Slow { .. } => "slow",
+            NetworkTier::Home { .. } => "home",
+            NetworkTier::Standard { .. } => "standard",
+            NetworkTier::Performance { .. } => "performance",
+            NetworkTier::Enterprise { .. } => "enterprise",
+            NetworkTier::DataCenter { .. } => "data_center",
+        }
+    }
 }
 
 /// STOQ Transport configuration for QUIC over IPv6
@@ -100,6 +150,9 @@ pub struct TransportConfig {
     pub max_datagram_size: usize,
     /// Congestion control algorithm
     pub congestion_control: CongestionControl,
+    /// Congestion controller tunables
+    #[serde(default)]
+    pub congestion: CongestionConfig,
     /// Enable memory pool optimization for zero-copy
     pub enable_memory_pool: bool,
     /// Memory pool size for zero-copy operations
@@ -114,12 +167,28 @@ pub struct TransportConfig {
     pub enable_falcon_crypto: bool,
     /// FALCON variant to use
     pub falcon_variant: FalconVariant,
+    /// Which STOQ frames must be sent and received in FALCON-signed groups
+    #[serde(default)]
+    pub frame_signing: FrameSigningPolicy,
+    /// Accept relayed streams as their final receiver, advertised to peers
+    /// through ALPN
+    #[serde(default)]
+    pub relayed_streams: bool,
+    /// Admission control applied to incoming connections before the handshake
+    #[serde(default)]
+    pub admission: AdmissionConfig,
+    /// Multi-address dialing behaviour
+    #[serde(default)]
+    pub dial: DialConfig,
+    /// Tier table used by adaptive connections
+    #[serde(default)]
+    pub adaptation: TieredPolicyConfig,
 }
 
 /// Congestion control algorithms
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CongestionControl {
-    /// BBR v2 for maximum throughput
+    /// BBR for maximum throughput (quinn's experimental BBR controller)
     Bbr2,
     /// CUBIC (default)
     Cubic,
@@ -151,6 +220,7 @@ impl Default for TransportConfig {
             enable_zero_copy: true, // Zero-copy optimization
             max_datagram_size: 65507, // Maximum UDP datagram
             congestion_control: CongestionControl::default(),
+            congestion: CongestionConfig::default(),
             enable_memory_pool: true, // Memory pool optimization
             memory_pool_size: 1024, // 1024 buffers per pool
             frame_batch_size: 64, // Batch 64 frames per syscall
@@ -158,6 +228,11 @@ impl Default for TransportConfig {
             enable_large_send_offload: true, // LSO for large transfers
             enable_falcon_crypto: true, // Quantum-resistant FALCON cryptography
             falcon_variant: FalconVariant::Falcon1024, // Maximum security level
+            frame_signing: FrameSigningPolicy::Off,
+            relayed_streams: false,
+            admission: AdmissionConfig::default(),
+            dial: DialConfig::default(),
+            adaptation: TieredPolicyConfig::default(),
         }
     }
 }
@@ -174,6 +249,7 @@ impl TransportConfig {
                 self.frame_batch_size = 4;
                 self.enable_zero_copy = false;
                 self.max_datagram_size = 1200; // Conservative MTU
+                self.congestion_control = CongestionControl::NewReno;
                 debug!("Adapted config for slow network tier");
             },
             NetworkTier::Home { .. } => {
@@ -184,6 +260,7 @@ impl TransportConfig {
                 self.frame_batch_size = 16;
                 self.enable_zero_copy = true;
                 self.max_datagram_size = 1500;
+                self.congestion_control = CongestionControl::Cubic;
                 debug!("Adapted config for home network tier");
             },
             NetworkTier::Standard { .. } => {
@@ -195,6 +272,7 @@ impl TransportConfig {
                 self.enable_zero_copy = true;
                 self.enable_large_send_offload = true;
                 self.max_datagram_size = 9000; // Jumbo frames
+                self.congestion_control = CongestionControl::Bbr2;
                 debug!("Adapted config for standard gigabit network tier");
             },
             NetworkTier::Performance { .. } | NetworkTier::Enterprise { .. } | NetworkTier::DataCenter { .. } => {
@@ -208,6 +286,7 @@ impl TransportConfig {
                 self.enable_large_send_offload = true;
                 self.enable_cpu_affinity = true;
                 self.max_datagram_size = 9000; // Jumbo frames
+                self.congestion_control = CongestionControl::Bbr2;
                 debug!("Adapted config for high-performance network tier");
             }
         }
@@ -221,6 +300,8 @@ pub struct Endpoint {
     pub address: Ipv6Addr,
     /// Port number
     pub port: u16,
+    /// Interface index for link-local addresses, 0 when unscoped
+    pub scope_id: u32,
     /// Optional server name for SNI
     pub server_name: Option<String>,
 }
@@ -231,9 +312,16 @@ impl Endpoint {
         Self {
             address,
             port,
+            scope_id: 0,
             server_name: None,
         }
     }
+
+    /// Set the interface index a link-local address is reached through
+    pub fn with_scope_id(mut self, scope_id: u32) -> Self {
+        self.scope_id = scope_id;
+        self
+    }
     
     /// Set server name for SNI
     pub fn with_server_name(mut self, name: String) -> Self {
@@ -243,58 +331,16 @@ impl Endpoint {
     
     /// Convert to socket address
     pub fn to_socket_addr(&self) -> SocketAddr {
-        SocketAddr::from((self.address, self.port))
+        SocketAddr::V6(SocketAddrV6::new(self.address, self.port, 0, self.scope_id))
     }
 }
 
-/// Memory buffer pool for efficient buffer reuse (simplified for safety)
-pub struct MemoryPool {
-    buffer_size: usize,
-    allocated_count: AtomicUsize,
-    max_buffers: usize,
-}
-
-impl MemoryPool {
-    /// Create a new memory pool for efficient buffer management
-    pub fn new(buffer_size: usize, max_buffers: usize) -> Self {
-        Self {
-            buffer_size,
-            allocated_count: AtomicUsize::new(0),
-            max_buffers,
-        }
-    }
-    
-    /// Get a buffer from the pool (simplified for safety)
-    pub fn get_buffer(&self) -> Option<BytesMut> {
-        // Allocate new buffer if under limit
-        if self.allocated_count.load(Ordering::Relaxed) < self.max_buffers {
-            self.allocated_count.fetch_add(1, Ordering::Relaxed);
-            return Some(BytesMut::with_capacity(self.buffer_size));
-        }
-
-        None
-    }
-    
-    /// Return buffer to pool for reuse
-    pub fn return_buffer(&self, mut buffer: BytesMut) {
-        if buffer.capacity() >= self.buffer_size {
-            // Clear buffer and drop safely - memory safety first
-            buffer.clear();
-            // Note: Actual zero-copy optimization requires careful lifetime management
-            // For now, we prioritize safety by allowing normal deallocation
-            // TODO: Implement proper shared buffer pool with Arc<Mutex<Vec<BytesMut>>>
-        }
-    }
-    
-    /// Get current pool statistics
-    pub fn stats(&self) -> (usize, usize) {
-        (0, self.allocated_count.load(Ordering::Relaxed)) // Pool size = 0 (no reuse)
+impl From<SocketAddrV6> for Endpoint {
+    fn from(addr: SocketAddrV6) -> Self {
+        Self::new(*addr.ip(), addr.port()).with_scope_id(addr.scope_id())
     }
 }
 
-unsafe impl Send for MemoryPool {}
-unsafe impl Sync for MemoryPool {}
-
 /// Frame batch for syscall reduction optimization
 pub struct FrameBatch {
     frames: Vec<Bytes>,
@@ -342,6 +388,15 @@ pub struct Connection {
     memory_pool: Arc<MemoryPool>,
     frame_batch: Arc<Mutex<FrameBatch>>,
     last_activity: AtomicU64,
+    counters: Arc<ConnectionCounters>,
+    span: Span,
+    /// Whether the peer agreed to receive relayed streams
+    hop_preambles: bool,
+    /// Signs and verifies the seed and relay preambles sent over the connection
+    signing: Arc<ConnectionSigning>,
+    /// The transport's trace parent hook, for inbound streams
+    #[cfg(feature = "trace-context")]
+    trace_parent: Arc<RwLock<Option<TraceParent>>>,
 }
 
 impl Connection {
@@ -353,6 +408,15 @@ impl Connection {
         memory_pool: Arc<MemoryPool>,
         frame_batch_size: usize,
     ) -> Self {
+        let span = info_span!(
+            "stoq.connection",
+            connection_id = ?inner.stable_id(),
+            remote = %inner.remote_address(),
+        );
+        let hop_preambles = inner.handshake_data()
+            .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+            .is_some_and(|data| data.protocol.as_deref() == Some(HOP_ALPN));
+        let signing = Arc::new(ConnectionSigning::unsigned(inner.clone()));
         Self {
             inner,
             endpoint,
@@ -360,8 +424,29 @@ impl Connection {
             memory_pool,
             frame_batch: Arc::new(Mutex::new(FrameBatch::new(frame_batch_size))),
             last_activity: AtomicU64::new(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()),
+            counters: Arc::new(ConnectionCounters::default()),
+            span,
+            hop_preambles,
+            signing,
+            #[cfg(feature = "trace-context")]
+            trace_parent: Arc::default(),
         }
     }
+
+    /// Sign and verify preambles per `protocol`, and prove the local FALCON key to the peer
+    fn with_signing(mut self, protocol: Arc<StoqProtocolHandler>, identity_timeout: Duration) -> Self {
+        let signing = ConnectionSigning::new(self.inner.clone(), protocol, identity_timeout);
+        signing.spawn_identity_proof();
+        self.signing = Arc::new(signing);
+        self
+    }
+
+    /// Parent inbound streams that carry a trace context through the transport's hook
+    #[cfg(feature = "trace-context")]
+    fn with_trace_parent(mut self, trace_parent: Arc<RwLock<Option<TraceParent>>>) -> Self {
+        self.trace_parent = trace_parent;
+        self
+    }
     
     /// Get the connection ID
     pub fn id(&self) -> String {
@@ -373,27 +458,73 @@ impl Connection {
         &self.endpoint
     }
     
+    /// Tracing span covering this connection's lifetime
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     /// Open a new bidirectional stream
     pub async fn open_stream(&self) -> Result<Stream> {
-        let (send, recv) = self.inner.open_bi().await?;
-        Ok(Stream::new(send, recv, self.metrics.clone()))
+        let (send, recv) = self.inner.open_bi().instrument(self.span.clone()).await?;
+        self.counters.streams_opened.fetch_add(1, Ordering::Relaxed);
+        Ok(Stream::new(send, recv, self.metrics.clone(), &self.span, "outbound", self.signing.clone()))
+    }
+
+    /// Open a new bidirectional stream that carries the caller's trace context
+    ///
+    /// The context is sent ahead of the stream data so the peer's stream span
+    /// can be parented to the caller's span, see
+    /// [`StoqTransport::set_trace_parent`]. Both peers need the
+    /// `trace-context` feature.
+    #[cfg(feature = "trace-context")]
+    pub async fn open_stream_with_trace_context(&self, context: &TraceContext) -> Result<Stream> {
+        let mut stream = self.open_stream().await?;
+        stream.send.write_all(&trace_context::encode_preamble(context)).await?;
+        stream.set_trace_context(context.clone());
+        Ok(stream)
     }
     
     /// Accept an incoming bidirectional stream
     pub async fn accept_stream(&self) -> Result<Stream> {
-        let (send, recv) = self.inner.accept_bi().await?;
-        Ok(Stream::new(send, recv, self.metrics.clone()))
+        let (send, recv) = self.inner.accept_bi().instrument(self.span.clone()).await?;
+        self.counters.streams_accepted.fetch_add(1, Ordering::Relaxed);
+        let mut stream = Stream::new(send, recv, self.metrics.clone(), &self.span, "inbound", self.signing.clone());
+        stream.hop_preambles = self.hop_preambles;
+        #[cfg(feature = "trace-context")]
+        {
+            stream.trace_parent = self.trace_parent.read().clone();
+        }
+        Ok(stream)
     }
     
+    /// Get path and traffic statistics for this connection
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats::sample(&self.inner.stats(), &self.counters)
+    }
+
+    /// Send an unreliable datagram, counting it as dropped on failure
+    fn send_datagram(&self, data: Bytes) -> bool {
+        match self.inner.send_datagram(data) {
+            Ok(()) => true,
+            Err(_) => {
+                self.counters.datagrams_dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
     /// Check if connection is still active
     pub fn is_active(&self) -> bool {
         // In Quinn 0.11+, we check the close reason instead
         self.inner.close_reason().is_none()
     }
     
-    /// Close the connection gracefully
-    pub fn close(&self) {
-        self.inner.close(0u32.into(), b"closing");
+    /// Close the connection with an application error code and reason
+    ///
+    /// Use [`error_codes::NO_ERROR`] for a graceful close. The peer sees the
+    /// code through [`StoqError::peer_error_code`].
+    pub fn close(&self, code: VarInt, reason: &[u8]) {
+        self.inner.close(code, reason);
     }
 }
 
@@ -402,53 +533,216 @@ pub struct Stream {
     send: quinn::SendStream,
     recv: quinn::RecvStream,
     metrics: Arc<TransportMetrics>,
+    span: Span,
+    /// Relays the stream crossed, if it was relayed
+    hop_trail: Option<Vec<HopInfo>>,
+    /// Whether the stream may open with a relay header
+    hop_preambles: bool,
+    /// The connection's preamble signing
+    signing: Arc<ConnectionSigning>,
+    #[cfg(feature = "trace-context")]
+    trace_context: Option<TraceContext>,
+    /// Opens the parent of the stream span once the peer's context is read
+    #[cfg(feature = "trace-context")]
+    trace_parent: Option<TraceParent>,
 }
 
 impl Stream {
-    fn new(send: quinn::SendStream, recv: quinn::RecvStream, metrics: Arc<TransportMetrics>) -> Self {
-        Self { send, recv, metrics }
+    fn new(
+        send: quinn::SendStream,
+        recv: quinn::RecvStream,
+        metrics: Arc<TransportMetrics>,
+        connection_span: &Span,
+        direction: &'static str,
+        signing: Arc<ConnectionSigning>,
+    ) -> Self {
+        #[cfg(feature = "trace-context")]
+        let span = debug_span!(
+            parent: connection_span,
+            "stoq.stream",
+            stream_id = %send.id(),
+            direction,
+            trace_id = tracing::field::Empty,
+            parent_span_id = tracing::field::Empty,
+        );
+        #[cfg(not(feature = "trace-context"))]
+        let span = debug_span!(parent: connection_span, "stoq.stream", stream_id = %send.id(), direction);
+        Self {
+            send,
+            recv,
+            metrics,
+            span,
+            hop_trail: None,
+            hop_preambles: false,
+            signing,
+            #[cfg(feature = "trace-context")]
+            trace_context: None,
+            #[cfg(feature = "trace-context")]
+            trace_parent: None,
+        }
+    }
+
+    /// Tracing span covering this stream's lifetime
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Relays a relayed stream crossed, in order, available once data has been received
+    ///
+    /// `None` for streams that were not opened with
+    /// [`StoqTransport::open_relayed_stream`]; empty for a relayed stream
+    /// sent straight to its receiver.
+    pub fn hop_trail(&self) -> Option<&[HopInfo]> {
+        self.hop_trail.as_deref()
+    }
+
+    /// Trace context sent by the peer, available once data has been received
+    ///
+    /// [`Self::span`] records the context, and is parented to it when the
+    /// transport has a [`StoqTransport::set_trace_parent`] hook.
+    #[cfg(feature = "trace-context")]
+    pub fn trace_context(&self) -> Option<&TraceContext> {
+        self.trace_context.as_ref()
+    }
+
+    #[cfg(feature = "trace-context")]
+    fn set_trace_context(&mut self, context: TraceContext) {
+        self.span.record("trace_id", context.trace_id_hex().as_str());
+        self.span.record("parent_span_id", context.parent_id_hex().as_str());
+        self.trace_context = Some(context);
+    }
+
+    /// Reopen the stream span under the span the trace parent hook opens for
+    /// the peer's context, then record the context
+    #[cfg(feature = "trace-context")]
+    fn receive_trace_context(&mut self, context: TraceContext) {
+        if let Some(trace_parent) = &self.trace_parent {
+            let span = debug_span!(
+                parent: &trace_parent(&context),
+                "stoq.stream",
+                stream_id = %self.send.id(),
+                direction = "inbound",
+                trace_id = tracing::field::Empty,
+                parent_span_id = tracing::field::Empty,
+            );
+            span.follows_from(&self.span);
+            self.span = span;
+        }
+        self.set_trace_context(context);
     }
     
     /// Send data over the stream with zero-copy optimization
     pub async fn send(&mut self, data: &[u8]) -> Result<()> {
-        // Use zero-copy when possible
-        if data.len() <= 1024 * 1024 { // 1MB threshold for zero-copy
-            let bytes = Bytes::copy_from_slice(data);
-            self.send.write_all(&bytes).await?;
-        } else {
-            // Large data - use streaming
-            self.send.write_all(data).await?;
-        }
-        self.send.finish()?;
-        self.metrics.record_bytes_sent(data.len());
-        Ok(())
+        let span = debug_span!(parent: &self.span, "stoq.stream.send", bytes = data.len());
+        async {
+            // Use zero-copy when possible
+            if data.len() <= 1024 * 1024 { // 1MB threshold for zero-copy
+                let bytes = Bytes::copy_from_slice(data);
+                self.send.write_all(&bytes).await?;
+            } else {
+                // Large data - use streaming
+                self.send.write_all(data).await?;
+            }
+            self.send.finish()?;
+            self.metrics.record_bytes_sent(data.len());
+            Ok(())
+        }.instrument(span).await
     }
     
     /// Send bytes directly for zero-copy operations
     pub async fn send_bytes(&mut self, bytes: Bytes) -> Result<()> {
-        self.send.write_all(&bytes).await?;
-        self.send.finish()?;
-        self.metrics.record_bytes_sent(bytes.len());
-        Ok(())
+        let span = debug_span!(parent: &self.span, "stoq.stream.send", bytes = bytes.len());
+        async {
+            self.send.write_all(&bytes).await?;
+            self.send.finish()?;
+            self.metrics.record_bytes_sent(bytes.len());
+            Ok(())
+        }.instrument(span).await
     }
     
     /// Receive data from the stream
     pub async fn receive(&mut self) -> Result<Bytes> {
-        let data = self.recv.read_to_end(crate::STOQ_MTU).await?;
-        self.metrics.record_bytes_received(data.len());
-        Ok(data.into())
+        let span = debug_span!(parent: &self.span, "stoq.stream.receive", bytes = tracing::field::Empty);
+        async {
+            // The relay header is bounded on its own and does not count against the MTU
+            let mut prefix = Vec::new();
+            if self.hop_preambles {
+                match relay::sniff_header(&mut self.recv, &self.signing).await? {
+                    (Some(header), _) if !header.route.is_empty() => {
+                        return Err(StoqError::Protocol("relayed stream is addressed to another hop".to_string()));
+                    }
+                    (Some(header), _) => self.hop_trail = Some(header.hops()),
+                    (None, read) => prefix = read,
+                }
+            }
+            let limit = crate::STOQ_MTU - prefix.len();
+            let rest = self.recv.read_to_end(limit).await
+                .map_err(|e| StoqError::from_read_to_end(e, crate::STOQ_MTU))?;
+            let data = if prefix.is_empty() { Bytes::from(rest) } else { Bytes::from([prefix, rest].concat()) };
+
+            #[cfg(feature = "trace-context")]
+            let data = match trace_context::split_preamble(data) {
+                (Some(context), rest) => {
+                    self.receive_trace_context(context);
+                    rest
+                }
+                (None, data) => data,
+            };
+
+            Span::current().record("bytes", data.len());
+            self.metrics.record_bytes_received(data.len());
+            Ok(data)
+        }.instrument(span).await
+    }
+
+    /// Receive a payload sent by [`StoqTransport::seed`] and acknowledge it
+    ///
+    /// Reads up to `limit` bytes. A payload that does not match the packet ID
+    /// in its seed frame is refused with a stream reset, so the sender tries
+    /// another node.
+    pub async fn receive_seed(&mut self, limit: usize) -> Result<(SeedFrame, Bytes)> {
+        let span = debug_span!(parent: &self.span, "stoq.stream.receive_seed", bytes = tracing::field::Empty);
+        async {
+            let (frame, payload) = seed::receive(self, limit).await?;
+            Span::current().record("bytes", payload.len());
+            self.metrics.record_bytes_received(payload.len());
+            Ok((frame, payload))
+        }.instrument(span).await
+    }
+
+    /// Abandon the send half, telling the peer why with an application error code
+    pub fn reset(&mut self, code: VarInt) -> Result<()> {
+        self.send.reset(code)?;
+        Ok(())
+    }
+
+    /// Stop accepting data on the receive half, telling the peer why with an
+    /// application error code
+    pub fn stop(&mut self, code: VarInt) -> Result<()> {
+        self.recv.stop(code)?;
+        Ok(())
     }
 }
 
+impl Drop for Stream {
+    fn drop(&mut self) {
+        debug!(parent: &self.span, "stream closed");
+    }
+}
+
+/// How often connection monitors check for migration and idleness
+const CONNECTION_MONITOR_INTERVAL: Duration = Duration::from_secs(1);
+
 /// STOQ transport implementation using QUIC over IPv6
 pub struct StoqTransport {
-    config: TransportConfig,
+    config: Arc<RwLock<TransportConfig>>,
     endpoint: Arc<quinn::Endpoint>,
     connections: Arc<DashMap<String, Arc<Connection>>>,
     connection_pool: Arc<DashMap<String, Vec<Arc<Connection>>>>,
     pub cert_manager: Arc<CertificateManager>,
     pub(crate) metrics: Arc<TransportMetrics>,
-    cached_client_config: Arc<RwLock<Option<quinn::ClientConfig>>>,
+    cached_client_config: Arc<RwLock<quinn::ClientConfig>>,
+    server_config: Arc<RwLock<quinn::ServerConfig>>,
     memory_pool: Arc<MemoryPool>,
     connection_multiplexer: Arc<DashMap<String, VecDeque<Arc<Connection>>>>,
     performance_stats: Arc<RwLock<PerformanceStats>>,
@@ -462,11 +756,25 @@ pub struct StoqTransport {
     adaptation_manager: Arc<AdaptationManager>,
     /// Adaptive connections mapping
     adaptive_connections: Arc<DashMap<String, Arc<AdaptiveConnection>>>,
+    /// Connection lifecycle event channel
+    events: ConnectionEvents,
+    /// Pre-handshake admission control for incoming connections
+    admission: Arc<AdmissionController>,
+    /// Winning addresses from previous multi-address dials
+    dial_cache: Arc<DialCache>,
+    /// Admitted handshakes still in progress, shared by concurrent accepts
+    handshakes: Arc<tokio::sync::Mutex<JoinSet<Handshake>>>,
+    /// Opens the parent span of inbound streams that carry a trace context
+    #[cfg(feature = "trace-context")]
+    trace_parent: Arc<RwLock<Option<TraceParent>>>,
     /// eBPF transport acceleration (if available)
     #[cfg(feature = "ebpf")]
     ebpf_transport: Option<Arc<RwLock<ebpf::EbpfTransport>>>,
 }
 
+/// Outcome of an admitted handshake, with the address it came from
+type Handshake = (SocketAddr, Reservation, std::result::Result<quinn::Connection, quinn::ConnectionError>);
+
 /// Performance statistics for transport monitoring
 #[derive(Debug, Default)]
 pub struct PerformanceStats {
@@ -480,6 +788,25 @@ pub struct PerformanceStats {
     pub connection_reuse_count: AtomicU64,
 }
 
+/// Build the QUIC transport parameters shared by the server and client side
+fn quinn_transport_config(config: &TransportConfig) -> Result<QuinnTransportConfig> {
+    let mut transport_config = QuinnTransportConfig::default();
+    transport_config.max_concurrent_bidi_streams(config.max_concurrent_streams.into());
+    transport_config.max_concurrent_uni_streams(config.max_concurrent_streams.into());
+    let idle_timeout = quinn::IdleTimeout::try_from(config.max_idle_timeout)
+        .map_err(|_| StoqError::Config(format!("max_idle_timeout out of range: {:?}", config.max_idle_timeout)))?;
+    transport_config.max_idle_timeout(Some(idle_timeout));
+
+    // QUIC performance optimizations
+    transport_config.send_window(config.send_buffer_size as u64);
+    transport_config.receive_window(VarInt::try_from(config.receive_buffer_size as u64).unwrap_or(VarInt::MAX));
+    transport_config.datagram_receive_buffer_size(Some(config.max_datagram_size));
+    transport_config.datagram_send_buffer_size(config.max_datagram_size);
+
+    transport_config.congestion_controller_factory(config.congestion.controller_factory(&config.congestion_control));
+    Ok(transport_config)
+}
+
 impl StoqTransport {
     /// Create a new STOQ transport using QUIC over IPv6
     pub async fn new(config: TransportConfig) -> Result<Self> {
@@ -498,64 +825,39 @@ impl StoqTransport {
             )
         };
         
-        let cert_manager = Arc::new(CertificateManager::new(cert_config).await?);
-        
-        // Configure QUIC transport for adaptive network tiers performance
-        let mut server_transport_config = QuinnTransportConfig::default();
-        server_transport_config.max_concurrent_bidi_streams(config.max_concurrent_streams.into());
-        server_transport_config.max_concurrent_uni_streams(config.max_concurrent_streams.into());
-        server_transport_config.max_idle_timeout(Some(config.max_idle_timeout.try_into()?));
-        
-        // QUIC performance optimizations
-        server_transport_config.send_window(config.send_buffer_size as u64);
-        server_transport_config.receive_window(VarInt::try_from(config.receive_buffer_size as u64).unwrap_or(VarInt::MAX));
-        server_transport_config.datagram_receive_buffer_size(Some(config.max_datagram_size));
-        server_transport_config.datagram_send_buffer_size(config.max_datagram_size);
-        
-        // Create client transport config
-        let mut client_transport_config = QuinnTransportConfig::default();
-        client_transport_config.max_concurrent_bidi_streams(config.max_concurrent_streams.into());
-        client_transport_config.max_concurrent_uni_streams(config.max_concurrent_streams.into());
-        client_transport_config.max_idle_timeout(Some(config.max_idle_timeout.try_into()?));
-        client_transport_config.send_window(config.send_buffer_size as u64);
-        client_transport_config.receive_window(VarInt::try_from(config.receive_buffer_size as u64).unwrap_or(VarInt::MAX));
-        client_transport_config.datagram_receive_buffer_size(Some(config.max_datagram_size));
-        client_transport_config.datagram_send_buffer_size(config.max_datagram_size);
-        
-        // Advanced congestion control for high performance
-        match config.congestion_control {
-            CongestionControl::Bbr2 => {
-                // BBR v2 would be configured here when available in Quinn
-                debug!("Using BBR-optimized settings for high performance");
-            }
-            CongestionControl::Cubic => {
-                debug!("Using CUBIC congestion control");
-            }
-            CongestionControl::NewReno => {
-                debug!("Using NewReno congestion control");
-            }
-        }
+        let events = ConnectionEvents::new();
+        let cert_manager = Arc::new(CertificateManager::new(cert_config).await?.with_events(events.clone()));
         
+        let transport_config = Arc::new(quinn_transport_config(&config)?);
+        debug!("Using {:?} congestion control", config.congestion_control);
+
         // Create server configuration with TLS
-        let rustls_server_config = cert_manager.server_crypto_config().await?;
+        let mut rustls_server_config = cert_manager.server_crypto_config().await?;
+        rustls_server_config.alpn_protocols = if config.relayed_streams {
+            vec![HOP_ALPN.to_vec(), STOQ_ALPN.to_vec()]
+        } else {
+            vec![STOQ_ALPN.to_vec()]
+        };
         let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(
             quinn::crypto::rustls::QuicServerConfig::try_from(rustls_server_config)?
         ));
-        server_config.transport_config(Arc::new(server_transport_config));
+        server_config.transport_config(transport_config.clone());
         
         // Create client configuration with TLS and cache it for performance
-        let rustls_client_config = cert_manager.client_crypto_config().await?;
+        let mut rustls_client_config = cert_manager.client_crypto_config().await?;
+        // Servers accepting relayed streams pick the hop protocol
+        rustls_client_config.alpn_protocols = vec![HOP_ALPN.to_vec(), STOQ_ALPN.to_vec()];
         let mut client_config = quinn::ClientConfig::new(Arc::new(
             quinn::crypto::rustls::QuicClientConfig::try_from(rustls_client_config)?
         ));
-        client_config.transport_config(Arc::new(client_transport_config));
+        client_config.transport_config(transport_config);
         
         // Bind to IPv6 address ONLY - enforce IPv6-only networking
         let socket_addr = SocketAddr::from((config.bind_address, config.port));
         
         // Verify we're binding to IPv6
         if !socket_addr.is_ipv6() {
-            return Err(anyhow!("STOQ only supports IPv6 addresses, got: {}", socket_addr));
+            return Err(StoqError::Config(format!("STOQ only supports IPv6 addresses, got: {}", socket_addr)));
         }
         
         let socket = std::net::UdpSocket::bind(socket_addr)?;
@@ -582,9 +884,18 @@ impl StoqTransport {
             socket
         };
         
+        #[allow(unused_mut)]
+        let mut endpoint_config = quinn::EndpointConfig::default();
+
+        // Issue connection IDs through a tracker so XDP can admit them under attack
+        #[cfg(feature = "ebpf")]
+        let cid_tracker = ebpf::ddos::ConnectionIdTracker::new();
+        #[cfg(feature = "ebpf")]
+        endpoint_config.cid_generator(cid_tracker.generator_factory());
+
         let mut endpoint = quinn::Endpoint::new(
-            quinn::EndpointConfig::default(),
-            Some(server_config),
+            endpoint_config,
+            Some(server_config.clone()),
             socket,
             Arc::new(quinn::TokioRuntime),
         )?;
@@ -614,6 +925,11 @@ impl StoqTransport {
             info!("FALCON cryptography disabled");
             None
         };
+        if config.frame_signing != FrameSigningPolicy::Off && falcon_transport.is_none() {
+            return Err(StoqError::Config(format!(
+                "frame signing policy {:?} needs FALCON cryptography", config.frame_signing
+            )));
+        }
 
         // Initialize protocol extensions
         let extensions = Arc::new(DefaultStoqExtensions::with_metrics(metrics.clone()));
@@ -623,7 +939,7 @@ impl StoqTransport {
             extensions.clone(),
             falcon_transport.clone(),
             config.max_datagram_size,
-        ));
+        ).with_frame_signing(config.frame_signing));
 
         // Create handshake extension
         let handshake_extension = Arc::new(StoqHandshakeExtension::new(
@@ -633,7 +949,10 @@ impl StoqTransport {
         ));
 
         // Create adaptation manager with 1 second interval
-        let adaptation_manager = Arc::new(AdaptationManager::new(Duration::from_secs(1)));
+        let adaptation_policy = TieredPolicy::new(config.adaptation.clone())?;
+        let adaptation_manager = Arc::new(
+            AdaptationManager::new(Duration::from_secs(1)).with_policy(Arc::new(adaptation_policy))
+        );
 
         // Initialize eBPF transport acceleration if available
         #[cfg(feature = "ebpf")]
@@ -642,6 +961,14 @@ impl StoqTransport {
                 if ebpf.is_available() {
                     info!("eBPF transport acceleration available");
 
+                    let port = endpoint.local_addr()?.port();
+                    if let Err(e) = ebpf.configure_xdp(&ebpf::xdp::XdpFilterConfig::default(), port, config.bind_address) {
+                        warn!("Failed to configure XDP filter: {}", e);
+                    }
+                    if let Err(e) = ebpf.track_connection_ids(cid_tracker) {
+                        warn!("Failed to publish connection IDs to XDP: {}", e);
+                    }
+
                     // Try to attach XDP to loopback for testing
                     if config.bind_address == Ipv6Addr::LOCALHOST {
                         if let Err(e) = ebpf.attach_xdp("lo") {
@@ -661,14 +988,17 @@ impl StoqTransport {
             }
         };
 
+        let admission = Arc::new(AdmissionController::new(config.admission.clone(), config.max_connections));
+
         Ok(Self {
-            config,
+            config: Arc::new(RwLock::new(config)),
             endpoint: Arc::new(endpoint),
             connections: Arc::new(DashMap::new()),
             connection_pool: Arc::new(DashMap::new()),
             cert_manager,
             metrics,
-            cached_client_config: Arc::new(RwLock::new(Some(client_config))),
+            cached_client_config: Arc::new(RwLock::new(client_config)),
+            server_config: Arc::new(RwLock::new(server_config)),
             memory_pool,
             connection_multiplexer: Arc::new(DashMap::new()),
             performance_stats: Arc::new(RwLock::new(PerformanceStats::default())),
@@ -677,12 +1007,19 @@ impl StoqTransport {
             handshake_extension,
             adaptation_manager,
             adaptive_connections: Arc::new(DashMap::new()),
+            events,
+            admission,
+            dial_cache: Arc::new(DialCache::new()),
+            handshakes: Arc::new(tokio::sync::Mutex::new(JoinSet::new())),
+            #[cfg(feature = "trace-context")]
+            trace_parent: Arc::default(),
             #[cfg(feature = "ebpf")]
             ebpf_transport,
         })
     }
     
     /// Connect to a remote endpoint with connection pooling for performance
+    #[tracing::instrument(name = "stoq.connect", skip_all, fields(remote = %endpoint.to_socket_addr()))]
     pub async fn connect(&self, endpoint: &Endpoint) -> Result<Arc<Connection>> {
         let pool_key = format!("{}:{}", endpoint.address, endpoint.port);
         
@@ -699,34 +1036,137 @@ impl StoqTransport {
         debug!("Creating new connection to [{}]:{}", endpoint.address, endpoint.port);
         
         let socket_addr = endpoint.to_socket_addr();
-        let connecting = self.endpoint.connect(socket_addr, endpoint.server_name.as_deref().unwrap_or("localhost"))?;
-        
-        let quinn_conn = connecting.await?;
+        let client_config = self.cached_client_config.read().clone();
+        let connecting = self.endpoint.connect_with(client_config, socket_addr, endpoint.server_name.as_deref().unwrap_or("localhost"))?;
         
-        let quinn_conn_arc = Arc::new(quinn_conn);
+        let quinn_conn = connecting.instrument(info_span!("stoq.handshake", remote = %socket_addr)).await?;
+
+        let connection = self.register_outbound(quinn_conn, endpoint.clone());
+        info!("Connected to {} with adaptive optimization (pool_size={})", socket_addr, self.config.read().connection_pool_size);
+        Ok(connection)
+    }
+
+    /// Open a stream to the last endpoint of `route` through the relays before it
+    ///
+    /// Each relay must be running [`serve_relay`](Self::serve_relay) and the
+    /// receiver must enable [`TransportConfig::relayed_streams`]; the stream is
+    /// refused if it would cross more than `max_hops` relays. The receiver
+    /// sees the relays in [`Stream::hop_trail`].
+    pub async fn open_relayed_stream(&self, route: &[Endpoint], max_hops: u8) -> Result<Stream> {
+        let (first, rest) = route.split_first()
+            .ok_or_else(|| StoqError::Config("relay route is empty".to_string()))?;
+        if rest.len() > max_hops as usize {
+            return Err(StoqError::Config(format!("route crosses {} relays, more than max_hops {}", rest.len(), max_hops)));
+        }
+        let header = RelayHeader::new(rest.to_vec(), max_hops);
+
+        let connection = self.connect(first).await?;
+        if rest.is_empty() && !connection.hop_preambles {
+            return Err(StoqError::Protocol(format!("{} does not accept relayed streams", first.to_socket_addr())));
+        }
+        let preamble = relay::encode_preamble(&connection.signing, &header)?;
+        let mut stream = connection.open_stream().await?;
+        stream.send.write_all(&preamble).await?;
+        Ok(stream)
+    }
+
+    /// Relay streams opened by [`open_relayed_stream`](Self::open_relayed_stream)
+    ///
+    /// Takes over accepting connections: every stream opened to this node is
+    /// forwarded to the next hop named in its header, and streams without one
+    /// are refused. Relaying stops when the handle is dropped or shut down.
+    pub fn serve_relay(&self, config: RelayConfig) -> RelayHandle {
+        info!("Serving as a STOQ relay (max_hops={})", config.max_hops);
+        relay::spawn(self.clone(), config)
+    }
+
+    /// Deliver `payload` to the seed nodes in `info` and wait for a quorum
+    ///
+    /// Picks `replication_factor` nodes by reliability, replaces any that
+    /// fail with untried alternates, and returns once the quorum set by
+    /// `config` has acknowledged. Receivers read the payload with
+    /// [`Stream::receive_seed`].
+    #[tracing::instrument(name = "stoq.seed", skip_all, fields(nodes = info.seed_nodes.len(), bytes = payload.len()))]
+    pub async fn seed(&self, info: &SeedInfo, payload: Bytes, config: &SeedConfig) -> Result<SeedReport> {
+        seed::fan_out(self, info, payload, config).await
+    }
+
+    /// Seed a packet to the nodes its protocol extensions choose
+    pub async fn seed_packet(&self, packet: &StoqPacket, config: &SeedConfig) -> Result<SeedReport> {
+        let info = self.protocol_handler.seed_info(packet)
+            .ok_or_else(|| StoqError::Config("packet has no seed information".to_string()))?;
+        self.seed(&info, packet.data.clone(), config).await
+    }
+
+    /// Connect to a peer advertising several addresses
+    ///
+    /// Candidates are raced with staggered starts (see [`DialConfig`]); the
+    /// first completed handshake wins and the other attempts are cancelled.
+    /// The winning address is tried first on later dials to the same peer.
+    #[tracing::instrument(name = "stoq.connect_multi", skip_all, fields(candidates = endpoint.candidates.len()))]
+    pub async fn connect_multi(&self, endpoint: &MultiEndpoint) -> Result<Arc<Connection>> {
+        let candidates = self.dial_cache.order(endpoint);
+        debug!("Dialing {} candidate addresses", candidates.len());
+
+        let client_config = self.cached_client_config.read().clone();
+        let attempt_delay = self.config.read().dial.attempt_delay;
+        let (quinn_conn, winner) = dial::race(
+            &self.endpoint,
+            &client_config,
+            candidates,
+            endpoint.server_name.as_deref().unwrap_or("localhost"),
+            attempt_delay,
+        ).await?;
+        self.dial_cache.record(endpoint, winner);
+
+        let mut single = Endpoint::from(winner);
+        single.server_name = endpoint.server_name.clone();
+        let connection = self.register_outbound(quinn_conn, single);
+        info!("Connected to {} via multi-address dial", winner);
+        Ok(connection)
+    }
 
-        let connection = Arc::new(Connection::new_optimized(
-            quinn_conn_arc.as_ref().clone(),
-            endpoint.clone(),
+    /// Wrap an established QUIC connection with the transport's signing and tracing
+    fn new_connection(&self, quinn_conn: quinn::Connection, endpoint: Endpoint) -> Connection {
+        let connection = Connection::new_optimized(
+            quinn_conn,
+            endpoint,
             self.metrics.clone(),
             self.memory_pool.clone(),
-            self.config.frame_batch_size,
-        ));
+            self.config.read().frame_batch_size,
+        ).with_signing(self.protocol_handler.clone(), self.config.read().connection_timeout);
+        #[cfg(feature = "trace-context")]
+        let connection = connection.with_trace_parent(self.trace_parent.clone());
+        connection
+    }
+
+    /// Track a freshly established outbound connection
+    fn register_outbound(&self, quinn_conn: quinn::Connection, endpoint: Endpoint) -> Arc<Connection> {
+        let socket_addr = quinn_conn.remote_address();
+        let quinn_conn_arc = Arc::new(quinn_conn);
+
+        let connection = Arc::new(self.new_connection(quinn_conn_arc.as_ref().clone(), endpoint));
 
         let conn_id = connection.id();
         self.connections.insert(conn_id.clone(), connection.clone());
 
-        // Register connection with adaptation manager
-        self.adaptation_manager.register_connection(conn_id.clone(), quinn_conn_arc.clone());
-
-        // Create and store adaptive connection wrapper
-        let adaptive_conn = Arc::new(AdaptiveConnection::new(quinn_conn_arc));
-        self.adaptive_connections.insert(conn_id, adaptive_conn);
+        // Create adaptive connection wrapper and register it with the adaptation manager
+        let adaptive_conn = Arc::new(
+            AdaptiveConnection::new(quinn_conn_arc)
+                .with_policy(self.adaptation_manager.policy())
+                .with_events(conn_id.clone(), self.events.clone())
+        );
+        self.adaptation_manager.register_adaptive_connection(conn_id.clone(), adaptive_conn.clone());
+        self.adaptive_connections.insert(conn_id.clone(), adaptive_conn);
 
         self.metrics.record_connection_established();
-
-        info!("Connected to {} with adaptive optimization (pool_size={})", socket_addr, self.config.connection_pool_size);
-        Ok(connection)
+        self.events.emit(ConnectionEvent::Established {
+            connection_id: conn_id,
+            remote: socket_addr,
+            direction: ConnectionDirection::Outbound,
+        });
+        self.spawn_connection_monitor(connection.clone(), ConnectionDirection::Outbound);
+        connection
     }
     
     /// Return connection to pool for reuse (optimization)
@@ -738,7 +1178,7 @@ impl StoqTransport {
         let pool_key = format!("{}:{}", connection.endpoint().address, connection.endpoint().port);
         let mut pool = self.connection_pool.entry(pool_key).or_insert_with(Vec::new);
         
-        if pool.len() < self.config.connection_pool_size {
+        if pool.len() < self.config.read().connection_pool_size {
             pool.push(connection);
         }
     }
@@ -752,7 +1192,9 @@ impl StoqTransport {
     pub fn falcon_sign(&self, data: &[u8]) -> Result<Option<falcon::FalconSignature>> {
         if let Some(falcon) = &self.falcon_transport {
             let falcon_guard = falcon.read();
-            Ok(Some(falcon_guard.sign_handshake_data(data)?))
+            let signature = falcon_guard.sign_handshake_data(data)
+                .map_err(|e| StoqError::Falcon(e.to_string()))?;
+            Ok(Some(signature))
         } else {
             Ok(None)
         }
@@ -763,100 +1205,266 @@ impl StoqTransport {
         if let Some(falcon) = &self.falcon_transport {
             let falcon_guard = falcon.read();
             falcon_guard.verify_handshake_signature(key_id, signature, data)
+                .map_err(|e| StoqError::Falcon(e.to_string()))
         } else {
-            Err(anyhow!("FALCON transport not enabled"))
+            Err(StoqError::Unavailable("FALCON transport"))
         }
     }
 
     /// Accept incoming connections
+    ///
+    /// Each connection attempt passes admission control before its handshake
+    /// runs; refused, retried and ignored attempts are handled here and the
+    /// loop waits for the next one. Admitted handshakes run concurrently, and
+    /// whichever completes first is returned; the rest stay queued for later
+    /// calls, so one slow or stalled peer cannot hold up the others. Each
+    /// holds its admission slot while it runs, and gives it back if it fails.
+    #[tracing::instrument(name = "stoq.accept", skip_all, fields(remote = tracing::field::Empty))]
     pub async fn accept(&self) -> Result<Arc<Connection>> {
-        let incoming = self.endpoint.accept().await.ok_or_else(|| anyhow!("No incoming connection"))?;
-        let quinn_conn = incoming.await?;
-        
+        let mut handshakes = self.handshakes.lock().await;
+        let (quinn_conn, reservation) = loop {
+            let incoming = tokio::select! {
+                incoming = self.endpoint.accept() => incoming.ok_or(StoqError::EndpointClosed)?,
+                Some(joined) = handshakes.join_next(), if !handshakes.is_empty() => {
+                    // A failed handshake's reservation is dropped here, freeing its slot
+                    match joined {
+                        Ok((_, reservation, Ok(conn))) => break (conn, reservation),
+                        Ok((remote_addr, _, Err(e))) => debug!("Handshake with {} failed: {}", remote_addr, e),
+                        Err(e) => warn!("Handshake task failed: {}", e),
+                    }
+                    self.metrics.record_connection_failure();
+                    continue;
+                }
+            };
+            let remote_addr = incoming.remote_address();
+
+            let reservation = match self.admission.admit(remote_addr, incoming.remote_address_validated()) {
+                Ok(reservation) => reservation,
+                Err(AdmissionDecision::Ignore) => {
+                    incoming.ignore();
+                    continue;
+                }
+                Err(AdmissionDecision::Retry) => {
+                    if incoming.may_retry() {
+                        // Retry cannot fail when may_retry() holds
+                        let _ = incoming.retry();
+                    } else {
+                        incoming.refuse();
+                    }
+                    continue;
+                }
+                Err(_) => {
+                    incoming.refuse();
+                    continue;
+                }
+            };
+
+            let handshake = async move { (remote_addr, reservation, async move { incoming.accept()?.await }.await) };
+            handshakes.spawn(handshake.instrument(info_span!("stoq.handshake", remote = %remote_addr)));
+        };
+        drop(handshakes);
+
         let remote_addr = quinn_conn.remote_address();
-        let endpoint = Endpoint::new(
-            match remote_addr {
-                SocketAddr::V6(addr) => *addr.ip(),
-                SocketAddr::V4(_) => return Err(anyhow!("IPv4 connections are not supported - STOQ is IPv6-only")),
-            },
-            remote_addr.port(),
-        );
+        Span::current().record("remote", tracing::field::display(remote_addr));
+        let endpoint = match remote_addr {
+            SocketAddr::V6(addr) => Endpoint::from(addr),
+            SocketAddr::V4(_) => return Err(StoqError::Ipv4Rejected(remote_addr)),
+        };
+        reservation.confirm();
         
-        let connection = Arc::new(Connection::new_optimized(
-            quinn_conn,
-            endpoint,
-            self.metrics.clone(),
-            self.memory_pool.clone(),
-            self.config.frame_batch_size,
-        ));
+        let connection = Arc::new(self.new_connection(quinn_conn, endpoint));
         
         self.connections.insert(connection.id(), connection.clone());
         self.metrics.record_connection_established();
+        self.events.emit(ConnectionEvent::Established {
+            connection_id: connection.id(),
+            remote: remote_addr,
+            direction: ConnectionDirection::Inbound,
+        });
+        self.spawn_connection_monitor(connection.clone(), ConnectionDirection::Inbound);
 
         info!("Accepted connection from {}", remote_addr);
         Ok(connection)
     }
     
-    /// Send data with transport layer optimizations
-    pub async fn send(&self, conn: &Connection, data: &[u8]) -> Result<()> {
-        let start_time = std::time::Instant::now();
+    /// Subscribe to connection lifecycle events
+    ///
+    /// Only events emitted after subscribing are delivered. Slow subscribers
+    /// that fall more than the channel capacity behind receive
+    /// `RecvError::Lagged` and skip ahead.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Install a callback consulted for every incoming connection attempt
+    ///
+    /// The callback runs before the handshake and before the built-in limits,
+    /// so it must be cheap. Returning [`AdmissionDecision::Accept`] defers to
+    /// the configured limits; any other decision is applied immediately.
+    pub fn set_admission_filter<F>(&self, filter: F)
+    where
+        F: Fn(&IncomingInfo) -> AdmissionDecision + Send + Sync + 'static,
+    {
+        self.admission.set_filter(Some(Arc::new(filter)));
+    }
+
+    /// Remove the admission callback installed by [`Self::set_admission_filter`]
+    pub fn clear_admission_filter(&self) {
+        self.admission.set_filter(None);
+    }
+
+    /// Install a hook that opens the parent span of inbound streams carrying
+    /// a trace context
+    ///
+    /// The hook runs once the peer's context frame has been read, and the
+    /// stream's `stoq.stream` span is reopened as a child of the span it
+    /// returns. Applies to streams accepted after the call.
+    #[cfg(feature = "trace-context")]
+    pub fn set_trace_parent<F>(&self, parent: F)
+    where
+        F: Fn(&TraceContext) -> Span + Send + Sync + 'static,
+    {
+        *self.trace_parent.write() = Some(Arc::new(parent));
+    }
+
+    /// Remove the hook installed by [`Self::set_trace_parent`]
+    #[cfg(feature = "trace-context")]
+    pub fn clear_trace_parent(&self) {
+        *self.trace_parent.write() = None;
+    }
+
+    /// Get admission control counters
+    pub fn admission_stats(&self) -> AdmissionStats {
+        self.admission.stats()
+    }
+
+    /// Watch a connection for migration, idleness and close, emitting events
+    /// and dropping it from the transport's tables once closed
+    fn spawn_connection_monitor(&self, connection: Arc<Connection>, direction: ConnectionDirection) {
+        let events = self.events.clone();
+        let admission = self.admission.clone();
+        let connections = self.connections.clone();
+        let adaptive_connections = self.adaptive_connections.clone();
+        let adaptation_manager = self.adaptation_manager.clone();
+        let metrics = self.metrics.clone();
+        let idle_threshold = self.config.read().max_idle_timeout / 2;
 
-        // Try eBPF zero-copy send if available
-        #[cfg(feature = "ebpf")]
-        {
-            if let Some(ebpf) = &self.ebpf_transport {
-                if let Ok(socket) = ebpf.read().create_af_xdp_socket("lo", 0) {
-                    if socket.send(data).await.is_ok() {
-                        self.metrics.record_bytes_sent(data.len());
-                        self.performance_stats.read().zero_copy_operations.fetch_add(1, Ordering::Relaxed);
-                        return Ok(());
+        tokio::spawn(async move {
+            let conn_id = connection.id();
+            let mut remote = connection.inner.remote_address();
+            let mut ticker = tokio::time::interval(CONNECTION_MONITOR_INTERVAL);
+            let mut last_datagrams = 0;
+            let mut quiet_since = std::time::Instant::now();
+            let mut idle_reported = false;
+            let mut last_stats: Option<ConnectionStats> = None;
+
+            let close_reason = loop {
+                tokio::select! {
+                    reason = connection.inner.closed() => break reason,
+                    _ = ticker.tick() => {
+                        let current_remote = connection.inner.remote_address();
+                        if current_remote != remote {
+                            events.emit(ConnectionEvent::Migrated {
+                                connection_id: conn_id.clone(),
+                                from: remote,
+                                to: current_remote,
+                            });
+                            remote = current_remote;
+                        }
+
+                        let stats = connection.stats();
+                        metrics.record_connection_stats(last_stats.as_ref(), &stats);
+                        let datagrams = stats.datagrams_sent + stats.datagrams_received;
+                        if datagrams != last_datagrams {
+                            last_datagrams = datagrams;
+                            quiet_since = std::time::Instant::now();
+                            idle_reported = false;
+                        } else if !idle_reported && quiet_since.elapsed() >= idle_threshold {
+                            events.emit(ConnectionEvent::Idle {
+                                connection_id: conn_id.clone(),
+                                idle_for: quiet_since.elapsed(),
+                            });
+                            idle_reported = true;
+                        }
+                        last_stats = Some(stats);
                     }
                 }
+            };
+
+            connections.remove(&conn_id);
+            adaptive_connections.remove(&conn_id);
+            adaptation_manager.unregister_connection(&conn_id);
+            metrics.record_connection_stats(last_stats.as_ref(), &connection.stats());
+            metrics.record_connection_closed();
+            if direction == ConnectionDirection::Inbound {
+                admission.connection_closed(&connection.endpoint().address);
             }
-        }
+
+            let reason = close_reason.to_string();
+            let error = StoqError::Connection(close_reason);
+            metrics.record_connection_close_kind(error.kind());
+            debug!(parent: connection.span(), "Connection {} closed: {}", conn_id, reason);
+            events.emit(ConnectionEvent::Closed {
+                connection_id: conn_id,
+                kind: error.kind(),
+                code: error.peer_error_code(),
+                reason,
+            });
+        });
+    }
+
+    /// Send data with transport layer optimizations
+    #[tracing::instrument(name = "stoq.send", skip_all, fields(connection_id = %conn.id(), bytes = data.len()))]
+    pub async fn send(&self, conn: &Connection, data: &[u8]) -> Result<()> {
+        let start_time = std::time::Instant::now();
+        let (enable_zero_copy, max_datagram_size, frame_batch_size) = {
+            let config = self.config.read();
+            (config.enable_zero_copy, config.max_datagram_size, config.frame_batch_size)
+        };
 
         // Apply STOQ protocol extensions (tokenization, sharding)
-        let extension_frames = self.protocol_handler.apply_extensions(data)?;
+        let extension_frames = self.protocol_handler.apply_extensions(data, &conn.signing.channel_binding()?)?;
 
         // Send extension frames as QUIC datagrams
         for frame in extension_frames {
-            if conn.inner.send_datagram(frame.clone()).is_err() {
+            if !conn.send_datagram(frame.clone()) {
                 debug!("Failed to send extension frame as datagram, will include in stream");
             }
         }
 
-        if self.config.enable_zero_copy {
+        if enable_zero_copy {
             // Try memory pool buffer first for maximum performance
-            if let Some(mut buffer) = self.memory_pool.get_buffer() {
-                if data.len() <= buffer.capacity() {
-                    buffer.put_slice(data);
-                    let bytes = buffer.freeze();
-                    
-                    // Try zero-copy datagram send
-                    if data.len() <= self.config.max_datagram_size {
-                        if conn.inner.send_datagram(bytes.clone()).is_ok() {
-                            self.performance_stats.read().zero_copy_operations.fetch_add(1, Ordering::Relaxed);
-                            self.performance_stats.read().memory_pool_hits.fetch_add(1, Ordering::Relaxed);
-                            return Ok(());
-                        }
-                    }
-                    
-                    // Fallback to stream with zero-copy buffer
-                    let mut stream = conn.open_stream().await?;
-                    stream.send_bytes(bytes).await?;
+            if let Some(mut buffer) = self.memory_pool.get_buffer(data.len()) {
+                {
+                    let perf_stats = self.performance_stats.read();
+                    let pool_counter = if buffer.is_reused() {
+                        &perf_stats.memory_pool_hits
+                    } else {
+                        &perf_stats.memory_pool_misses
+                    };
+                    pool_counter.fetch_add(1, Ordering::Relaxed);
+                }
+
+                buffer.put_slice(data);
+                let bytes = buffer.freeze();
+
+                // Try zero-copy datagram send
+                if data.len() <= max_datagram_size
+                    && conn.send_datagram(bytes.clone())
+                {
                     self.performance_stats.read().zero_copy_operations.fetch_add(1, Ordering::Relaxed);
-                    self.performance_stats.read().memory_pool_hits.fetch_add(1, Ordering::Relaxed);
                     return Ok(());
-                } else {
-                    // Return buffer to pool if too small
-                    self.memory_pool.return_buffer(buffer);
                 }
-            } else {
-                self.performance_stats.read().memory_pool_misses.fetch_add(1, Ordering::Relaxed);
+
+                // Fallback to stream with zero-copy buffer
+                let mut stream = conn.open_stream().await?;
+                stream.send_bytes(bytes).await?;
+                self.performance_stats.read().zero_copy_operations.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
             }
             
             // Large data optimization with frame batching
-            if data.len() > self.config.max_datagram_size && self.config.frame_batch_size > 1 {
+            if data.len() > max_datagram_size && frame_batch_size > 1 {
                 return self.send_large_data_batched(conn, data).await;
             }
         }
@@ -880,9 +1488,12 @@ impl StoqTransport {
     
     /// Send large data with frame batching for performance
     async fn send_large_data_batched(&self, conn: &Connection, data: &[u8]) -> Result<()> {
-        let chunk_size = self.config.max_datagram_size;
+        let (chunk_size, frame_batch_size) = {
+            let config = self.config.read();
+            (config.max_datagram_size, config.frame_batch_size)
+        };
         let mut chunks = data.chunks(chunk_size);
-        let mut batch = FrameBatch::new(self.config.frame_batch_size);
+        let mut batch = FrameBatch::new(frame_batch_size);
         
         while let Some(chunk) = chunks.next() {
             let bytes = Bytes::copy_from_slice(chunk);
@@ -891,7 +1502,7 @@ impl StoqTransport {
                 // Batch is full, send all frames
                 let frames = batch.flush();
                 for frame in frames {
-                    if conn.inner.send_datagram(frame).is_err() {
+                    if !conn.send_datagram(frame) {
                         // Fallback to stream for failed datagrams
                         let mut stream = conn.open_stream().await?;
                         stream.send(&chunk).await?;
@@ -906,7 +1517,7 @@ impl StoqTransport {
             let frames = batch.flush();
             for frame in frames {
                 let frame_len = frame.len();
-                if conn.inner.send_datagram(frame).is_err() {
+                if !conn.send_datagram(frame) {
                     // Fallback to stream
                     let mut stream = conn.open_stream().await?;
                     let fallback_data = vec![0u8; frame_len]; // Safe fallback data
@@ -920,8 +1531,10 @@ impl StoqTransport {
     }
     
     /// Receive data with zero-copy optimization for performance
+    #[tracing::instrument(name = "stoq.receive", skip_all, fields(connection_id = %conn.id()))]
     pub async fn receive(&self, conn: &Connection) -> Result<Bytes> {
-        if self.config.enable_zero_copy {
+        let enable_zero_copy = self.config.read().enable_zero_copy;
+        if enable_zero_copy {
             // Try datagram receive first for maximum performance
             if let Ok(datagram) = conn.inner.read_datagram().await {
                 return Ok(datagram);
@@ -939,16 +1552,17 @@ impl StoqTransport {
         
         // Add performance metrics
         let perf_stats = self.performance_stats.read();
-        let (pool_available, pool_allocated) = self.memory_pool.stats();
+        let pool_stats = self.memory_pool.stats();
         
-        info!("Performance: {:.1} Gbps peak, Zero-copy ops: {}, Pool hits/misses: {}/{}, Frame batches: {}",
+        debug!("Performance: {:.1} Gbps peak, Zero-copy ops: {}, Pool hits/misses: {}/{}, Frame batches: {}",
               perf_stats.peak_throughput_gbps.load(Ordering::Relaxed) as f64 / 1000.0,
               perf_stats.zero_copy_operations.load(Ordering::Relaxed),
               perf_stats.memory_pool_hits.load(Ordering::Relaxed),
               perf_stats.memory_pool_misses.load(Ordering::Relaxed),
               perf_stats.frame_batches_sent.load(Ordering::Relaxed));
         
-        info!("Memory Pool Stats: Available buffers: {}, Allocated: {}", pool_available, pool_allocated);
+        debug!("Memory Pool Stats: Available buffers: {}, Outstanding: {}, High water: {}",
+              pool_stats.available, pool_stats.outstanding, pool_stats.high_water);
         
         base_stats
     }
@@ -964,7 +1578,7 @@ impl StoqTransport {
         
         // Close all active connections
         for conn in self.connections.iter() {
-            conn.close();
+            conn.close(error_codes::SHUTTING_DOWN, b"shutdown");
         }
         self.connections.clear();
         
@@ -972,11 +1586,54 @@ impl StoqTransport {
         self.connection_pool.clear();
         
         // Close endpoint
-        self.endpoint.close(0u32.into(), b"shutdown");
+        self.endpoint.close(error_codes::SHUTTING_DOWN, b"shutdown");
         
         info!("STOQ transport shutdown complete");
     }
     
+    /// Render all transport metrics in the OpenMetrics text format
+    ///
+    /// Per-connection series are labelled with the peer address and adaptive
+    /// tier; connection closes are labelled with their [`crate::ErrorKind`].
+    pub fn openmetrics(&self) -> String {
+        let mut encoder = OpenMetricsEncoder::new();
+        openmetrics::encode_transport_stats(&mut encoder, &self.metrics.get_stats(self.connections.len()));
+        openmetrics::encode_protocol_metrics(&mut encoder, &self.metrics.get_protocol_metrics());
+        openmetrics::encode_interval_metrics(&mut encoder, &self.metrics.get_interval_metrics());
+        openmetrics::encode_performance_stats(&mut encoder, &self.performance_stats.read(), &self.memory_pool.stats());
+        openmetrics::encode_admission_stats(&mut encoder, &self.admission.stats());
+        openmetrics::encode_connection_closes(&mut encoder, &self.metrics.connection_closes_by_kind());
+
+        let connections: Vec<_> = self.connections.iter()
+            .map(|entry| {
+                let adaptive = self.adaptive_connections.get(entry.key());
+                openmetrics::ConnectionSample {
+                    peer: entry.value().inner.remote_address(),
+                    tier: adaptive.as_ref().map(|conn| conn.current_tier().name()),
+                    stats: entry.value().stats(),
+                    adaptation: adaptive.as_ref().map(|conn| conn.adaptation_stats()),
+                }
+            })
+            .collect();
+        openmetrics::encode_connections(&mut encoder, &connections);
+
+        encoder.finish()
+    }
+
+    /// Serve [`Self::openmetrics`] over HTTP at `GET /metrics` on `addr`
+    ///
+    /// Bind to a loopback or management address; the endpoint has no
+    /// authentication. The server runs until the returned handle is dropped.
+    pub async fn serve_metrics(&self, addr: SocketAddr) -> Result<MetricsServer> {
+        let transport = self.clone();
+        MetricsServer::bind(addr, move || transport.openmetrics()).await
+    }
+
+    /// Get buffer pool statistics
+    pub fn memory_pool_stats(&self) -> MemoryPoolStats {
+        self.memory_pool.stats()
+    }
+
     /// Get connection pool statistics for monitoring
     pub fn pool_stats(&self) -> Vec<(String, usize)> {
         self.connection_pool
@@ -1006,9 +1663,14 @@ impl StoqTransport {
         self.metrics.get_interval_metrics()
     }
 
-    /// Reset interval metrics for periodic reporting
-    pub fn reset_interval_metrics(&self) {
-        self.metrics.reset_interval_metrics();
+    /// Close the current reporting interval and return its metrics
+    pub fn reset_interval_metrics(&self) -> IntervalMetrics {
+        self.metrics.reset_interval_metrics()
+    }
+
+    /// Recently completed reporting intervals, oldest first
+    pub fn interval_history(&self) -> Vec<IntervalMetrics> {
+        self.metrics.interval_history()
     }
     
     /// Enable connection multiplexing for specific endpoint (optimization)
@@ -1050,10 +1712,27 @@ impl StoqTransport {
     }
 
     /// Adapt transport configuration for detected network tier
-    pub fn adapt_config_for_tier(&mut self, gbps: f64) {
+    pub fn adapt_config_for_tier(&self, gbps: f64) -> Result<ReconfigureReport> {
         let tier = NetworkTier::from_gbps(gbps);
-        self.config.adapt_for_network_tier(&tier);
+        let mut config = self.config();
+        config.adapt_for_network_tier(&tier);
         info!("Adapted STOQ configuration for network tier: {:?}", tier);
+        self.update_live_config(config)
+    }
+
+    /// Rebuild the QUIC transport parameters from the current config
+    ///
+    /// Applies to connections established afterwards, in both directions;
+    /// the congestion controller of an established connection is fixed.
+    fn apply_transport_config(&self, config: &TransportConfig) -> Result<()> {
+        let transport_config = Arc::new(quinn_transport_config(config)?);
+        self.cached_client_config.write().transport_config(transport_config.clone());
+
+        let mut server_config = self.server_config.write();
+        server_config.transport_config(transport_config);
+        self.endpoint.set_server_config(Some(server_config.clone()));
+        debug!("Using {:?} congestion control for new connections", config.congestion_control);
+        Ok(())
     }
 
     /// Get local address of the endpoint
@@ -1075,26 +1754,44 @@ impl StoqTransport {
         info!("Started adaptive connection optimization");
     }
 
-    /// Update configuration for all live connections
-    pub async fn update_live_config(&mut self, new_config: TransportConfig) {
-        info!("Updating configuration for all live connections");
-
-        // Update stored config
-        self.config = new_config.clone();
-
-        // Update all adaptive connections
-        for entry in self.adaptive_connections.iter() {
-            let conn = entry.value().clone();
+    /// Current transport configuration
+    pub fn config(&self) -> TransportConfig {
+        self.config.read().clone()
+    }
 
-            // Force immediate adaptation with new config
-            tokio::spawn(async move {
-                if let Err(e) = conn.force_adapt().await {
-                    warn!("Failed to adapt connection: {}", e);
-                }
-            });
+    /// Reconfigure the transport at runtime
+    ///
+    /// New connections in both directions use `new_config`. Stream limits and
+    /// flow-control windows are also pushed to live connections, and admission
+    /// limits apply from the next connection attempt; the returned report
+    /// lists which changed parameters only apply after a reconnect or a
+    /// transport restart.
+    pub fn update_live_config(&self, new_config: TransportConfig) -> Result<ReconfigureReport> {
+        let mut report = ReconfigureReport::diff(&self.config.read(), &new_config);
+        if !report.is_empty() {
+            self.apply_transport_config(&new_config)?;
+        }
+        if report.applied_any(&["adaptation"]) {
+            self.set_adaptation_policy(Arc::new(TieredPolicy::new(new_config.adaptation.clone())?));
+        }
+        if report.applied_any(&["admission", "max_connections"]) {
+            self.admission.reconfigure(new_config.admission.clone(), new_config.max_connections);
         }
+        if report.applied_any(reconfigure::LIVE_CONNECTION_FIELDS) {
+            for entry in self.connections.iter() {
+                reconfigure::apply_live(&entry.value().inner, &new_config);
+                report.connections_updated += 1;
+            }
+        }
+        *self.config.write() = new_config;
 
-        info!("Configuration updated for {} live connections", self.adaptive_connections.len());
+        if !report.is_empty() {
+            info!(
+                "Transport reconfigured: applied {:?} to {} live connections, reconnect needed for {:?}, restart needed for {:?}",
+                report.applied, report.connections_updated, report.requires_reconnect, report.requires_restart
+            );
+        }
+        Ok(report)
     }
 
     /// Get adaptive connection by ID
@@ -1108,7 +1805,7 @@ impl StoqTransport {
             conn.force_adapt().await?;
             Ok(())
         } else {
-            Err(anyhow!("Connection not found: {}", id))
+            Err(StoqError::ConnectionNotFound(id.to_string()))
         }
     }
 
@@ -1133,6 +1830,11 @@ impl StoqTransport {
         }
     }
 
+    /// Replace the adaptation policy for current and future connections
+    pub fn set_adaptation_policy(&self, policy: Arc<dyn AdaptationPolicy>) {
+        self.adaptation_manager.set_policy(policy);
+    }
+
     /// Manually set network tier for a connection
     pub async fn set_connection_tier(&self, id: &str, tier: NetworkTier) -> Result<()> {
         if let Some(conn) = self.get_adaptive_connection(id) {
@@ -1142,7 +1844,7 @@ impl StoqTransport {
             info!("Set network tier for connection {}: {:?}", id, tier);
             Ok(())
         } else {
-            Err(anyhow!("Connection not found: {}", id))
+            Err(StoqError::ConnectionNotFound(id.to_string()))
         }
     }
 
@@ -1192,30 +1894,65 @@ impl StoqTransport {
             info!("XDP acceleration enabled on interface {}", interface);
             Ok(())
         } else {
-            Err(anyhow!("eBPF transport not available"))
+            Err(StoqError::Unavailable("eBPF transport"))
         }
     }
 
     #[cfg(not(feature = "ebpf"))]
     pub fn attach_xdp_to_interface(&self, _interface: &str) -> Result<()> {
-        Err(anyhow!("eBPF feature not compiled"))
+        Err(StoqError::Unavailable("eBPF feature"))
+    }
+
+    /// Replace the XDP filter switches, keeping it on this transport's port and address
+    #[cfg(feature = "ebpf")]
+    pub fn configure_xdp_filter(&self, filter: &ebpf::xdp::XdpFilterConfig) -> Result<()> {
+        if let Some(ebpf) = &self.ebpf_transport {
+            let port = self.endpoint.local_addr()?.port();
+            let bind_address = self.config.read().bind_address;
+            ebpf.read().configure_xdp(filter, port, bind_address)?;
+            Ok(())
+        } else {
+            Err(StoqError::Unavailable("eBPF transport"))
+        }
+    }
+
+    /// Set XDP DDoS mitigation: rate limits, Initial size and version checks, under attack mode
+    #[cfg(feature = "ebpf")]
+    pub fn configure_ddos_mitigation(&self, config: &ebpf::ddos::DdosConfig) -> Result<()> {
+        if let Some(ebpf) = &self.ebpf_transport {
+            ebpf.read().configure_ddos(config)?;
+            Ok(())
+        } else {
+            Err(StoqError::Unavailable("eBPF transport"))
+        }
     }
 
-    /// Create AF_XDP zero-copy socket for interface
+    /// Move the endpoint onto an AF_XDP socket on `interface`'s `queue_id`
+    ///
+    /// XDP redirects the endpoint's datagrams on that queue to the socket and
+    /// quinn sends and receives through it; traffic on other queues is lost to
+    /// the endpoint, so bind one queue per interface. The endpoint must be
+    /// bound to a specific IPv6 address.
     #[cfg(feature = "ebpf")]
     pub fn create_zero_copy_socket(&self, interface: &str, queue_id: u32) -> Result<()> {
         if let Some(ebpf) = &self.ebpf_transport {
-            let _socket = ebpf.read().create_af_xdp_socket(interface, queue_id)?;
-            info!("Created AF_XDP zero-copy socket for {}:{}", interface, queue_id);
+            let local = match self.endpoint.local_addr()? {
+                SocketAddr::V6(local) if !local.ip().is_unspecified() => local,
+                other => return Err(StoqError::Config(format!("AF_XDP needs a specific IPv6 bind address, not {}", other))),
+            };
+            let socket = ebpf.read().redirect_to_af_xdp(interface, queue_id)?;
+            let udp = ebpf::udp::XdpUdpSocket::new(socket, local)?;
+            self.endpoint.rebind_abstract(Arc::new(udp))?;
+            info!("Endpoint {} now runs over AF_XDP on {}:{}", local, interface, queue_id);
             Ok(())
         } else {
-            Err(anyhow!("eBPF transport not available"))
+            Err(StoqError::Unavailable("eBPF transport"))
         }
     }
 
     #[cfg(not(feature = "ebpf"))]
     pub fn create_zero_copy_socket(&self, _interface: &str, _queue_id: u32) -> Result<()> {
-        Err(anyhow!("eBPF feature not compiled"))
+        Err(StoqError::Unavailable("eBPF feature"))
     }
 }
 
@@ -1248,6 +1985,7 @@ impl Clone for StoqTransport {
             cert_manager: self.cert_manager.clone(),
             metrics: self.metrics.clone(),
             cached_client_config: self.cached_client_config.clone(),
+            server_config: self.server_config.clone(),
             memory_pool: self.memory_pool.clone(),
             connection_multiplexer: self.connection_multiplexer.clone(),
             performance_stats: self.performance_stats.clone(),
@@ -1256,6 +1994,12 @@ impl Clone for StoqTransport {
             handshake_extension: self.handshake_extension.clone(),
             adaptation_manager: self.adaptation_manager.clone(),
             adaptive_connections: self.adaptive_connections.clone(),
+            events: self.events.clone(),
+            admission: self.admission.clone(),
+            dial_cache: self.dial_cache.clone(),
+            handshakes: self.handshakes.clone(),
+            #[cfg(feature = "trace-context")]
+            trace_parent: self.trace_parent.clone(),
             #[cfg(feature = "ebpf")]
             ebpf_transport: self.ebpf_transport.clone(),
         }
@@ -1272,6 +2016,12 @@ impl Clone for Connection {
             memory_pool: self.memory_pool.clone(),
             frame_batch: self.frame_batch.clone(),
             last_activity: AtomicU64::new(self.last_activity.load(Ordering::Relaxed)),
+            counters: self.counters.clone(),
+            span: self.span.clone(),
+            hop_preambles: self.hop_preambles,
+            signing: self.signing.clone(),
+            #[cfg(feature = "trace-context")]
+            trace_parent: self.trace_parent.clone(),
         }
     }
 }
@@ -1279,12 +2029,23 @@ impl Clone for Connection {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::testing::{connected_pair, test_config, test_transport};
     
     #[test]
     fn test_endpoint_creation() {
         let endpoint = Endpoint::new(Ipv6Addr::LOCALHOST, 9292);
         assert_eq!(endpoint.port, 9292);
         assert_eq!(endpoint.address, Ipv6Addr::LOCALHOST);
+        assert_eq!(endpoint.scope_id, 0);
+    }
+
+    #[test]
+    fn test_endpoint_keeps_link_local_scope() {
+        let scoped = SocketAddrV6::new("fe80::1".parse().unwrap(), 9292, 0, 3);
+        let endpoint = Endpoint::from(scoped);
+        assert_eq!(endpoint.scope_id, 3);
+        assert_eq!(endpoint.to_socket_addr(), SocketAddr::V6(scoped));
+        assert_eq!(MultiEndpoint::from(endpoint).candidates, vec![scoped]);
     }
     
     #[test]
@@ -1306,4 +2067,308 @@ mod tests {
         let transport = StoqTransport::new(config).await;
         assert!(transport.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_peer_close_code_surfaces_in_error() {
+        let pair = connected_pair(test_config()).await;
+        let accept = tokio::spawn(async move { pair.accepted.accept_stream().await.err().unwrap() });
+
+        pair.connection.close(error_codes::UNAUTHORIZED, b"denied");
+
+        let err = accept.await.unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::ApplicationClosed);
+        assert_eq!(err.peer_error_code(), Some(error_codes::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn test_connection_lifecycle_events() {
+        let (server, server_endpoint) = test_transport(test_config()).await;
+        let (client, _) = test_transport(test_config()).await;
+        let mut client_events = client.subscribe();
+
+        let accept = tokio::spawn(async move { server.accept().await.unwrap() });
+        let conn = client.connect(&server_endpoint).await.unwrap();
+        let _server_conn = accept.await.unwrap();
+
+        match client_events.recv().await.unwrap() {
+            ConnectionEvent::Established { connection_id, direction, .. } => {
+                assert_eq!(connection_id, conn.id());
+                assert_eq!(direction, ConnectionDirection::Outbound);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        conn.close(error_codes::NO_ERROR, b"done");
+
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let ConnectionEvent::Closed { connection_id, kind, .. } = client_events.recv().await.unwrap() {
+                    return (connection_id, kind);
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(closed.0, conn.id());
+        assert_eq!(closed.1, crate::ErrorKind::LocallyClosed);
+        assert_eq!(client.active_connections(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_admission_filter_refuses_before_handshake() {
+        let (server, server_endpoint) = test_transport(test_config()).await;
+        let (client, _) = test_transport(test_config()).await;
+
+        server.set_admission_filter(|_| AdmissionDecision::Refuse);
+        let accept = {
+            let server = server.clone();
+            tokio::spawn(async move { server.accept().await })
+        };
+
+        let err = client.connect(&server_endpoint).await.err().unwrap();
+        assert!(matches!(err, StoqError::Connection(quinn::ConnectionError::ConnectionClosed(_))), "{:?}", err);
+
+        let stats = server.admission_stats();
+        assert_eq!(stats.refused, 1);
+        assert_eq!(stats.accepted, 0);
+        assert!(!accept.is_finished());
+        accept.abort();
+    }
+
+    #[tokio::test]
+    async fn test_prefix_cap_holds_for_concurrent_handshakes() {
+        const ATTEMPTS: usize = 5;
+        let config = TransportConfig {
+            admission: AdmissionConfig { max_connections_per_prefix: Some(1), ..AdmissionConfig::default() },
+            ..test_config()
+        };
+        let (server, server_endpoint) = test_transport(config).await;
+        let accept = {
+            let server = server.clone();
+            tokio::spawn(async move {
+                let mut accepted = Vec::new();
+                while let Ok(connection) = server.accept().await {
+                    accepted.push(connection);
+                }
+                accepted
+            })
+        };
+
+        // Every client is on ::1, so all attempts share one /64 and arrive together
+        let mut clients = Vec::new();
+        for _ in 0..ATTEMPTS {
+            clients.push(test_transport(test_config()).await.0);
+        }
+        let results = futures::future::join_all(clients.iter().map(|client| client.connect(&server_endpoint))).await;
+
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        let stats = server.admission_stats();
+        assert_eq!(stats.refused, ATTEMPTS as u64 - 1);
+        assert_eq!((stats.accepted, stats.active), (1, 1));
+        accept.abort();
+    }
+
+    #[tokio::test]
+    async fn test_stalled_handshake_does_not_block_accept() {
+        let (server, server_endpoint) = test_transport(test_config()).await;
+        let (stalled, _) = test_transport(test_config()).await;
+        let (client, _) = test_transport(test_config()).await;
+
+        // Forward the stalled client's packets but never its server's replies
+        let proxy = tokio::net::UdpSocket::bind("[::1]:0").await.unwrap();
+        let proxy_endpoint = Endpoint::new(Ipv6Addr::LOCALHOST, proxy.local_addr().unwrap().port());
+        let (forwarded, first_forward) = tokio::sync::oneshot::channel();
+        let server_addr = server_endpoint.to_socket_addr();
+        tokio::spawn(async move {
+            let mut forwarded = Some(forwarded);
+            let mut buf = [0u8; 2048];
+            while let Ok((len, from)) = proxy.recv_from(&mut buf).await {
+                if from != server_addr {
+                    let _ = proxy.send_to(&buf[..len], server_addr).await;
+                    if let Some(forwarded) = forwarded.take() {
+                        let _ = forwarded.send(());
+                    }
+                }
+            }
+        });
+        let _stalled = tokio::spawn(async move { stalled.connect(&proxy_endpoint).await });
+        first_forward.await.unwrap();
+
+        let (accepted, conn) = tokio::time::timeout(
+            Duration::from_secs(5),
+            async { tokio::join!(server.accept(), client.connect(&server_endpoint)) },
+        ).await.unwrap();
+        assert_eq!(accepted.unwrap().endpoint().port, client.local_addr().unwrap().port());
+        assert!(conn.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connect_multi_skips_dead_candidates() {
+        let config = TransportConfig {
+            dial: DialConfig { attempt_delay: Duration::from_millis(50) },
+            ..test_config()
+        };
+        let (server, server_endpoint) = test_transport(config.clone()).await;
+        let (client, _) = test_transport(config).await;
+
+        let accept = tokio::spawn(async move { server.accept().await.unwrap() });
+
+        // Documentation prefix is unroutable, so the loopback candidate must win
+        let peer = MultiEndpoint::new()
+            .with_address("2001:db8::1".parse().unwrap(), server_endpoint.port)
+            .with_address(Ipv6Addr::LOCALHOST, server_endpoint.port);
+        let conn = client.connect_multi(&peer).await.unwrap();
+        assert_eq!(conn.endpoint().address, Ipv6Addr::LOCALHOST);
+        let _server_conn = accept.await.unwrap();
+
+        assert_eq!(*client.dial_cache.winner(&peer).unwrap().ip(), Ipv6Addr::LOCALHOST);
+        assert_eq!(*client.dial_cache.order(&peer)[0].ip(), Ipv6Addr::LOCALHOST);
+    }
+
+    #[tokio::test]
+    async fn test_connection_stats_from_quic_path() {
+        let pair = connected_pair(test_config()).await;
+        let accepted = pair.accepted;
+        let accept = tokio::spawn(async move {
+            let mut stream = accepted.accept_stream().await.unwrap();
+            let data = stream.receive().await.unwrap();
+            (accepted.stats(), data.len())
+        });
+
+        let mut stream = pair.connection.open_stream().await.unwrap();
+        stream.send(&[7u8; 1024]).await.unwrap();
+
+        let (server_stats, received) = accept.await.unwrap();
+        assert_eq!(received, 1024);
+        assert_eq!(server_stats.streams_accepted, 1);
+        assert!(server_stats.bytes_received >= 1024);
+
+        let stats = pair.connection.stats();
+        assert_eq!(stats.streams_opened, 1);
+        assert!(stats.bytes_sent >= 1024);
+        assert!(stats.cwnd > 0);
+        assert!(!stats.rtt.is_zero());
+    }
+
+    #[tokio::test]
+    async fn test_tier_change_selects_congestion_controller() {
+        use quinn::congestion::{Bbr, NewReno};
+
+        let (client, _) = test_transport(test_config()).await;
+        let mut servers = Vec::new();
+        for _ in 0..2 {
+            let (server, endpoint) = test_transport(test_config()).await;
+            servers.push(endpoint);
+            tokio::spawn(async move {
+                let _conn = server.accept().await.unwrap();
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            });
+        }
+
+        let before = client.connect(&servers[0]).await.unwrap();
+        assert!(before.inner.congestion_state().into_any().is::<Bbr>());
+
+        // Slow tier switches to NewReno for connections opened afterwards
+        client.adapt_config_for_tier(0.01).unwrap();
+        let after = client.connect(&servers[1]).await.unwrap();
+        assert!(after.inner.congestion_state().into_any().is::<NewReno>());
+        assert!(before.inner.congestion_state().into_any().is::<Bbr>());
+    }
+
+    #[tokio::test]
+    async fn test_update_live_config_reports_scope() {
+        let config = test_config();
+        let pair = connected_pair(config.clone()).await;
+        let client = &pair.client;
+
+        assert!(client.update_live_config(config.clone()).unwrap().is_empty());
+
+        let report = client.update_live_config(TransportConfig {
+            max_concurrent_streams: 8,
+            max_idle_timeout: Duration::from_secs(30),
+            ..config.clone()
+        }).unwrap();
+        assert_eq!(report.applied, vec!["max_concurrent_streams"]);
+        assert_eq!(report.requires_reconnect, vec!["max_idle_timeout"]);
+        assert_eq!(report.connections_updated, 1);
+        assert_eq!(client.config().max_concurrent_streams, 8);
+
+        // Admission limits reach the controller, not the connections
+        let (late, _) = test_transport(test_config()).await;
+        let report = pair.server.update_live_config(TransportConfig { max_connections: Some(1), ..config }).unwrap();
+        assert_eq!(report.applied, vec!["max_connections"]);
+        assert_eq!(report.connections_updated, 0);
+        assert_eq!(pair.server.config().max_connections, Some(1));
+        let accept = {
+            let server = pair.server.clone();
+            tokio::spawn(async move { server.accept().await })
+        };
+        assert!(late.connect(&pair.server_endpoint).await.is_err());
+        assert_eq!(pair.server.admission_stats().refused, 1);
+        accept.abort();
+    }
+
+    #[tokio::test]
+    async fn test_openmetrics_exposition() {
+        let pair = connected_pair(test_config()).await;
+
+        let text = pair.client.openmetrics();
+        assert!(text.ends_with("# EOF\n"));
+        assert!(text.contains("stoq_connections_established_total 1\n"));
+        assert!(text.contains(&format!("stoq_connection_cwnd_bytes{{peer=\"[::1]:{}\",tier=\"", pair.server_endpoint.port)));
+    }
+
+    #[cfg(feature = "trace-context")]
+    #[tokio::test]
+    async fn test_trace_context_reaches_peer_stream() {
+        let pair = connected_pair(test_config()).await;
+        let accepted = pair.accepted;
+        let accept = tokio::spawn(async move {
+            let mut stream = accepted.accept_stream().await.unwrap();
+            let data = stream.receive().await.unwrap();
+            (data, stream.trace_context().cloned())
+        });
+
+        let context: TraceContext = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap();
+        let mut stream = pair.connection.open_stream_with_trace_context(&context).await.unwrap();
+        stream.send(b"traced").await.unwrap();
+
+        let (data, received) = accept.await.unwrap();
+        assert_eq!(&data[..], b"traced");
+        assert_eq!(received, Some(context));
+    }
+
+    #[cfg(feature = "trace-context")]
+    #[tokio::test]
+    async fn test_trace_parent_hook_parents_received_stream_span() {
+        use tracing_subscriber::registry::{LookupSpan, Registry};
+
+        let _subscriber = tracing::subscriber::set_default(Registry::default());
+        let pair = connected_pair(test_config()).await;
+        let remote_parent = Arc::new(Mutex::new(None));
+        let hook_parent = remote_parent.clone();
+        pair.server.set_trace_parent(move |context| {
+            let span = info_span!("remote.parent", trace_id = %context.trace_id_hex());
+            *hook_parent.lock() = Some((span.id().unwrap(), context.clone()));
+            span
+        });
+
+        let accepted = pair.accepted;
+        let accept = tokio::spawn(async move {
+            let mut stream = accepted.accept_stream().await.unwrap();
+            stream.receive().await.unwrap();
+            stream
+        });
+        let context: TraceContext = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap();
+        let mut stream = pair.connection.open_stream_with_trace_context(&context).await.unwrap();
+        stream.send(b"traced").await.unwrap();
+        let received = accept.await.unwrap();
+
+        let (parent_id, parent_context) = remote_parent.lock().clone().expect("hook was not called");
+        assert_eq!(parent_context, context);
+        tracing::dispatcher::get_default(|dispatch| {
+            let registry = dispatch.downcast_ref::<Registry>().unwrap();
+            let span = registry.span(&received.span().id().unwrap()).unwrap();
+            assert_eq!(span.name(), "stoq.stream");
+            assert_eq!(span.parent().map(|parent| parent.id()), Some(parent_id.clone()));
+        });
+    }
 }
\ No newline at end of file
@@ -0,0 +1,440 @@
+//! OpenMetrics exposition
+//!
+//! Renders transport metrics in the OpenMetrics text format so Prometheus can
+//! scrape STOQ nodes, either through [`super::StoqTransport::openmetrics`] or
+//! the small built-in HTTP endpoint started by
+//! [`super::StoqTransport::serve_metrics`].
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, info};
+
+use super::{AdmissionStats, ConnectionStats, IntervalMetrics, MemoryPoolStats, PerformanceStats, ProtocolMetrics};
+use super::adaptive::AdaptationStats;
+use crate::error::{ErrorKind, Result};
+use crate::TransportStats;
+
+/// Content type for OpenMetrics text exposition
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Longest request head the scrape endpoint reads before giving up
+const MAX_REQUEST_HEAD: usize = 8192;
+
+/// Time allowed for a scraper to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Label set attached to one sample
+pub type Labels = Vec<(&'static str, String)>;
+
+/// Metric family type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricType {
+    /// Monotonic counter; samples get a `_total` suffix
+    Counter,
+    /// Value that can go up and down
+    Gauge,
+}
+
+/// Incremental OpenMetrics text writer
+#[derive(Debug, Default)]
+pub struct OpenMetricsEncoder {
+    out: String,
+}
+
+impl OpenMetricsEncoder {
+    /// Create an empty encoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write a complete metric family; all samples of a family must be
+    /// written in one call
+    pub fn family(&mut self, name: &str, metric_type: MetricType, help: &str, samples: &[(Labels, f64)]) {
+        let (type_name, suffix) = match metric_type {
+            MetricType::Counter => ("counter", "_total"),
+            MetricType::Gauge => ("gauge", ""),
+        };
+        let _ = writeln!(self.out, "# TYPE {} {}", name, type_name);
+        let _ = writeln!(self.out, "# HELP {} {}", name, escape_help(help));
+
+        for (labels, value) in samples {
+            self.out.push_str(name);
+            self.out.push_str(suffix);
+            if !labels.is_empty() {
+                self.out.push('{');
+                for (i, (key, label_value)) in labels.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push(',');
+                    }
+                    let _ = write!(self.out, "{}=\"{}\"", key, escape_label(label_value));
+                }
+                self.out.push('}');
+            }
+            let _ = writeln!(self.out, " {}", format_value(*value));
+        }
+    }
+
+    /// Write an unlabelled counter
+    pub fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.family(name, MetricType::Counter, help, &[(Vec::new(), value as f64)]);
+    }
+
+    /// Write an unlabelled gauge
+    pub fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.family(name, MetricType::Gauge, help, &[(Vec::new(), value)]);
+    }
+
+    /// Finish the exposition with the mandatory `# EOF` marker
+    pub fn finish(mut self) -> String {
+        self.out.push_str("# EOF\n");
+        self.out
+    }
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Encode transport-wide totals
+pub fn encode_transport_stats(encoder: &mut OpenMetricsEncoder, stats: &TransportStats) {
+    encoder.counter("stoq_bytes_sent", "Application bytes sent", stats.bytes_sent);
+    encoder.counter("stoq_bytes_received", "Application bytes received", stats.bytes_received);
+    encoder.counter("stoq_connections_established", "Connections established", stats.total_connections);
+    encoder.gauge("stoq_connections_active", "Currently open connections", stats.active_connections as f64);
+    encoder.gauge("stoq_throughput_gbps", "Average throughput since start in Gbps", stats.throughput_gbps);
+    encoder.gauge("stoq_latency_avg_microseconds", "Average round-trip time", stats.avg_latency_us as f64);
+}
+
+/// Encode protocol extension and path counters
+pub fn encode_protocol_metrics(encoder: &mut OpenMetricsEncoder, metrics: &ProtocolMetrics) {
+    encoder.counter("stoq_packets_tokenized", "Packets tokenized", metrics.packets_tokenized);
+    encoder.counter("stoq_packets_sharded", "Shards produced", metrics.packets_sharded);
+    encoder.counter("stoq_shards_reassembled", "Shard sets reassembled", metrics.shards_reassembled);
+    encoder.counter("stoq_hop_routes", "Hop routes processed", metrics.hop_routes_processed);
+    encoder.family(
+        "stoq_latency_microseconds",
+        MetricType::Gauge,
+        "Round-trip time percentiles",
+        &[
+            (vec![("quantile", "0.5".to_string())], metrics.p50_latency_us as f64),
+            (vec![("quantile", "0.95".to_string())], metrics.p95_latency_us as f64),
+            (vec![("quantile", "0.99".to_string())], metrics.p99_latency_us as f64),
+        ],
+    );
+    encoder.counter("stoq_connection_failures", "Failed connection attempts", metrics.connection_failures);
+    encoder.counter("stoq_packet_drops", "Packets dropped", metrics.packet_drops);
+    encoder.counter("stoq_sharding_errors", "Sharding errors", metrics.sharding_errors);
+    encoder.counter("stoq_reassembly_errors", "Shard reassembly errors", metrics.reassembly_errors);
+    encoder.counter("stoq_token_validation_failures", "Token validation failures", metrics.token_validation_failures);
+    encoder.counter("stoq_lost_packets", "Packets declared lost", metrics.lost_packets);
+    encoder.counter("stoq_congestion_events", "Congestion events", metrics.congestion_events);
+    encoder.counter("stoq_retransmitted_bytes", "Bytes declared lost and retransmitted", metrics.retransmitted_bytes);
+    encoder.counter("stoq_datagrams_dropped", "Outgoing datagrams dropped", metrics.datagrams_dropped);
+}
+
+/// Encode rates over the current reporting interval
+pub fn encode_interval_metrics(encoder: &mut OpenMetricsEncoder, metrics: &IntervalMetrics) {
+    encoder.gauge("stoq_interval_duration_seconds", "Length of the current reporting interval", metrics.duration_secs);
+    encoder.gauge("stoq_interval_bytes_sent", "Bytes sent during the reporting interval", metrics.bytes_sent as f64);
+    encoder.gauge("stoq_interval_bytes_received", "Bytes received during the reporting interval", metrics.bytes_received as f64);
+    encoder.gauge("stoq_interval_throughput_gbps", "Throughput over the reporting interval in Gbps", metrics.throughput_gbps);
+    encoder.gauge("stoq_interval_packets_per_second", "Tokenized packets per second over the reporting interval", metrics.packets_per_sec);
+    encoder.gauge("stoq_interval_connections_per_second", "New connections per second over the reporting interval", metrics.connections_per_sec);
+    encoder.gauge("stoq_interval_latency_p50_microseconds", "Median latency over the reporting interval", metrics.p50_latency_us as f64);
+    encoder.gauge("stoq_interval_latency_p99_microseconds", "99th percentile latency over the reporting interval", metrics.p99_latency_us as f64);
+}
+
+/// Encode datapath optimization counters
+pub fn encode_performance_stats(encoder: &mut OpenMetricsEncoder, stats: &PerformanceStats, pool: &MemoryPoolStats) {
+    encoder.gauge(
+        "stoq_peak_throughput_gbps",
+        "Peak observed throughput in Gbps",
+        stats.peak_throughput_gbps.load(Ordering::Relaxed) as f64 / 1000.0,
+    );
+    encoder.counter("stoq_zero_copy_operations", "Zero-copy sends", stats.zero_copy_operations.load(Ordering::Relaxed));
+    encoder.counter("stoq_frame_batches_sent", "Frame batches sent", stats.frame_batches_sent.load(Ordering::Relaxed));
+    encoder.counter("stoq_connection_reuse", "Pooled connections reused", stats.connection_reuse_count.load(Ordering::Relaxed));
+    encoder.counter("stoq_memory_pool_hits", "Buffer requests served from the pool", pool.hits);
+    encoder.counter("stoq_memory_pool_misses", "Buffer requests that allocated", pool.misses);
+    encoder.gauge("stoq_memory_pool_available", "Idle pooled buffers", pool.available as f64);
+    encoder.gauge("stoq_memory_pool_outstanding", "Pooled buffers in use", pool.outstanding as f64);
+    encoder.gauge("stoq_memory_pool_high_water", "Most pooled buffers in use at once", pool.high_water as f64);
+}
+
+/// Encode admission control decisions
+pub fn encode_admission_stats(encoder: &mut OpenMetricsEncoder, stats: &AdmissionStats) {
+    encoder.family(
+        "stoq_admission_decisions",
+        MetricType::Counter,
+        "Incoming connection attempts by admission decision",
+        &[
+            (vec![("decision", "accept".to_string())], stats.accepted as f64),
+            (vec![("decision", "refuse".to_string())], stats.refused as f64),
+            (vec![("decision", "retry".to_string())], stats.retried as f64),
+            (vec![("decision", "ignore".to_string())], stats.ignored as f64),
+        ],
+    );
+}
+
+/// Encode connection closes labelled by error kind
+pub fn encode_connection_closes(encoder: &mut OpenMetricsEncoder, closes: &[(ErrorKind, u64)]) {
+    let samples: Vec<_> = closes.iter()
+        .map(|(kind, count)| (vec![("kind", kind.as_str().to_string())], *count as f64))
+        .collect();
+    encoder.family("stoq_connections_closed", MetricType::Counter, "Connection closes by reason", &samples);
+}
+
+/// Per-connection snapshot used for labelled series
+pub struct ConnectionSample {
+    /// Remote socket address
+    pub peer: SocketAddr,
+    /// Adaptive network tier name, if the connection is adaptive
+    pub tier: Option<&'static str>,
+    /// QUIC path statistics
+    pub stats: ConnectionStats,
+    /// Adaptation statistics, if the connection is adaptive
+    pub adaptation: Option<AdaptationStats>,
+}
+
+/// Encode per-connection series labelled by peer and tier
+pub fn encode_connections(encoder: &mut OpenMetricsEncoder, connections: &[ConnectionSample]) {
+    let labels = |sample: &ConnectionSample| -> Labels {
+        vec![
+            ("peer", sample.peer.to_string()),
+            ("tier", sample.tier.unwrap_or("unknown").to_string()),
+        ]
+    };
+    let series = |f: &dyn Fn(&ConnectionSample) -> f64| -> Vec<(Labels, f64)> {
+        connections.iter().map(|sample| (labels(sample), f(sample))).collect()
+    };
+
+    encoder.family("stoq_connection_rtt_seconds", MetricType::Gauge, "Smoothed round-trip time",
+                   &series(&|s| s.stats.rtt.as_secs_f64()));
+    encoder.family("stoq_connection_cwnd_bytes", MetricType::Gauge, "Congestion window",
+                   &series(&|s| s.stats.cwnd as f64));
+    encoder.family("stoq_connection_lost_packets", MetricType::Counter, "Packets declared lost",
+                   &series(&|s| s.stats.lost_packets as f64));
+    encoder.family("stoq_connection_congestion_events", MetricType::Counter, "Congestion events",
+                   &series(&|s| s.stats.congestion_events as f64));
+    encoder.family("stoq_connection_sent_bytes", MetricType::Counter, "UDP bytes sent",
+                   &series(&|s| s.stats.bytes_sent as f64));
+    encoder.family("stoq_connection_received_bytes", MetricType::Counter, "UDP bytes received",
+                   &series(&|s| s.stats.bytes_received as f64));
+
+    let adaptive: Vec<_> = connections.iter()
+        .filter_map(|sample| sample.adaptation.as_ref().map(|stats| (labels(sample), stats.adaptation_count as f64)))
+        .collect();
+    encoder.family("stoq_connection_adaptations", MetricType::Counter, "Tier adaptations applied", &adaptive);
+}
+
+/// Minimal HTTP endpoint serving `GET /metrics`
+///
+/// The server stops when dropped, cutting off scrapes in progress; use
+/// [`Self::shutdown`] to let them finish.
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+    stop: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+impl MetricsServer {
+    /// Bind to `addr` and serve the exposition produced by `render`
+    pub async fn bind<F>(addr: SocketAddr, render: F) -> Result<Self>
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let render = Arc::new(render);
+        info!("Serving OpenMetrics on http://{}/metrics", local_addr);
+
+        let (stop, mut stopped) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut scrapes = JoinSet::new();
+            loop {
+                let accepted = tokio::select! {
+                    _ = &mut stopped => break,
+                    accepted = listener.accept() => accepted,
+                };
+                let (socket, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        debug!("Metrics listener accept failed: {}", e);
+                        continue;
+                    }
+                };
+                while scrapes.try_join_next().is_some() {}
+                let render = render.clone();
+                scrapes.spawn(async move {
+                    if let Err(e) = serve_scrape(socket, render.as_ref()).await {
+                        debug!("Metrics scrape from {} failed: {}", peer, e);
+                    }
+                });
+            }
+            drop(listener);
+            while scrapes.join_next().await.is_some() {}
+        });
+
+        Ok(Self { local_addr, stop: Some(stop), task })
+    }
+
+    /// Address the endpoint is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting scrapes and wait for those in progress to finish
+    pub async fn shutdown(mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve_scrape<F: Fn() -> String>(mut socket: tokio::net::TcpStream, render: &F) -> std::io::Result<()> {
+    let mut head = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    let read_head = async {
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST_HEAD {
+            let n = socket.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            head.extend_from_slice(&chunk[..n]);
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    tokio::time::timeout(REQUEST_TIMEOUT, read_head).await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "request timed out"))??;
+
+    let request_line = head.split(|b| *b == b'\r').next().unwrap_or_default();
+    let mut parts = std::str::from_utf8(request_line).unwrap_or_default().split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+
+    let response = match (method, path) {
+        (Some("GET"), Some(path)) if path == "/metrics" || path.starts_with("/metrics?") => {
+            let body = render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                CONTENT_TYPE, body.len(), body,
+            )
+        }
+        (Some("GET"), _) => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+        _ => "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_family_format() {
+        let mut encoder = OpenMetricsEncoder::new();
+        encoder.family(
+            "stoq_connections_closed",
+            MetricType::Counter,
+            "Connection closes by reason",
+            &[(vec![("kind", ErrorKind::Timeout.as_str().to_string())], 3.0)],
+        );
+        encoder.gauge("stoq_rtt", "RTT", 0.5);
+        let text = encoder.finish();
+
+        assert_eq!(
+            text,
+            "# TYPE stoq_connections_closed counter\n\
+             # HELP stoq_connections_closed Connection closes by reason\n\
+             stoq_connections_closed_total{kind=\"timeout\"} 3\n\
+             # TYPE stoq_rtt gauge\n\
+             # HELP stoq_rtt RTT\n\
+             stoq_rtt 0.5\n\
+             # EOF\n"
+        );
+    }
+
+    #[test]
+    fn test_label_escaping() {
+        let mut encoder = OpenMetricsEncoder::new();
+        encoder.family("m", MetricType::Gauge, "h", &[(vec![("peer", "a\"b\\c\nd".to_string())], 1.0)]);
+        assert!(encoder.finish().contains(r#"m{peer="a\"b\\c\nd"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn test_scrape_endpoint() {
+        let server = MetricsServer::bind("[::1]:0".parse().unwrap(), || {
+            let mut encoder = OpenMetricsEncoder::new();
+            encoder.counter("stoq_test", "Test counter", 7);
+            encoder.finish()
+        }).await.unwrap();
+
+        let mut socket = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+        socket.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains(CONTENT_TYPE));
+        assert!(response.ends_with("stoq_test_total 7\n# EOF\n"));
+
+        let mut socket = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+        socket.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_finishes_scrapes_in_progress() {
+        let server = MetricsServer::bind("[::1]:0".parse().unwrap(), || OpenMetricsEncoder::new().finish())
+            .await
+            .unwrap();
+        let addr = server.local_addr();
+
+        let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+        socket.write_all(b"GET /metrics HTTP/1.1\r\n").await.unwrap();
+        // Let the server pick the connection up before it stops accepting
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let shutdown = tokio::spawn(server.shutdown());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!shutdown.is_finished());
+
+        socket.write_all(b"\r\n").await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        shutdown.await.unwrap();
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+}
@@ -0,0 +1,386 @@
+//! Adaptation policies
+//!
+//! An [`AdaptationPolicy`] turns the recent [`NetworkConditions`] of a
+//! connection into a [`NetworkTier`] and the [`ConnectionParameters`] to use
+//! for it. [`TieredPolicy`] is table-driven: the default table reproduces the
+//! built-in tier boundaries, and custom tables (e.g. for satellite links,
+//! where high RTT is normal) can be loaded from YAML:
+//!
+//! ```yaml
+//! hysteresis:
+//!   min_stability: { secs: 30, nanos: 0 }
+//!   required_consecutive: 5
+//! rtt_penalties: []
+//! tiers:
+//!   - tier: slow
+//!     min_gbps: 0.0
+//!     parameters: { max_streams: 10, congestion_control: NewReno }
+//!   - tier: home
+//!     min_gbps: 0.02
+//!     parameters: { max_streams: 50, congestion_control: Cubic }
+//! ```
+//!
+//! Parameters omitted from a tier keep their [`ConnectionParameters`] default.
+
+use std::path::Path;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+
+use super::NetworkTier;
+use super::CongestionControl;
+use super::adaptive::{ConnectionParameters, NetworkConditions};
+use crate::error::{Result, StoqError};
+
+/// Decides the tier and parameters of an adaptive connection
+pub trait AdaptationPolicy: Send + Sync {
+    /// Classify the link from recent conditions (oldest first, never empty)
+    fn detect_tier(&self, history: &[NetworkConditions]) -> NetworkTier;
+
+    /// Parameters to apply for `tier`
+    fn parameters(&self, tier: &NetworkTier, history: &[NetworkConditions]) -> ConnectionParameters;
+
+    /// How stable a new tier must be before switching to it
+    fn hysteresis(&self) -> HysteresisConfig {
+        HysteresisConfig::default()
+    }
+}
+
+/// Tier-change damping
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HysteresisConfig {
+    /// Minimum time between tier changes
+    pub min_stability: Duration,
+    /// Consecutive measurements of a new tier required to switch
+    pub required_consecutive: u32,
+}
+
+impl Default for HysteresisConfig {
+    fn default() -> Self {
+        Self {
+            min_stability: Duration::from_secs(5),
+            required_consecutive: 3,
+        }
+    }
+}
+
+/// Tier names as used in policy tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TierKind {
+    /// [`NetworkTier::Slow`]
+    Slow,
+    /// [`NetworkTier::Home`]
+    Home,
+    /// [`NetworkTier::Standard`]
+    Standard,
+    /// [`NetworkTier::Performance`]
+    Performance,
+    /// [`NetworkTier::Enterprise`]
+    Enterprise,
+    /// [`NetworkTier::DataCenter`]
+    DataCenter,
+}
+
+impl TierKind {
+    /// Tier carrying the estimated bandwidth
+    pub fn tier(self, gbps: f64) -> NetworkTier {
+        match self {
+            TierKind::Slow => NetworkTier::Slow { mbps: gbps * 1000.0 },
+            TierKind::Home => NetworkTier::Home { mbps: gbps * 1000.0 },
+            TierKind::Standard => NetworkTier::Standard { gbps },
+            TierKind::Performance => NetworkTier::Performance { gbps },
+            TierKind::Enterprise => NetworkTier::Enterprise { gbps },
+            TierKind::DataCenter => NetworkTier::DataCenter { gbps },
+        }
+    }
+}
+
+impl From<&NetworkTier> for TierKind {
+    fn from(tier: &NetworkTier) -> Self {
+        match tier {
+            NetworkTier::Slow { .. } => TierKind::Slow,
+            NetworkTier::Home { .. } => TierKind::Home,
+            NetworkTier::Standard { .. } => TierKind::Standard,
+            NetworkTier::Performance { .. } => TierKind::Performance,
+            NetworkTier::Enterprise { .. } => TierKind::Enterprise,
+            NetworkTier::DataCenter { .. } => TierKind::DataCenter,
+        }
+    }
+}
+
+/// Multiply the bandwidth estimate by `factor` when a metric exceeds `above`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Penalty {
+    /// Threshold the metric must exceed
+    pub above: f64,
+    /// Factor applied to the estimate
+    pub factor: f64,
+}
+
+impl Penalty {
+    fn new(above: f64, factor: f64) -> Self {
+        Self { above, factor }
+    }
+}
+
+/// One row of a tier table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TierRule {
+    /// Tier selected by this row
+    pub tier: TierKind,
+    /// Lowest estimated bandwidth (Gbps) classified as this tier
+    pub min_gbps: f64,
+    /// Parameters applied in this tier
+    #[serde(default)]
+    pub parameters: ConnectionParameters,
+}
+
+/// Table-driven policy configuration
+///
+/// Penalty lists are checked in order and the first matching entry applies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TieredPolicyConfig {
+    /// Tier-change damping
+    pub hysteresis: HysteresisConfig,
+    /// Penalties by RTT in milliseconds
+    pub rtt_penalties: Vec<Penalty>,
+    /// Penalties by packet loss percentage
+    pub loss_penalties: Vec<Penalty>,
+    /// Penalties by jitter in milliseconds
+    pub jitter_penalties: Vec<Penalty>,
+    /// Tier table; the row with the highest `min_gbps` not above the estimate wins
+    pub tiers: Vec<TierRule>,
+}
+
+impl Default for TieredPolicyConfig {
+    fn default() -> Self {
+        let rule = |tier, min_gbps, parameters| TierRule { tier, min_gbps, parameters };
+        Self {
+            hysteresis: HysteresisConfig::default(),
+            rtt_penalties: vec![
+                Penalty::new(100.0, 0.5), // Satellite/intercontinental
+                Penalty::new(50.0, 0.7),  // WAN
+                Penalty::new(20.0, 0.9),  // Metro
+            ],
+            loss_penalties: vec![
+                Penalty::new(5.0, 0.3),
+                Penalty::new(2.0, 0.5),
+                Penalty::new(0.5, 0.8),
+            ],
+            jitter_penalties: vec![Penalty::new(20.0, 0.7)],
+            tiers: vec![
+                rule(TierKind::Slow, 0.0, ConnectionParameters {
+                    stream_window: 256 * 1024,
+                    connection_window: 512 * 1024,
+                    max_streams: 10,
+                    max_datagram_size: 1200,
+                    keep_alive_interval: Some(Duration::from_secs(60)),
+                    idle_timeout: Duration::from_secs(300),
+                    congestion_control: CongestionControl::NewReno,
+                    send_buffer_size: 128 * 1024,
+                    receive_buffer_size: 128 * 1024,
+                }),
+                rule(TierKind::Home, 0.1, ConnectionParameters {
+                    stream_window: 2 * 1024 * 1024,
+                    connection_window: 4 * 1024 * 1024,
+                    max_streams: 50,
+                    max_datagram_size: 1500,
+                    keep_alive_interval: Some(Duration::from_secs(45)),
+                    idle_timeout: Duration::from_secs(180),
+                    congestion_control: CongestionControl::Cubic,
+                    send_buffer_size: 1024 * 1024,
+                    receive_buffer_size: 1024 * 1024,
+                }),
+                rule(TierKind::Standard, 1.0, ConnectionParameters {
+                    stream_window: 8 * 1024 * 1024,
+                    connection_window: 16 * 1024 * 1024,
+                    max_streams: 100,
+                    max_datagram_size: 9000, // Jumbo frames
+                    keep_alive_interval: Some(Duration::from_secs(30)),
+                    idle_timeout: Duration::from_secs(120),
+                    congestion_control: CongestionControl::Bbr2,
+                    send_buffer_size: 4 * 1024 * 1024,
+                    receive_buffer_size: 4 * 1024 * 1024,
+                }),
+                rule(TierKind::Performance, 2.5, ConnectionParameters {
+                    stream_window: 16 * 1024 * 1024,
+                    connection_window: 32 * 1024 * 1024,
+                    max_streams: 200,
+                    max_datagram_size: 9000,
+                    keep_alive_interval: Some(Duration::from_secs(20)),
+                    idle_timeout: Duration::from_secs(90),
+                    congestion_control: CongestionControl::Bbr2,
+                    send_buffer_size: 8 * 1024 * 1024,
+                    receive_buffer_size: 8 * 1024 * 1024,
+                }),
+                rule(TierKind::Enterprise, 10.0, data_center_parameters()),
+                rule(TierKind::DataCenter, 25.0, data_center_parameters()),
+            ],
+        }
+    }
+}
+
+fn data_center_parameters() -> ConnectionParameters {
+    ConnectionParameters {
+        stream_window: 32 * 1024 * 1024,
+        connection_window: 64 * 1024 * 1024,
+        max_streams: 1000,
+        max_datagram_size: 9000,
+        keep_alive_interval: Some(Duration::from_secs(10)),
+        idle_timeout: Duration::from_secs(60),
+        congestion_control: CongestionControl::Bbr2,
+        send_buffer_size: 16 * 1024 * 1024,
+        receive_buffer_size: 16 * 1024 * 1024,
+    }
+}
+
+impl TieredPolicyConfig {
+    /// Parse a policy table from YAML
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        let config: Self = serde_yaml::from_str(yaml)
+            .map_err(|e| StoqError::Config(format!("invalid adaptation policy: {}", e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load a policy table from a YAML file
+    pub fn from_yaml_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_yaml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Check that the table can classify every estimate
+    pub fn validate(&self) -> Result<()> {
+        if !self.tiers.iter().any(|rule| rule.min_gbps <= 0.0) {
+            return Err(StoqError::Config("adaptation policy needs a tier with min_gbps 0".to_string()));
+        }
+        if self.tiers.iter().any(|rule| !rule.min_gbps.is_finite()) {
+            return Err(StoqError::Config("adaptation policy tier bounds must be finite".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Table-driven [`AdaptationPolicy`]; the default table is the built-in behavior
+#[derive(Debug, Clone, Default)]
+pub struct TieredPolicy {
+    config: TieredPolicyConfig,
+}
+
+impl TieredPolicy {
+    /// Create a policy from a validated table
+    pub fn new(config: TieredPolicyConfig) -> Result<Self> {
+        config.validate()?;
+        Ok(Self { config })
+    }
+
+    /// Estimated bandwidth in Gbps after penalties
+    pub fn estimate_gbps(&self, conditions: &NetworkConditions) -> f64 {
+        let mut estimated_gbps = conditions.bandwidth_estimate / 1000.0;
+
+        // Blend in measured throughput
+        if conditions.throughput_mbps > 0.0 {
+            estimated_gbps = (estimated_gbps + (conditions.throughput_mbps / 1000.0)) / 2.0;
+        }
+
+        let penalty = |penalties: &[Penalty], value: f64| {
+            penalties.iter().find(|p| value > p.above).map_or(1.0, |p| p.factor)
+        };
+        estimated_gbps * penalty(&self.config.rtt_penalties, conditions.rtt_ms)
+            * penalty(&self.config.loss_penalties, conditions.packet_loss)
+            * penalty(&self.config.jitter_penalties, conditions.jitter_ms)
+    }
+
+    fn rule_for(&self, kind: TierKind) -> Option<&TierRule> {
+        self.config.tiers.iter().find(|rule| rule.tier == kind)
+    }
+}
+
+impl AdaptationPolicy for TieredPolicy {
+    fn detect_tier(&self, history: &[NetworkConditions]) -> NetworkTier {
+        let gbps = history.last().map_or(0.0, |conditions| self.estimate_gbps(conditions));
+        let kind = self.config.tiers.iter()
+            .filter(|rule| rule.min_gbps <= gbps)
+            .max_by(|a, b| a.min_gbps.total_cmp(&b.min_gbps))
+            .map_or(TierKind::Slow, |rule| rule.tier);
+        kind.tier(gbps)
+    }
+
+    fn parameters(&self, tier: &NetworkTier, _history: &[NetworkConditions]) -> ConnectionParameters {
+        self.rule_for(TierKind::from(tier))
+            .map(|rule| rule.parameters.clone())
+            .unwrap_or_default()
+    }
+
+    fn hysteresis(&self) -> HysteresisConfig {
+        self.config.hysteresis.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn conditions(bandwidth_mbps: f64, rtt_ms: f64) -> NetworkConditions {
+        NetworkConditions {
+            rtt_ms,
+            bandwidth_estimate: bandwidth_mbps,
+            last_update: Instant::now(),
+            ..NetworkConditions::default()
+        }
+    }
+
+    #[test]
+    fn test_default_table_matches_builtin_tiers() {
+        let policy = TieredPolicy::default();
+        for (mbps, rtt, expected) in [
+            (50.0, 1.0, TierKind::Slow),
+            (500.0, 1.0, TierKind::Home),
+            (1500.0, 1.0, TierKind::Standard),
+            (5000.0, 1.0, TierKind::Performance),
+            (30000.0, 1.0, TierKind::DataCenter),
+            // High RTT halves the estimate
+            (1500.0, 600.0, TierKind::Home),
+        ] {
+            let tier = policy.detect_tier(&[conditions(mbps, rtt)]);
+            assert_eq!(TierKind::from(&tier), expected, "{} Mbps @ {} ms", mbps, rtt);
+        }
+
+        let params = policy.parameters(&NetworkTier::Slow { mbps: 10.0 }, &[]);
+        assert_eq!(params.max_streams, 10);
+        assert!(matches!(params.congestion_control, CongestionControl::NewReno));
+    }
+
+    #[test]
+    fn test_yaml_table_for_satellite_links() {
+        let policy = TieredPolicy::new(TieredPolicyConfig::from_yaml(r#"
+hysteresis:
+  min_stability: { secs: 30, nanos: 0 }
+  required_consecutive: 5
+rtt_penalties: []
+tiers:
+  - tier: slow
+    min_gbps: 0.0
+    parameters: { max_streams: 10, congestion_control: NewReno }
+  - tier: home
+    min_gbps: 0.02
+    parameters: { max_streams: 50, congestion_control: Cubic }
+"#).unwrap()).unwrap();
+
+        // 600 ms RTT no longer penalizes a 50 Mbps satellite link
+        let tier = policy.detect_tier(&[conditions(50.0, 600.0)]);
+        assert_eq!(TierKind::from(&tier), TierKind::Home);
+
+        let params = policy.parameters(&tier, &[]);
+        assert_eq!(params.max_streams, 50);
+        assert_eq!(params.stream_window, ConnectionParameters::default().stream_window);
+        assert_eq!(policy.hysteresis().required_consecutive, 5);
+    }
+
+    #[test]
+    fn test_table_without_floor_rejected() {
+        let yaml = "tiers:\n  - tier: home\n    min_gbps: 0.1\n";
+        assert!(TieredPolicyConfig::from_yaml(yaml).is_err());
+    }
+}
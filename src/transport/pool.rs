@@ -0,0 +1,307 @@
+//! Reusable buffer pool
+//!
+//! Buffers are grouped into power-of-four size classes, each backed by a
+//! lock-free `ArrayQueue`. A [`PooledBuffer`] returns its allocation to the
+//! pool when dropped, and [`PooledBuffer::freeze`] hands out a `Bytes` whose
+//! owner does the same once the last clone is dropped, so buffers are
+//! recycled even after crossing task boundaries. Memory retained by idle
+//! buffers is bounded; buffers that would exceed the budget are freed.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use bytes::{Bytes, BytesMut};
+use crossbeam::queue::ArrayQueue;
+use serde::{Serialize, Deserialize};
+
+/// Smallest size class
+const MIN_CLASS_SIZE: usize = 1024;
+
+/// Growth factor between consecutive size classes
+const CLASS_GROWTH: usize = 4;
+
+/// Buffer pool statistics
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MemoryPoolStats {
+    /// Requests served with a recycled buffer
+    pub hits: u64,
+    /// Requests that needed a fresh allocation
+    pub misses: u64,
+    /// Idle buffers ready for reuse
+    pub available: usize,
+    /// Buffers currently handed out
+    pub outstanding: usize,
+    /// Highest number of buffers handed out at once
+    pub high_water: usize,
+    /// Bytes held by idle buffers
+    pub retained_bytes: usize,
+    /// Buffers freed instead of recycled because the pool was full
+    pub discarded: u64,
+}
+
+struct SizeClass {
+    size: usize,
+    free: ArrayQueue<BytesMut>,
+}
+
+struct PoolInner {
+    classes: Vec<SizeClass>,
+    max_retained_bytes: usize,
+    retained_bytes: AtomicUsize,
+    outstanding: AtomicUsize,
+    high_water: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    discarded: AtomicU64,
+}
+
+impl PoolInner {
+    /// Smallest class that can hold `len` bytes
+    fn class_for_len(&self, len: usize) -> Option<&SizeClass> {
+        self.classes.iter().find(|class| class.size >= len)
+    }
+
+    /// Largest class a buffer of `capacity` bytes can serve
+    fn class_for_capacity(&self, capacity: usize) -> Option<&SizeClass> {
+        self.classes.iter().rev().find(|class| class.size <= capacity)
+    }
+
+    /// Return a buffer handed out by this pool
+    fn release(&self, buffer: BytesMut) {
+        self.outstanding.fetch_sub(1, Ordering::Relaxed);
+        self.recycle(buffer);
+    }
+
+    /// Keep a buffer for reuse if a class fits it and the budget allows
+    fn recycle(&self, mut buffer: BytesMut) {
+        let capacity = buffer.capacity();
+        let Some(class) = self.class_for_capacity(capacity) else {
+            return;
+        };
+
+        if self.retained_bytes.fetch_add(capacity, Ordering::Relaxed) + capacity > self.max_retained_bytes {
+            self.retained_bytes.fetch_sub(capacity, Ordering::Relaxed);
+            self.discarded.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        buffer.clear();
+        if class.free.push(buffer).is_err() {
+            self.retained_bytes.fetch_sub(capacity, Ordering::Relaxed);
+            self.discarded.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Memory buffer pool for efficient buffer reuse
+#[derive(Clone)]
+pub struct MemoryPool {
+    inner: Arc<PoolInner>,
+}
+
+impl MemoryPool {
+    /// Create a pool serving buffers up to `buffer_size` bytes, retaining at
+    /// most `max_buffers` full-size buffers' worth of idle memory
+    pub fn new(buffer_size: usize, max_buffers: usize) -> Self {
+        let largest = buffer_size.max(MIN_CLASS_SIZE).next_power_of_two();
+        let mut classes = Vec::new();
+        let mut size = MIN_CLASS_SIZE;
+        loop {
+            let size_clamped = size.min(largest);
+            classes.push(SizeClass {
+                size: size_clamped,
+                free: ArrayQueue::new(max_buffers.max(1)),
+            });
+            if size_clamped == largest {
+                break;
+            }
+            size *= CLASS_GROWTH;
+        }
+
+        Self {
+            inner: Arc::new(PoolInner {
+                classes,
+                max_retained_bytes: largest.saturating_mul(max_buffers),
+                retained_bytes: AtomicUsize::new(0),
+                outstanding: AtomicUsize::new(0),
+                high_water: AtomicUsize::new(0),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                discarded: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Largest buffer size the pool serves
+    pub fn max_buffer_size(&self) -> usize {
+        self.inner.classes.last().map(|class| class.size).unwrap_or(0)
+    }
+
+    /// Get an empty buffer with capacity for at least `len` bytes
+    ///
+    /// Returns None if `len` exceeds [`Self::max_buffer_size`].
+    pub fn get_buffer(&self, len: usize) -> Option<PooledBuffer> {
+        let class = self.inner.class_for_len(len)?;
+
+        let (buffer, reused) = match class.free.pop() {
+            Some(buffer) => {
+                self.inner.retained_bytes.fetch_sub(buffer.capacity(), Ordering::Relaxed);
+                self.inner.hits.fetch_add(1, Ordering::Relaxed);
+                (buffer, true)
+            }
+            None => {
+                self.inner.misses.fetch_add(1, Ordering::Relaxed);
+                (BytesMut::with_capacity(class.size), false)
+            }
+        };
+
+        let outstanding = self.inner.outstanding.fetch_add(1, Ordering::Relaxed) + 1;
+        self.inner.high_water.fetch_max(outstanding, Ordering::Relaxed);
+
+        Some(PooledBuffer {
+            buffer: Some(buffer),
+            reused,
+            pool: self.inner.clone(),
+        })
+    }
+
+    /// Donate a buffer that was not obtained from this pool
+    pub fn return_buffer(&self, buffer: BytesMut) {
+        self.inner.recycle(buffer);
+    }
+
+    /// Get current pool statistics
+    pub fn stats(&self) -> MemoryPoolStats {
+        MemoryPoolStats {
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+            available: self.inner.classes.iter().map(|class| class.free.len()).sum(),
+            outstanding: self.inner.outstanding.load(Ordering::Relaxed),
+            high_water: self.inner.high_water.load(Ordering::Relaxed),
+            retained_bytes: self.inner.retained_bytes.load(Ordering::Relaxed),
+            discarded: self.inner.discarded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Buffer checked out of a [`MemoryPool`], returned to it on drop
+pub struct PooledBuffer {
+    buffer: Option<BytesMut>,
+    reused: bool,
+    pool: Arc<PoolInner>,
+}
+
+impl PooledBuffer {
+    /// Whether this buffer was recycled rather than freshly allocated
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// Convert into immutable `Bytes`; the allocation returns to the pool
+    /// when the last clone is dropped
+    pub fn freeze(mut self) -> Bytes {
+        let buffer = self.buffer.take().unwrap_or_default();
+        Bytes::from_owner(RecycleOnDrop {
+            buffer,
+            pool: self.pool.clone(),
+        })
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        self.buffer.as_ref().expect("buffer present until dropped")
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        self.buffer.as_mut().expect("buffer present until dropped")
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.release(buffer);
+        }
+    }
+}
+
+/// `Bytes` owner that hands its allocation back to the pool
+struct RecycleOnDrop {
+    buffer: BytesMut,
+    pool: Arc<PoolInner>,
+}
+
+impl AsRef<[u8]> for RecycleOnDrop {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for RecycleOnDrop {
+    fn drop(&mut self) {
+        self.pool.release(std::mem::take(&mut self.buffer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BufMut;
+
+    #[test]
+    fn test_buffers_are_reused() {
+        let pool = MemoryPool::new(65507, 4);
+        assert_eq!(pool.max_buffer_size(), 65536);
+
+        let buffer = pool.get_buffer(1500).unwrap();
+        assert!(!buffer.is_reused());
+        assert_eq!(buffer.capacity(), 4096);
+        drop(buffer);
+
+        let buffer = pool.get_buffer(2000).unwrap();
+        assert!(buffer.is_reused());
+
+        let stats = pool.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!(stats.outstanding, 1);
+        assert!(pool.get_buffer(100_000).is_none());
+    }
+
+    #[test]
+    fn test_frozen_bytes_recycle_after_last_clone() {
+        let pool = MemoryPool::new(4096, 4);
+        let mut buffer = pool.get_buffer(10).unwrap();
+        buffer.put_slice(b"hello");
+        let bytes = buffer.freeze();
+        let clone = bytes.clone();
+        assert_eq!(&clone[..], b"hello");
+
+        drop(bytes);
+        assert_eq!(pool.stats().available, 0);
+        drop(clone);
+
+        let stats = pool.stats();
+        assert_eq!(stats.available, 1);
+        assert_eq!(stats.outstanding, 0);
+        assert!(pool.get_buffer(10).unwrap().is_reused());
+    }
+
+    #[test]
+    fn test_retained_memory_is_bounded() {
+        let pool = MemoryPool::new(1024, 2);
+        let buffers: Vec<_> = (0..4).map(|_| pool.get_buffer(1024).unwrap()).collect();
+        assert_eq!(pool.stats().high_water, 4);
+        drop(buffers);
+
+        let stats = pool.stats();
+        assert_eq!(stats.available, 2);
+        assert_eq!(stats.retained_bytes, 2048);
+        assert_eq!(stats.discarded, 2);
+        assert_eq!(stats.outstanding, 0);
+    }
+}
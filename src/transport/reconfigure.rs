@@ -0,0 +1,226 @@
+//! Runtime transport reconfiguration
+//!
+//! QUIC lets a few limits change on an established connection: stream
+//! concurrency, the connection receive window and the send window. Admission
+//! limits, the adaptation policy and settings the transport reads on each use
+//! also apply right away. Everything else is negotiated or fixed at the
+//! handshake and only reaches connections established after the change, and
+//! settings consumed while building the transport need a new one.
+
+use quinn::VarInt;
+use serde::{Serialize, Deserialize};
+
+use super::TransportConfig;
+use super::adaptive::ConnectionParameters;
+
+/// Push the names of `fields` that differ between `old` and `new` onto `list`
+///
+/// Compared via Debug since not every field type implements PartialEq.
+macro_rules! classify {
+    ($old:ident, $new:ident, $list:expr; $($field:ident),+ $(,)?) => {
+        $(
+            if format!("{:?}", $old.$field) != format!("{:?}", $new.$field) {
+                $list.push(stringify!($field).to_string());
+            }
+        )+
+    };
+}
+
+/// Which changed parameters took effect where
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReconfigureReport {
+    /// Parameters in effect right away, on live connections or the transport
+    pub applied: Vec<String>,
+    /// Parameters used only by connections established after the change
+    pub requires_reconnect: Vec<String>,
+    /// Parameters that need a new transport (bound socket) to take effect
+    pub requires_restart: Vec<String>,
+    /// Live connections the applied parameters were pushed to
+    pub connections_updated: usize,
+}
+
+impl ReconfigureReport {
+    /// Classify the differences between two configurations
+    pub fn diff(old: &TransportConfig, new: &TransportConfig) -> Self {
+        let mut report = Self::default();
+
+        classify!(old, new, report.applied;
+            max_concurrent_streams,
+            send_buffer_size,
+            receive_buffer_size,
+            adaptation,
+            admission,
+            max_connections,
+            connection_pool_size,
+            enable_zero_copy,
+            dial,
+        );
+        classify!(old, new, report.requires_reconnect;
+            max_idle_timeout,
+            max_datagram_size,
+            congestion_control,
+            congestion,
+            enable_0rtt,
+            enable_migration,
+            enable_falcon_crypto,
+            falcon_variant,
+            frame_batch_size,
+            connection_timeout,
+        );
+        // The signing policy is fixed in the shared protocol handler and the
+        // relay ALPN in the server's TLS config, both built with the transport
+        classify!(old, new, report.requires_restart;
+            bind_address,
+            port,
+            frame_signing,
+            relayed_streams,
+            enable_memory_pool,
+            memory_pool_size,
+            cert_rotation_interval,
+            enable_cpu_affinity,
+            enable_large_send_offload,
+        );
+
+        // Socket buffers follow the windows but are only sized at bind time
+        for field in ["send_buffer_size", "receive_buffer_size"] {
+            if report.applied.iter().any(|applied| applied == field) {
+                report.requires_restart.push(format!("{} (socket buffer)", field));
+            }
+        }
+
+        report
+    }
+
+    /// Classify the differences between two sets of adaptive connection parameters
+    pub fn diff_parameters(old: &ConnectionParameters, new: &ConnectionParameters) -> Self {
+        let mut report = Self::default();
+        classify!(old, new, report.applied; max_streams, connection_window, send_buffer_size);
+        classify!(old, new, report.requires_reconnect;
+            stream_window,
+            max_datagram_size,
+            keep_alive_interval,
+            idle_timeout,
+            congestion_control,
+        );
+        classify!(old, new, report.requires_restart; receive_buffer_size);
+        report
+    }
+
+    /// Whether nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.requires_reconnect.is_empty() && self.requires_restart.is_empty()
+    }
+
+    /// Whether any of `fields` was applied
+    pub(crate) fn applied_any(&self, fields: &[&str]) -> bool {
+        self.applied.iter().any(|applied| fields.contains(&applied.as_str()))
+    }
+}
+
+/// Applied fields that [`apply_live`] pushes to established connections
+pub(crate) const LIVE_CONNECTION_FIELDS: &[&str] = &["max_concurrent_streams", "send_buffer_size", "receive_buffer_size"];
+
+/// Push the live-adjustable limits of `config` to an established connection
+pub(crate) fn apply_live(connection: &quinn::Connection, config: &TransportConfig) {
+    let streams = VarInt::from_u32(config.max_concurrent_streams);
+    connection.set_max_concurrent_bi_streams(streams);
+    connection.set_max_concurrent_uni_streams(streams);
+    connection.set_send_window(config.send_buffer_size as u64);
+    connection.set_receive_window(VarInt::try_from(config.receive_buffer_size as u64).unwrap_or(VarInt::MAX));
+}
+
+/// Push the live-adjustable adaptive parameters to an established connection
+pub(crate) fn apply_live_parameters(connection: &quinn::Connection, params: &ConnectionParameters) {
+    connection.set_max_concurrent_bi_streams(VarInt::from_u32(params.max_streams));
+    connection.set_max_concurrent_uni_streams(VarInt::from_u32(params.max_streams / 2));
+    connection.set_send_window(params.send_buffer_size as u64);
+    connection.set_receive_window(VarInt::try_from(params.connection_window).unwrap_or(VarInt::MAX));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{AdmissionConfig, CongestionConfig, CongestionControl, DialConfig, FrameSigningPolicy, TieredPolicyConfig};
+    use crate::transport::falcon::FalconVariant;
+
+    #[test]
+    fn test_diff_classifies_changes() {
+        let old = TransportConfig::default();
+        assert!(ReconfigureReport::diff(&old, &old).is_empty());
+
+        let new = TransportConfig {
+            max_concurrent_streams: 10,
+            receive_buffer_size: 1024 * 1024,
+            congestion_control: CongestionControl::NewReno,
+            connection_timeout: std::time::Duration::from_secs(1),
+            port: 1,
+            ..old.clone()
+        };
+        let report = ReconfigureReport::diff(&old, &new);
+        assert_eq!(report.applied, vec!["max_concurrent_streams", "receive_buffer_size"]);
+        assert_eq!(report.requires_reconnect, vec!["congestion_control", "connection_timeout"]);
+        assert_eq!(report.requires_restart, vec!["port", "receive_buffer_size (socket buffer)"]);
+    }
+
+    #[test]
+    fn test_diff_classifies_every_field() {
+        let old = TransportConfig::default();
+        // Every field listed, so a new one fails to compile until classified here
+        let new = TransportConfig {
+            bind_address: "fd00::1".parse().unwrap(),
+            port: 1,
+            max_connections: None,
+            connection_timeout: std::time::Duration::from_secs(1),
+            enable_migration: false,
+            enable_0rtt: true,
+            max_idle_timeout: std::time::Duration::from_secs(1),
+            cert_rotation_interval: std::time::Duration::from_secs(1),
+            max_concurrent_streams: 1,
+            send_buffer_size: 1,
+            receive_buffer_size: 1,
+            connection_pool_size: 1,
+            enable_zero_copy: false,
+            max_datagram_size: 1200,
+            congestion_control: CongestionControl::NewReno,
+            congestion: CongestionConfig::default().with_initial_window(1),
+            enable_memory_pool: false,
+            memory_pool_size: 1,
+            frame_batch_size: 1,
+            enable_cpu_affinity: false,
+            enable_large_send_offload: false,
+            enable_falcon_crypto: false,
+            falcon_variant: FalconVariant::Falcon512,
+            frame_signing: FrameSigningPolicy::All,
+            relayed_streams: true,
+            admission: AdmissionConfig { retry_threshold: Some(1), ..AdmissionConfig::default() },
+            dial: DialConfig { attempt_delay: std::time::Duration::from_millis(1) },
+            adaptation: TieredPolicyConfig { tiers: Vec::new(), ..TieredPolicyConfig::default() },
+        };
+        let report = ReconfigureReport::diff(&old, &new);
+
+        let mut fields: Vec<&String> = report.applied.iter()
+            .chain(&report.requires_reconnect)
+            .chain(report.requires_restart.iter().filter(|field| !field.ends_with("(socket buffer)")))
+            .collect();
+        let classified = fields.len();
+        fields.sort();
+        fields.dedup();
+        assert_eq!(fields.len(), classified, "a field is classified twice");
+        assert_eq!(classified, 28);
+        assert!(report.applied_any(LIVE_CONNECTION_FIELDS));
+    }
+
+    #[test]
+    fn test_diff_parameters_classifies_changes() {
+        let old = ConnectionParameters::default();
+        let new = ConnectionParameters {
+            max_streams: 10,
+            idle_timeout: std::time::Duration::from_secs(1),
+            ..old.clone()
+        };
+        let report = ReconfigureReport::diff_parameters(&old, &new);
+        assert_eq!(report.applied, vec!["max_streams"]);
+        assert_eq!(report.requires_reconnect, vec!["idle_timeout"]);
+        assert!(report.requires_restart.is_empty());
+    }
+}
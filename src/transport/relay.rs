@@ -0,0 +1,543 @@
+//! Multi-hop relay role
+//!
+//! Edge nodes reach isolated clusters through bastion relays. The sender
+//! opens a stream with [`StoqTransport::open_relayed_stream`], naming the
+//! relays and the final receiver, and every relay running
+//! [`StoqTransport::serve_relay`] forwards it one hop further (see
+//! [`RelayHeader`] for the preamble). The final receiver, which opts in with
+//! [`TransportConfig::relayed_streams`](super::TransportConfig::relayed_streams),
+//! reads the trail of relays from [`Stream::hop_trail`]. Every hop verifies
+//! the header it receives against its frame signing policy and signs the
+//! header it forwards with its own key.
+//!
+//! Relays enforce `max_hops`, refuse routes that revisit a hop, and splice
+//! both directions one chunk at a time, so QUIC flow control pushes back on
+//! the sender when a downstream hop is slow.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use dashmap::DashMap;
+use quinn::VarInt;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::Arc;
+use std::time::Duration;
+use parking_lot::Mutex;
+use std::future::Future;
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, debug_span, Instrument};
+
+use super::signing::ConnectionSigning;
+use super::{Connection, Endpoint, StoqTransport, Stream};
+use crate::error::{Result, StoqError};
+use crate::extensions::HopInfo;
+use crate::protocol::error_codes;
+use crate::protocol::frame_types::STOQ_HOP;
+use crate::protocol::frames;
+use crate::protocol::relay::{RelayHeader, MAX_HEADER_LEN};
+use crate::protocol::signing::MAX_SIGNATURE_LEN;
+
+/// Most data a relay holds per direction before the next hop accepts it
+const SPLICE_CHUNK: usize = 64 * 1024;
+
+/// Relay role settings
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    /// Ceiling on relays a stream may traverse, whatever its sender asks for
+    pub max_hops: u8,
+    /// Next hops this relay forwards to (None = any)
+    pub allowed_next_hops: Option<Vec<SocketAddr>>,
+    /// Address recorded in the trail instead of the one the stream arrived on
+    pub advertised_address: Option<SocketAddrV6>,
+    /// Metadata attached to this relay's trail entries
+    pub metadata: HashMap<String, String>,
+    /// Time allowed to connect to a next hop
+    pub connect_timeout: Duration,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            max_hops: 8,
+            allowed_next_hops: None,
+            advertised_address: None,
+            metadata: HashMap::new(),
+            connect_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RelayConfig {
+    fn allows(&self, next: &Endpoint) -> bool {
+        self.allowed_next_hops.as_ref().is_none_or(|allowed| allowed.contains(&next.to_socket_addr()))
+    }
+}
+
+/// Running relay role
+///
+/// Dropping the handle stops accepting and aborts the streams being relayed;
+/// [`Self::shutdown`] closes the relay's connections first.
+pub struct RelayHandle {
+    relay: Arc<Relay>,
+    accept: JoinHandle<()>,
+}
+
+impl RelayHandle {
+    /// Stop accepting, close the relay's connections and wait for its streams to end
+    pub async fn shutdown(self) {
+        self.accept.abort();
+        let connections = self.relay.inbound.iter().map(|entry| entry.value().clone())
+            .chain(self.relay.next_hops.iter().map(|entry| entry.value().clone()));
+        for connection in connections {
+            connection.close(error_codes::NO_ERROR, b"relay shutting down");
+        }
+        let mut tasks = std::mem::take(&mut *self.relay.tasks.lock());
+        while tasks.join_next().await.is_some() {}
+    }
+}
+
+impl Drop for RelayHandle {
+    fn drop(&mut self) {
+        self.accept.abort();
+        self.relay.tasks.lock().abort_all();
+    }
+}
+
+struct Relay {
+    transport: StoqTransport,
+    config: RelayConfig,
+    /// Connections accepted from previous hops, by connection ID
+    inbound: DashMap<String, Arc<Connection>>,
+    /// Connections to next hops, shared by the streams relayed over them
+    next_hops: DashMap<SocketAddr, Arc<Connection>>,
+    /// Per-connection and per-stream tasks, ended with the handle
+    tasks: Mutex<JoinSet<()>>,
+}
+
+impl Relay {
+    fn spawn_task<F: Future<Output = ()> + Send + 'static>(&self, task: F) {
+        let mut tasks = self.tasks.lock();
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(task);
+    }
+}
+
+/// Accept connections on `transport` and relay every stream they open
+pub(super) fn spawn(transport: StoqTransport, config: RelayConfig) -> RelayHandle {
+    let relay = Arc::new(Relay {
+        transport,
+        config,
+        inbound: DashMap::new(),
+        next_hops: DashMap::new(),
+        tasks: Mutex::new(JoinSet::new()),
+    });
+    let accept = tokio::spawn({
+        let relay = relay.clone();
+        async move {
+            loop {
+                let connection = match relay.transport.accept().await {
+                    Ok(connection) => connection,
+                    Err(StoqError::EndpointClosed) => break,
+                    Err(e) => {
+                        debug!("Relay accept failed: {}", e);
+                        continue;
+                    }
+                };
+                relay.inbound.insert(connection.id(), connection.clone());
+                relay.spawn_task({
+                    let relay = relay.clone();
+                    async move {
+                        while let Ok(stream) = connection.accept_stream().await {
+                            relay.spawn_task({
+                                let relay = relay.clone();
+                                let connection = connection.clone();
+                                async move { relay.forward(&connection, stream).await }
+                            });
+                        }
+                        relay.inbound.remove(&connection.id());
+                    }
+                });
+            }
+        }
+    });
+    RelayHandle { relay, accept }
+}
+
+impl Relay {
+    async fn forward(&self, incoming: &Connection, mut upstream: Stream) {
+        let span = debug_span!(parent: upstream.span(), "stoq.relay", next_hop = tracing::field::Empty);
+        async {
+            let mut downstream = match self.open_next_hop(incoming, &mut upstream).await {
+                Ok(downstream) => downstream,
+                Err((code, e)) => {
+                    debug!("Refused relayed stream: {}", e);
+                    let _ = upstream.stop(code);
+                    let _ = upstream.reset(code);
+                    return;
+                }
+            };
+            let (sent, returned) = tokio::join!(
+                splice(&mut upstream.recv, &mut downstream.send),
+                splice(&mut downstream.recv, &mut upstream.send),
+            );
+            match (sent, returned) {
+                (Ok(sent), Ok(returned)) => debug!("Relayed {} bytes forward and {} back", sent, returned),
+                (Err(e), _) | (_, Err(e)) => debug!("Relayed stream ended with error: {}", e),
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Read the header, check the route and open the stream to the next hop
+    async fn open_next_hop(&self, incoming: &Connection, upstream: &mut Stream) -> std::result::Result<Stream, (VarInt, StoqError)> {
+        let violation = |e| (error_codes::PROTOCOL_VIOLATION, e);
+        let mut header = read_header(&mut upstream.recv, &incoming.signing).await.map_err(violation)?;
+        if header.route.is_empty() {
+            return Err(violation(StoqError::Protocol("relayed stream has no next hop".to_string())));
+        }
+        let next = header.route.remove(0);
+        tracing::Span::current().record("next_hop", tracing::field::display(next.to_socket_addr()));
+
+        let hop = self.local_hop(incoming);
+        let revisits = header.visited(hop.address, hop.port)
+            || header.visited(next.address, next.port)
+            || (next.address, next.port) == (hop.address, hop.port);
+        if revisits {
+            return Err((error_codes::ROUTING_LOOP, StoqError::Protocol(format!("route revisits {}", next.to_socket_addr()))));
+        }
+        if !self.config.allows(&next) {
+            return Err((error_codes::UNAUTHORIZED, StoqError::Protocol(format!("next hop {} not allowed", next.to_socket_addr()))));
+        }
+        header.max_hops = header.max_hops.min(self.config.max_hops);
+        header.push_hop(hop).map_err(|e| (error_codes::HOP_LIMIT_EXCEEDED, e))?;
+
+        let unreachable = |e| (error_codes::NEXT_HOP_UNREACHABLE, e);
+        let connection = self.next_hop(&next).await.map_err(unreachable)?;
+        if header.route.is_empty() && !connection.hop_preambles {
+            return Err(unreachable(StoqError::Protocol(format!("{} does not accept relayed streams", next.to_socket_addr()))));
+        }
+        let preamble = encode_preamble(&connection.signing, &header).map_err(violation)?;
+        let mut downstream = connection.open_stream().await.map_err(unreachable)?;
+        downstream.send.write_all(&preamble).await.map_err(|e| unreachable(e.into()))?;
+        self.transport.metrics.record_hop_route();
+        Ok(downstream)
+    }
+
+    /// This relay's trail entry for a stream arriving on `incoming`
+    fn local_hop(&self, incoming: &Connection) -> HopInfo {
+        let local = self.transport.local_addr().ok();
+        let (address, port) = match self.config.advertised_address {
+            Some(advertised) => (*advertised.ip(), advertised.port()),
+            None => {
+                let address = match (incoming.inner.local_ip(), local) {
+                    (Some(IpAddr::V6(ip)), _) => ip,
+                    (_, Some(SocketAddr::V6(local))) => *local.ip(),
+                    _ => Ipv6Addr::UNSPECIFIED,
+                };
+                (address, local.map_or(0, |local| local.port()))
+            }
+        };
+        HopInfo {
+            address,
+            port,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            metadata: self.config.metadata.clone(),
+        }
+    }
+
+    async fn next_hop(&self, endpoint: &Endpoint) -> Result<Arc<Connection>> {
+        let key = endpoint.to_socket_addr();
+        if let Some(connection) = self.next_hops.get(&key) {
+            if connection.is_active() {
+                return Ok(connection.clone());
+            }
+        }
+        let timeout = self.config.connect_timeout;
+        let connection = tokio::time::timeout(timeout, self.transport.connect(endpoint))
+            .await
+            .map_err(|_| StoqError::Timeout(timeout))??;
+        self.next_hops.insert(key, connection.clone());
+        Ok(connection)
+    }
+}
+
+/// The preamble opening a relayed stream on the connection `signing` belongs to
+///
+/// A `STOQ_HOP` marker, then the header frame in a length-prefixed frame group.
+pub(super) fn encode_preamble(signing: &ConnectionSigning, header: &RelayHeader) -> Result<Bytes> {
+    let group = signing.encode(&[header.encode()?])?;
+    let mut buf = BytesMut::with_capacity(group.len() + 8);
+    frames::encode_varint(&mut buf, STOQ_HOP);
+    buf.put_slice(&group);
+    Ok(buf.freeze())
+}
+
+/// Read a relay header from the start of a stream
+async fn read_header(recv: &mut quinn::RecvStream, signing: &ConnectionSigning) -> Result<RelayHeader> {
+    if read_varint(recv).await? != STOQ_HOP {
+        return Err(StoqError::Protocol("stream does not start with a relay header".to_string()));
+    }
+    read_header_group(recv, signing).await
+}
+
+/// Read a relay header if the stream starts with one
+///
+/// Otherwise returns the bytes consumed while checking, at most a varint's
+/// worth, which belong to the stream data.
+pub(super) async fn sniff_header(recv: &mut quinn::RecvStream, signing: &ConnectionSigning) -> Result<(Option<RelayHeader>, Vec<u8>)> {
+    let mut buf = [0u8; 8];
+    if read_some(recv, &mut buf[..1]).await? == 0 {
+        return Ok((None, Vec::new()));
+    }
+    let len = 1 << (buf[0] >> 6);
+    let read = 1 + read_some(recv, &mut buf[1..len]).await?;
+    if read < len || frames::decode_varint(&mut &buf[..len]) != Some(STOQ_HOP) {
+        return Ok((None, buf[..read].to_vec()));
+    }
+    Ok((Some(read_header_group(recv, signing).await?), Vec::new()))
+}
+
+/// Fill `buf`, stopping short only where the stream ends
+async fn read_some(recv: &mut quinn::RecvStream, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match recv.read(&mut buf[read..]).await? {
+            Some(n) => read += n,
+            None => break,
+        }
+    }
+    Ok(read)
+}
+
+/// Read and verify the frame group following the marker, which holds the header
+async fn read_header_group(recv: &mut quinn::RecvStream, signing: &ConnectionSigning) -> Result<RelayHeader> {
+    let len = read_varint(recv).await?.into_inner() as usize;
+    if len > MAX_HEADER_LEN + MAX_SIGNATURE_LEN {
+        return Err(StoqError::Protocol(format!("relay header of {} bytes is too long", len)));
+    }
+    let mut group = vec![0u8; len];
+    recv.read_exact(&mut group).await?;
+    match <[Bytes; 1]>::try_from(signing.verify(group.into()).await?) {
+        Ok([frame]) => RelayHeader::decode(frame),
+        Err(_) => Err(StoqError::Protocol("relay preamble must hold exactly the header".to_string())),
+    }
+}
+
+async fn read_varint(recv: &mut quinn::RecvStream) -> Result<VarInt> {
+    let mut buf = [0u8; 8];
+    recv.read_exact(&mut buf[..1]).await?;
+    let len = 1 << (buf[0] >> 6);
+    recv.read_exact(&mut buf[1..len]).await?;
+    frames::decode_varint(&mut &buf[..len]).ok_or_else(|| StoqError::Protocol("bad varint".to_string()))
+}
+
+/// Copy `recv` into `send` until it finishes, passing resets and stops along
+async fn splice(recv: &mut quinn::RecvStream, send: &mut quinn::SendStream) -> Result<u64> {
+    let mut total = 0;
+    loop {
+        match recv.read_chunk(SPLICE_CHUNK, true).await {
+            Ok(Some(chunk)) => {
+                total += chunk.bytes.len() as u64;
+                if let Err(e) = send.write_chunk(chunk.bytes).await {
+                    if let quinn::WriteError::Stopped(code) = e {
+                        let _ = recv.stop(code);
+                    }
+                    return Err(e.into());
+                }
+            }
+            Ok(None) => {
+                send.finish()?;
+                return Ok(total);
+            }
+            Err(e) => {
+                if let quinn::ReadError::Reset(code) = e {
+                    let _ = send.reset(code);
+                }
+                return Err(e.into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{signing_config, test_config, test_transport, trust};
+    use super::super::TransportConfig;
+    use super::*;
+
+    fn receiver_config() -> TransportConfig {
+        TransportConfig { relayed_streams: true, ..test_config() }
+    }
+
+    #[tokio::test]
+    async fn test_stream_crosses_two_relays() {
+        let (first, first_endpoint) = test_transport(test_config()).await;
+        let (second, second_endpoint) = test_transport(test_config()).await;
+        let (receiver, receiver_endpoint) = test_transport(receiver_config()).await;
+        let (sender, _) = test_transport(test_config()).await;
+        // Two trail entries this size push the header past the stream MTU
+        let config = RelayConfig {
+            metadata: HashMap::from([
+                ("role".to_string(), "bastion".to_string()),
+                ("note".to_string(), "x".repeat(crate::STOQ_MTU / 2)),
+            ]),
+            ..RelayConfig::default()
+        };
+        let _relays = [first.serve_relay(config.clone()), second.serve_relay(config)];
+
+        let accept = tokio::spawn(async move {
+            let connection = receiver.accept().await.unwrap();
+            let mut stream = connection.accept_stream().await.unwrap();
+            let request = stream.receive().await.unwrap();
+            let trail = stream.hop_trail().unwrap().to_vec();
+            stream.send(&[&request[..], b" back"].concat()).await.unwrap();
+            (request, trail)
+        });
+
+        let route = [first_endpoint.clone(), second_endpoint.clone(), receiver_endpoint];
+        let mut stream = sender.open_relayed_stream(&route, 4).await.unwrap();
+        stream.send(b"hello").await.unwrap();
+        assert_eq!(&stream.receive().await.unwrap()[..], b"hello back");
+
+        let (request, trail) = accept.await.unwrap();
+        assert_eq!(&request[..], b"hello");
+        let ports: Vec<u16> = trail.iter().map(|hop| hop.port).collect();
+        assert_eq!(ports, [first_endpoint.port, second_endpoint.port]);
+        assert!(trail.iter().all(|hop| hop.address == Ipv6Addr::LOCALHOST && hop.metadata["role"] == "bastion"));
+        assert!(trail[0].timestamp <= trail[1].timestamp);
+        assert_eq!(first.get_protocol_metrics().hop_routes_processed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_relay_refuses_bad_routes() {
+        let (relay, relay_endpoint) = test_transport(test_config()).await;
+        let (_receiver, receiver_endpoint) = test_transport(receiver_config()).await;
+        let (sender, _) = test_transport(test_config()).await;
+        let _relay = relay.serve_relay(RelayConfig {
+            max_hops: 1,
+            connect_timeout: Duration::from_secs(1),
+            ..RelayConfig::default()
+        });
+
+        let refusal = |route: Vec<Endpoint>, max_hops| {
+            let sender = sender.clone();
+            async move {
+                let mut stream = sender.open_relayed_stream(&route, max_hops).await.unwrap();
+                // The relay may stop the stream before the send completes
+                let _ = stream.send(b"hello").await;
+                stream.receive().await.unwrap_err().peer_error_code()
+            }
+        };
+
+        // Back to the relay itself
+        let looped = vec![relay_endpoint.clone(), relay_endpoint.clone(), receiver_endpoint.clone()];
+        assert_eq!(refusal(looped, 4).await, Some(error_codes::ROUTING_LOOP));
+
+        // The relay's own limit applies even when the sender allows more
+        let (second, second_endpoint) = test_transport(test_config()).await;
+        let _second_relay = second.serve_relay(RelayConfig::default());
+        let long = vec![second_endpoint, relay_endpoint.clone(), receiver_endpoint.clone()];
+        assert_eq!(refusal(long, 4).await, Some(error_codes::HOP_LIMIT_EXCEEDED));
+
+        // Nothing listens on port 1
+        let dead = vec![relay_endpoint, Endpoint::new(Ipv6Addr::LOCALHOST, 1)];
+        assert_eq!(refusal(dead, 4).await, Some(error_codes::NEXT_HOP_UNREACHABLE));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_closes_relayed_streams() {
+        let (relay, relay_endpoint) = test_transport(test_config()).await;
+        let (receiver, receiver_endpoint) = test_transport(receiver_config()).await;
+        let (sender, _) = test_transport(test_config()).await;
+        let handle = relay.serve_relay(RelayConfig::default());
+
+        // The receiver never answers, so the relay keeps the return leg open
+        let (received, request) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let connection = receiver.accept().await.unwrap();
+            let mut stream = connection.accept_stream().await.unwrap();
+            let _ = received.send(stream.receive().await.unwrap());
+            std::future::pending::<()>().await;
+        });
+
+        let mut stream = sender.open_relayed_stream(&[relay_endpoint, receiver_endpoint], 4).await.unwrap();
+        stream.send(b"hello").await.unwrap();
+        assert_eq!(&request.await.unwrap()[..], b"hello");
+
+        tokio::time::timeout(Duration::from_secs(5), handle.shutdown()).await.unwrap();
+        let err = stream.receive().await.unwrap_err();
+        assert_eq!(err.peer_error_code(), Some(error_codes::NO_ERROR));
+    }
+
+    #[tokio::test]
+    async fn test_receiver_must_accept_relayed_streams() {
+        let (relay, relay_endpoint) = test_transport(test_config()).await;
+        let (receiver, receiver_endpoint) = test_transport(test_config()).await;
+        let (sender, _) = test_transport(test_config()).await;
+        let _relay = relay.serve_relay(RelayConfig::default());
+
+        let (received, mut data) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok(connection) = receiver.accept().await {
+                let received = received.clone();
+                tokio::spawn(async move {
+                    while let Ok(mut stream) = connection.accept_stream().await {
+                        let _ = received.send(stream.receive().await.unwrap());
+                    }
+                });
+            }
+        });
+
+        let direct = sender.open_relayed_stream(&[receiver_endpoint.clone()], 4).await;
+        assert!(matches!(direct, Err(StoqError::Protocol(_))));
+
+        let mut stream = sender.open_relayed_stream(&[relay_endpoint, receiver_endpoint.clone()], 4).await.unwrap();
+        let _ = stream.send(b"hello").await;
+        assert_eq!(stream.receive().await.unwrap_err().peer_error_code(), Some(error_codes::NEXT_HOP_UNREACHABLE));
+
+        // Without the hop protocol, data that looks like a header is just data
+        let header = RelayHeader::new(Vec::new(), 4).encode().unwrap();
+        let connection = sender.connect(&receiver_endpoint).await.unwrap();
+        let mut stream = connection.open_stream().await.unwrap();
+        stream.send(&header).await.unwrap();
+        assert_eq!(data.recv().await.unwrap(), header);
+    }
+
+    #[tokio::test]
+    async fn test_each_hop_verifies_the_previous_one() {
+        let signing_receiver = || TransportConfig { relayed_streams: true, ..signing_config() };
+        let (relay, relay_endpoint) = test_transport(signing_config()).await;
+        let (receiver, receiver_endpoint) = test_transport(signing_receiver()).await;
+        let (stranger, stranger_endpoint) = test_transport(signing_receiver()).await;
+        let (sender, _) = test_transport(signing_config()).await;
+        // The receiver trusts the relay but not the sender, whose header the
+        // relay re-signs; the stranger trusts nobody
+        trust(&relay, &sender);
+        trust(&receiver, &relay);
+        let _relay = relay.serve_relay(RelayConfig::default());
+
+        let accept = |transport: StoqTransport| tokio::spawn(async move {
+            let connection = transport.accept().await.unwrap();
+            let mut stream = connection.accept_stream().await.unwrap();
+            let received = stream.receive().await;
+            (received, stream.hop_trail().map(<[HopInfo]>::len))
+        });
+
+        let accepted = accept(receiver);
+        let mut stream = sender.open_relayed_stream(&[relay_endpoint.clone(), receiver_endpoint], 4).await.unwrap();
+        stream.send(b"hello").await.unwrap();
+        let (received, hops) = accepted.await.unwrap();
+        assert_eq!(&received.unwrap()[..], b"hello");
+        assert_eq!(hops, Some(1));
+
+        let refused = accept(stranger);
+        let mut stream = sender.open_relayed_stream(&[relay_endpoint, stranger_endpoint], 4).await.unwrap();
+        let _ = stream.send(b"hello").await;
+        let (received, hops) = refused.await.unwrap();
+        assert!(matches!(received, Err(StoqError::Falcon(_))));
+        assert_eq!(hops, None);
+    }
+}
@@ -0,0 +1,410 @@
+//! Seed fan-out
+//!
+//! [`StoqTransport::seed`] pushes one payload, such as a config blob, to
+//! `replication_factor` of the nodes named in a [`SeedInfo`] and returns once
+//! a quorum of them has acknowledged it. Nodes are tried most reliable first;
+//! one that fails or times out is replaced by the next untried alternate.
+//! Deliveries still in flight when the quorum is reached carry on in the
+//! background.
+//!
+//! Every delivery is its own stream: a [`SeedFrame`] whose `packet_id` is the
+//! SHA-256 of the payload, in a frame group signed when the frame signing
+//! policy covers seed frames, then the payload itself. The receiver reads it
+//! with [`Stream::receive_seed`], which verifies the group, checks the hash and
+//! answers with the `STOQ_SEED` frame type followed by the `packet_id`.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::task::{self, JoinSet};
+use tracing::{debug, warn};
+
+use super::signing::{self, ConnectionSigning};
+use super::{Endpoint, StoqTransport, Stream};
+use crate::error::{Result, StoqError};
+use crate::extensions::{SeedInfo, SeedNode, SeedPriority};
+use crate::protocol::error_codes;
+use crate::protocol::frame_types::STOQ_SEED;
+use crate::protocol::frames::{self, SeedFrame, StoqFrame};
+
+/// Length of an acknowledgement: the frame type varint and the packet ID
+const ACK_LEN: usize = 8 + 32;
+
+/// Seed fan-out settings
+#[derive(Debug, Clone)]
+pub struct SeedConfig {
+    /// Acknowledgements needed before seeding completes (None = every replica
+    /// for [`SeedPriority::Critical`], a majority of them otherwise)
+    pub quorum: Option<usize>,
+    /// Time allowed for one node to connect, take the payload and acknowledge it
+    pub attempt_timeout: Duration,
+}
+
+impl Default for SeedConfig {
+    fn default() -> Self {
+        Self {
+            quorum: None,
+            attempt_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl SeedConfig {
+    /// Acknowledgements required to seed according to `info`
+    fn required(&self, info: &SeedInfo) -> Result<usize> {
+        let replicas = info.replication_factor as usize;
+        if replicas == 0 {
+            return Err(StoqError::Config("replication factor must be at least 1".to_string()));
+        }
+        if replicas > info.seed_nodes.len() {
+            return Err(StoqError::Config(format!(
+                "replication factor {} exceeds the {} seed nodes",
+                replicas,
+                info.seed_nodes.len()
+            )));
+        }
+        let required = self.quorum.unwrap_or(match info.priority {
+            SeedPriority::Critical => replicas,
+            _ => replicas / 2 + 1,
+        });
+        if required == 0 || required > replicas {
+            return Err(StoqError::Config(format!("quorum {} is not within 1..={}", required, replicas)));
+        }
+        Ok(required)
+    }
+}
+
+/// Outcome of a completed fan-out
+#[derive(Debug)]
+pub struct SeedReport {
+    /// SHA-256 of the payload, as sent in the seed frame
+    pub packet_id: [u8; 32],
+    /// Nodes that acknowledged before the quorum was reached
+    pub acked: Vec<SeedNode>,
+    /// Nodes that failed, with the reason
+    pub failed: Vec<(SeedNode, StoqError)>,
+    /// Deliveries still running in the background
+    pub pending: usize,
+}
+
+/// Packet ID of a seeded payload
+pub fn packet_id(payload: &[u8]) -> [u8; 32] {
+    Sha256::digest(payload).into()
+}
+
+/// Candidate nodes, most reliable first
+fn rank(nodes: &[SeedNode]) -> Vec<SeedNode> {
+    let mut ranked = nodes.to_vec();
+    ranked.sort_by_key(|node| std::cmp::Reverse(node.reliability));
+    ranked
+}
+
+/// QUIC send priority of a delivery stream
+fn stream_priority(priority: &SeedPriority) -> i32 {
+    match priority {
+        SeedPriority::Low => -1,
+        SeedPriority::Normal => 0,
+        SeedPriority::High => 1,
+        SeedPriority::Critical => 2,
+    }
+}
+
+fn ack(packet_id: &[u8; 32]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(ACK_LEN);
+    frames::encode_varint(&mut buf, STOQ_SEED);
+    buf.put_slice(packet_id);
+    buf.freeze()
+}
+
+/// Deliver `payload` to enough of `info`'s nodes to reach the quorum
+pub(super) async fn fan_out(transport: &StoqTransport, info: &SeedInfo, payload: Bytes, config: &SeedConfig) -> Result<SeedReport> {
+    let required = config.required(info)?;
+    let packet_id = packet_id(&payload);
+    let frame = StoqFrame::Seed(SeedFrame { seed_info: info.clone(), packet_id })
+        .encode()
+        .map_err(|e| StoqError::Protocol(e.to_string()))?;
+    let delivery = Delivery {
+        transport: transport.clone(),
+        frame,
+        payload,
+        ack: ack(&packet_id),
+        priority: stream_priority(&info.priority),
+        timeout: config.attempt_timeout,
+    };
+
+    let mut candidates = rank(&info.seed_nodes).into_iter();
+    let mut attempts = Attempts::default();
+    for node in candidates.by_ref().take(info.replication_factor as usize) {
+        attempts.spawn(&delivery, node);
+    }
+
+    let mut report = SeedReport { packet_id, acked: Vec::new(), failed: Vec::new(), pending: 0 };
+    while report.acked.len() < required {
+        if report.acked.len() + attempts.len() < required {
+            break;
+        }
+        let Some((node, result)) = attempts.join_next().await else { break };
+        match result {
+            Ok(()) => report.acked.push(node),
+            Err(e) => {
+                debug!("Seeding to [{}]:{} failed: {}", node.address, node.port, e);
+                report.failed.push((node, e));
+                if let Some(alternate) = candidates.next() {
+                    attempts.spawn(&delivery, alternate);
+                }
+            }
+        }
+    }
+
+    report.pending = attempts.len();
+    attempts.tasks.detach_all();
+    if report.acked.len() < required {
+        warn!("Seeding reached {} of {} required nodes", report.acked.len(), required);
+        return Err(StoqError::Quorum { acked: report.acked.len(), required });
+    }
+    Ok(report)
+}
+
+/// Deliveries in flight, keyed by task so a panicked one can still be
+/// attributed to its node
+#[derive(Default)]
+struct Attempts {
+    tasks: JoinSet<Result<()>>,
+    nodes: HashMap<task::Id, SeedNode>,
+}
+
+impl Attempts {
+    fn spawn(&mut self, delivery: &Delivery, node: SeedNode) {
+        let handle = self.tasks.spawn(delivery.clone().run(node.clone()));
+        self.nodes.insert(handle.id(), node);
+    }
+
+    fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    async fn join_next(&mut self) -> Option<(SeedNode, Result<()>)> {
+        let (id, result) = match self.tasks.join_next_with_id().await? {
+            Ok((id, result)) => (id, result),
+            Err(e) => (e.id(), Err(StoqError::Other(anyhow::anyhow!("seed delivery task failed: {}", e)))),
+        };
+        let node = self.nodes.remove(&id).expect("every task is spawned with its node");
+        Some((node, result))
+    }
+}
+
+/// One payload on its way to a seed node
+#[derive(Clone)]
+struct Delivery {
+    transport: StoqTransport,
+    /// The encoded seed frame, grouped and signed per connection
+    frame: Bytes,
+    payload: Bytes,
+    ack: Bytes,
+    priority: i32,
+    timeout: Duration,
+}
+
+impl Delivery {
+    async fn run(self, node: SeedNode) -> Result<()> {
+        tokio::time::timeout(self.timeout, self.deliver(&node))
+            .await
+            .unwrap_or(Err(StoqError::Timeout(self.timeout)))
+    }
+
+    async fn deliver(&self, node: &SeedNode) -> Result<()> {
+        let connection = self.transport.connect(&Endpoint::new(node.address, node.port)).await?;
+        let preamble = connection.signing.encode(std::slice::from_ref(&self.frame))?;
+        let mut stream = connection.open_stream().await?;
+        stream.send.set_priority(self.priority)?;
+        stream.send.write_all(&preamble).await?;
+        stream.send.write_chunk(self.payload.clone()).await?;
+        stream.send.finish()?;
+
+        let ack = stream.recv.read_to_end(ACK_LEN).await.map_err(|e| StoqError::from_read_to_end(e, ACK_LEN))?;
+        if ack != self.ack {
+            return Err(StoqError::Protocol("seed acknowledgement does not match the packet".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Read a seeded payload from `stream` and acknowledge it
+pub(super) async fn receive(stream: &mut Stream, limit: usize) -> Result<(SeedFrame, Bytes)> {
+    let mut data = Bytes::from(stream.recv.read_to_end(limit).await.map_err(|e| StoqError::from_read_to_end(e, limit))?);
+
+    let frame = match read_preamble(&stream.signing, &mut data).await {
+        Ok(frame) => frame,
+        Err(e) => {
+            let _ = stream.reset(error_codes::PROTOCOL_VIOLATION);
+            return Err(e);
+        }
+    };
+    if packet_id(&data) != frame.packet_id {
+        let _ = stream.reset(error_codes::PROTOCOL_VIOLATION);
+        return Err(StoqError::Protocol("seeded payload does not match its packet ID".to_string()));
+    }
+
+    stream.send.write_all(&ack(&frame.packet_id)).await?;
+    stream.send.finish()?;
+    Ok((frame, data))
+}
+
+/// Verify the frame group opening a delivery and take its seed frame
+async fn read_preamble(signing: &ConnectionSigning, data: &mut Bytes) -> Result<SeedFrame> {
+    let not_seed = || StoqError::Protocol("stream does not start with a seed frame".to_string());
+    let group = signing::split_group(data).map_err(|_| not_seed())?;
+    match <[Bytes; 1]>::try_from(signing.verify(group).await?) {
+        Ok([frame]) => match StoqFrame::decode(frame) {
+            Ok(StoqFrame::Seed(frame)) => Ok(frame),
+            _ => Err(not_seed()),
+        },
+        Err(_) => Err(not_seed()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{signing_config, test_config, test_transport, trust};
+    use super::*;
+    use std::net::Ipv6Addr;
+    use tokio::sync::mpsc;
+
+    async fn node() -> (StoqTransport, SeedNode) {
+        let (transport, endpoint) = test_transport(test_config()).await;
+        (transport, SeedNode { address: endpoint.address, port: endpoint.port, reliability: 50 })
+    }
+
+    /// Accept seeds on `transport`, reporting each payload with its node's port
+    fn serve(transport: StoqTransport, port: u16, seen: mpsc::UnboundedSender<(u16, Bytes)>) {
+        tokio::spawn(async move {
+            while let Ok(connection) = transport.accept().await {
+                let seen = seen.clone();
+                tokio::spawn(async move {
+                    while let Ok(mut stream) = connection.accept_stream().await {
+                        if let Ok((_, payload)) = stream.receive_seed(1 << 20).await {
+                            let _ = seen.send((port, payload));
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    fn info(nodes: Vec<SeedNode>, replication_factor: u32, priority: SeedPriority) -> SeedInfo {
+        SeedInfo { seed_nodes: nodes, replication_factor, priority }
+    }
+
+    #[test]
+    fn test_quorum_and_ranking() {
+        let node = |port, reliability| SeedNode { address: Ipv6Addr::LOCALHOST, port, reliability };
+        let nodes = vec![node(1, 40), node(2, 90), node(3, 70)];
+        let config = SeedConfig::default();
+
+        assert_eq!(config.required(&info(nodes.clone(), 3, SeedPriority::Normal)).unwrap(), 2);
+        assert_eq!(config.required(&info(nodes.clone(), 3, SeedPriority::Critical)).unwrap(), 3);
+        assert!(config.required(&info(nodes.clone(), 4, SeedPriority::Normal)).is_err());
+        assert!(config.required(&info(nodes.clone(), 0, SeedPriority::Normal)).is_err());
+        let config = SeedConfig { quorum: Some(3), ..SeedConfig::default() };
+        assert!(config.required(&info(nodes.clone(), 2, SeedPriority::Low)).is_err());
+
+        let ports: Vec<u16> = rank(&nodes).iter().map(|node| node.port).collect();
+        assert_eq!(ports, [2, 3, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_seed_reaches_quorum_through_alternate() {
+        let (sender, _) = node().await;
+        let (seen_tx, mut seen) = mpsc::unbounded_channel();
+        let mut nodes = Vec::new();
+        for reliability in [60, 50] {
+            let (transport, mut seed) = node().await;
+            seed.reliability = reliability;
+            serve(transport, seed.port, seen_tx.clone());
+            nodes.push(seed);
+        }
+        // The most reliable node is down, so the least reliable one stands in
+        let (down, mut dead) = node().await;
+        down.shutdown().await;
+        dead.reliability = 99;
+        nodes.push(dead.clone());
+        let (standby, standby_node) = node().await;
+        serve(standby, standby_node.port, seen_tx);
+        nodes.push(SeedNode { reliability: 10, ..standby_node.clone() });
+
+        let payload = Bytes::from(vec![7u8; 256 * 1024]);
+        let config = SeedConfig { quorum: Some(3), attempt_timeout: Duration::from_millis(500) };
+        let report = sender.seed(&info(nodes, 3, SeedPriority::High), payload.clone(), &config).await.unwrap();
+
+        assert_eq!(report.packet_id, packet_id(&payload));
+        assert_eq!(report.acked.len(), 3);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0.port, dead.port);
+        assert!(report.acked.iter().any(|node| node.port == standby_node.port));
+        for _ in 0..3 {
+            let (_, received) = seen.recv().await.unwrap();
+            assert_eq!(received, payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_seed_fails_without_quorum() {
+        let (sender, _) = node().await;
+        let (live, live_node) = node().await;
+        let (seen_tx, _seen) = mpsc::unbounded_channel();
+        serve(live, live_node.port, seen_tx);
+        let (down, dead) = node().await;
+        down.shutdown().await;
+
+        let config = SeedConfig { attempt_timeout: Duration::from_millis(500), ..SeedConfig::default() };
+        let result = sender.seed(&info(vec![live_node, dead], 2, SeedPriority::Critical), Bytes::from_static(b"cfg"), &config).await;
+        assert!(matches!(result, Err(StoqError::Quorum { required: 2, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_receive_seed_rejects_corrupt_payload() {
+        let (sender, _) = node().await;
+        let (receiver, receiver_node) = node().await;
+        let accept = tokio::spawn(async move {
+            let connection = receiver.accept().await.unwrap();
+            let mut stream = connection.accept_stream().await.unwrap();
+            stream.receive_seed(1024).await.map(|(_, payload)| payload)
+        });
+
+        let info = info(vec![receiver_node.clone()], 1, SeedPriority::Normal);
+        let frame = StoqFrame::Seed(SeedFrame { seed_info: info, packet_id: packet_id(b"original") }).encode().unwrap();
+        let connection = sender.connect(&Endpoint::new(receiver_node.address, receiver_node.port)).await.unwrap();
+        let preamble = connection.signing.encode(&[frame]).unwrap();
+        let mut stream = connection.open_stream().await.unwrap();
+        stream.send.write_all(&preamble).await.unwrap();
+        stream.send.write_all(b"tampered").await.unwrap();
+        stream.send.finish().unwrap();
+
+        let ack = stream.recv.read_to_end(ACK_LEN).await;
+        assert_eq!(StoqError::from_read_to_end(ack.unwrap_err(), ACK_LEN).stream_error_code(), Some(error_codes::PROTOCOL_VIOLATION));
+        assert!(matches!(accept.await.unwrap(), Err(StoqError::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn test_routing_policy_refuses_unsigned_seed() {
+        let (unsigned_sender, _) = node().await;
+        let (signed_sender, _) = test_transport(signing_config()).await;
+        let (receiver, endpoint) = test_transport(signing_config()).await;
+        trust(&receiver, &signed_sender);
+        let (seen_tx, mut seen) = mpsc::unbounded_channel();
+        serve(receiver, endpoint.port, seen_tx);
+
+        let info = info(vec![SeedNode { address: endpoint.address, port: endpoint.port, reliability: 50 }], 1, SeedPriority::Normal);
+        let config = SeedConfig { attempt_timeout: Duration::from_secs(5), ..SeedConfig::default() };
+        let payload = Bytes::from_static(b"cfg");
+
+        let refused = unsigned_sender.seed(&info, payload.clone(), &config).await.unwrap_err();
+        assert!(matches!(refused, StoqError::Quorum { acked: 0, required: 1 }));
+
+        let report = signed_sender.seed(&info, payload.clone(), &config).await.unwrap();
+        assert_eq!(report.acked.len(), 1);
+        assert_eq!(seen.recv().await.unwrap().1, payload);
+        assert!(seen.try_recv().is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Frame groups on a connection
+//!
+//! Seed and relay preambles are sent as frame groups (see
+//! [`crate::protocol::signing`]) rather than bare frames, so the transport's
+//! [`FrameSigningPolicy`](super::FrameSigningPolicy) applies to them: they are
+//! signed with the local FALCON key when the policy covers them, and on the
+//! way in a signed group is verified and an unsigned one is refused if the
+//! policy covers its frames. On a stream, a group follows its varint length.
+//!
+//! Signatures are bound to the connection through TLS keying material
+//! exported as [`CHANNEL_BINDING_LABEL`]. With FALCON enabled, each side opens
+//! a unidirectional stream once connected and sends the proof of its key on
+//! it. A receiver reads the peer's proof when the first signed group arrives
+//! and from then on accepts only groups signed by that key, so peers without
+//! FALCON, which cannot sign, never need to send one.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use quinn::VarInt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::debug;
+
+use crate::error::{Result, StoqError};
+use crate::extensions::DefaultStoqExtensions;
+use crate::protocol::signing::{self, MAX_SIGNATURE_LEN};
+use crate::protocol::StoqProtocolHandler;
+use crate::protocol::frames;
+
+/// TLS exporter label of the keying material signatures are bound to
+pub(crate) const CHANNEL_BINDING_LABEL: &[u8] = b"EXPORTER-stoq-frame-signing";
+
+/// Time allowed for the peer's identity proof when no transport sets one
+const IDENTITY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Signs and verifies the frame groups sent over one connection
+pub(crate) struct ConnectionSigning {
+    connection: quinn::Connection,
+    protocol: Arc<StoqProtocolHandler>,
+    /// Key ID the peer proved, or why it did not, once a signed group needed it
+    peer_key_id: OnceCell<std::result::Result<String, String>>,
+    identity_timeout: Duration,
+}
+
+impl ConnectionSigning {
+    /// Sign and verify according to `protocol`'s policy and FALCON keys,
+    /// waiting up to `identity_timeout` for the peer's identity proof
+    pub(crate) fn new(connection: quinn::Connection, protocol: Arc<StoqProtocolHandler>, identity_timeout: Duration) -> Self {
+        Self {
+            connection,
+            protocol,
+            peer_key_id: OnceCell::new(),
+            identity_timeout,
+        }
+    }
+
+    /// Neither sign nor require signatures, for connections built outside a transport
+    pub(crate) fn unsigned(connection: quinn::Connection) -> Self {
+        let protocol = StoqProtocolHandler::new(Arc::new(DefaultStoqExtensions::new()), None, crate::STOQ_MTU);
+        Self::new(connection, Arc::new(protocol), IDENTITY_TIMEOUT)
+    }
+
+    /// Keying material shared by both ends of this connection and no other
+    pub(crate) fn channel_binding(&self) -> Result<[u8; 32]> {
+        let mut binding = [0u8; 32];
+        self.connection.export_keying_material(&mut binding, CHANNEL_BINDING_LABEL, b"")
+            .map_err(|_| StoqError::Falcon("connection has no keying material to bind signatures to".to_string()))?;
+        Ok(binding)
+    }
+
+    /// Send the proof of the local FALCON key to the peer in the background,
+    /// if there is a key
+    pub(crate) fn spawn_identity_proof(&self) {
+        let proof = self.channel_binding()
+            .and_then(|binding| self.protocol.encode_identity(&binding, self.connection.side()));
+        let proof = match proof {
+            Ok(Some(proof)) => proof,
+            Ok(None) => return,
+            Err(e) => {
+                debug!("Cannot prove the local FALCON key: {}", e);
+                return;
+            }
+        };
+        let connection = self.connection.clone();
+        tokio::spawn(async move {
+            let sent = async {
+                let mut send = connection.open_uni().await?;
+                send.write_all(&proof).await?;
+                send.finish()?;
+                Ok::<_, StoqError>(())
+            };
+            if let Err(e) = sent.await {
+                debug!("Failed to send the FALCON identity proof: {}", e);
+            }
+        });
+    }
+
+    /// The key the peer proved on this connection, read on first use
+    async fn peer_key_id(&self) -> Result<&str> {
+        let proven = self.peer_key_id.get_or_init(|| async {
+            tokio::time::timeout(self.identity_timeout, self.read_identity())
+                .await
+                .unwrap_or(Err(StoqError::Timeout(self.identity_timeout)))
+                .map_err(|e| e.to_string())
+        }).await;
+        proven.as_deref().map_err(|e| StoqError::Falcon(format!("peer did not prove a FALCON key: {}", e)))
+    }
+
+    async fn read_identity(&self) -> Result<String> {
+        let mut recv = self.connection.accept_uni().await?;
+        let proof = recv.read_to_end(MAX_SIGNATURE_LEN).await
+            .map_err(|e| StoqError::from_read_to_end(e, MAX_SIGNATURE_LEN))?;
+        self.protocol.verify_identity(proof.into(), &self.channel_binding()?, !self.connection.side())
+    }
+
+    /// The length-prefixed group carrying the encoded `frames`
+    pub(crate) fn encode(&self, frames: &[Bytes]) -> Result<Bytes> {
+        let group = self.protocol.encode_frame_group(frames, &self.channel_binding()?)?;
+        let mut buf = BytesMut::with_capacity(group.len() + 8);
+        // Groups are built in memory, far below the 2^62 varint ceiling
+        frames::encode_varint(&mut buf, VarInt::from_u64(group.len() as u64).expect("group length fits in a varint"));
+        buf.put_slice(&group);
+        Ok(buf.freeze())
+    }
+
+    /// Verify a group, without its length prefix, and return its encoded frames
+    pub(crate) async fn verify(&self, group: Bytes) -> Result<Vec<Bytes>> {
+        let peer_key_id = if signing::is_signed(&group) { Some(self.peer_key_id().await?) } else { None };
+        self.protocol.verify_frame_group(group, &self.channel_binding()?, peer_key_id)
+    }
+}
+
+/// Split the length-prefixed group off the front of `data`
+pub(crate) fn split_group(data: &mut Bytes) -> Result<Bytes> {
+    let len = frames::decode_varint(data)
+        .ok_or_else(|| StoqError::Protocol("frame group length missing".to_string()))?
+        .into_inner();
+    if len > data.len() as u64 {
+        return Err(StoqError::Protocol(format!("frame group of {} bytes is truncated", len)));
+    }
+    Ok(data.split_to(len as usize))
+}
@@ -0,0 +1,65 @@
+//! Shared setup for transport tests
+
+use std::net::Ipv6Addr;
+use std::sync::Arc;
+
+use super::falcon::FalconVariant;
+use super::{Connection, Endpoint, FrameSigningPolicy, StoqTransport, TransportConfig};
+
+/// Loopback config on an ephemeral port, without FALCON
+pub(crate) fn test_config() -> TransportConfig {
+    TransportConfig {
+        port: 0,
+        enable_falcon_crypto: false,
+        ..TransportConfig::default()
+    }
+}
+
+/// Like [`test_config`], but signing hop and seed frames and refusing them unsigned
+pub(crate) fn signing_config() -> TransportConfig {
+    TransportConfig {
+        enable_falcon_crypto: true,
+        falcon_variant: FalconVariant::Falcon512,
+        frame_signing: FrameSigningPolicy::Routing,
+        ..test_config()
+    }
+}
+
+/// Make `truster` accept frames signed by `signer`
+pub(crate) fn trust(truster: &StoqTransport, signer: &StoqTransport) {
+    let key = signer.falcon_transport().unwrap().read().get_local_public_key().unwrap().clone();
+    truster.falcon_transport().unwrap().write().add_trusted_key(key.id(), key);
+}
+
+/// A transport listening on `config`, with the loopback endpoint peers dial it on
+pub(crate) async fn test_transport(config: TransportConfig) -> (StoqTransport, Endpoint) {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let transport = StoqTransport::new(config).await.unwrap();
+    let endpoint = Endpoint::new(Ipv6Addr::LOCALHOST, transport.local_addr().unwrap().port());
+    (transport, endpoint)
+}
+
+/// A client connected to a server, with both ends of the connection
+pub(crate) struct ConnectedPair {
+    pub server: StoqTransport,
+    pub server_endpoint: Endpoint,
+    pub client: StoqTransport,
+    /// The client's end
+    pub connection: Arc<Connection>,
+    /// The server's end
+    pub accepted: Arc<Connection>,
+}
+
+/// Connect two fresh transports built from `config`
+pub(crate) async fn connected_pair(config: TransportConfig) -> ConnectedPair {
+    let (server, server_endpoint) = test_transport(config.clone()).await;
+    let (client, _) = test_transport(config).await;
+    let (accepted, connection) = tokio::join!(server.accept(), client.connect(&server_endpoint));
+    ConnectedPair {
+        server,
+        server_endpoint,
+        client,
+        connection: connection.unwrap(),
+        accepted: accepted.unwrap(),
+    }
+}
@@ -20,7 +20,7 @@ async fn test_live_connection_adaptation() {
     config.port = 0; // Random port
     config.send_buffer_size = 1024 * 1024; // 1MB initial
 
-    let mut transport = StoqTransport::new(config.clone()).await.unwrap();
+    let transport = StoqTransport::new(config.clone()).await.unwrap();
 
     // Start adaptation manager
     transport.start_adaptation().await;
@@ -67,7 +67,7 @@ async fn test_live_connection_adaptation() {
     new_config.send_buffer_size = 16 * 1024 * 1024; // 16MB
     new_config.max_concurrent_streams = 1000;
 
-    transport.update_live_config(new_config).await;
+    transport.update_live_config(new_config).unwrap();
 
     // Wait for adaptation to apply
     sleep(Duration::from_millis(2000)).await;
@@ -296,7 +296,7 @@ async fn test_real_network_simulation() {
     let mut config = TransportConfig::default();
     config.bind_address = Ipv6Addr::UNSPECIFIED;
 
-    let mut transport = StoqTransport::new(config.clone()).await.unwrap();
+    let transport = StoqTransport::new(config.clone()).await.unwrap();
 
     transport.start_adaptation().await;
 
@@ -108,7 +108,7 @@ async fn test_real_throughput() {
             gbps
         );
 
-        conn.close();
+        conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
     }
 
     println!("\n=== Testing Connection Pooling Performance ===\n");
@@ -134,7 +134,7 @@ async fn test_real_throughput() {
     println!("Pooled connection: {:.2} ms", warm_connect_time.as_millis());
     println!("Speedup: {:.1}x", cold_connect_time.as_secs_f64() / warm_connect_time.as_secs_f64());
 
-    conn2.close();
+    conn2.close(stoq::protocol::error_codes::NO_ERROR, b"done");
 
     println!("\n=== Testing Concurrent Streams ===\n");
 
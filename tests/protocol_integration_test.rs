@@ -14,6 +14,10 @@ use std::sync::Arc;
 use std::net::Ipv6Addr;
 use tokio::time::{sleep, Duration};
 
+/// Stands in for the keying material a connection would export; nothing
+/// here is signed, so any value does
+const CHANNEL_BINDING: &[u8] = &[0; 32];
+
 #[test]
 fn test_protocol_frames_encoding() {
     println!("\n=== Testing Protocol Frame Encoding ===");
@@ -133,7 +137,7 @@ fn test_protocol_handler_integration() {
 
     // Test applying extensions to data
     let test_data = b"This is test data that will have protocol extensions applied";
-    let frames = handler.apply_extensions(test_data).unwrap();
+    let frames = handler.apply_extensions(test_data, CHANNEL_BINDING).unwrap();
 
     assert!(!frames.is_empty(), "No extension frames generated");
     println!("✓ Generated {} extension frames", frames.len());
@@ -265,7 +269,7 @@ async fn test_end_to_end_protocol_integration() {
 
     // Check that extension frames were generated
     let handler = client_transport.protocol_handler();
-    let frames = handler.apply_extensions(test_data).unwrap();
+    let frames = handler.apply_extensions(test_data, CHANNEL_BINDING).unwrap();
     assert!(!frames.is_empty());
     println!("✓ Protocol extensions applied: {} frames", frames.len());
 
@@ -340,7 +344,7 @@ fn test_protocol_extension_in_real_packets() {
     let packet_data = b"Real packet data that would be sent over QUIC";
 
     // Apply extensions - this should generate actual QUIC frames
-    let frames = handler.apply_extensions(packet_data).unwrap();
+    let frames = handler.apply_extensions(packet_data, CHANNEL_BINDING).unwrap();
 
     // Verify we have frames
     assert!(!frames.is_empty(), "No frames generated - extensions not integrated!");
@@ -134,7 +134,7 @@ async fn test_real_vs_claimed_performance() {
             reality_percent
         );
 
-        conn.close();
+        conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
     }
 
     println!("└──────────────────┴──────────────┴──────────────┴─────────────┘\n");
@@ -329,7 +329,7 @@ async fn test_real_latency_measurements() {
     }
 
     // Cleanup
-    conn.close();
+    conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
     client.shutdown().await;
     server.shutdown().await;
 
@@ -403,7 +403,7 @@ async fn test_real_connection_scaling() {
                         if let Ok(mut stream) = conn.open_stream().await {
                             let _ = stream.send(&[0x42; 1024]).await;
                         }
-                        conn.close();
+                        conn.close(stoq::protocol::error_codes::NO_ERROR, b"done");
                         Ok(connect_time)
                     }
                     Err(e) => {